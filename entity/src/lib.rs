@@ -2,4 +2,23 @@
 
 pub mod prelude;
 
+pub mod album;
+pub mod browse_album;
+pub mod browse_artist;
+pub mod browse_genre;
+pub mod client;
+pub mod hidden_directory;
+pub mod play_history;
+pub mod play_queue;
+pub mod playback_position;
+pub mod playlist;
+pub mod scan_error;
+pub mod slug;
 pub mod track;
+pub mod track_annotation;
+pub mod track_artist;
+pub mod track_change;
+pub mod track_lyrics;
+pub mod user_content_filter;
+pub mod user_folder;
+pub mod user_role;
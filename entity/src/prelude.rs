@@ -1,3 +1,22 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.15
 
+pub use super::album::Entity as Album;
+pub use super::browse_album::Entity as BrowseAlbum;
+pub use super::browse_artist::Entity as BrowseArtist;
+pub use super::browse_genre::Entity as BrowseGenre;
+pub use super::client::Entity as Client;
+pub use super::hidden_directory::Entity as HiddenDirectory;
+pub use super::play_history::Entity as PlayHistory;
+pub use super::play_queue::Entity as PlayQueue;
+pub use super::playback_position::Entity as PlaybackPosition;
+pub use super::playlist::Entity as Playlist;
+pub use super::scan_error::Entity as ScanError;
+pub use super::slug::Entity as Slug;
 pub use super::track::Entity as Track;
+pub use super::track_annotation::Entity as TrackAnnotation;
+pub use super::track_artist::Entity as TrackArtist;
+pub use super::track_change::Entity as TrackChange;
+pub use super::track_lyrics::Entity as TrackLyrics;
+pub use super::user_content_filter::Entity as UserContentFilter;
+pub use super::user_folder::Entity as UserFolder;
+pub use super::user_role::Entity as UserRole;
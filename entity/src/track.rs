@@ -5,7 +5,7 @@ use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json;
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "track")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -21,6 +21,7 @@ pub struct Model {
     pub year: Option<i32>,
     pub genre: String,
     pub album_artist: String,
+    pub album_artist_tag: String,
     pub publisher: String,
     pub catalog_number: String,
     pub duration_seconds: i32,
@@ -33,6 +34,51 @@ pub struct Model {
     pub album_art_path: Option<String>,
     pub album_art_mime_type: Option<String>,
     pub album_art_size: Option<i32>,
+    pub images: serde_json::Value,
+    pub content_type: Option<String>,
+    pub isrc: Option<String>,
+    pub barcode: Option<String>,
+    pub language: Option<String>,
+    pub release_country: Option<String>,
+    pub explicit: Option<bool>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub comment: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub search_text: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub search_title: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub search_artist: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub search_album: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub search_album_artist: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub search_genre: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub transliteration: Option<String>,
+    pub sort_artist: Option<String>,
+    pub sort_album: Option<String>,
+    pub sort_title: Option<String>,
+    pub is_hidden: bool,
+    pub missing_since: Option<chrono::DateTime<Utc>>,
+    pub content_hash: Option<String>,
+    pub tag_hash: Option<String>,
+    pub library_root: Option<String>,
+    pub audio_md5: Option<String>,
+    pub audio_md5_status: Option<String>,
+    pub audio_md5_checked: Option<chrono::DateTime<Utc>>,
+    pub media_type: String,
+    pub chapters: serde_json::Value,
+    pub audio_info: serde_json::Value,
+    pub codec: Option<String>,
+    pub codec_profile: Option<String>,
+    pub encoder: Option<String>,
+    #[sea_orm(column_type = "Double", nullable)]
+    pub bpm: Option<f64>,
+    pub initial_key: Option<String>,
+    pub file_size: Option<i64>,
+    pub added: chrono::DateTime<Utc>,
     pub created: chrono::DateTime<Utc>,
     pub modified: chrono::DateTime<Utc>,
 }
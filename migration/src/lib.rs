@@ -1,6 +1,49 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20240607_224721_create_table_track;
+mod m20261016_000001_add_track_created_index;
+mod m20261016_000002_create_table_scan_error;
+mod m20261016_000003_create_table_play_queue;
+mod m20261016_000004_add_track_content_type;
+mod m20261016_000005_create_table_playlist;
+mod m20261016_000006_add_track_isrc_barcode;
+mod m20261016_000007_create_table_slug;
+mod m20261016_000008_add_track_text_index;
+mod m20261016_000009_create_table_track_artist;
+mod m20261016_000010_add_track_hidden;
+mod m20261016_000011_create_table_play_history;
+mod m20261016_000012_create_table_album;
+mod m20261016_000013_create_table_playback_position;
+mod m20261016_000014_add_track_missing;
+mod m20261016_000015_add_track_library_root;
+mod m20261016_000016_create_table_track_annotation;
+mod m20261016_000017_add_track_audio_md5;
+mod m20261016_000018_add_playlist_public;
+mod m20261016_000019_add_track_year_index;
+mod m20261016_000020_create_table_client;
+mod m20261016_000021_add_track_images;
+mod m20261016_000022_create_table_user_folder;
+mod m20261016_000023_add_track_tag_hash;
+mod m20261016_000024_create_table_track_lyrics;
+mod m20261016_000025_add_explicit_to_track;
+mod m20261016_000026_create_table_user_content_filter;
+mod m20261016_000027_add_track_search_fields;
+mod m20261016_000028_create_table_user_role;
+mod m20261016_000029_add_playlist_recommendation;
+mod m20261016_000030_create_table_track_change;
+mod m20261016_000031_add_track_media_type;
+mod m20261016_000032_add_album_release_type;
+mod m20261017_000033_add_track_comment;
+mod m20261017_000034_add_track_album_artist_tag;
+mod m20261017_000035_add_track_audio_info;
+mod m20261017_000036_add_track_language_country;
+mod m20261017_000037_create_browse_tables;
+mod m20261017_000038_add_track_codec_encoder;
+mod m20261017_000039_add_track_bpm_key;
+mod m20261017_000040_add_browse_added;
+mod m20261017_000041_add_track_file_size;
+mod m20261017_000042_add_track_added;
+mod m20261017_000043_add_scan_error_retries;
 
 pub struct Migrator;
 
@@ -9,6 +52,49 @@ impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
         vec![
             Box::new(m20240607_224721_create_table_track::Migration),
+            Box::new(m20261016_000001_add_track_created_index::Migration),
+            Box::new(m20261016_000002_create_table_scan_error::Migration),
+            Box::new(m20261016_000003_create_table_play_queue::Migration),
+            Box::new(m20261016_000004_add_track_content_type::Migration),
+            Box::new(m20261016_000005_create_table_playlist::Migration),
+            Box::new(m20261016_000006_add_track_isrc_barcode::Migration),
+            Box::new(m20261016_000007_create_table_slug::Migration),
+            Box::new(m20261016_000008_add_track_text_index::Migration),
+            Box::new(m20261016_000009_create_table_track_artist::Migration),
+            Box::new(m20261016_000010_add_track_hidden::Migration),
+            Box::new(m20261016_000011_create_table_play_history::Migration),
+            Box::new(m20261016_000012_create_table_album::Migration),
+            Box::new(m20261016_000013_create_table_playback_position::Migration),
+            Box::new(m20261016_000014_add_track_missing::Migration),
+            Box::new(m20261016_000015_add_track_library_root::Migration),
+            Box::new(m20261016_000016_create_table_track_annotation::Migration),
+            Box::new(m20261016_000017_add_track_audio_md5::Migration),
+            Box::new(m20261016_000018_add_playlist_public::Migration),
+            Box::new(m20261016_000019_add_track_year_index::Migration),
+            Box::new(m20261016_000020_create_table_client::Migration),
+            Box::new(m20261016_000021_add_track_images::Migration),
+            Box::new(m20261016_000022_create_table_user_folder::Migration),
+            Box::new(m20261016_000023_add_track_tag_hash::Migration),
+            Box::new(m20261016_000024_create_table_track_lyrics::Migration),
+            Box::new(m20261016_000025_add_explicit_to_track::Migration),
+            Box::new(m20261016_000026_create_table_user_content_filter::Migration),
+            Box::new(m20261016_000027_add_track_search_fields::Migration),
+            Box::new(m20261016_000028_create_table_user_role::Migration),
+            Box::new(m20261016_000029_add_playlist_recommendation::Migration),
+            Box::new(m20261016_000030_create_table_track_change::Migration),
+            Box::new(m20261016_000031_add_track_media_type::Migration),
+            Box::new(m20261016_000032_add_album_release_type::Migration),
+            Box::new(m20261017_000033_add_track_comment::Migration),
+            Box::new(m20261017_000034_add_track_album_artist_tag::Migration),
+            Box::new(m20261017_000035_add_track_audio_info::Migration),
+            Box::new(m20261017_000036_add_track_language_country::Migration),
+            Box::new(m20261017_000037_create_browse_tables::Migration),
+            Box::new(m20261017_000038_add_track_codec_encoder::Migration),
+            Box::new(m20261017_000039_add_track_bpm_key::Migration),
+            Box::new(m20261017_000040_add_browse_added::Migration),
+            Box::new(m20261017_000041_add_track_file_size::Migration),
+            Box::new(m20261017_000042_add_track_added::Migration),
+            Box::new(m20261017_000043_add_scan_error_retries::Migration),
         ]
     }
 }
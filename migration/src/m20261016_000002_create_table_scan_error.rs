@@ -0,0 +1,71 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ScanError::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ScanError::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ScanError::Path).string().not_null())
+                    .col(ColumnDef::new(ScanError::Kind).string().not_null())
+                    .col(ColumnDef::new(ScanError::Message).text().not_null())
+                    .col(ColumnDef::new(ScanError::ScanId).string().not_null())
+                    .col(ColumnDef::new(ScanError::Occurred).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Index on path for clearing errors once a file scans successfully
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_scan_error_path")
+                    .table(ScanError::Table)
+                    .col(ScanError::Path)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Index on scan run for per-scan error listings
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_scan_error_scan_id")
+                    .table(ScanError::Table)
+                    .col(ScanError::ScanId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ScanError::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ScanError {
+    Table,
+    Id,
+    Path,
+    Kind,
+    Message,
+    ScanId,
+    Occurred,
+}
@@ -0,0 +1,64 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PlayQueue::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PlayQueue::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PlayQueue::User).string().not_null())
+                    .col(ColumnDef::new(PlayQueue::Client).string().not_null())
+                    .col(ColumnDef::new(PlayQueue::TrackIds).json_binary().not_null())
+                    .col(ColumnDef::new(PlayQueue::CurrentTrackId).integer())
+                    .col(ColumnDef::new(PlayQueue::PositionMs).big_integer().not_null())
+                    .col(ColumnDef::new(PlayQueue::Changed).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // One saved queue per user and client
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_play_queue_user_client")
+                    .table(PlayQueue::Table)
+                    .col(PlayQueue::User)
+                    .col(PlayQueue::Client)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PlayQueue::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PlayQueue {
+    Table,
+    Id,
+    User,
+    Client,
+    TrackIds,
+    CurrentTrackId,
+    PositionMs,
+    Changed,
+}
@@ -0,0 +1,47 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Playlist::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Playlist::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Playlist::Name).string().not_null())
+                    .col(ColumnDef::new(Playlist::Comment).text().not_null().default(""))
+                    .col(ColumnDef::new(Playlist::TrackIds).json_binary().not_null())
+                    .col(ColumnDef::new(Playlist::Created).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(Playlist::Changed).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Playlist::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Playlist {
+    Table,
+    Id,
+    Name,
+    Comment,
+    TrackIds,
+    Created,
+    Changed,
+}
@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Normalized ISRC and UPC/EAN barcode; NULL until the file is rescanned
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Track::Isrc).string())
+                    .add_column_if_not_exists(ColumnDef::new(Track::Barcode).string())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_isrc")
+                    .table(Track::Table)
+                    .col(Track::Isrc)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_barcode")
+                    .table(Track::Table)
+                    .col(Track::Barcode)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .drop_column(Track::Isrc)
+                    .drop_column(Track::Barcode)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    Isrc,
+    Barcode,
+}
@@ -0,0 +1,85 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Slug::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Slug::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Slug::Kind).string().not_null())
+                    .col(ColumnDef::new(Slug::Key).text().not_null())
+                    .col(ColumnDef::new(Slug::Slug).string().not_null())
+                    .col(ColumnDef::new(Slug::LegacyId).text())
+                    .col(ColumnDef::new(Slug::Created).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // One slug per artist or album, and slugs unique within their kind
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_slug_kind_key")
+                    .table(Slug::Table)
+                    .col(Slug::Kind)
+                    .col(Slug::Key)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_slug_kind_slug")
+                    .table(Slug::Table)
+                    .col(Slug::Kind)
+                    .col(Slug::Slug)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_slug_legacy_id")
+                    .table(Slug::Table)
+                    .col(Slug::LegacyId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Slug::Table).to_owned())
+            .await
+    }
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(DeriveIden)]
+enum Slug {
+    Table,
+    Id,
+    Kind,
+    Key,
+    Slug,
+    LegacyId,
+    Created,
+}
@@ -0,0 +1,62 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Normalized search text, romaji transliteration and sort keys derived
+        // from the tags; NULL until the next scan fills them in
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Track::SearchText).text())
+                    .add_column_if_not_exists(ColumnDef::new(Track::Transliteration).text())
+                    .add_column_if_not_exists(ColumnDef::new(Track::SortArtist).string())
+                    .add_column_if_not_exists(ColumnDef::new(Track::SortAlbum).string())
+                    .add_column_if_not_exists(ColumnDef::new(Track::SortTitle).string())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_sort_keys")
+                    .table(Track::Table)
+                    .col(Track::SortArtist)
+                    .col(Track::SortAlbum)
+                    .col(Track::SortTitle)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .drop_column(Track::SearchText)
+                    .drop_column(Track::Transliteration)
+                    .drop_column(Track::SortArtist)
+                    .drop_column(Track::SortAlbum)
+                    .drop_column(Track::SortTitle)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    SearchText,
+    Transliteration,
+    SortArtist,
+    SortAlbum,
+    SortTitle,
+}
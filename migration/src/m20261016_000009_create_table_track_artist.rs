@@ -0,0 +1,92 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Each credited artist of a track, so collaborations can be browsed
+        // under every participant
+        manager
+            .create_table(
+                Table::create()
+                    .table(TrackArtist::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(TrackArtist::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(TrackArtist::TrackId).integer().not_null())
+                    .col(ColumnDef::new(TrackArtist::Artist).string().not_null())
+                    .col(ColumnDef::new(TrackArtist::Position).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_track_artist_track")
+                            .from(TrackArtist::Table, TrackArtist::TrackId)
+                            .to(Track::Table, Track::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_artist_track_id")
+                    .table(TrackArtist::Table)
+                    .col(TrackArtist::TrackId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_artist_artist")
+                    .table(TrackArtist::Table)
+                    .col(TrackArtist::Artist)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Start every track with its artist tag as written; the next scan
+        // splits collaborations
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"INSERT INTO "track_artist" ("track_id", "artist", "position")
+                   SELECT "id", "artist", 0 FROM "track" WHERE "artist" <> ''"#,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TrackArtist::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TrackArtist {
+    Table,
+    Id,
+    TrackId,
+    Artist,
+    Position,
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    Id,
+}
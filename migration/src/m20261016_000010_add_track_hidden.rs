@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Hidden tracks stay in the library but are left out of client responses
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Track::IsHidden).boolean().not_null().default(false))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(HiddenDirectory::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(HiddenDirectory::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(HiddenDirectory::Path).string().not_null().unique_key())
+                    .col(ColumnDef::new(HiddenDirectory::Created).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(HiddenDirectory::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .drop_column(Track::IsHidden)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    IsHidden,
+}
+
+#[derive(DeriveIden)]
+enum HiddenDirectory {
+    Table,
+    Id,
+    Path,
+    Created,
+}
@@ -0,0 +1,87 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // One row per completed play, for listening statistics
+        manager
+            .create_table(
+                Table::create()
+                    .table(PlayHistory::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PlayHistory::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PlayHistory::User).string().not_null())
+                    .col(ColumnDef::new(PlayHistory::Client).string())
+                    .col(ColumnDef::new(PlayHistory::TrackId).integer().not_null())
+                    .col(ColumnDef::new(PlayHistory::PlayedAt).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(PlayHistory::SecondsPlayed).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_play_history_track")
+                            .from(PlayHistory::Table, PlayHistory::TrackId)
+                            .to(Track::Table, Track::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Statistics always cover one user over a time range
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_play_history_user_played_at")
+                    .table(PlayHistory::Table)
+                    .col(PlayHistory::User)
+                    .col(PlayHistory::PlayedAt)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_play_history_track_id")
+                    .table(PlayHistory::Table)
+                    .col(PlayHistory::TrackId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PlayHistory::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PlayHistory {
+    Table,
+    Id,
+    User,
+    Client,
+    TrackId,
+    PlayedAt,
+    SecondsPlayed,
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    Id,
+}
@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Per-album data precomputed after each scan, so album listings don't
+        // need per-album lookups
+        manager
+            .create_table(
+                Table::create()
+                    .table(Album::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Album::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Album::AlbumArtist).string().not_null())
+                    .col(ColumnDef::new(Album::Name).string().not_null())
+                    .col(ColumnDef::new(Album::ArtTrackId).integer())
+                    .col(ColumnDef::new(Album::Updated).timestamp_with_time_zone().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_album_art_track")
+                            .from(Album::Table, Album::ArtTrackId)
+                            .to(Track::Table, Track::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_album_album_artist_name")
+                    .table(Album::Table)
+                    .col(Album::AlbumArtist)
+                    .col(Album::Name)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Album::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+#[allow(clippy::enum_variant_names)]
+enum Album {
+    Table,
+    Id,
+    AlbumArtist,
+    Name,
+    ArtTrackId,
+    Updated,
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    Id,
+}
@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Where each user left off in a track, from playback heartbeats
+        manager
+            .create_table(
+                Table::create()
+                    .table(PlaybackPosition::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PlaybackPosition::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PlaybackPosition::User).string().not_null())
+                    .col(ColumnDef::new(PlaybackPosition::Client).string())
+                    .col(ColumnDef::new(PlaybackPosition::TrackId).integer().not_null())
+                    .col(ColumnDef::new(PlaybackPosition::PositionMs).big_integer().not_null())
+                    .col(ColumnDef::new(PlaybackPosition::Updated).timestamp_with_time_zone().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_playback_position_track")
+                            .from(PlaybackPosition::Table, PlaybackPosition::TrackId)
+                            .to(Track::Table, Track::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_playback_position_user_track_id")
+                    .table(PlaybackPosition::Table)
+                    .col(PlaybackPosition::User)
+                    .col(PlaybackPosition::TrackId)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PlaybackPosition::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PlaybackPosition {
+    Table,
+    Id,
+    User,
+    Client,
+    TrackId,
+    PositionMs,
+    Updated,
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    Id,
+}
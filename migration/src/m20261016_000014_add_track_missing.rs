@@ -0,0 +1,52 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Tracks whose files disappear are marked missing instead of deleted, so
+        // play history survives an unmounted library. The content hash lets a
+        // file that reappears under another path take its old row back.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Track::MissingSince).timestamp_with_time_zone())
+                    .add_column_if_not_exists(ColumnDef::new(Track::ContentHash).string())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_content_hash")
+                    .table(Track::Table)
+                    .col(Track::ContentHash)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .drop_column(Track::MissingSince)
+                    .drop_column(Track::ContentHash)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    MissingSince,
+    ContentHash,
+}
@@ -0,0 +1,80 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Per-user ratings, stars and play counts, e.g. imported from another server
+        manager
+            .create_table(
+                Table::create()
+                    .table(TrackAnnotation::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(TrackAnnotation::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(TrackAnnotation::User).string().not_null())
+                    .col(ColumnDef::new(TrackAnnotation::TrackId).integer().not_null())
+                    .col(ColumnDef::new(TrackAnnotation::Rating).small_integer())
+                    .col(ColumnDef::new(TrackAnnotation::Starred).timestamp_with_time_zone())
+                    .col(ColumnDef::new(TrackAnnotation::PlayCount).integer().not_null().default(0))
+                    .col(ColumnDef::new(TrackAnnotation::LastPlayed).timestamp_with_time_zone())
+                    .col(ColumnDef::new(TrackAnnotation::Updated).timestamp_with_time_zone().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_track_annotation_track")
+                            .from(TrackAnnotation::Table, TrackAnnotation::TrackId)
+                            .to(Track::Table, Track::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_annotation_user_track_id")
+                    .table(TrackAnnotation::Table)
+                    .col(TrackAnnotation::User)
+                    .col(TrackAnnotation::TrackId)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TrackAnnotation::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TrackAnnotation {
+    Table,
+    Id,
+    User,
+    TrackId,
+    Rating,
+    Starred,
+    PlayCount,
+    LastPlayed,
+    Updated,
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    Id,
+}
@@ -0,0 +1,43 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // MD5 of the decoded audio from a FLAC file's STREAMINFO block, and
+        // the result of checking it against the audio during a deep scan
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Track::AudioMd5).string())
+                    .add_column_if_not_exists(ColumnDef::new(Track::AudioMd5Status).string())
+                    .add_column_if_not_exists(ColumnDef::new(Track::AudioMd5Checked).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .drop_column(Track::AudioMd5)
+                    .drop_column(Track::AudioMd5Status)
+                    .drop_column(Track::AudioMd5Checked)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    AudioMd5,
+    AudioMd5Status,
+    AudioMd5Checked,
+}
@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Every distinct client (client name + User-Agent) seen streaming, with
+        // per-client playback overrides set by an admin
+        manager
+            .create_table(
+                Table::create()
+                    .table(Client::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Client::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Client::Name).string().not_null())
+                    .col(ColumnDef::new(Client::UserAgent).string().not_null())
+                    .col(ColumnDef::new(Client::FirstSeen).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(Client::LastSeen).timestamp_with_time_zone().not_null())
+                    .col(ColumnDef::new(Client::ForceProfile).string())
+                    .col(ColumnDef::new(Client::MaxBitrate).integer())
+                    .col(ColumnDef::new(Client::DisableChunking).boolean().not_null().default(false))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_client_name_user_agent")
+                    .table(Client::Table)
+                    .col(Client::Name)
+                    .col(Client::UserAgent)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Client::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Client {
+    Table,
+    Id,
+    Name,
+    UserAgent,
+    FirstSeen,
+    LastSeen,
+    ForceProfile,
+    MaxBitrate,
+    DisableChunking,
+}
@@ -0,0 +1,59 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Folders a user is limited to; users without any see the whole library
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserFolder::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserFolder::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserFolder::User).string().not_null())
+                    .col(ColumnDef::new(UserFolder::Path).string().not_null())
+                    .col(ColumnDef::new(UserFolder::Created).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_user_folder_user_path")
+                    .table(UserFolder::Table)
+                    .col(UserFolder::User)
+                    .col(UserFolder::Path)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserFolder::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserFolder {
+    Table,
+    Id,
+    User,
+    Path,
+    Created,
+}
@@ -0,0 +1,67 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Lyrics fetched from an external provider for tracks without their
+        // own, with where they came from. A null content records that no
+        // provider had them, so they aren't looked up on every request.
+        manager
+            .create_table(
+                Table::create()
+                    .table(TrackLyrics::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(TrackLyrics::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(TrackLyrics::TrackId).integer().not_null().unique_key())
+                    .col(ColumnDef::new(TrackLyrics::Provider).string())
+                    .col(ColumnDef::new(TrackLyrics::Synced).boolean().not_null().default(false))
+                    .col(ColumnDef::new(TrackLyrics::Content).text())
+                    .col(ColumnDef::new(TrackLyrics::SourceUrl).string())
+                    .col(ColumnDef::new(TrackLyrics::License).string())
+                    .col(ColumnDef::new(TrackLyrics::Fetched).timestamp_with_time_zone().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_track_lyrics_track")
+                            .from(TrackLyrics::Table, TrackLyrics::TrackId)
+                            .to(Track::Table, Track::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TrackLyrics::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TrackLyrics {
+    Table,
+    Id,
+    TrackId,
+    Provider,
+    Synced,
+    Content,
+    SourceUrl,
+    License,
+    Fetched,
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    Id,
+}
@@ -0,0 +1,44 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Content restrictions for a user; users without a row see everything
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserContentFilter::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserContentFilter::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserContentFilter::User).string().not_null().unique_key())
+                    .col(ColumnDef::new(UserContentFilter::HideExplicit).boolean().not_null().default(false))
+                    .col(ColumnDef::new(UserContentFilter::Modified).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserContentFilter::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserContentFilter {
+    Table,
+    Id,
+    User,
+    HideExplicit,
+    Modified,
+}
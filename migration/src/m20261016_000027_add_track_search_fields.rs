@@ -0,0 +1,50 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Normalized, lowercased copies of the filterable tags, so filters match
+        // regardless of case without relying on the database's collation;
+        // NULL until the next scan fills them in
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Track::SearchTitle).text())
+                    .add_column_if_not_exists(ColumnDef::new(Track::SearchArtist).text())
+                    .add_column_if_not_exists(ColumnDef::new(Track::SearchAlbum).text())
+                    .add_column_if_not_exists(ColumnDef::new(Track::SearchAlbumArtist).text())
+                    .add_column_if_not_exists(ColumnDef::new(Track::SearchGenre).text())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .drop_column(Track::SearchTitle)
+                    .drop_column(Track::SearchArtist)
+                    .drop_column(Track::SearchAlbum)
+                    .drop_column(Track::SearchAlbumArtist)
+                    .drop_column(Track::SearchGenre)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    SearchTitle,
+    SearchArtist,
+    SearchAlbum,
+    SearchAlbumArtist,
+    SearchGenre,
+}
@@ -0,0 +1,44 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // What a user may do beyond browsing and streaming; users without a row may do everything
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserRole::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserRole::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserRole::User).string().not_null().unique_key())
+                    .col(ColumnDef::new(UserRole::Download).boolean().not_null().default(true))
+                    .col(ColumnDef::new(UserRole::Modified).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserRole::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserRole {
+    Table,
+    Id,
+    User,
+    Download,
+    Modified,
+}
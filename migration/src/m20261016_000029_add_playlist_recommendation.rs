@@ -0,0 +1,43 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Playlists built from a Last.fm user's listening, which a refresh
+        // rebuilds; NULL for ordinary playlists
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Playlist::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Playlist::LastfmUser).string())
+                    .add_column_if_not_exists(ColumnDef::new(Playlist::MixSize).integer())
+                    .add_column_if_not_exists(ColumnDef::new(Playlist::Refreshed).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Playlist::Table)
+                    .drop_column(Playlist::LastfmUser)
+                    .drop_column(Playlist::MixSize)
+                    .drop_column(Playlist::Refreshed)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Playlist {
+    Table,
+    LastfmUser,
+    MixSize,
+    Refreshed,
+}
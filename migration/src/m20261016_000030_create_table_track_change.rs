@@ -0,0 +1,70 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // One row per change to a track's metadata, with the values before and after
+        manager
+            .create_table(
+                Table::create()
+                    .table(TrackChange::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(TrackChange::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(TrackChange::TrackId).integer().not_null())
+                    .col(ColumnDef::new(TrackChange::Source).string().not_null())
+                    .col(ColumnDef::new(TrackChange::Changes).json_binary().not_null())
+                    .col(ColumnDef::new(TrackChange::Created).timestamp_with_time_zone().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_track_change_track")
+                            .from(TrackChange::Table, TrackChange::TrackId)
+                            .to(Track::Table, Track::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_change_track_id")
+                    .table(TrackChange::Table)
+                    .col(TrackChange::TrackId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TrackChange::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TrackChange {
+    Table,
+    Id,
+    TrackId,
+    Source,
+    Changes,
+    Created,
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    Id,
+}
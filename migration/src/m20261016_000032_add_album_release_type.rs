@@ -0,0 +1,62 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Whether an album is an album, EP, single, live album or
+        // compilation, from its tags or its length
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Album::Table)
+                    .add_column_if_not_exists(
+                        ColumnDef::new(Album::ReleaseType)
+                            .string()
+                            .not_null()
+                            .default("album"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_album_release_type")
+                    .table(Album::Table)
+                    .col(Album::ReleaseType)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_album_release_type")
+                    .table(Album::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Album::Table)
+                    .drop_column(Album::ReleaseType)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Album {
+    Table,
+    ReleaseType,
+}
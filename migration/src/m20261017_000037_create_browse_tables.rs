@@ -0,0 +1,127 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Artist, genre and album listings of the default view (music, hidden
+        // tracks left out), rebuilt after each scan so browsing doesn't group
+        // every track on each request. Empty until the first scan after upgrading.
+        manager
+            .create_table(
+                Table::create()
+                    .table(BrowseArtist::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(BrowseArtist::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(BrowseArtist::Artist).string().not_null().unique_key())
+                    .col(ColumnDef::new(BrowseArtist::AlbumCount).big_integer().not_null())
+                    .col(ColumnDef::new(BrowseArtist::SongCount).big_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(BrowseGenre::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(BrowseGenre::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(BrowseGenre::Genre).string().not_null().unique_key())
+                    .col(ColumnDef::new(BrowseGenre::SongCount).big_integer().not_null())
+                    .col(ColumnDef::new(BrowseGenre::AlbumCount).big_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(BrowseAlbum::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(BrowseAlbum::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(BrowseAlbum::AlbumArtist).string().not_null())
+                    .col(ColumnDef::new(BrowseAlbum::Album).string().not_null())
+                    .col(ColumnDef::new(BrowseAlbum::Year).integer())
+                    .col(ColumnDef::new(BrowseAlbum::TrackCount).big_integer().not_null())
+                    .col(ColumnDef::new(BrowseAlbum::DurationSeconds).big_integer().not_null())
+                    .col(ColumnDef::new(BrowseAlbum::Format).json_binary().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_browse_album_album_artist_album")
+                    .table(BrowseAlbum::Table)
+                    .col(BrowseAlbum::AlbumArtist)
+                    .col(BrowseAlbum::Album)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(BrowseAlbum::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(BrowseGenre::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(BrowseArtist::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum BrowseArtist {
+    Table,
+    Id,
+    Artist,
+    AlbumCount,
+    SongCount,
+}
+
+#[derive(DeriveIden)]
+enum BrowseGenre {
+    Table,
+    Id,
+    Genre,
+    SongCount,
+    AlbumCount,
+}
+
+#[derive(DeriveIden)]
+enum BrowseAlbum {
+    Table,
+    Id,
+    AlbumArtist,
+    Album,
+    Year,
+    TrackCount,
+    DurationSeconds,
+    Format,
+}
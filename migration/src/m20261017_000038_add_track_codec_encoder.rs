@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Codec, codec profile and encoder of the audio stream, copied out of
+        // audio_info so they can be filtered on
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Track::Codec).string())
+                    .add_column_if_not_exists(ColumnDef::new(Track::CodecProfile).string())
+                    .add_column_if_not_exists(ColumnDef::new(Track::Encoder).string())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Files read since audio_info was recorded already have them there
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"UPDATE "track" SET
+                    "codec" = "audio_info"->>'codec',
+                    "codec_profile" = "audio_info"->>'codec_profile',
+                    "encoder" = "audio_info"->>'encoder'"#,
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_codec")
+                    .table(Track::Table)
+                    .col(Track::Codec)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_encoder")
+                    .table(Track::Table)
+                    .col(Track::Encoder)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .drop_column(Track::Codec)
+                    .drop_column(Track::CodecProfile)
+                    .drop_column(Track::Encoder)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    Codec,
+    CodecProfile,
+    Encoder,
+}
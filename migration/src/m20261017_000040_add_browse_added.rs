@@ -0,0 +1,69 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // When the newest track of each artist and album was added, for
+        // listing recently added ones first
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(BrowseArtist::Table)
+                    .add_column_if_not_exists(ColumnDef::new(BrowseArtist::Added).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(BrowseAlbum::Table)
+                    .add_column_if_not_exists(ColumnDef::new(BrowseAlbum::Added).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Listings fall back to live queries while the tables are empty, until
+        // the next scan rebuilds them with the new column filled in
+        let db = manager.get_connection();
+        db.execute_unprepared(r#"DELETE FROM "browse_artist""#).await?;
+        db.execute_unprepared(r#"DELETE FROM "browse_album""#).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(BrowseArtist::Table)
+                    .drop_column(BrowseArtist::Added)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(BrowseAlbum::Table)
+                    .drop_column(BrowseAlbum::Added)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum BrowseArtist {
+    Table,
+    Added,
+}
+
+#[derive(DeriveIden)]
+enum BrowseAlbum {
+    Table,
+    Added,
+}
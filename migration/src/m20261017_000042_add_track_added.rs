@@ -0,0 +1,70 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // When the row was first stored, as opposed to `created`, which is the
+        // file's birth time and changes when files are copied or restored.
+        // Filled by the database on insert; the scanner's upsert leaves it alone
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .add_column_if_not_exists(
+                        ColumnDef::new(Track::Added)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Existing rows were added at some unknown point; their file's birth
+        // time is the closest record of it
+        manager
+            .get_connection()
+            .execute_unprepared(r#"UPDATE "track" SET "added" = "created""#)
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_added")
+                    .table(Track::Table)
+                    .col(Track::Added)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_track_added")
+                    .table(Track::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Track::Table)
+                    .drop_column(Track::Added)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    Added,
+}
@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // How often a file has failed in a row, and when a later scan should
+        // read it again; NULL once it has failed too often to retry
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ScanError::Table)
+                    .add_column_if_not_exists(ColumnDef::new(ScanError::Attempts).integer().not_null().default(1))
+                    .add_column_if_not_exists(ColumnDef::new(ScanError::NextRetryAt).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ScanError::Table)
+                    .drop_column(ScanError::Attempts)
+                    .drop_column(ScanError::NextRetryAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ScanError {
+    Table,
+    Attempts,
+    NextRetryAt,
+}
@@ -0,0 +1,69 @@
+use std::env;
+
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbBackend, DbErr, Statement};
+
+const DEFAULT_VARIOUS_ARTISTS: &str = "Various Artists";
+
+/// Album artist for files without an album artist tag, so their albums
+/// don't split up by track artist
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AlbumArtistFallback {
+    /// Leave the album artist empty, as tagged
+    Off,
+    /// The artist of the other tracks of the album in the same folder when they
+    /// all share one, otherwise this name
+    Folder { various_artists: String },
+}
+
+impl AlbumArtistFallback {
+    /// ALBUM_ARTIST_FALLBACK: "folder" (default) or "off", with mixed albums
+    /// credited to VARIOUS_ARTISTS_NAME (default "Various Artists")
+    pub fn from_env() -> Self {
+        match env::var("ALBUM_ARTIST_FALLBACK").unwrap_or_default().trim().to_lowercase().as_str() {
+            "off" | "false" | "none" => AlbumArtistFallback::Off,
+            value => {
+                if !matches!(value, "" | "folder" | "true") {
+                    log::warn!("Unknown ALBUM_ARTIST_FALLBACK {:?}, expected folder or off; using folder", value);
+                }
+                let various_artists = env::var("VARIOUS_ARTISTS_NAME")
+                    .ok()
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or_else(|| DEFAULT_VARIOUS_ARTISTS.to_string());
+                AlbumArtistFallback::Folder { various_artists }
+            }
+        }
+    }
+}
+
+/// Set every track's effective album artist from its album artist tag and the
+/// fallback, also for unchanged files when the fallback settings change. Albums
+/// are the tracks sharing a folder and album tag; one tagged track gives its
+/// album artist to the rest, and tracks without an album keep their own artist.
+pub async fn assign_album_artists(db: &DatabaseConnection, fallback: &AlbumArtistFallback) -> Result<(), DbErr> {
+    let statement = match fallback {
+        AlbumArtistFallback::Off => Statement::from_string(
+            DbBackend::Postgres,
+            r#"UPDATE "track" SET "album_artist" = "album_artist_tag" WHERE "album_artist" <> "album_artist_tag""#,
+        ),
+        AlbumArtistFallback::Folder { various_artists } => Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"UPDATE "track" SET "album_artist" = "effective"."album_artist"
+               FROM (
+                   SELECT "id", CASE
+                       WHEN "album_artist_tag" <> '' THEN "album_artist_tag"
+                       WHEN "album" = '' THEN "artist"
+                       WHEN max("album_artist_tag") OVER "album" <> '' THEN max("album_artist_tag") OVER "album"
+                       WHEN min("artist") OVER "album" = max("artist") OVER "album" THEN "artist"
+                       ELSE $1
+                   END AS "album_artist"
+                   FROM "track"
+                   WINDOW "album" AS (PARTITION BY regexp_replace("path", '/[^/]*$', ''), "album")
+               ) AS "effective"
+               WHERE "track"."id" = "effective"."id" AND "track"."album_artist" <> "effective"."album_artist""#,
+            [various_artists.as_str().into()],
+        ),
+    };
+    db.execute(statement).await?;
+    Ok(())
+}
@@ -0,0 +1,1240 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path as FsPath, PathBuf};
+use std::str::FromStr;
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{Json, Response},
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use lofty::file::AudioFile;
+use log::{error, info};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, NotSet, QueryFilter, QueryOrder, QuerySelect};
+use sea_query::Expr;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use entity::prelude::{Album, BrowseAlbum, Track};
+use entity::{album, browse_album, track};
+
+use crate::api::{self, AlbumArtQuery, AppState, TrackResponse};
+use crate::error::{ApiError, ApiPath, ApiQuery, ApiResult};
+use crate::formats::{FormatSummary, FormatSummaryBuilder};
+use crate::hidden::{Visibility, VisibilityQuery};
+use crate::media_types::{MediaTypeQuery, MediaTypes};
+use crate::paths;
+use crate::proxy::RequestOrigin;
+use crate::release_types::{self, ReleaseTypeQuery, ReleaseTypes};
+use crate::sessions::{tracked_stream, NewSession};
+use crate::slugs;
+use crate::text_index::TextNormalization;
+use crate::transcoder::{self, ChapterMark, StreamMetadata, TranscodeOptions};
+
+// Image files checked, in order, when looking up folder art (compared case-insensitively)
+const FOLDER_ART_NAMES: &[&str] = &[
+    "cover.jpg", "cover.jpeg", "cover.png", "folder.jpg", "folder.jpeg", "folder.png",
+    "front.jpg", "front.jpeg", "front.png", "album.jpg", "album.png",
+];
+
+/// How tracks are grouped into albums
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlbumMode {
+    /// An album is every track sharing the same album artist and album tags
+    #[default]
+    Tags,
+    /// An album is every track in the same folder, regardless of tags
+    Folder,
+}
+
+impl FromStr for AlbumMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "tags" | "tag" => Ok(AlbumMode::Tags),
+            "folder" | "folders" | "directory" => Ok(AlbumMode::Folder),
+            other => Err(format!("unknown album mode '{}'", other)),
+        }
+    }
+}
+
+/// Name of the virtual album that gathers an album artist's tracks without an
+/// album tag, so they can be reached by album like any other track
+pub const SINGLES_ALBUM: &str = "Singles";
+
+// Separator between album artist and album name inside an album ID.
+// The unit separator control character never appears in real tags.
+const ALBUM_ID_SEPARATOR: char = '\u{1f}';
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AlbumQuery {
+    /// "tracks" for albums with nested tracks, "summary" for albums without them
+    pub include: Option<String>,
+    /// Page number; with page or per_page, a page of album objects is returned
+    pub page: Option<u64>,
+    /// Albums per page (default: 20, max: 100)
+    pub per_page: Option<u64>,
+    /// artist (album artist, then name; the default), name, added (newest
+    /// first) or tracks (most first)
+    pub sort: Option<String>,
+    /// Only albums whose name contains this, ignoring case and accents
+    pub name: Option<String>,
+    /// Only albums whose album artist contains this, ignoring case and accents
+    pub album_artist: Option<String>,
+    /// Only albums from this year or later
+    pub from_year: Option<i32>,
+    /// Only albums from this year or earlier
+    pub to_year: Option<i32>,
+}
+
+impl AlbumQuery {
+    fn paged(&self) -> bool {
+        self.page.is_some() || self.per_page.is_some()
+    }
+
+    // Whether albums have to be filtered, sorted or paged rather than listed whole
+    fn narrows(&self) -> bool {
+        self.paged()
+            || self.sort.is_some()
+            || self.name.is_some()
+            || self.album_artist.is_some()
+            || self.from_year.is_some()
+            || self.to_year.is_some()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AlbumResponse {
+    pub id: String,
+    pub name: String,
+    pub album_artist: String,
+    pub year: Option<i32>,
+    pub track_count: usize,
+    pub duration_seconds: i64,
+    /// When the album's newest track was added
+    pub added: Option<DateTime<Utc>>,
+    /// Track to fetch album art from with /tracks/:id/albumart
+    pub art_track_id: Option<i32>,
+    /// album, ep, single, live or compilation
+    pub release_type: String,
+    /// Formats of the album's tracks, e.g. "FLAC 16/44.1"
+    pub format: FormatSummary,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tracks: Vec<TrackResponse>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AlbumPage {
+    pub albums: Vec<AlbumResponse>,
+    pub total: u64,
+    pub page: u64,
+    pub per_page: u64,
+    pub total_pages: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum AlbumListResponse {
+    Names(Vec<String>),
+    Albums(Vec<AlbumResponse>),
+    Page(AlbumPage),
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DecadeQuery {
+    /// First year of a decade, e.g. 1990, to list its albums instead of the decades
+    pub decade: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DecadeCount {
+    /// First year of the decade
+    pub decade: i32,
+    pub album_count: usize,
+    pub track_count: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum DecadeListResponse {
+    Decades(Vec<DecadeCount>),
+    Albums(Vec<AlbumResponse>),
+}
+
+/// Key identifying a tag-based album, used for slug lookups
+pub fn album_key(album_artist: &str, album: &str) -> String {
+    format!("{}{}{}", album_artist, ALBUM_ID_SEPARATOR, album)
+}
+
+/// The name to show for an album tag: the tag itself, or the singles album
+/// for tracks without one
+pub fn album_name(album: &str) -> &str {
+    if album.is_empty() { SINGLES_ALBUM } else { album }
+}
+
+/// Build the opaque album ID for an album artist + album name pair. Albums
+/// with a slug use that instead; these IDs are still accepted as legacy IDs.
+pub fn album_id(album_artist: &str, album: &str) -> String {
+    URL_SAFE_NO_PAD.encode(album_key(album_artist, album))
+}
+
+/// Every album artist + album name pair an album key could stand for, with
+/// the shortest album artist first. Tags can hold any character, the
+/// separator included, which makes such a key ambiguous; callers try each
+/// pair in turn.
+pub fn split_album_key(key: &str) -> Vec<(String, String)> {
+    key.match_indices(ALBUM_ID_SEPARATOR)
+        .map(|(at, separator)| (key[..at].to_string(), key[at + separator.len()..].to_string()))
+        .collect()
+}
+
+/// Decode an album ID back into the album artist + album name pairs it could
+/// stand for (see split_album_key); empty when it isn't an album ID
+pub fn parse_album_id(id: &str) -> Vec<(String, String)> {
+    URL_SAFE_NO_PAD.decode(id)
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .map(|key| split_album_key(&key))
+        .unwrap_or_default()
+}
+
+/// Build the opaque album ID for a folder when albums are grouped by folder
+pub fn folder_album_id(folder: &str) -> String {
+    URL_SAFE_NO_PAD.encode(folder)
+}
+
+/// Decode a folder album ID back into its folder path
+pub fn parse_folder_album_id(id: &str) -> Option<String> {
+    String::from_utf8(URL_SAFE_NO_PAD.decode(id).ok()?).ok()
+}
+
+/// The folder a track belongs to. Works for local paths and s3:// keys alike.
+pub fn album_folder(path: &str) -> &str {
+    path.rsplit_once('/').map(|(folder, _)| folder).unwrap_or("")
+}
+
+/// The folder album a track belongs to: its folder relative to the library
+/// folder it is in, so an album split across library folders (e.g. an SSD and
+/// an archive drive) is one album. Tracks directly in a library folder, or not
+/// yet assigned one by a scan, use the full folder path.
+pub fn folder_album_key<'a>(path: &'a str, library_root: Option<&str>) -> &'a str {
+    let folder = album_folder(path);
+    library_root
+        .and_then(|root| paths::strip_root(folder, root))
+        .filter(|rest| !rest.is_empty())
+        .unwrap_or(folder)
+}
+
+// Keys of tracks outside any library folder are full paths, which is also
+// what folder album IDs held before library folders were recorded
+fn is_full_folder_path(key: &str) -> bool {
+    paths::is_absolute(key) || key.contains("://")
+}
+
+/// Name a folder album after its most common album tag, falling back to the folder name
+pub fn folder_album_name<'a>(folder: &str, album_tags: impl Iterator<Item = &'a str>) -> String {
+    let mut tag_counts: HashMap<&str, usize> = HashMap::new();
+    for tag in album_tags.filter(|tag| !tag.is_empty()) {
+        *tag_counts.entry(tag).or_default() += 1;
+    }
+
+    tag_counts.into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(tag, _)| tag.to_string())
+        .unwrap_or_else(|| folder.rsplit('/').next().unwrap_or(folder).to_string())
+}
+
+/// Find a cover image next to the tracks of a local folder
+pub fn find_folder_art(folder: &FsPath) -> Option<PathBuf> {
+    let files: Vec<PathBuf> = std::fs::read_dir(folder).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+
+    FOLDER_ART_NAMES.iter().find_map(|name| {
+        files.iter()
+            .find(|path| {
+                path.file_name()
+                    .and_then(|file_name| file_name.to_str())
+                    .map(|file_name| file_name.eq_ignore_ascii_case(name))
+                    .unwrap_or(false)
+            })
+            .cloned()
+    })
+}
+
+/// Group tracks by their folder album key, with tracks in disc/track order
+fn group_tracks_by_folder(tracks: Vec<track::Model>) -> Vec<AlbumResponse> {
+    let mut folders: Vec<(String, Vec<track::Model>)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for track in tracks {
+        let folder = folder_album_key(&track.path, track.library_root.as_deref()).to_string();
+        match positions.get(&folder) {
+            Some(&position) => folders[position].1.push(track),
+            None => {
+                positions.insert(folder.clone(), folders.len());
+                folders.push((folder, vec![track]));
+            }
+        }
+    }
+    folders.sort_by(|a, b| a.0.cmp(&b.0));
+
+    folders.into_iter()
+        .map(|(folder, mut tracks)| {
+            tracks.sort_by(|a, b| {
+                a.disc_number.cmp(&b.disc_number)
+                    .then(a.track_number.cmp(&b.track_number))
+                    .then_with(|| a.title.cmp(&b.title))
+            });
+
+            let name = folder_album_name(&folder, tracks.iter().map(|track| track.album.as_str()));
+            let album_artist = tracks.iter()
+                .map(|track| track.album_artist.as_str())
+                .find(|artist| !artist.is_empty())
+                .or_else(|| tracks.first().map(|track| track.artist.as_str()))
+                .unwrap_or_default()
+                .to_string();
+
+            // Embedded art first; otherwise the album art endpoint may still find a cover in the folder
+            let art_track_id = tracks.iter()
+                .find(|track| track.album_art_path.is_some())
+                .or(tracks.first())
+                .map(|track| track.id);
+            let mut format = FormatSummaryBuilder::default();
+            for track in &tracks {
+                format.add_track(track);
+            }
+            let duration_seconds = tracks.iter().map(|track| track.duration_seconds as i64).sum();
+            let tag_values: Vec<String> = tracks.iter().map(|track| release_types::tag_values(&track.tags)).collect();
+
+            AlbumResponse {
+                id: folder_album_id(&folder),
+                name,
+                album_artist,
+                year: tracks.iter().find_map(|track| track.year),
+                track_count: tracks.len(),
+                duration_seconds,
+                added: tracks.iter().map(|track| track.created).max(),
+                art_track_id,
+                release_type: release_types::classify(tag_values.iter().map(String::as_str), tracks.len(), duration_seconds).to_string(),
+                format: format.build(),
+                tracks: tracks.into_iter().map(TrackResponse::from).collect(),
+            }
+        })
+        .collect()
+}
+
+/// Group tracks that are already ordered by album artist, album, disc and track
+/// into albums, preserving that order
+fn group_tracks_by_album(tracks: Vec<track::Model>) -> Vec<AlbumResponse> {
+    let mut albums: Vec<AlbumResponse> = Vec::new();
+    let mut formats: Vec<FormatSummaryBuilder> = Vec::new();
+
+    for track in tracks {
+        let same_album = albums.last()
+            .map(|album| album.album_artist == track.album_artist && album.name == track.album)
+            .unwrap_or(false);
+
+        if !same_album {
+            albums.push(AlbumResponse {
+                id: album_id(&track.album_artist, &track.album),
+                name: track.album.clone(),
+                album_artist: track.album_artist.clone(),
+                year: None,
+                track_count: 0,
+                duration_seconds: 0,
+                added: None,
+                art_track_id: None,
+                release_type: String::new(),
+                format: FormatSummary::default(),
+                tracks: Vec::new(),
+            });
+            formats.push(FormatSummaryBuilder::default());
+        }
+
+        let album = albums.last_mut().unwrap();
+        album.year = album.year.or(track.year);
+        album.art_track_id = album.art_track_id.or(track.album_art_path.as_ref().map(|_| track.id));
+        album.track_count += 1;
+        album.duration_seconds += track.duration_seconds as i64;
+        album.added = album.added.max(Some(track.created));
+        formats.last_mut().unwrap().add_track(&track);
+        album.tracks.push(TrackResponse::from(track));
+    }
+
+    for (album, format) in albums.iter_mut().zip(&formats) {
+        album.format = format.build();
+        let tag_values: Vec<String> = album.tracks.iter().map(|track| release_types::tag_values(&track.tags)).collect();
+        album.release_type = release_types::classify(tag_values.iter().map(String::as_str), album.track_count, album.duration_seconds).to_string();
+    }
+    albums
+}
+
+// GET /albums - Get list of unique albums, optionally with nested tracks (?include=tracks)
+#[utoipa::path(
+    get,
+    path = "/albums",
+    tag = "library",
+    params(AlbumQuery, MediaTypeQuery, ReleaseTypeQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Album names, or albums with tracks when include=tracks", body = AlbumListResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_albums(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<AlbumQuery>,
+    media_types: MediaTypes,
+    release_types: ReleaseTypes,
+    visibility: Visibility,
+) -> ApiResult<Json<AlbumListResponse>> {
+    let condition = visibility.condition().add(media_types.condition());
+    if state.album_mode == AlbumMode::Folder {
+        return get_folder_albums(&state, params, condition, &release_types).await.map(Json);
+    }
+    let default_view = visibility.is_default() && media_types.is_default();
+
+    if params.include.as_deref() == Some("tracks") {
+        // Fetch every album track in one ordered query and group in memory to avoid N+1 lookups
+        let tracks = Track::find()
+            .filter(condition.add(release_types.condition()))
+            .order_by_asc(track::Column::AlbumArtist)
+            .order_by_asc(track::Column::Album)
+            .order_by_asc(track::Column::DiscNumber)
+            .order_by_asc(track::Column::TrackNumber)
+            .order_by_asc(track::Column::Title)
+            .all(&state.db)
+            .await?;
+
+        let slugs = slugs::slugs_by_key(&state.db, slugs::ALBUM).await?;
+        let rows = albums_by_key(&state.db).await?;
+        let mut albums = group_tracks_by_album(tracks);
+        for album in &mut albums {
+            let key = album_key(&album.album_artist, &album.name);
+            if let Some(slug) = slugs.get(&key) {
+                album.id = slug.clone();
+            }
+            if let Some(row) = rows.get(&key) {
+                album.release_type = row.release_type.clone();
+            }
+            album.name = album_name(&album.name).to_string();
+        }
+
+        narrow_albums(&params, state.text_normalization, &mut albums)?;
+        return album_list(&params, albums).map(Json);
+    }
+
+    if params.include.as_deref() == Some("summary") || params.narrows() {
+        let mut albums = if default_view {
+            browse_table_albums(&state, condition, &release_types).await?
+        } else {
+            summarize_albums(&state, condition.add(release_types.condition()), Condition::all()).await?
+        };
+        narrow_albums(&params, state.text_normalization, &mut albums)?;
+        if params.include.as_deref() == Some("summary") || params.paged() {
+            return album_list(&params, albums).map(Json);
+        }
+        return Ok(Json(AlbumListResponse::Names(album_names(&params, albums))));
+    }
+
+    let mut albums: Vec<String> = if default_view {
+        browse_table_albums(&state, condition, &release_types).await?
+            .into_iter()
+            .map(|album| album.name)
+            .collect()
+    } else {
+        let tags: Vec<String> = Track::find()
+            .select_only()
+            .column(track::Column::Album)
+            .distinct()
+            .filter(condition.add(release_types.condition()))
+            .into_tuple()
+            .all(&state.db)
+            .await?;
+        tags.iter().map(|album| album_name(album).to_string()).collect()
+    };
+    albums.sort();
+    albums.dedup();
+
+    Ok(Json(AlbumListResponse::Names(albums)))
+}
+
+// Filter and sort album objects as the query asks. Without a sort they stay
+// in the order they were listed in.
+fn narrow_albums(params: &AlbumQuery, normalization: TextNormalization, albums: &mut Vec<AlbumResponse>) -> ApiResult<()> {
+    let name = params.name.as_deref().map(|name| normalization.search_key(name));
+    let album_artist = params.album_artist.as_deref().map(|artist| normalization.search_key(artist));
+    albums.retain(|album| {
+        name.as_ref().is_none_or(|name| normalization.search_key(&album.name).contains(name.as_str()))
+            && album_artist.as_ref().is_none_or(|artist| normalization.search_key(&album.album_artist).contains(artist.as_str()))
+            && params.from_year.is_none_or(|from| album.year.is_some_and(|year| year >= from))
+            && params.to_year.is_none_or(|to| album.year.is_some_and(|year| year <= to))
+    });
+    match params.sort.as_deref() {
+        None | Some("artist") => {}
+        Some("name") => albums.sort_by_cached_key(|album| normalization.search_key(&album.name)),
+        // Newest and largest first; albums without a creation time last
+        Some("added") => albums.sort_by_key(|album| Reverse(album.added)),
+        Some("tracks") => albums.sort_by_key(|album| Reverse(album.track_count)),
+        Some(_) => return Err(ApiError::invalid_field("sort", "must be artist, name, added or tracks")),
+    }
+    Ok(())
+}
+
+// Album objects, or a page of them when the query asks for one
+fn album_list(params: &AlbumQuery, albums: Vec<AlbumResponse>) -> ApiResult<AlbumListResponse> {
+    if !params.paged() {
+        return Ok(AlbumListResponse::Albums(albums));
+    }
+    let (page, per_page) = api::pagination(params.page, params.per_page)?;
+    let (albums, total, total_pages) = api::paginate(albums, page, per_page);
+    Ok(AlbumListResponse::Page(AlbumPage { albums, total, page, per_page, total_pages }))
+}
+
+// Names of albums, once each: alphabetical, or in the order asked for
+fn album_names(params: &AlbumQuery, albums: Vec<AlbumResponse>) -> Vec<String> {
+    let mut names: Vec<String> = albums.into_iter().map(|album| album.name).collect();
+    if params.sort.is_none() {
+        names.sort();
+        names.dedup();
+    } else {
+        let mut seen = HashSet::new();
+        names.retain(|name| seen.insert(name.clone()));
+    }
+    names
+}
+
+/// album_artist, album, year, track_count, duration_seconds, added
+pub type AlbumSummaryRow = (String, String, Option<i32>, i64, Option<i64>, Option<DateTime<Utc>>);
+
+// album_artist, album, extension, bit_depth, sample_rate, track_count, total audio bitrate
+type AlbumFormatRow = (String, String, String, i32, i32, i64, Option<i64>);
+
+/// Formats of the tracks matching `condition` per tag-based album, keyed by album_key.
+/// Tracks without an album tag (the singles album) are under the album artist
+/// and an empty name.
+pub async fn album_formats(db: &DatabaseConnection, condition: Condition) -> Result<HashMap<String, FormatSummaryBuilder>, DbErr> {
+    let rows: Vec<AlbumFormatRow> = Track::find()
+        .select_only()
+        .column(track::Column::AlbumArtist)
+        .column(track::Column::Album)
+        .column(track::Column::Extension)
+        .column(track::Column::BitDepth)
+        .column(track::Column::SampleRate)
+        .column_as(track::Column::Id.count(), "track_count")
+        .column_as(track::Column::AudioBitrate.sum(), "bitrate_total")
+        .filter(condition)
+        .group_by(track::Column::AlbumArtist)
+        .group_by(track::Column::Album)
+        .group_by(track::Column::Extension)
+        .group_by(track::Column::BitDepth)
+        .group_by(track::Column::SampleRate)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let mut formats: HashMap<String, FormatSummaryBuilder> = HashMap::new();
+    for (album_artist, album, extension, bit_depth, sample_rate, track_count, bitrate_total) in rows {
+        formats.entry(album_key(&album_artist, &album))
+            .or_default()
+            .add(&extension, bit_depth, sample_rate, bitrate_total.unwrap_or(0), track_count as usize);
+    }
+    Ok(formats)
+}
+
+/// Per-album counts of the tracks matching `condition`, keeping groups that
+/// match `having`, by album artist and album
+pub async fn album_summary_rows(db: &DatabaseConnection, condition: Condition, having: Condition) -> Result<Vec<AlbumSummaryRow>, DbErr> {
+    Track::find()
+        .select_only()
+        .column(track::Column::AlbumArtist)
+        .column(track::Column::Album)
+        .column_as(track::Column::Year.min(), "year")
+        .column_as(track::Column::Id.count(), "track_count")
+        .column_as(track::Column::DurationSeconds.sum(), "duration_seconds")
+        .column_as(track::Column::Added.max(), "added")
+        .filter(condition)
+        .group_by(track::Column::AlbumArtist)
+        .group_by(track::Column::Album)
+        .having(having)
+        .order_by_asc(track::Column::AlbumArtist)
+        .order_by_asc(track::Column::Album)
+        .into_tuple()
+        .all(db)
+        .await
+}
+
+// Album summaries of the tracks matching `condition`, keeping groups that match `having`
+async fn summarize_albums(state: &AppState, condition: Condition, having: Condition) -> ApiResult<Vec<AlbumResponse>> {
+    let rows = album_summary_rows(&state.db, condition.clone(), having).await?;
+    let formats = album_formats(&state.db, condition).await?;
+    let rows = rows.into_iter()
+        .map(|(album_artist, name, year, track_count, duration_seconds, added)| {
+            let format = formats.get(&album_key(&album_artist, &name)).map(FormatSummaryBuilder::build).unwrap_or_default();
+            (album_artist, name, year, track_count, duration_seconds.unwrap_or(0), added, format)
+        })
+        .collect();
+    album_responses(state, rows).await
+}
+
+// Album summaries of the default view (music, hidden tracks left out, every
+// folder) from the browse table, filtered by release type. Aggregated from
+// `condition` instead until the first scan after upgrading fills the table.
+async fn browse_table_albums(state: &AppState, condition: Condition, release_types: &ReleaseTypes) -> ApiResult<Vec<AlbumResponse>> {
+    let rows = BrowseAlbum::find()
+        .order_by_asc(browse_album::Column::AlbumArtist)
+        .order_by_asc(browse_album::Column::Album)
+        .all(&state.db)
+        .await?;
+    if rows.is_empty() {
+        return summarize_albums(state, condition.add(release_types.condition()), Condition::all()).await;
+    }
+
+    let rows = rows.into_iter()
+        .map(|row| {
+            let format = serde_json::from_value(row.format).unwrap_or_default();
+            (row.album_artist, row.album, row.year, row.track_count, row.duration_seconds, row.added, format)
+        })
+        .collect();
+    let mut albums = album_responses(state, rows).await?;
+    albums.retain(|album| release_types.includes(&album.release_type));
+    Ok(albums)
+}
+
+// album_artist, album, year, track_count, duration_seconds, added, format
+type AlbumRow = (String, String, Option<i32>, i64, i64, Option<DateTime<Utc>>, FormatSummary);
+
+// Summaries of tag-based albums with their slugs, art and release types
+async fn album_responses(state: &AppState, rows: Vec<AlbumRow>) -> ApiResult<Vec<AlbumResponse>> {
+    let slugs = slugs::slugs_by_key(&state.db, slugs::ALBUM).await?;
+    let stored = albums_by_key(&state.db).await?;
+    let albums = rows.into_iter()
+        .map(|(album_artist, name, year, track_count, duration_seconds, added, format)| {
+            let key = album_key(&album_artist, &name);
+            let stored = stored.get(&key);
+            AlbumResponse {
+                id: slugs.get(&key).cloned().unwrap_or_else(|| album_id(&album_artist, &name)),
+                art_track_id: stored.and_then(|album| album.art_track_id),
+                release_type: stored.map_or(release_types::ALBUM, |album| album.release_type.as_str()).to_string(),
+                format,
+                name: album_name(&name).to_string(),
+                album_artist,
+                year,
+                track_count: track_count as usize,
+                duration_seconds,
+                added,
+                tracks: Vec::new(),
+            }
+        })
+        .collect();
+
+    Ok(albums)
+}
+
+// Decades with album and track counts. An album belongs to the decade of its
+// earliest year; albums without a year are left out.
+fn count_decades(albums: &[AlbumResponse]) -> Vec<DecadeCount> {
+    let mut decades: Vec<DecadeCount> = Vec::new();
+    let mut years: Vec<(i32, usize)> = albums.iter()
+        .filter_map(|album| album.year.map(|year| (year.div_euclid(10) * 10, album.track_count)))
+        .collect();
+    years.sort_by_key(|(decade, _)| *decade);
+    for (decade, track_count) in years {
+        match decades.last_mut() {
+            Some(last) if last.decade == decade => {
+                last.album_count += 1;
+                last.track_count += track_count;
+            }
+            _ => decades.push(DecadeCount { decade, album_count: 1, track_count }),
+        }
+    }
+    decades
+}
+
+// GET /albums/by-decade - Decades with album counts, or the albums of one decade (?decade=1990)
+#[utoipa::path(
+    get,
+    path = "/albums/by-decade",
+    tag = "library",
+    params(DecadeQuery, MediaTypeQuery, ReleaseTypeQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Decades with counts, or albums of the requested decade by year", body = DecadeListResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_albums_by_decade(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<DecadeQuery>,
+    media_types: MediaTypes,
+    release_types: ReleaseTypes,
+    visibility: Visibility,
+) -> ApiResult<Json<DecadeListResponse>> {
+    let range = params.decade.map(api::decade_range).transpose()?;
+    let condition = visibility.condition().add(media_types.condition());
+
+    let mut albums = if state.album_mode == AlbumMode::Folder {
+        let tracks = Track::find()
+            .filter(condition)
+            .order_by_asc(track::Column::Path)
+            .all(&state.db)
+            .await?;
+        let mut albums = group_tracks_by_folder(tracks);
+        albums.retain(|album| release_types.includes(&album.release_type));
+        for album in &mut albums {
+            album.tracks.clear();
+        }
+        albums
+    } else if visibility.is_default() && media_types.is_default() {
+        // Albums are kept when their earliest year is in the decade, below
+        browse_table_albums(&state, condition, &release_types).await?
+    } else {
+        let condition = condition.add(release_types.condition());
+        match range {
+            // Only albums with a track from the decade are aggregated, found
+            // through the year index, and kept when their earliest year is in it
+            Some((from, to)) => {
+                let in_decade = condition.add(Expr::cust_with_values(
+                    r#"("album_artist", "album") IN (SELECT "album_artist", "album" FROM "track" WHERE "year" BETWEEN $1 AND $2)"#,
+                    [from, to],
+                ));
+                let starts_in_decade = Condition::all().add(Expr::cust_with_values(r#"MIN("year") BETWEEN $1 AND $2"#, [from, to]));
+                summarize_albums(&state, in_decade, starts_in_decade).await?
+            }
+            None => summarize_albums(&state, condition, Condition::all()).await?,
+        }
+    };
+
+    let Some((from, to)) = range else {
+        return Ok(Json(DecadeListResponse::Decades(count_decades(&albums))));
+    };
+    albums.retain(|album| album.year.is_some_and(|year| (from..=to).contains(&year)));
+    albums.sort_by(|a, b| a.year.cmp(&b.year)
+        .then_with(|| a.album_artist.cmp(&b.album_artist))
+        .then_with(|| a.name.cmp(&b.name)));
+    Ok(Json(DecadeListResponse::Albums(albums)))
+}
+
+/// The album table's rows (art track and release type of each tag-based
+/// album), keyed by album_key
+pub async fn albums_by_key(db: &DatabaseConnection) -> Result<HashMap<String, album::Model>, DbErr> {
+    Ok(Album::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|album| (album_key(&album.album_artist, &album.name), album))
+        .collect())
+}
+
+// art track, release type tags, track count, duration_seconds
+type FoundAlbum = (Option<i32>, Vec<String>, usize, i64);
+
+/// Bring the album table up to date with the library: one row per tag-based
+/// album, referencing the first track (in disc/track order) with embedded art,
+/// with its release type. Singles albums are always singles. Only albums whose art reference or release type
+/// changed are written. Returns the albums
+/// that were added as (album artist, name), or none when the table was empty
+/// before, so the first import doesn't count as new albums.
+pub async fn refresh_albums(db: &DatabaseConnection) -> Result<Vec<(String, String)>, DbErr> {
+    let tracks: Vec<(i32, String, String, bool, i32, String)> = Track::find()
+        .select_only()
+        .column(track::Column::Id)
+        .column(track::Column::AlbumArtist)
+        .column(track::Column::Album)
+        .column_as(track::Column::AlbumArtPath.is_not_null(), "has_art")
+        .column(track::Column::DurationSeconds)
+        .column_as(Expr::cust(release_types::tag_values_sql()), "release_type_tags")
+        .filter(track::Column::MissingSince.is_null())
+        .order_by_asc(track::Column::AlbumArtist)
+        .order_by_asc(track::Column::Album)
+        .order_by_asc(track::Column::DiscNumber)
+        .order_by_asc(track::Column::TrackNumber)
+        .order_by_asc(track::Column::Title)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let mut found: HashMap<(String, String), FoundAlbum> = HashMap::new();
+    for (id, album_artist, album, has_art, duration_seconds, tag_values) in tracks {
+        let (art, album_tag_values, track_count, album_duration) = found.entry((album_artist, album)).or_default();
+        if art.is_none() && has_art {
+            *art = Some(id);
+        }
+        album_tag_values.push(tag_values);
+        *track_count += 1;
+        *album_duration += duration_seconds as i64;
+    }
+    let mut expected: HashMap<(String, String), (Option<i32>, &'static str)> = found.into_iter()
+        .map(|(key, (art, tag_values, track_count, duration_seconds))| {
+            let release_type = if key.1.is_empty() {
+                release_types::SINGLE
+            } else {
+                release_types::classify(tag_values.iter().map(String::as_str), track_count, duration_seconds)
+            };
+            (key, (art, release_type))
+        })
+        .collect();
+
+    let now = chrono::Utc::now();
+    let mut changed = 0;
+    let mut stale_ids = Vec::new();
+    let existing = Album::find().all(db).await?;
+    let first_import = existing.is_empty();
+    for row in existing {
+        match expected.remove(&(row.album_artist, row.name)) {
+            Some((art_track_id, release_type)) if art_track_id == row.art_track_id && release_type == row.release_type => {}
+            Some((art_track_id, release_type)) => {
+                album::ActiveModel {
+                    id: Set(row.id),
+                    art_track_id: Set(art_track_id),
+                    release_type: Set(release_type.to_string()),
+                    updated: Set(now),
+                    ..Default::default()
+                }
+                .update(db)
+                .await?;
+                changed += 1;
+            }
+            None => stale_ids.push(row.id),
+        }
+    }
+
+    for chunk in stale_ids.chunks(1000) {
+        Album::delete_many()
+            .filter(album::Column::Id.is_in(chunk.iter().copied()))
+            .exec(db)
+            .await?;
+    }
+
+    // Whatever is left is new
+    let new_rows: Vec<album::ActiveModel> = expected.into_iter()
+        .map(|((album_artist, name), (art_track_id, release_type))| album::ActiveModel {
+            id: NotSet,
+            album_artist: Set(album_artist),
+            name: Set(name),
+            art_track_id: Set(art_track_id),
+            release_type: Set(release_type.to_string()),
+            updated: Set(now),
+        })
+        .collect();
+    for chunk in new_rows.chunks(1000) {
+        Album::insert_many(chunk.to_vec()).exec(db).await?;
+    }
+
+    changed += stale_ids.len() + new_rows.len();
+    if changed > 0 {
+        info!("Updated {} albums", changed);
+    }
+    if first_import {
+        return Ok(Vec::new());
+    }
+    Ok(new_rows.into_iter()
+        .map(|row| (row.album_artist.unwrap(), row.name.unwrap()))
+        .collect())
+}
+
+fn album_not_found(id: &str) -> ApiError {
+    ApiError::not_found(format!("Album {} not found", id))
+}
+
+async fn get_folder_albums(state: &AppState, params: AlbumQuery, condition: Condition, release_types: &ReleaseTypes) -> ApiResult<AlbumListResponse> {
+    let include = params.include.as_deref();
+    // Release types of folders are worked out from their tracks, so filtering
+    // by them needs every track
+    if include == Some("tracks") || include == Some("summary") || !release_types.is_all() || params.narrows() {
+        let tracks = Track::find()
+            .filter(condition.clone())
+            .order_by_asc(track::Column::Path)
+            .all(&state.db)
+            .await?;
+
+        let mut albums = group_tracks_by_folder(tracks);
+        albums.retain(|album| release_types.includes(&album.release_type));
+        narrow_albums(&params, state.text_normalization, &mut albums)?;
+        if include != Some("tracks") {
+            for album in &mut albums {
+                album.tracks.clear();
+            }
+        }
+        if include != Some("tracks") && include != Some("summary") && !params.paged() {
+            if params.sort.is_none() {
+                let mut names: Vec<String> = albums.into_iter().map(|album| album.name).collect();
+                names.sort();
+                return Ok(AlbumListResponse::Names(names));
+            }
+            return Ok(AlbumListResponse::Names(album_names(&params, albums)));
+        }
+        return album_list(&params, albums);
+    }
+
+    // Only the path, library folder and album tag are needed to name each folder
+    let rows: Vec<(String, Option<String>, String)> = Track::find()
+        .select_only()
+        .column(track::Column::Path)
+        .column(track::Column::LibraryRoot)
+        .column(track::Column::Album)
+        .filter(condition)
+        .into_tuple()
+        .all(&state.db)
+        .await?;
+
+    let mut folders: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (path, library_root, album) in &rows {
+        folders.entry(folder_album_key(path, library_root.as_deref()))
+            .or_default()
+            .push(album);
+    }
+
+    let mut names: Vec<String> = folders.into_iter()
+        .map(|(folder, albums)| folder_album_name(folder, albums.into_iter()))
+        .collect();
+    names.sort();
+
+    Ok(AlbumListResponse::Names(names))
+}
+
+// GET /albums/:id/tracks - Get an album with its tracks in disc/track order
+#[utoipa::path(
+    get,
+    path = "/albums/{id}/tracks",
+    tag = "library",
+    params(("id" = String, Path, description = "Album slug or ID"), VisibilityQuery),
+    responses(
+        (status = 200, description = "Album with tracks", body = AlbumResponse),
+        (status = 404, description = "Album not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_album_tracks(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+    visibility: Visibility,
+) -> ApiResult<Json<AlbumResponse>> {
+    Ok(Json(find_album(&state, &id, &visibility).await?))
+}
+
+/// Find an album with its tracks in disc/track order by its slug or ID. Tag-based
+/// albums are identified by their slug when they have one.
+pub async fn find_album(state: &AppState, id: &str, visibility: &Visibility) -> ApiResult<AlbumResponse> {
+    if state.album_mode == AlbumMode::Folder {
+        let folder = parse_folder_album_id(id).ok_or_else(|| album_not_found(id))?;
+
+        // A relative folder can be under any library folder. Either LIKE also
+        // matches subfolders, which are separate albums.
+        let path_condition = if is_full_folder_path(&folder) {
+            track::Column::Path.starts_with(format!("{}/", folder))
+        } else {
+            track::Column::Path.contains(format!("/{}/", folder))
+        };
+        let tracks: Vec<track::Model> = Track::find()
+            .filter(path_condition)
+            .filter(visibility.condition())
+            .all(&state.db)
+            .await?
+            .into_iter()
+            .filter(|track| {
+                folder_album_key(&track.path, track.library_root.as_deref()) == folder
+                    || album_folder(&track.path) == folder
+            })
+            .collect();
+
+        return group_tracks_by_folder(tracks)
+            .into_iter()
+            .next()
+            .ok_or_else(|| album_not_found(id));
+    }
+
+    // Slugs first, then the base64 IDs handed out before slugs existed. A key
+    // is only ambiguous when a tag holds the separator; if more than one of
+    // its pairs is an album, the one with the shortest album artist wins and
+    // the others can't be reached by this ID. Keys are stored in slugs and
+    // handed out as legacy IDs, so the encoding can't change to rule this out.
+    let candidates = match slugs::resolve(&state.db, slugs::ALBUM, id).await? {
+        Some(key) => split_album_key(&key),
+        None => parse_album_id(id),
+    };
+    let mut found = None;
+    for (album_artist, album) in candidates {
+        let tracks = Track::find()
+            .filter(track::Column::AlbumArtist.eq(album_artist.as_str()))
+            .filter(track::Column::Album.eq(album.as_str()))
+            .filter(visibility.condition())
+            .order_by_asc(track::Column::DiscNumber)
+            .order_by_asc(track::Column::TrackNumber)
+            .order_by_asc(track::Column::Title)
+            .all(&state.db)
+            .await?;
+        if let Some(grouped) = group_tracks_by_album(tracks).into_iter().next() {
+            found = Some((album_artist, album, grouped));
+            break;
+        }
+    }
+    let (album_artist, album, mut found) = found.ok_or_else(|| album_not_found(id))?;
+    let key = album_key(&album_artist, &album);
+    let stored = Album::find()
+        .filter(album::Column::AlbumArtist.eq(album_artist))
+        .filter(album::Column::Name.eq(album.as_str()))
+        .one(&state.db)
+        .await?;
+    if let Some(stored) = stored {
+        found.release_type = stored.release_type;
+    }
+    if let Some(slug) = slugs::slug_for(&state.db, slugs::ALBUM, &key).await? {
+        found.id = slug;
+    }
+    found.name = album_name(&album).to_string();
+    Ok(found)
+}
+
+// GET /albums/:id/cover - Get an album's cover, or placeholder art when it has none
+#[utoipa::path(
+    get,
+    path = "/albums/{id}/cover",
+    tag = "library",
+    params(("id" = String, Path, description = "Album slug or ID"), AlbumArtQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Album art image, or placeholder art (image/svg+xml) when there is none", content_type = "image/*"),
+        (status = 400, description = "Unknown image type", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Album or album art not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_album_cover(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+    ApiQuery(params): ApiQuery<AlbumArtQuery>,
+    visibility: Visibility,
+) -> ApiResult<Response<Body>> {
+    let image_type = params.image_type()?;
+    let album = find_album(&state, &id, &visibility).await?;
+
+    let art_track = match album.art_track_id {
+        Some(track_id) => Track::find_by_id(track_id).one(&state.db).await?,
+        None => None,
+    };
+    match art_track.and_then(|track| api::album_art_file(&state, &track, image_type)) {
+        Some((file_path, mime_type)) => api::album_art_response(file_path, mime_type).await,
+        None if params.placeholder(image_type) => api::placeholder_response(&album.name),
+        None => Err(ApiError::not_found(match image_type {
+            Some(image_type) => format!("Album {} has no {} image", id, image_type),
+            None => format!("Album {} has no album art", id),
+        })),
+    }
+}
+
+// Bitrate of album streams without a profile
+const ALBUM_STREAM_BITRATE: u32 = 256;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AlbumStreamQuery {
+    /// Transcoding profile to stream with (default: 256 kbps MP3)
+    pub profile: Option<String>,
+    /// Signed stream token for the album's first track, from /tracks/:id/stream-url
+    pub token: Option<String>,
+    pub client: Option<String>,
+}
+
+// A local file's duration to the millisecond, as read from its audio properties
+fn file_duration_ms(path: &FsPath) -> Option<i64> {
+    let duration = lofty::read_from_path(path).ok()?.properties().duration();
+    (!duration.is_zero()).then_some(duration.as_millis() as i64)
+}
+
+// GET /albums/:id/stream - Stream a whole album as one gapless stream with a chapter per track
+#[utoipa::path(
+    get,
+    path = "/albums/{id}/stream",
+    tag = "library",
+    params(("id" = String, Path, description = "Album slug or ID"), AlbumStreamQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "The album's tracks transcoded into one stream", content_type = "audio/mpeg"),
+        (status = 400, description = "Unknown transcoding profile", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "A signed stream token is required", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The stream token is invalid or expired, or demo mode is enabled", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Album not found", body = Problem, content_type = "application/problem+json"),
+        (status = 501, description = "The album isn't in a local music folder", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_album_stream(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+    ApiQuery(params): ApiQuery<AlbumStreamQuery>,
+    visibility: Visibility,
+    origin: RequestOrigin,
+    headers: HeaderMap,
+) -> ApiResult<Response<Body>> {
+    if state.demo_mode {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "demo_mode", "Not available in demo mode"));
+    }
+    let album = find_album(&state, &id, &visibility).await?;
+    let first_track = album.tracks.first().ok_or_else(|| album_not_found(&id))?;
+    api::check_stream_token(&state, first_track.id, params.token.as_deref())?;
+
+    let options = match params.profile.as_deref() {
+        Some(name) => state.transcoding
+            .profile(name)
+            .ok_or_else(|| ApiError::invalid_field("profile", format!("unknown profile '{}'", name.trim())))?
+            .options(),
+        None => TranscodeOptions::mp3(ALBUM_STREAM_BITRATE),
+    };
+
+    // ffmpeg reads every file of the album at once, so they have to be on disk
+    let mut inputs = Vec::with_capacity(album.tracks.len());
+    for track in &album.tracks {
+        let local_path = state.storage.local_path(&track.path).ok_or_else(|| {
+            ApiError::new(StatusCode::NOT_IMPLEMENTED, "not_local", "Only albums in local music folders can be streamed whole")
+        })?;
+        inputs.push(local_path);
+    }
+
+    // Stored durations are whole seconds, which would drift by up to a second
+    // per track, so chapters are laid out from the files' own durations
+    let stored_ms: Vec<i64> = album.tracks.iter().map(|track| track.duration_seconds as i64 * 1000).collect();
+    let paths = inputs.clone();
+    let durations_ms = tokio::task::spawn_blocking(move || {
+        paths.iter().zip(stored_ms).map(|(path, stored)| file_duration_ms(path).unwrap_or(stored)).collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| ApiError::internal(e.to_string()))?;
+    let mut chapters = Vec::with_capacity(album.tracks.len());
+    let mut start_ms = 0;
+    for (track, duration_ms) in album.tracks.iter().zip(durations_ms) {
+        let end_ms = start_ms + duration_ms;
+        chapters.push(ChapterMark { title: track.title.clone(), start_ms, end_ms });
+        start_ms = end_ms;
+    }
+
+    // The album's most common sample rate, so as few tracks as possible are resampled
+    let mut sample_rates: HashMap<i32, usize> = HashMap::new();
+    for track in album.tracks.iter().filter(|track| track.sample_rate > 0) {
+        *sample_rates.entry(track.sample_rate).or_default() += 1;
+    }
+    let sample_rate = sample_rates.into_iter()
+        .max_by_key(|&(rate, count)| (count, rate))
+        .map_or(44_100, |(rate, _)| rate as u32);
+
+    let metadata = StreamMetadata {
+        title: album.name.clone(),
+        artist: album.album_artist.clone(),
+        chapters,
+    };
+    let output = transcoder::concat_stream(&inputs, sample_rate, &metadata, &options).map_err(|e| {
+        error!("Failed to stream album {}: {}", id, e);
+        ApiError::internal("Failed to start transcoding the album")
+    })?;
+
+    // Listed in /admin/sessions under the album's first track
+    let user_agent = headers.get(header::USER_AGENT).and_then(|value| value.to_str().ok());
+    let new_session = NewSession {
+        user: visibility.user().map(str::to_string),
+        client: params.client.or_else(|| user_agent.map(str::to_string)),
+        address: origin.client_ip.map(|ip| ip.to_string()),
+        track_id: first_track.id,
+        title: album.name.clone(),
+        artist: album.album_artist.clone(),
+        bitrate: options.bitrate_kbps as i32,
+        total_bytes: 0,
+    };
+
+    // The length isn't known until ffmpeg is done, so the body is sent chunked
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, options.content_type)
+        .header(header::ACCEPT_RANGES, "none")
+        .header(header::CACHE_CONTROL, "no-store")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, OPTIONS")
+        .body(tracked_stream(output, state.sessions.start(new_session)))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{album_id, album_key, parse_album_id, split_album_key, ALBUM_ID_SEPARATOR};
+
+    fn pair(album_artist: &str, album: &str) -> (String, String) {
+        (album_artist.to_string(), album.to_string())
+    }
+
+    #[test]
+    fn round_trips_album_ids() {
+        for (album_artist, album) in [
+            ("Artist", "Album"),
+            ("", ""),
+            ("AC-DC", "High-Voltage"),
+            ("100% Pure", "50%_off"),
+            ("坂本龍一", "音楽図鑑"),
+            ("Björk", "Homogénic 🎵"),
+        ] {
+            let expected = vec![pair(album_artist, album)];
+            assert_eq!(split_album_key(&album_key(album_artist, album)), expected);
+            assert_eq!(parse_album_id(&album_id(album_artist, album)), expected);
+        }
+    }
+
+    #[test]
+    fn album_ids_with_separator_list_every_pair() {
+        let album_artist = format!("A{}B", ALBUM_ID_SEPARATOR);
+        let album = format!("C-%{}日本", ALBUM_ID_SEPARATOR);
+        let pairs = parse_album_id(&album_id(&album_artist, &album));
+        assert_eq!(pairs, vec![
+            pair("A", &format!("B{}C-%{}日本", ALBUM_ID_SEPARATOR, ALBUM_ID_SEPARATOR)),
+            pair(&album_artist, &album),
+            pair(&format!("{}{}C-%", album_artist, ALBUM_ID_SEPARATOR), "日本"),
+        ]);
+        // Every pair maps back to the same ID
+        for (album_artist, album) in &pairs {
+            assert_eq!(album_id(album_artist, album), album_id(&pairs[1].0, &pairs[1].1));
+        }
+    }
+
+    // Characters that trip up album IDs: the separator, base64's URL-safe
+    // alphabet, URL escapes, path separators and multi-byte text
+    const ADVERSARIAL: [char; 14] = [ALBUM_ID_SEPARATOR, '-', '_', '%', '/', '+', '=', ' ', 'a', 'Z', '0', 'é', '日', '🎵'];
+
+    // A small xorshift generator, seeded so failures reproduce
+    struct Names(u64);
+
+    impl Names {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn name(&mut self) -> String {
+            let length = self.next() % 7;
+            (0..length).map(|_| ADVERSARIAL[(self.next() % ADVERSARIAL.len() as u64) as usize]).collect()
+        }
+    }
+
+    #[test]
+    fn round_trips_generated_names() {
+        let mut names = Names(0x5eed_a1b0_u64);
+        for _ in 0..10_000 {
+            let (album_artist, album) = (names.name(), names.name());
+            let id = album_id(&album_artist, &album);
+            let pairs = parse_album_id(&id);
+            assert_eq!(pairs, split_album_key(&album_key(&album_artist, &album)));
+            assert!(pairs.contains(&pair(&album_artist, &album)), "{:?} / {:?} not in {:?}", album_artist, album, pairs);
+            for (other_artist, other_album) in &pairs {
+                assert_eq!(album_id(other_artist, other_album), id);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_non_album_ids() {
+        assert!(parse_album_id("not base64!").is_empty());
+        // Valid base64 that isn't UTF-8, and UTF-8 without a separator
+        assert!(parse_album_id("_w").is_empty());
+        assert!(parse_album_id("QWxidW0").is_empty());
+    }
+}
@@ -1,35 +1,113 @@
 use axum::{
-    body::Body,
-    extract::{Path, Query, State},
-    http::{header, HeaderMap, StatusCode},
-    response::{Json, Response},
-    routing::{get, post},
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, patch, post},
     Router,
 };
-use log::error;
+use chrono::{DateTime, NaiveDate, Utc};
+use log::{error, info};
 use sea_orm::{
-    ColumnTrait, Condition, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
-    QueryOrder, QuerySelect,
+    ColumnTrait, Condition, DatabaseConnection, EntityTrait, JoinType, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect, QueryTrait, RelationTrait, Select,
 };
+use sea_query::Expr;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use std::sync::Arc;
 use tower_http::services::ServeFile;
 
-use entity::prelude::Track;
-use entity::track;
+use entity::prelude::{ScanError, Track, TrackArtist};
+use entity::{scan_error, track, track_artist};
+use crate::albums::{self, AlbumMode};
+use crate::audio_info;
+use crate::audiobooks;
+use crate::media_types::{MediaTypeQuery, MediaTypes};
+use crate::release_types::{self, ReleaseTypeQuery, ReleaseTypes};
+use crate::capabilities;
+use crate::browse::{self, ArtistCount, ArtistIndex, CountCache, GenreCount};
+use crate::content_type;
+use crate::downloads;
+use crate::duplicates::{self, FormatPreference, TrackAlternate};
+use crate::conversion::{self, ConversionJobs};
+use crate::error::{ApiError, ApiJson, ApiPath, ApiQuery, ApiResult};
+use crate::formats::{FormatSummary, FormatSummaryBuilder};
+use crate::diagnostics;
+use crate::backups::{self, Backups};
+use crate::query_log::{self, QueryLog};
+use crate::write_queue::WriteQueue;
+use crate::music_folders;
+use crate::musical_keys;
+use crate::stream_metrics::{self, METRICS};
+use crate::hidden::{self, Visibility, VisibilityQuery};
+use crate::history;
+use crate::hls;
+use crate::images;
+use crate::clients;
+use crate::import;
+use crate::in_progress::InProgressFilter;
+use crate::integrity;
 use crate::lastfm;
+use crate::listening;
+use crate::openapi;
+use crate::paths;
+use crate::permissions;
+use crate::placeholder;
+use crate::playlist_covers::{self, PlaylistCovers};
+use crate::previews::{self, PreviewClips};
+use crate::playback::{self, PlaybackTracker};
+use crate::playlists;
+use crate::recommendations;
+use crate::playqueue;
+use crate::proxy::{self, ProxyConfig, RequestOrigin};
+use crate::scanner;
+use crate::search_query::SearchQuery;
+use crate::shuffle;
+use crate::slugs;
+use crate::sessions::{tracked_body, NewSession, SessionInfo, SessionRegistry};
+use crate::scheduler::{self, ScanStatus, ScanTracker};
+use crate::storage::{self, Storage};
+use crate::stream_token::StreamSigner;
+use crate::streaming::{self, RangeRequest};
+use crate::text_index::{self, IgnoredArticles, TextNormalization};
+use crate::transcoder::{self, TranscodeOptions, TranscodeProfile, TranscodingConfig};
+use crate::webhooks::{self, Webhooks};
+use crate::lyric_providers::{self, LyricsFetcher};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: DatabaseConnection,
     pub music_path: String,
+    pub storage: Arc<dyn Storage>,
+    pub counts: Arc<CountCache>,
+    pub scans: Arc<ScanTracker>,
+    pub album_mode: AlbumMode,
+    pub text_normalization: TextNormalization,
+    pub ignored_articles: Arc<IgnoredArticles>,
+    pub sessions: Arc<SessionRegistry>,
+    pub conversions: Arc<ConversionJobs>,
+    pub playback: Arc<PlaybackTracker>,
+    pub demo_mode: bool,
+    pub previews: Arc<PreviewClips>,
+    pub playlist_covers: Arc<PlaylistCovers>,
+    pub transcoding: Arc<TranscodingConfig>,
+    pub stream_signer: Option<StreamSigner>,
+    pub webhooks: Arc<Webhooks>,
+    pub lyrics: Arc<LyricsFetcher>,
+    pub proxy: Arc<ProxyConfig>,
+    pub backups: Arc<Backups>,
+    pub query_log: Arc<QueryLog>,
+    pub writes: Arc<WriteQueue>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct TrackQuery {
     pub page: Option<u64>,
     pub per_page: Option<u64>,
@@ -38,9 +116,29 @@ pub struct TrackQuery {
     pub album: Option<String>,
     pub genre: Option<String>,
     pub album_artist: Option<String>,
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+    pub modified_after: Option<String>,
+    pub isrc: Option<String>,
+    pub barcode: Option<String>,
+    /// ISO 639 language code, e.g. jpn
+    pub language: Option<String>,
+    /// ISO 3166 release country code, e.g. JP
+    pub country: Option<String>,
+    /// First year of a decade, e.g. 1990 for tracks from 1990 to 1999
+    pub decade: Option<i32>,
+    /// Only tracks tagged with at least this BPM
+    pub bpm_min: Option<f64>,
+    /// Only tracks tagged with at most this BPM
+    pub bpm_max: Option<f64>,
+    /// Only tracks in this key, in standard (Am), Camelot (8A) or Open Key (1m) notation
+    pub key: Option<String>,
+    /// Order by `bpm` or by `key` around the Camelot wheel, instead of artist,
+    /// album and title; tracks without one come last
+    pub sort: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct TrackResponse {
     pub id: i32,
     pub path: String,
@@ -52,7 +150,11 @@ pub struct TrackResponse {
     pub track_number: Option<i32>,
     pub year: Option<i32>,
     pub genre: String,
+    /// Album artist the track is listed under: its tag, or the fallback for
+    /// files without one (ALBUM_ARTIST_FALLBACK)
     pub album_artist: String,
+    /// Album artist tag as written in the file
+    pub album_artist_tag: String,
     pub publisher: String,
     pub catalog_number: String,
     pub duration_seconds: i32,
@@ -61,17 +163,58 @@ pub struct TrackResponse {
     pub sample_rate: i32,
     pub bit_depth: i32,
     pub channels: i32,
+    /// e.g. "MP3", "AAC", "FLAC"; null for files not read since it was recorded
+    pub codec: Option<String>,
+    /// e.g. "MPEG-1 Layer III", "HE-AAC"
+    pub codec_profile: Option<String>,
+    /// Encoder named in the tags or the LAME header, e.g. "LAME3.100"
+    pub encoder: Option<String>,
+    /// Tempo from the BPM tag
+    pub bpm: Option<f64>,
+    /// Key from the INITIALKEY tag in standard notation, e.g. "Am" or "F#"
+    pub initial_key: Option<String>,
+    /// Size of the file in bytes; null for tracks stored before sizes were
+    /// recorded until the next full scan
+    pub file_size: Option<i64>,
+    #[schema(value_type = Object)]
     pub tags: Value,
     pub album_art_path: Option<String>,
     pub album_art_mime_type: Option<String>,
     pub album_art_size: Option<i32>,
-    pub created: chrono::DateTime<chrono::Utc>,
-    pub modified: chrono::DateTime<chrono::Utc>,
+    /// Embedded image types available from /tracks/{id}/albumart?type=
+    pub image_types: Vec<String>,
+    pub content_type: String,
+    pub isrc: Option<String>,
+    pub barcode: Option<String>,
+    /// ISO 639 language of the lyrics, from the LANGUAGE tag
+    pub language: Option<String>,
+    /// ISO 3166 country of the release, from MusicBrainz's RELEASECOUNTRY tag
+    pub release_country: Option<String>,
+    /// Parental advisory from the tags: true for explicit, false for clean, null when not tagged
+    pub explicit: Option<bool>,
+    /// The file's comment or description, unless changed with PATCH /tracks/{id}
+    pub comment: Option<String>,
+    pub is_hidden: bool,
+    /// Library folder the file is in (MUSIC_PATH or one of EXTRA_MUSIC_PATHS)
+    pub library_root: Option<String>,
+    /// music, podcast, audiobook or sfx
+    pub media_type: String,
+    /// When the server first stored the track
+    pub added: DateTime<Utc>,
+    /// The file's creation time, or its modification time where the filesystem has none
+    pub created: DateTime<Utc>,
+    pub modified: DateTime<Utc>,
+    /// Other copies of the song, returned by search with group_duplicates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alternates: Option<Vec<TrackAlternate>>,
 }
 
 impl From<track::Model> for TrackResponse {
     fn from(model: track::Model) -> Self {
+        let image_types = images::image_types(&model);
         let tags = model.tags;
+        let content_type = model.content_type
+            .unwrap_or_else(|| content_type::from_path(&model.path));
 
         Self {
             id: model.id,
@@ -85,6 +228,7 @@ impl From<track::Model> for TrackResponse {
             year: model.year,
             genre: model.genre,
             album_artist: model.album_artist,
+            album_artist_tag: model.album_artist_tag,
             publisher: model.publisher,
             catalog_number: model.catalog_number,
             duration_seconds: model.duration_seconds,
@@ -93,26 +237,73 @@ impl From<track::Model> for TrackResponse {
             sample_rate: model.sample_rate,
             bit_depth: model.bit_depth,
             channels: model.channels,
+            codec: model.codec,
+            codec_profile: model.codec_profile,
+            encoder: model.encoder,
+            bpm: model.bpm,
+            initial_key: model.initial_key,
+            file_size: model.file_size,
             tags,
             album_art_path: model.album_art_path,
             album_art_mime_type: model.album_art_mime_type,
             album_art_size: model.album_art_size,
+            image_types,
+            content_type,
+            isrc: model.isrc,
+            barcode: model.barcode,
+            language: model.language,
+            release_country: model.release_country,
+            explicit: model.explicit,
+            comment: model.comment,
+            is_hidden: model.is_hidden,
+            library_root: model.library_root,
+            media_type: model.media_type,
+            added: model.added,
             created: model.created,
             modified: model.modified,
+            alternates: None,
         }
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct TrackListResponse {
     pub tracks: Vec<TrackResponse>,
     pub total: u64,
     pub page: u64,
     pub per_page: u64,
     pub total_pages: u64,
+    /// Matching tracks per decade, returned by search
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decades: Option<Vec<DecadeFacet>>,
+    /// Matching tracks per language, returned by search
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub languages: Option<Vec<LanguageFacet>>,
+    /// Matching tracks per release country, returned by search
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub countries: Option<Vec<CountryFacet>>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
+pub struct DecadeFacet {
+    /// First year of the decade
+    pub decade: i32,
+    pub track_count: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LanguageFacet {
+    pub language: String,
+    pub track_count: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CountryFacet {
+    pub country: String,
+    pub track_count: i64,
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct TrackStatsResponse {
     pub total_tracks: u64,
     pub total_duration_seconds: i64,
@@ -124,68 +315,202 @@ pub struct TrackStatsResponse {
 pub fn create_router(state: AppState) -> Router {
     Router::new()
         .route("/tracks", get(get_tracks))
-        .route("/tracks/:id", get(get_track_by_id))
+        .route("/tracks/:id", get(get_track_by_id).patch(hidden::update_track))
+        .route("/tracks/:id/history", get(history::get_track_history))
+        .route("/tracks/:id/history/:change_id/revert", post(history::revert_change))
         .route("/tracks/:id/play", get(play_track))
         .route("/tracks/:id/albumart", get(get_album_art))
+        .route("/tracks/:id/preview", get(previews::get_preview))
+        .route("/tracks/:id/audio-info", get(audio_info::get_audio_info))
+        .route("/tracks/:id/stream-url", get(get_stream_url))
+        .route("/tracks/:id/hls/index.m3u8", get(hls::get_master_playlist))
+        .route("/tracks/:id/hls/audio.m3u8", get(hls::get_audio_playlist))
+        .route("/tracks/:id/hls/audio.aac", get(hls::get_audio_segment))
+        .route("/tracks/:id/lyrics", get(lyric_providers::get_lyrics))
+        .route("/tracks/:id/hls/lyrics.m3u8", get(hls::get_lyrics_playlist))
+        .route("/tracks/:id/hls/lyrics.vtt", get(hls::get_lyrics_vtt))
+        // Original files for Subsonic clients, which tell streaming and downloading apart
+        .route("/download", get(downloads::download))
+        .route("/download.view", get(downloads::download))
+        .route("/transcoding/profiles", get(get_transcoding_profiles))
         .route("/tracks/search", get(search_tracks))
+        .route("/tracks/batch", post(get_tracks_batch))
+        .route("/tracks/shuffle", get(shuffle::shuffle_tracks))
         .route("/stats", get(get_stats))
+        .route("/stats/listening", get(listening::get_listening_stats))
         .route("/artists", get(get_artists))
-        .route("/albums", get(get_albums))
+        .route("/indexes", get(get_artist_indexes))
+        .route("/music-folders", get(music_folders::get_music_folders))
+        .route("/music-folders/:id", get(music_folders::get_music_folder))
+        .route("/artists/:id", get(get_artist))
+        .route("/albums", get(albums::get_albums))
+        .route("/albums/by-decade", get(albums::get_albums_by_decade))
+        .route("/albums/:id/tracks", get(albums::get_album_tracks))
+        .route("/albums/:id/cover", get(albums::get_album_cover))
+        .route("/albums/:id/stream", get(albums::get_album_stream))
+        .route("/audiobooks", get(audiobooks::get_audiobooks))
+        .route("/audiobooks/:id", get(audiobooks::get_audiobook))
         .route("/genres", get(get_genres))
         .route("/rescan", post(rescan_library))
+        .route("/rescan/path", post(rescan_path))
+        .route("/rescan/status", get(get_scan_status))
+        .route("/rescan/errors", get(get_scan_errors))
         // Last.fm integration routes
         .route("/lastfm/auth", get(lastfm::get_auth_url))
         .route("/lastfm/callback", get(lastfm::auth_callback))
         .route("/lastfm/session", post(lastfm::create_session))
         .route("/tracks/:id/scrobble", post(lastfm::scrobble_track))
         .route("/tracks/:id/now-playing", post(lastfm::update_now_playing))
+        // Listening history
+        .route("/tracks/:id/plays", post(listening::create_play))
+        .route("/tracks/:id/annotation", get(listening::get_annotation))
+        .route("/tracks/:id/progress", get(playback::get_progress).post(playback::update_progress))
+        .route("/admin/now-playing", get(playback::get_now_playing))
+        .route("/admin/quality-report", get(integrity::get_quality_report))
+        .route("/admin/diagnostics", get(diagnostics::get_diagnostics))
+        .route("/admin/backup", get(backups::get_backup_status).post(backups::start_backup))
+        .route("/admin/streaming-metrics", get(stream_metrics::get_streaming_metrics))
+        .route("/admin/query-log", get(query_log::get_query_log).put(query_log::update_query_log))
+        .route("/admin/lyrics/fetch", post(lyric_providers::fetch_missing_lyrics))
+        .route("/admin/previews/generate", post(previews::generate_previews))
+        .route("/admin/clients", get(clients::get_clients))
+        .route("/admin/clients/:id", patch(clients::update_client))
+        .route("/admin/webhooks/test", post(webhooks::test_webhooks))
+        // Ratings, play counts and playlists from other servers
+        .route("/admin/import", post(import::import_library).layer(DefaultBodyLimit::max(import::IMPORT_BODY_LIMIT)))
+        // Active stream sessions
+        .route("/admin/sessions", get(get_sessions))
+        .route("/admin/sessions/:id", delete(terminate_session))
+        // Tracks and directories hidden from clients
+        .route("/admin/hidden-directories", get(hidden::get_hidden_directories).post(hidden::hide_directory))
+        .route("/admin/hidden-directories/:id", delete(hidden::unhide_directory))
+        // Folders users are limited to
+        .route("/admin/users", get(permissions::get_users))
+        .route("/admin/users/:user/folders", get(permissions::get_user_folders).put(permissions::update_user_folders))
+        .route("/admin/users/:user/content-filter", get(permissions::get_content_filter).put(permissions::update_content_filter))
+        .route("/admin/users/:user/roles", get(permissions::get_user_roles).put(permissions::update_user_roles))
+        // Batch format conversion
+        .route("/admin/conversions", get(conversion::get_conversions).post(conversion::start_conversion))
+        .route("/admin/conversions/:id", get(conversion::get_conversion).delete(conversion::cancel_conversion))
+        // Play queue persistence
+        .route("/playqueue", get(playqueue::get_play_queue).post(playqueue::save_play_queue))
+        // Playlists
+        .route("/playlists", get(playlists::get_playlists))
+        .route("/playlists/import", post(playlists::import_playlist))
+        .route("/playlists/recommended", post(recommendations::create_recommended_playlist))
+        .route("/playlists/:id/refresh", post(recommendations::refresh_playlist))
+        .route("/playlists/:id/cover", get(playlist_covers::get_playlist_cover))
+        .route("/playlists/:id", get(playlists::get_playlist).patch(playlists::update_playlist))
         // Documentation routes
         .route_service("/docs", ServeFile::new("api-docs.html"))
-        .route_service("/openapi.yaml", ServeFile::new("openapi.yaml"))
+        .route("/openapi.yaml", get(openapi::openapi_yaml))
+        .route("/openapi.json", get(openapi::openapi_json))
+        .route("/capabilities", get(capabilities::get_capabilities))
+        .fallback(capabilities::unknown_route)
+        .layer(middleware::from_fn_with_state(state.clone(), demo_guard))
+        .layer(middleware::from_fn_with_state(state.clone(), proxy::log_requests))
+        .layer(middleware::from_fn(query_log::scope_requests))
         .with_state(state)
 }
 
+// Demo mode is read-only: anything that changes state, talks to Last.fm on
+// someone's behalf or exposes other listeners is refused
+async fn demo_guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if state.demo_mode {
+        let read_only = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+        let path = request.uri().path();
+        if !read_only || path.starts_with("/lastfm") || path.starts_with("/admin") {
+            return ApiError::new(StatusCode::FORBIDDEN, "demo_mode", "Not available in demo mode").into_response();
+        }
+    }
+    next.run(request).await
+}
+
 // GET /tracks - List tracks with pagination and optional filters
+#[utoipa::path(
+    get,
+    path = "/tracks",
+    tag = "tracks",
+    params(TrackQuery, MediaTypeQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Page of tracks", body = TrackListResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 async fn get_tracks(
     State(state): State<AppState>,
-    Query(params): Query<TrackQuery>,
-) -> Result<Json<TrackListResponse>, StatusCode> {
-    let page = params.page.unwrap_or(1);
-    let per_page = params.per_page.unwrap_or(20).min(100); // Max 100 per page
+    ApiQuery(params): ApiQuery<TrackQuery>,
+    media_types: MediaTypes,
+    visibility: Visibility,
+) -> ApiResult<Json<TrackListResponse>> {
+    let (page, per_page) = pagination(params.page, params.per_page)?;
 
     let mut query = Track::find();
 
     // Apply filters
-    let mut condition = Condition::all();
+    let mut condition = visibility.condition().add(media_types.condition());
     if let Some(title) = params.title {
-        condition = condition.add(track::Column::Title.contains(&title));
+        condition = condition.add(text_index::contains_ignoring_case(state.text_normalization, track::Column::SearchTitle, track::Column::Title, &title));
     }
     if let Some(artist) = params.artist {
-        condition = condition.add(track::Column::Artist.contains(&artist));
+        condition = condition.add(text_index::contains_ignoring_case(state.text_normalization, track::Column::SearchArtist, track::Column::Artist, &artist));
     }
     if let Some(album) = params.album {
-        condition = condition.add(track::Column::Album.contains(&album));
+        condition = condition.add(text_index::contains_ignoring_case(state.text_normalization, track::Column::SearchAlbum, track::Column::Album, &album));
     }
     if let Some(genre) = params.genre {
-        condition = condition.add(track::Column::Genre.contains(&genre));
+        condition = condition.add(text_index::contains_ignoring_case(state.text_normalization, track::Column::SearchGenre, track::Column::Genre, &genre));
     }
     if let Some(album_artist) = params.album_artist {
-        condition = condition.add(track::Column::AlbumArtist.contains(&album_artist));
+        condition = condition.add(text_index::contains_ignoring_case(state.text_normalization, track::Column::SearchAlbumArtist, track::Column::AlbumArtist, &album_artist));
+    }
+    if let Some(isrc) = params.isrc {
+        let isrc = crate::scanner::normalize_isrc(&isrc)
+            .ok_or_else(|| ApiError::invalid_field("isrc", "must be a 12 character ISRC"))?;
+        condition = condition.add(track::Column::Isrc.eq(isrc));
+    }
+    if let Some(barcode) = params.barcode {
+        let barcode = crate::scanner::normalize_barcode(&barcode)
+            .ok_or_else(|| ApiError::invalid_field("barcode", "must be an 8, 12, 13 or 14 digit UPC/EAN"))?;
+        condition = condition.add(track::Column::Barcode.eq(barcode));
+    }
+    condition = condition.add(language_country_condition(params.language.as_deref(), params.country.as_deref())?);
+    condition = condition.add(date_condition(
+        params.created_after.as_deref(),
+        params.created_before.as_deref(),
+        params.modified_after.as_deref(),
+    )?);
+    if let Some(decade) = params.decade {
+        let (from, to) = decade_range(decade)?;
+        condition = condition.add(track::Column::Year.between(from, to));
+    }
+    if let Some(bpm_min) = params.bpm_min {
+        condition = condition.add(track::Column::Bpm.gte(bpm_min));
+    }
+    if let Some(bpm_max) = params.bpm_max {
+        condition = condition.add(track::Column::Bpm.lte(bpm_max));
+    }
+    if let Some(key) = params.key {
+        let key = musical_keys::normalize_key(&key)
+            .ok_or_else(|| ApiError::invalid_field("key", "must be a key such as Am, 8A or 1m"))?;
+        condition = condition.add(track::Column::InitialKey.eq(key));
     }
 
     query = query.filter(condition);
+    query = match params.sort.as_deref() {
+        None => query,
+        Some("bpm") => query.order_by_asc(track::Column::Bpm),
+        Some("key") => query.order_by_asc(Expr::cust(musical_keys::wheel_order_expr())),
+        Some(_) => return Err(ApiError::invalid_field("sort", "must be bpm or key")),
+    };
 
-    let total = query.clone().count(&state.db).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let total_pages = (total + per_page - 1) / per_page;
+    let total = query.clone().count(&state.db).await?;
+    let total_pages = total.div_ceil(per_page);
 
-    let tracks = query
-        .order_by_asc(track::Column::Artist)
-        .order_by_asc(track::Column::Album)
-        .order_by_asc(track::Column::Title)
+    let tracks = order_tracks(query)
         .paginate(&state.db, per_page)
         .fetch_page(page - 1)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .await?
         .into_iter()
         .map(TrackResponse::from)
         .collect();
@@ -196,254 +521,780 @@ async fn get_tracks(
         page,
         per_page,
         total_pages,
+        decades: None,
+        languages: None,
+        countries: None,
     }))
 }
 
+// Helper function to validate page/per_page, defaulting to the first page of 20
+pub fn pagination(page: Option<u64>, per_page: Option<u64>) -> ApiResult<(u64, u64)> {
+    let page = page.unwrap_or(1);
+    if page == 0 {
+        return Err(ApiError::invalid_field("page", "must be 1 or greater"));
+    }
+    let per_page = per_page.unwrap_or(20);
+    if per_page == 0 {
+        return Err(ApiError::invalid_field("per_page", "must be 1 or greater"));
+    }
+    Ok((page, per_page.min(100))) // Max 100 per page
+}
+
+// Helper function to cut a page out of a listing built in memory, returning
+// it with the total count and number of pages
+pub fn paginate<T>(items: Vec<T>, page: u64, per_page: u64) -> (Vec<T>, u64, u64) {
+    let total = items.len() as u64;
+    let items = items.into_iter()
+        .skip(((page - 1) * per_page) as usize)
+        .take(per_page as usize)
+        .collect();
+    (items, total, total.div_ceil(per_page))
+}
+
+// Helper function to parse a date query parameter into UTC.
+// Accepts RFC 3339 timestamps (any offset is converted to UTC) or plain
+// YYYY-MM-DD dates, which are taken as midnight UTC.
+fn parse_date_param(field: &str, value: &str) -> ApiResult<DateTime<Utc>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Ok(datetime.with_timezone(&Utc));
+    }
+
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|datetime| datetime.and_utc())
+        .ok_or_else(|| ApiError::invalid_field(field, "expected an RFC 3339 timestamp or YYYY-MM-DD date"))
+}
+
+// Helper function to turn a decade parameter (1990) into its first and last years
+pub fn decade_range(decade: i32) -> ApiResult<(i32, i32)> {
+    if decade.rem_euclid(10) != 0 {
+        return Err(ApiError::invalid_field("decade", "must be the first year of a decade, e.g. 1990"));
+    }
+    let last_year = decade.checked_add(9)
+        .ok_or_else(|| ApiError::invalid_field("decade", "is out of range"))?;
+    Ok((decade, last_year))
+}
+
+// Helper function to build the added/modified date range filter shared by /tracks and search.
+// created_after and created_before are about when the track was added to the
+// library, not the file's creation time, which changes when files are copied
+fn date_condition(
+    created_after: Option<&str>,
+    created_before: Option<&str>,
+    modified_after: Option<&str>,
+) -> ApiResult<Condition> {
+    let mut condition = Condition::all();
+    if let Some(created_after) = created_after {
+        condition = condition.add(track::Column::Added.gte(parse_date_param("created_after", created_after)?));
+    }
+    if let Some(created_before) = created_before {
+        condition = condition.add(track::Column::Added.lt(parse_date_param("created_before", created_before)?));
+    }
+    if let Some(modified_after) = modified_after {
+        condition = condition.add(track::Column::Modified.gte(parse_date_param("modified_after", modified_after)?));
+    }
+    Ok(condition)
+}
+
 // GET /tracks/:id - Get a specific track by ID
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID"), VisibilityQuery),
+    responses(
+        (status = 200, description = "Track", body = TrackResponse),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 async fn get_track_by_id(
     State(state): State<AppState>,
-    Path(id): Path<i32>,
-) -> Result<Json<TrackResponse>, StatusCode> {
+    ApiPath(id): ApiPath<i32>,
+    visibility: Visibility,
+) -> ApiResult<Json<TrackResponse>> {
     let track = Track::find_by_id(id)
+        .filter(visibility.condition())
         .one(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
 
     match track {
         Some(track) => Ok(Json(TrackResponse::from(track))),
-        None => Err(StatusCode::NOT_FOUND),
+        None => Err(ApiError::not_found(format!("Track {} not found", id))),
     }
 }
 
+// Tracks one POST /tracks/batch request can ask for
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Tracks to look up at once, by ID and/or by path
+#[derive(Deserialize, ToSchema)]
+pub struct TrackBatchRequest {
+    #[serde(default)]
+    pub ids: Vec<i32>,
+    /// Full paths, as in TrackResponse.path
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TrackBatchResponse {
+    /// Found tracks, in the order they were asked for (IDs first, then paths)
+    pub tracks: Vec<TrackResponse>,
+    /// Requested IDs with no track, or a hidden one
+    pub missing_ids: Vec<i32>,
+    /// Requested paths with no track, or a hidden one
+    pub missing_paths: Vec<String>,
+}
+
+// POST /tracks/batch - Get many tracks by ID or path in one request
+#[utoipa::path(
+    post,
+    path = "/tracks/batch",
+    tag = "tracks",
+    params(VisibilityQuery),
+    request_body = TrackBatchRequest,
+    responses(
+        (status = 200, description = "Found tracks, with the IDs and paths that weren't found", body = TrackBatchResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_tracks_batch(
+    State(state): State<AppState>,
+    visibility: Visibility,
+    ApiJson(request): ApiJson<TrackBatchRequest>,
+) -> ApiResult<Json<TrackBatchResponse>> {
+    if request.ids.len() + request.paths.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::invalid_field("ids", format!("at most {} IDs and paths can be looked up at once", MAX_BATCH_SIZE)));
+    }
+
+    let mut ids = request.ids;
+    let mut seen = HashSet::new();
+    ids.retain(|id| seen.insert(*id));
+    let mut paths = request.paths;
+    let mut seen = HashSet::new();
+    paths.retain(|path| seen.insert(path.clone()));
+
+    let mut found = Condition::any();
+    if !ids.is_empty() {
+        found = found.add(track::Column::Id.is_in(ids.iter().copied()));
+    }
+    if !paths.is_empty() {
+        found = found.add(track::Column::Path.is_in(paths.iter().cloned()));
+    }
+    let tracks = if ids.is_empty() && paths.is_empty() {
+        Vec::new()
+    } else {
+        Track::find()
+            .filter(found)
+            .filter(visibility.condition())
+            .all(&state.db)
+            .await?
+    };
+
+    let by_id: HashMap<i32, &track::Model> = tracks.iter().map(|track| (track.id, track)).collect();
+    let by_path: HashMap<&str, &track::Model> = tracks.iter().map(|track| (track.path.as_str(), track)).collect();
+    let mut response = TrackBatchResponse {
+        tracks: Vec::with_capacity(tracks.len()),
+        missing_ids: Vec::new(),
+        missing_paths: Vec::new(),
+    };
+    for id in ids {
+        match by_id.get(&id) {
+            Some(track) => response.tracks.push(TrackResponse::from((*track).clone())),
+            None => response.missing_ids.push(id),
+        }
+    }
+    for path in paths {
+        match by_path.get(path.as_str()) {
+            Some(track) => response.tracks.push(TrackResponse::from((*track).clone())),
+            None => response.missing_paths.push(path),
+        }
+    }
+    Ok(Json(response))
+}
+
 // GET /tracks/:id/play - Stream audio file with range support for web browsers
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PlayQuery {
+    pub client: Option<String>,
+    pub token: Option<String>,
+    /// Transcoding profile to stream with, or "original" for the file as it is.
+    /// Defaults to the profile assigned to the client or user, if any.
+    pub profile: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/play",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID"), PlayQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Whole audio file", content_type = "application/octet-stream"),
+        (status = 206, description = "Requested byte range of the audio file", content_type = "application/octet-stream"),
+        (status = 400, description = "Unknown transcoding profile", body = Problem, content_type = "application/problem+json"),
+        (status = 401, description = "A signed stream token is required", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The stream token is invalid or expired", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+        (status = 416, description = "Range not satisfiable", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 async fn play_track(
     State(state): State<AppState>,
-    Path(id): Path<i32>,
+    ApiPath(id): ApiPath<i32>,
+    ApiQuery(params): ApiQuery<PlayQuery>,
+    visibility: Visibility,
+    origin: RequestOrigin,
     headers: HeaderMap,
-) -> Result<Response<Body>, StatusCode> {
+) -> ApiResult<Response<Body>> {
+    check_stream_token(&state, id, params.token.as_deref())?;
+
     // Find the track in the database
     let track = Track::find_by_id(id)
+        .filter(visibility.condition())
         .one(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
 
     let track = match track {
         Some(track) => track,
-        None => return Err(StatusCode::NOT_FOUND),
+        None => return Err(ApiError::not_found(format!("Track {} not found", id))),
+    };
+
+    // Get file size from the storage backend holding the track
+    let entry = state.storage
+        .stat(&track.path)
+        .await?;
+
+    let file_size = entry.size;
+
+    // Record the client in the registry to pick up any overrides set for it
+    let user_agent = headers.get(header::USER_AGENT).and_then(|value| value.to_str().ok());
+    let known_client = clients::identify(&state.db, params.client.as_deref(), user_agent).await?;
+
+    // Register the stream so it shows up in /admin/sessions; clients that
+    // don't identify themselves are described by their User-Agent
+    let client = params.client.or_else(|| user_agent.map(str::to_string));
+    let mut new_session = NewSession {
+        user: visibility.user().map(str::to_string),
+        client,
+        address: origin.client_ip.map(|ip| ip.to_string()),
+        track_id: track.id,
+        title: track.title.clone(),
+        artist: track.artist.clone(),
+        bitrate: track.audio_bitrate,
+        total_bytes: file_size,
     };
 
-    // Get the file path
-    let file_path = PathBuf::from(&track.path);
+    // Demo mode only ever serves the track's preview clip, never the original file
+    if state.demo_mode {
+        let clip = previews::clip_body(&state, &track).await?;
+        new_session.total_bytes = clip.len() as u64;
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "audio/mpeg")
+            .header(header::CONTENT_LENGTH, clip.len().to_string())
+            .header(header::ACCEPT_RANGES, "none")
+            .header(header::CACHE_CONTROL, "public, max-age=3600")
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, OPTIONS")
+            .body(tracked_body(clip, state.sessions.start(new_session)))?);
+    }
 
-    // Check if file exists
-    if !file_path.exists() {
-        return Err(StatusCode::NOT_FOUND);
+    // A profile forced on the client wins over the one it asks for, unless
+    // it has since been removed from the configuration
+    let forced = known_client.as_ref()
+        .and_then(|client| client.force_profile.as_deref())
+        .filter(|name| name.eq_ignore_ascii_case("original") || state.transcoding.profile(name).is_some());
+    let profile = state.transcoding
+        .select(forced.or(params.profile.as_deref()), new_session.user.as_deref(), new_session.client.as_deref())
+        .map_err(|e| ApiError::invalid_field("profile", e))?
+        .cloned();
+    let profile = match known_client.as_ref().and_then(|client| client.max_bitrate) {
+        Some(max_bitrate) => clients::cap_bitrate(profile, max_bitrate as u32, &track),
+        None => profile,
+    };
+    if let Some(profile) = profile.filter(|profile| profile.needs_transcode(&track)) {
+        if profile.bitrate_kbps > 0 {
+            new_session.bitrate = profile.bitrate_kbps as i32;
+        }
+        METRICS.transcoded_request(&format!("{:?}", profile.format).to_lowercase());
+        return transcoded_response(&state, &track.path, profile.options(), new_session).await;
     }
 
-    // Get file metadata
-    let metadata = tokio::fs::metadata(&file_path)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Use the content type detected during scan, falling back to the extension
+    // table and then to sniffing the first bytes of the file
+    let mime_type = match track.content_type {
+        Some(content_type) => content_type,
+        None => {
+            let from_path = content_type::from_path(&track.path);
+            if from_path == content_type::OCTET_STREAM && file_size > 0 {
+                let header = state.storage
+                    .read_range(&track.path, 0, file_size.min(64) - 1)
+                    .await
+                    .unwrap_or_default();
+                content_type::sniff(&header).map(str::to_string).unwrap_or(from_path)
+            } else {
+                from_path
+            }
+        }
+    };
 
-    let file_size = metadata.len();
+    // Honour a Range header unless the client is known to mishandle partial responses
+    let disable_chunking = known_client.as_ref().is_some_and(|client| client.disable_chunking);
+    let range = match disable_chunking {
+        true => RangeRequest::Full,
+        false => streaming::parse_range(headers.get(header::RANGE), file_size),
+    };
+    METRICS.original_request(&track.extension);
+    streaming::file_response(&state, &track.path, file_size, &mime_type, range, !disable_chunking, new_session).await
+}
 
-    // Determine MIME type
-    let mime_type = mime_guess::from_path(&file_path)
-        .first_or_octet_stream()
-        .to_string();
+// Helper function to check a signed stream token. Tokens are optional unless
+// REQUIRE_SIGNED_STREAMS is set, but a token that is passed must be valid.
+pub fn check_stream_token(state: &AppState, track_id: i32, token: Option<&str>) -> ApiResult<()> {
+    let Some(signer) = &state.stream_signer else {
+        return Ok(());
+    };
+
+    match token {
+        Some(token) => signer.verify(track_id, token)
+            .map_err(|e| ApiError::new(StatusCode::FORBIDDEN, "invalid_stream_token", e.to_string())),
+        None if signer.required => Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "stream_token_required",
+            "A signed stream token is required, see /tracks/:id/stream-url",
+        )),
+        None => Ok(()),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamUrlQuery {
+    pub expires_in: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StreamUrlResponse {
+    /// Absolute URL of the stream, as the client reached the server
+    pub url: String,
+    pub token: String,
+    pub expires: DateTime<Utc>,
+}
+
+// GET /tracks/:id/stream-url - Get a signed, expiring URL for /tracks/:id/play
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/stream-url",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID"), StreamUrlQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Signed stream URL", body = StreamUrlResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+        (status = 501, description = "STREAM_SIGNING_KEY is not configured", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_stream_url(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    ApiQuery(params): ApiQuery<StreamUrlQuery>,
+    visibility: Visibility,
+    origin: RequestOrigin,
+) -> ApiResult<Json<StreamUrlResponse>> {
+    let signer = state.stream_signer.as_ref().ok_or_else(|| {
+        ApiError::new(StatusCode::NOT_IMPLEMENTED, "signing_disabled", "STREAM_SIGNING_KEY is not configured")
+    })?;
+
+    // Between a minute and a week, defaulting to an hour
+    let expires_in = params.expires_in.unwrap_or(3600);
+    if !(60..=7 * 24 * 3600).contains(&expires_in) {
+        return Err(ApiError::invalid_field("expires_in", "must be between 60 and 604800 seconds"));
+    }
+
+    Track::find_by_id(id)
+        .filter(visibility.condition())
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", id)))?;
+
+    let expires = Utc::now() + chrono::Duration::seconds(expires_in);
+    let token = signer.sign(id, expires);
+
+    // A URL handed out for a hidden track has to keep working when played
+    let include_hidden = if visibility.includes_hidden() { "&include_hidden=true" } else { "" };
+    Ok(Json(StreamUrlResponse {
+        url: origin.api_url(&format!("/tracks/{}/play?token={}{}", id, token, include_hidden)),
+        token,
+        expires,
+    }))
+}
+
+// Transcode a track for a profile or a demo mode preview. Range requests are
+// ignored since the output is generated on the fly.
+pub async fn transcoded_response(state: &AppState, path: &str, options: TranscodeOptions, mut new_session: NewSession) -> ApiResult<Response<Body>> {
+    let output = transcoder::transcode(state.storage.as_ref(), path, &options)
+        .await
+        .map_err(|e| {
+            error!("Failed to transcode {}: {}", path, e);
+            ApiError::internal("Failed to transcode track")
+        })?;
+    new_session.total_bytes = output.len() as u64;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, options.content_type)
+        .header(header::CONTENT_LENGTH, output.len().to_string())
+        .header(header::ACCEPT_RANGES, "none")
+        .header(header::CACHE_CONTROL, "public, max-age=3600")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, OPTIONS")
+        .body(tracked_body(Bytes::from(output), state.sessions.start(new_session)))?)
+}
 
-    // Parse Range header if present
-    let range_header = headers.get(header::RANGE);
+// GET /transcoding/profiles - List the profiles /tracks/:id/play can stream with
+#[utoipa::path(
+    get,
+    path = "/transcoding/profiles",
+    tag = "tracks",
+    responses(
+        (status = 200, description = "Configured transcoding profiles", body = [TranscodeProfile]),
+    )
+)]
+async fn get_transcoding_profiles(State(state): State<AppState>) -> Json<Vec<TranscodeProfile>> {
+    Json(state.transcoding.profiles.clone())
+}
 
-    if let Some(range_value) = range_header {
-        // Handle range request
-        let range_str = range_value.to_str().map_err(|_| StatusCode::BAD_REQUEST)?;
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AlbumArtQuery {
+    /// Embedded image to return: front, back, disc, artist or other (default: the album art)
+    #[serde(rename = "type")]
+    pub image_type: Option<String>,
+    /// Return generated placeholder art when there is no album art, instead of 404 Not Found (default: true)
+    pub placeholder: Option<bool>,
+}
 
-        if !range_str.starts_with("bytes=") {
-            return Err(StatusCode::RANGE_NOT_SATISFIABLE);
+impl AlbumArtQuery {
+    /// The requested image type, None for the album art
+    pub fn image_type(&self) -> ApiResult<Option<&'static str>> {
+        match self.image_type.as_deref().map(str::trim).filter(|value| !value.is_empty()) {
+            Some(value) => match images::IMAGE_TYPES.iter().find(|image_type| image_type.eq_ignore_ascii_case(value)) {
+                Some(image_type) => Ok(Some(*image_type)),
+                None => Err(ApiError::invalid_field("type", format!("must be one of {}", images::IMAGE_TYPES.join(", ")))),
+            },
+            None => Ok(None),
         }
+    }
 
-        let range_part = &range_str[6..]; // Remove "bytes="
-        let (start, end) = parse_range(range_part, file_size)?;
-
-        // Open file and seek to start position
-        let mut file = File::open(&file_path)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-        file.seek(std::io::SeekFrom::Start(start))
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-        // Read the requested range
-        let content_length = end - start + 1;
-        let mut buffer = vec![0u8; content_length as usize];
-        file.read_exact(&mut buffer)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-        // Build response with 206 Partial Content
-        let response = Response::builder()
-            .status(StatusCode::PARTIAL_CONTENT)
-            .header(header::CONTENT_TYPE, mime_type)
-            .header(header::CONTENT_LENGTH, content_length.to_string())
-            .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, file_size))
-            .header(header::ACCEPT_RANGES, "bytes")
-            .header(header::CACHE_CONTROL, "public, max-age=3600")
-            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-            .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, OPTIONS")
-            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Range, Content-Range, Content-Length")
-            .body(Body::from(buffer))
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    /// Whether to fall back to placeholder art, which only stands in for the album art
+    pub fn placeholder(&self, image_type: Option<&str>) -> bool {
+        self.placeholder.unwrap_or(true) && image_type.is_none_or(|image_type| image_type == images::FRONT)
+    }
+}
 
-        Ok(response)
+/// The file holding a track's album art, or another embedded image, with its
+/// MIME type when known. None when the track has none or the file is gone.
+pub fn album_art_file(state: &AppState, track: &track::Model, image_type: Option<&str>) -> Option<(PathBuf, Option<String>)> {
+    // In folder mode the folder's cover image is the album art, with embedded art as a fallback
+    let folder_art = if state.album_mode == AlbumMode::Folder && image_type.is_none_or(|image_type| image_type == images::FRONT) {
+        state.storage.local_path(albums::album_folder(&track.path))
+            .and_then(|folder| albums::find_folder_art(&folder))
     } else {
-        // Return full file
-        let file_content = tokio::fs::read(&file_path)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        None
+    };
 
-        let response = Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, mime_type)
-            .header(header::CONTENT_LENGTH, file_size.to_string())
-            .header(header::ACCEPT_RANGES, "bytes")
-            .header(header::CACHE_CONTROL, "public, max-age=3600")
-            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-            .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, OPTIONS")
-            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Range, Content-Range, Content-Length")
-            .body(Body::from(file_content))
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (file_path, mime_type) = match (folder_art, image_type) {
+        (Some(path), _) => (path, None),
+        (None, Some(image_type)) => {
+            let image = images::find_image(track, image_type)?;
+            (paths::to_native(&image.path), image.mime_type)
+        }
+        (None, None) => (paths::to_native(track.album_art_path.as_ref()?), track.album_art_mime_type.clone()),
+    };
+    file_path.exists().then_some((file_path, mime_type))
+}
 
-        Ok(response)
-    }
+pub fn image_response(content: Vec<u8>, mime_type: &str, max_age: u32) -> ApiResult<Response<Body>> {
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, mime_type)
+        .header(header::CONTENT_LENGTH, content.len().to_string())
+        .header(header::CACHE_CONTROL, format!("public, max-age={}", max_age))
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, OPTIONS")
+        .body(Body::from(content))?;
+    Ok(response)
 }
 
-// Helper function to parse Range header
-fn parse_range(range_str: &str, file_size: u64) -> Result<(u64, u64), StatusCode> {
-    if let Some(dash_pos) = range_str.find('-') {
-        let start_str = &range_str[..dash_pos];
-        let end_str = &range_str[dash_pos + 1..];
+/// Serve an album art file, cached by clients for 24 hours
+pub async fn album_art_response(file_path: PathBuf, mime_type: Option<String>) -> ApiResult<Response<Body>> {
+    let file_content = tokio::fs::read(&file_path)
+        .await?;
 
-        let start = if start_str.is_empty() {
-            // Suffix range like "-500" (last 500 bytes)
-            let suffix_length: u64 = end_str.parse().map_err(|_| StatusCode::RANGE_NOT_SATISFIABLE)?;
-            file_size.saturating_sub(suffix_length)
-        } else {
-            start_str.parse().map_err(|_| StatusCode::RANGE_NOT_SATISFIABLE)?
-        };
+    // Determine MIME type
+    let mime_type = mime_type
+        .or_else(|| content_type::sniff(&file_content).map(str::to_string))
+        .unwrap_or_else(|| content_type::from_path(&file_path.to_string_lossy()));
 
-        let end = if end_str.is_empty() {
-            // Range like "500-" (from 500 to end)
-            file_size - 1
-        } else {
-            let parsed_end: u64 = end_str.parse().map_err(|_| StatusCode::RANGE_NOT_SATISFIABLE)?;
-            std::cmp::min(parsed_end, file_size - 1)
-        };
+    image_response(file_content, &mime_type, 86400)
+}
 
-        if start <= end && end < file_size {
-            Ok((start, end))
-        } else {
-            Err(StatusCode::RANGE_NOT_SATISFIABLE)
-        }
-    } else {
-        Err(StatusCode::RANGE_NOT_SATISFIABLE)
-    }
+/// Serve placeholder art for an album. Cached for an hour, so art added
+/// later shows up soon.
+pub fn placeholder_response(album: &str) -> ApiResult<Response<Body>> {
+    image_response(placeholder::placeholder_svg(album).into_bytes(), placeholder::CONTENT_TYPE, 3600)
 }
 
-// GET /tracks/:id/albumart - Get album art for a specific track
+// GET /tracks/:id/albumart - Get album art, or another embedded image, for a specific track
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/albumart",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID"), AlbumArtQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Album art image, or placeholder art (image/svg+xml) when there is none", content_type = "image/*"),
+        (status = 400, description = "Unknown image type", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Track or album art not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 async fn get_album_art(
     State(state): State<AppState>,
-    Path(id): Path<i32>,
-) -> Result<Response<Body>, StatusCode> {
+    ApiPath(id): ApiPath<i32>,
+    ApiQuery(params): ApiQuery<AlbumArtQuery>,
+    visibility: Visibility,
+) -> ApiResult<Response<Body>> {
+    let image_type = params.image_type()?;
+
     // Find the track in the database
     let track = Track::find_by_id(id)
+        .filter(visibility.condition())
         .one(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
 
     let track = match track {
         Some(track) => track,
-        None => return Err(StatusCode::NOT_FOUND),
-    };
-
-    // Check if the track has album art
-    let album_art_path = match track.album_art_path {
-        Some(path) => path,
-        None => return Err(StatusCode::NOT_FOUND),
+        None => return Err(ApiError::not_found(format!("Track {} not found", id))),
     };
 
-    // Get the file path
-    let file_path = PathBuf::from(&album_art_path);
-
-    // Check if file exists
-    if !file_path.exists() {
-        return Err(StatusCode::NOT_FOUND);
+    match album_art_file(&state, &track, image_type) {
+        Some((file_path, mime_type)) => album_art_response(file_path, mime_type).await,
+        None if params.placeholder(image_type) => {
+            placeholder_response(if track.album.is_empty() { &track.title } else { &track.album })
+        }
+        None => Err(ApiError::not_found(match image_type {
+            Some(image_type) => format!("Track {} has no {} image", id, image_type),
+            None => format!("Track {} has no album art", id),
+        })),
     }
+}
 
-    // Read the file
-    let file_content = tokio::fs::read(&file_path)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+// Helper function to order track listings by the normalized sort keys, falling
+// back to the raw tags for tracks the text index hasn't reached yet
+fn order_tracks(query: Select<Track>) -> Select<Track> {
+    query
+        .order_by_asc(track::Column::SortArtist)
+        .order_by_asc(track::Column::SortAlbum)
+        .order_by_asc(track::Column::SortTitle)
+        .order_by_asc(track::Column::Artist)
+        .order_by_asc(track::Column::Album)
+        .order_by_asc(track::Column::Title)
+}
 
-    // Determine MIME type
-    let mime_type = track.album_art_mime_type
-        .unwrap_or_else(|| {
-            mime_guess::from_path(&file_path)
-                .first_or_octet_stream()
-                .to_string()
-        });
+// A page of search results with copies of the same song listed once, and
+// the number of songs
+async fn grouped_page(
+    db: &DatabaseConnection,
+    query: Select<Track>,
+    preference: &FormatPreference,
+    page: u64,
+    per_page: u64,
+) -> Result<(Vec<TrackResponse>, u64), sea_orm::DbErr> {
+    let groups = duplicates::group_tracks(db, order_tracks(query), preference).await?;
+    let total = groups.len() as u64;
+    let groups: Vec<_> = groups.into_iter()
+        .skip(((page - 1) * per_page) as usize)
+        .take(per_page as usize)
+        .collect();
 
-    // Build response
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, mime_type)
-        .header(header::CONTENT_LENGTH, file_content.len().to_string())
-        .header(header::CACHE_CONTROL, "public, max-age=86400") // Cache for 24 hours
-        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-        .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, OPTIONS")
-        .body(Body::from(file_content))
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let ids: Vec<i32> = groups.iter()
+        .flat_map(|group| std::iter::once(group.preferred).chain(group.alternates.iter().copied()))
+        .collect();
+    let mut models: HashMap<i32, track::Model> = Track::find()
+        .filter(track::Column::Id.is_in(ids))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| (model.id, model))
+        .collect();
 
-    Ok(response)
+    let tracks = groups.into_iter()
+        .filter_map(|group| {
+            let alternates = group.alternates.iter()
+                .filter_map(|id| models.get(id).map(TrackAlternate::from))
+                .collect();
+            let mut track = TrackResponse::from(models.remove(&group.preferred)?);
+            track.alternates = Some(alternates);
+            Some(track)
+        })
+        .collect();
+    Ok((tracks, total))
+}
+
+// Helper function to filter by ISO 639 language and ISO 3166 release country
+// codes, given in any case
+fn language_country_condition(language: Option<&str>, country: Option<&str>) -> ApiResult<Condition> {
+    let mut condition = Condition::all();
+    if let Some(language) = language {
+        let language = crate::scanner::normalize_language(language)
+            .ok_or_else(|| ApiError::invalid_field("language", "must be a 2 or 3 letter ISO 639 code such as jpn"))?;
+        condition = condition.add(track::Column::Language.eq(language));
+    }
+    if let Some(country) = country {
+        let country = crate::scanner::normalize_country(country)
+            .ok_or_else(|| ApiError::invalid_field("country", "must be a 2 letter ISO 3166 code such as JP"))?;
+        condition = condition.add(track::Column::ReleaseCountry.eq(country));
+    }
+    Ok(condition)
 }
 
+// Rounds a track's year down to its decade; floor division so 5 BC is in the -10s
+const DECADE_EXPR: &str = r#"(FLOOR("track"."year" / 10.0) * 10)::integer"#;
+
 // GET /tracks/search - Search tracks
+#[utoipa::path(
+    get,
+    path = "/tracks/search",
+    tag = "tracks",
+    params(
+        ("q" = String, Query, description = "Search query: plain text, or field filters with AND, OR, NOT and parentheses, e.g. artist:\"Miles Davis\" AND year:1959 NOT album:live"),
+        ("page" = Option<u64>, Query, description = "Page number (default: 1)"),
+        ("per_page" = Option<u64>, Query, description = "Items per page (default: 20, max: 100)"),
+        ("created_after" = Option<String>, Query, description = "Only tracks added to the library on or after this date"),
+        ("created_before" = Option<String>, Query, description = "Only tracks added to the library before this date"),
+        ("modified_after" = Option<String>, Query, description = "Only tracks modified on or after this date"),
+        ("decade" = Option<i32>, Query, description = "Only tracks from this decade, given as its first year (e.g. 1990)"),
+        ("language" = Option<String>, Query, description = "Only tracks in this ISO 639 language, e.g. jpn"),
+        ("country" = Option<String>, Query, description = "Only tracks released in this ISO 3166 country, e.g. JP"),
+        ("group_duplicates" = Option<bool>, Query, description = "List copies of a song in several formats once, with the others as alternates"),
+        ("prefer" = Option<String>, Query, description = "With group_duplicates, comma separated extensions to list first, e.g. mp3,m4a (default: the highest quality copy)"),
+        MediaTypeQuery,
+        VisibilityQuery,
+    ),
+    responses(
+        (status = 200, description = "Page of matching tracks with per-decade, language and country counts", body = TrackListResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 async fn search_tracks(
     State(state): State<AppState>,
-    Query(params): Query<HashMap<String, String>>,
-) -> Result<Json<TrackListResponse>, StatusCode> {
+    ApiQuery(params): ApiQuery<HashMap<String, String>>,
+    media_types: MediaTypes,
+    visibility: Visibility,
+) -> ApiResult<Json<TrackListResponse>> {
     let search_term = params.get("q").cloned().unwrap_or_default();
-    let page = params.get("page").and_then(|p| p.parse().ok()).unwrap_or(1);
-    let per_page = params.get("per_page").and_then(|p| p.parse().ok()).unwrap_or(20).min(100);
+    let page = params.get("page")
+        .map(|p| p.parse().map_err(|_| ApiError::invalid_field("page", "must be a positive integer")))
+        .transpose()?;
+    let per_page = params.get("per_page")
+        .map(|p| p.parse().map_err(|_| ApiError::invalid_field("per_page", "must be a positive integer")))
+        .transpose()?;
+    let (page, per_page) = pagination(page, per_page)?;
 
     if search_term.is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
+        return Err(ApiError::invalid_field("q", "search query is required"));
     }
 
-    let condition = Condition::any()
-        .add(track::Column::Title.contains(&search_term))
-        .add(track::Column::Artist.contains(&search_term))
-        .add(track::Column::Album.contains(&search_term))
-        .add(track::Column::Genre.contains(&search_term))
-        .add(track::Column::AlbumArtist.contains(&search_term));
-
-    let query = Track::find().filter(condition);
-
-    let total = query.clone().count(&state.db).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let total_pages = (total + per_page - 1) / per_page;
-
-    let tracks = query
-        .order_by_asc(track::Column::Artist)
-        .order_by_asc(track::Column::Album)
-        .order_by_asc(track::Column::Title)
-        .paginate(&state.db, per_page)
-        .fetch_page(page - 1)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    let condition = SearchQuery::parse(&search_term)?.condition(state.text_normalization);
+
+    let date_filter = date_condition(
+        params.get("created_after").map(String::as_str),
+        params.get("created_before").map(String::as_str),
+        params.get("modified_after").map(String::as_str),
+    )?;
+
+    let decade = params.get("decade")
+        .map(|d| d.parse().map_err(|_| ApiError::invalid_field("decade", "must be a year such as 1990")))
+        .transpose()?
+        .map(decade_range)
+        .transpose()?;
+
+    let query = Track::find()
+        .filter(condition)
+        .filter(date_filter)
+        .filter(media_types.condition())
+        .filter(visibility.condition());
+
+    let language_country = language_country_condition(
+        params.get("language").map(String::as_str),
+        params.get("country").map(String::as_str),
+    )?;
+
+    // Facets cover every match before the decade, language and country
+    // filters, so clients can offer the others to switch to
+    let languages = query.clone()
+        .select_only()
+        .column(track::Column::Language)
+        .column_as(track::Column::Id.count(), "track_count")
+        .filter(track::Column::Language.is_not_null())
+        .group_by(track::Column::Language)
+        .order_by_desc(track::Column::Id.count())
+        .order_by_asc(track::Column::Language)
+        .into_tuple::<(String, i64)>()
+        .all(&state.db)
+        .await?
         .into_iter()
-        .map(TrackResponse::from)
+        .map(|(language, track_count)| LanguageFacet { language, track_count })
+        .collect();
+    let countries = query.clone()
+        .select_only()
+        .column(track::Column::ReleaseCountry)
+        .column_as(track::Column::Id.count(), "track_count")
+        .filter(track::Column::ReleaseCountry.is_not_null())
+        .group_by(track::Column::ReleaseCountry)
+        .order_by_desc(track::Column::Id.count())
+        .order_by_asc(track::Column::ReleaseCountry)
+        .into_tuple::<(String, i64)>()
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|(country, track_count)| CountryFacet { country, track_count })
+        .collect();
+    let decades = query.clone()
+        .select_only()
+        .column_as(Expr::cust(DECADE_EXPR), "decade")
+        .column_as(track::Column::Id.count(), "track_count")
+        .filter(track::Column::Year.is_not_null())
+        .group_by(Expr::cust(DECADE_EXPR))
+        .order_by_asc(Expr::cust(DECADE_EXPR))
+        .into_tuple::<(i32, i64)>()
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|(decade, track_count)| DecadeFacet { decade, track_count })
         .collect();
+    let query = match decade {
+        Some((from, to)) => query.filter(track::Column::Year.between(from, to)),
+        None => query,
+    };
+    let query = query.filter(language_country);
+
+    let (tracks, total) = if params.get("group_duplicates").is_some_and(|group| group == "true") {
+        let preference = FormatPreference::parse(params.get("prefer").map(String::as_str));
+        grouped_page(&state.db, query, &preference, page, per_page).await?
+    } else {
+        let total = query.clone().count(&state.db).await?;
+        let tracks = order_tracks(query)
+            .paginate(&state.db, per_page)
+            .fetch_page(page - 1)
+            .await?
+            .into_iter()
+            .map(TrackResponse::from)
+            .collect();
+        (tracks, total)
+    };
+    let total_pages = total.div_ceil(per_page);
 
     Ok(Json(TrackListResponse {
         tracks,
@@ -451,50 +1302,64 @@ async fn search_tracks(
         page,
         per_page,
         total_pages,
+        decades: Some(decades),
+        languages: Some(languages),
+        countries: Some(countries),
     }))
 }
 
 // GET /stats - Get database statistics
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "library",
+    params(VisibilityQuery),
+    responses(
+        (status = 200, description = "Library statistics", body = TrackStatsResponse),
+    )
+)]
 async fn get_stats(
     State(state): State<AppState>,
-) -> Result<Json<TrackStatsResponse>, StatusCode> {
+    visibility: Visibility,
+) -> ApiResult<Json<TrackStatsResponse>> {
     let total_tracks = Track::find()
+        .filter(visibility.condition())
         .count(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
 
     let total_duration: Option<i64> = Track::find()
         .select_only()
         .column_as(track::Column::DurationSeconds.sum(), "total_duration")
+        .filter(visibility.condition())
         .into_tuple::<Option<i64>>()
         .one(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .await?
         .flatten();
 
-    let unique_artists = Track::find()
+    let unique_artists = TrackArtist::find()
         .select_only()
-        .column(track::Column::Artist)
+        .column(track_artist::Column::Artist)
         .distinct()
+        .join(JoinType::InnerJoin, track_artist::Relation::Track.def())
+        .filter(visibility.condition())
         .count(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
 
     let unique_albums = Track::find()
         .select_only()
         .column(track::Column::Album)
         .distinct()
+        .filter(visibility.condition())
         .count(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
 
     let unique_genres = Track::find()
         .select_only()
         .column(track::Column::Genre)
         .distinct()
+        .filter(visibility.condition())
         .count(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
 
     Ok(Json(TrackStatsResponse {
         total_tracks,
@@ -505,89 +1370,451 @@ async fn get_stats(
     }))
 }
 
-// GET /artists - Get list of unique artists
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CountsQuery {
+    pub counts: Option<bool>,
+    pub if_modified_since: Option<i64>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ArtistQuery {
+    pub counts: Option<bool>,
+    pub if_modified_since: Option<i64>,
+    /// Page number; with page or per_page, a page of artists with counts is returned
+    pub page: Option<u64>,
+    /// Artists per page (default: 20, max: 100)
+    pub per_page: Option<u64>,
+    /// name (the default), added (newest first), albums or tracks (most first)
+    pub sort: Option<String>,
+    /// Only artists whose name contains this, ignoring case and accents
+    pub name: Option<String>,
+}
+
+impl ArtistQuery {
+    fn paged(&self) -> bool {
+        self.page.is_some() || self.per_page.is_some()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ArtistPage {
+    pub artists: Vec<ArtistCount>,
+    pub total: u64,
+    pub page: u64,
+    pub per_page: u64,
+    pub total_pages: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum ArtistListResponse {
+    Names(Vec<String>),
+    Counts(Vec<ArtistCount>),
+    Page(ArtistPage),
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct IndexesQuery {
+    /// Milliseconds since the epoch; 304 when the library hasn't changed since
+    pub if_modified_since: Option<i64>,
+    /// Most loose files to list as children
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ArtistIndexResponse {
+    /// Space separated articles skipped at the start of artist names
+    pub ignored_articles: String,
+    /// Artists pinned above the index with INDEX_SHORTCUTS
+    pub shortcuts: Vec<ArtistCount>,
+    pub indexes: Vec<ArtistIndex>,
+    /// Files directly in a library folder, not in any artist or album folder
+    pub children: Vec<TrackResponse>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum GenreListResponse {
+    Names(Vec<String>),
+    Counts(Vec<GenreCount>),
+}
+
+// Helper function to get when the library last changed: the newest track
+// modification or the end of the last scan that wrote tracks, whichever is later.
+// Truncated to seconds to match HTTP dates.
+async fn library_last_modified(state: &AppState) -> ApiResult<Option<DateTime<Utc>>> {
+    let newest_track: Option<DateTime<Utc>> = Track::find()
+        .select_only()
+        .column_as(track::Column::Modified.max(), "modified")
+        .into_tuple::<Option<DateTime<Utc>>>()
+        .one(&state.db)
+        .await?
+        .flatten();
+
+    let last_modified = newest_track.max(state.scans.last_library_change());
+    Ok(last_modified.and_then(|time| DateTime::from_timestamp(time.timestamp(), 0)))
+}
+
+// Helper function to check If-Modified-Since (HTTP date header) or the
+// if_modified_since query parameter (milliseconds since the epoch)
+fn not_modified_since(headers: &HeaderMap, if_modified_since: Option<i64>, last_modified: DateTime<Utc>) -> bool {
+    let since = if_modified_since
+        .and_then(DateTime::from_timestamp_millis)
+        .or_else(|| {
+            headers.get(header::IF_MODIFIED_SINCE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+                .map(|time| time.with_timezone(&Utc))
+        });
+
+    since.map(|since| last_modified <= since).unwrap_or(false)
+}
+
+// GET /artists - Get list of unique artists, optionally with album/song counts (?counts=true).
+// Returns 304 Not Modified when the library hasn't changed since If-Modified-Since.
+#[utoipa::path(
+    get,
+    path = "/artists",
+    tag = "library",
+    params(ArtistQuery, MediaTypeQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Artist names, artists with counts when counts=true, or a page of them with page or per_page", body = ArtistListResponse),
+        (status = 304, description = "The library hasn't changed since If-Modified-Since"),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 async fn get_artists(
     State(state): State<AppState>,
-) -> Result<Json<Vec<String>>, StatusCode> {
-    let artists: Vec<String> = Track::find()
-        .select_only()
-        .column(track::Column::Artist)
-        .distinct()
-        .filter(track::Column::Artist.ne(""))
-        .order_by_asc(track::Column::Artist)
-        .into_tuple()
-        .all(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    ApiQuery(params): ApiQuery<ArtistQuery>,
+    media_types: MediaTypes,
+    visibility: Visibility,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    let last_modified = library_last_modified(&state).await?;
+    if let Some(last_modified) = last_modified {
+        if not_modified_since(&headers, params.if_modified_since, last_modified) {
+            return Ok(StatusCode::NOT_MODIFIED.into_response());
+        }
+    }
+
+    let narrowed = params.paged() || params.sort.is_some() || params.name.is_some();
+    let artists = if params.counts.unwrap_or(false) || narrowed {
+        let mut artists = state.counts
+            .artists(&state.db, &visibility, &media_types, state.text_normalization, &state.ignored_articles)
+            .await?
+            .as_ref()
+            .clone();
+        narrow_artists(&params, state.text_normalization, &mut artists)?;
+        if params.paged() {
+            let (page, per_page) = pagination(params.page, params.per_page)?;
+            let (artists, total, total_pages) = paginate(artists, page, per_page);
+            ArtistListResponse::Page(ArtistPage { artists, total, page, per_page, total_pages })
+        } else if params.counts.unwrap_or(false) {
+            ArtistListResponse::Counts(artists)
+        } else {
+            ArtistListResponse::Names(artists.into_iter().map(|artist| artist.name).collect())
+        }
+    } else {
+        let mut artists: Vec<String> = TrackArtist::find()
+            .select_only()
+            .column(track_artist::Column::Artist)
+            .distinct()
+            .join(JoinType::InnerJoin, track_artist::Relation::Track.def())
+            .filter(visibility.condition())
+            .filter(media_types.condition())
+            .into_tuple()
+            .all(&state.db)
+            .await?;
+        browse::sort_artist_names(&mut artists, state.text_normalization, &state.ignored_articles);
+        ArtistListResponse::Names(artists)
+    };
+
+    Ok(with_last_modified(Json(artists).into_response(), last_modified))
+}
+
+// Helper function to filter and sort artists as the query asks. Without a
+// sort they stay ordered by name, ignoring leading articles.
+fn narrow_artists(params: &ArtistQuery, normalization: TextNormalization, artists: &mut Vec<ArtistCount>) -> ApiResult<()> {
+    if let Some(name) = params.name.as_deref() {
+        let name = normalization.search_key(name);
+        artists.retain(|artist| normalization.search_key(&artist.name).contains(name.as_str()));
+    }
+    match params.sort.as_deref() {
+        None | Some("name") => {}
+        // Newest and largest first
+        Some("added") => artists.sort_by_key(|artist| Reverse(artist.added)),
+        Some("albums") => artists.sort_by_key(|artist| Reverse(artist.album_count)),
+        Some("tracks") => artists.sort_by_key(|artist| Reverse(artist.song_count)),
+        Some(_) => return Err(ApiError::invalid_field("sort", "must be name, added, albums or tracks")),
+    }
+    Ok(())
+}
+
+// Helper function to set Last-Modified on a library listing
+fn with_last_modified(mut response: Response, last_modified: Option<DateTime<Utc>>) -> Response {
+    if let Some(last_modified) = last_modified {
+        let value = last_modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        if let Ok(value) = header::HeaderValue::from_str(&value) {
+            response.headers_mut().insert(header::LAST_MODIFIED, value);
+        }
+    }
+    response
+}
+
+// GET /indexes - Artists grouped by first letter, ignoring leading articles like "The",
+// with shortcut artists and the files directly in library folders.
+// Returns 304 Not Modified when the library hasn't changed since If-Modified-Since.
+#[utoipa::path(
+    get,
+    path = "/indexes",
+    tag = "library",
+    params(IndexesQuery, MediaTypeQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Artists grouped by first letter", body = ArtistIndexResponse),
+        (status = 304, description = "The library hasn't changed since If-Modified-Since"),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_artist_indexes(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<IndexesQuery>,
+    media_types: MediaTypes,
+    visibility: Visibility,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    let last_modified = library_last_modified(&state).await?;
+    if let Some(last_modified) = last_modified {
+        if not_modified_since(&headers, params.if_modified_since, last_modified) {
+            return Ok(StatusCode::NOT_MODIFIED.into_response());
+        }
+    }
+
+    let artists = state.counts
+        .artists(&state.db, &visibility, &media_types, state.text_normalization, &state.ignored_articles)
+        .await?;
+    let children = browse::root_tracks(&state.db, visibility.condition().add(media_types.condition()), params.limit).await?;
+    let response = ArtistIndexResponse {
+        ignored_articles: state.ignored_articles.list(),
+        shortcuts: browse::index_shortcuts(&artists),
+        indexes: browse::artist_index(&artists, state.text_normalization, &state.ignored_articles),
+        children: children.into_iter().map(TrackResponse::from).collect(),
+    };
+    Ok(with_last_modified(Json(response).into_response(), last_modified))
+}
 
-    Ok(Json(artists))
+#[derive(Serialize, ToSchema)]
+pub struct ArtistAlbum {
+    pub id: String,
+    pub name: String,
+    pub album_artist: String,
+    pub year: Option<i32>,
+    pub track_count: i64,
+    /// Track to fetch album art from with /tracks/:id/albumart
+    pub art_track_id: Option<i32>,
+    /// album, ep, single, live or compilation
+    pub release_type: String,
+    /// Formats of the artist's tracks on the album
+    pub format: FormatSummary,
 }
 
-// GET /albums - Get list of unique albums
-async fn get_albums(
+#[derive(Serialize, ToSchema)]
+pub struct ArtistResponse {
+    pub id: String,
+    pub name: String,
+    pub song_count: i64,
+    /// Formats of all the artist's tracks
+    pub format: FormatSummary,
+    pub albums: Vec<ArtistAlbum>,
+}
+
+// Helper function to select the IDs of every track crediting an artist, including collaborations
+fn credited_tracks(artist: &str) -> sea_query::SelectStatement {
+    TrackArtist::find()
+        .select_only()
+        .column(track_artist::Column::TrackId)
+        .filter(track_artist::Column::Artist.eq(artist))
+        .into_query()
+}
+
+// GET /artists/:id - Get an artist by slug (or legacy URL-encoded name) with their albums
+#[utoipa::path(
+    get,
+    path = "/artists/{id}",
+    tag = "library",
+    params(("id" = String, Path, description = "Artist slug or URL-encoded name"), ReleaseTypeQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Artist with albums", body = ArtistResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Artist not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_artist(
     State(state): State<AppState>,
-) -> Result<Json<Vec<String>>, StatusCode> {
-    let albums: Vec<String> = Track::find()
+    ApiPath(id): ApiPath<String>,
+    release_types: ReleaseTypes,
+    visibility: Visibility,
+) -> ApiResult<Json<ArtistResponse>> {
+    let name = slugs::resolve(&state.db, slugs::ARTIST, &id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Artist {} not found", id)))?;
+
+    let rows: Vec<(String, String, Option<i32>, i64)> = Track::find()
         .select_only()
+        .column(track::Column::AlbumArtist)
         .column(track::Column::Album)
-        .distinct()
-        .filter(track::Column::Album.ne(""))
+        .column_as(track::Column::Year.min(), "year")
+        .column_as(track::Column::Id.count(), "track_count")
+        .filter(track::Column::Id.in_subquery(credited_tracks(&name)))
+        .filter(visibility.condition())
+        .group_by(track::Column::AlbumArtist)
+        .group_by(track::Column::Album)
         .order_by_asc(track::Column::Album)
         .into_tuple()
         .all(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
+
+    let album_slugs = slugs::slugs_by_key(&state.db, slugs::ALBUM).await?;
+    let stored_albums = albums::albums_by_key(&state.db).await?;
+    let album_formats = albums::album_formats(
+        &state.db,
+        visibility.condition().add(track::Column::Id.in_subquery(credited_tracks(&name))),
+    )
+    .await?;
+    let mut format = FormatSummaryBuilder::default();
+    for album_format in album_formats.values() {
+        format.merge(album_format);
+    }
+    let song_count = rows.iter().map(|(_, _, _, count)| count).sum();
+    let albums = rows.into_iter()
+        .map(|(album_artist, album, year, track_count)| {
+            let key = albums::album_key(&album_artist, &album);
+            let stored = stored_albums.get(&key);
+            ArtistAlbum {
+                id: album_slugs.get(&key)
+                    .cloned()
+                    .unwrap_or_else(|| albums::album_id(&album_artist, &album)),
+                art_track_id: stored.and_then(|album| album.art_track_id),
+                release_type: stored.map_or(release_types::ALBUM, |album| album.release_type.as_str()).to_string(),
+                format: album_formats.get(&key).map(FormatSummaryBuilder::build).unwrap_or_default(),
+                name: albums::album_name(&album).to_string(),
+                album_artist,
+                year,
+                track_count,
+            }
+        })
+        .filter(|album| release_types.includes(&album.release_type))
+        .collect();
+
+    let id = slugs::slug_for(&state.db, slugs::ARTIST, &name).await?.unwrap_or(id);
 
-    Ok(Json(albums))
+    Ok(Json(ArtistResponse {
+        id,
+        name,
+        song_count,
+        format: format.build(),
+        albums,
+    }))
 }
 
-// GET /genres - Get list of unique genres
+// GET /genres - Get list of unique genres, optionally with song/album counts (?counts=true)
+#[utoipa::path(
+    get,
+    path = "/genres",
+    tag = "library",
+    params(CountsQuery, MediaTypeQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Genre names, or genres with counts when counts=true", body = GenreListResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 async fn get_genres(
     State(state): State<AppState>,
-) -> Result<Json<Vec<String>>, StatusCode> {
+    ApiQuery(params): ApiQuery<CountsQuery>,
+    media_types: MediaTypes,
+    visibility: Visibility,
+) -> ApiResult<Json<GenreListResponse>> {
+    if params.counts.unwrap_or(false) {
+        let genres = state.counts
+            .genres(&state.db, &visibility, &media_types)
+            .await?;
+        return Ok(Json(GenreListResponse::Counts(genres.as_ref().clone())));
+    }
+
     let genres: Vec<String> = Track::find()
         .select_only()
         .column(track::Column::Genre)
         .distinct()
         .filter(track::Column::Genre.ne(""))
+        .filter(visibility.condition())
+        .filter(media_types.condition())
         .order_by_asc(track::Column::Genre)
         .into_tuple()
         .all(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .await?;
 
-    Ok(Json(genres))
+    Ok(Json(GenreListResponse::Names(genres)))
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct RescanResponse {
     pub message: String,
     pub status: String,
 }
 
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RescanQuery {
+    /// Also decode FLAC files changed since they were last verified to check
+    /// their audio checksums (slow)
+    #[serde(default)]
+    pub deep: bool,
+}
+
 // POST /rescan - Trigger a rescan of the music library
+#[utoipa::path(
+    post,
+    path = "/rescan",
+    tag = "scans",
+    params(RescanQuery),
+    responses(
+        (status = 200, description = "Rescan started", body = RescanResponse),
+        (status = 409, description = "A scan is already running", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 async fn rescan_library(
     State(state): State<AppState>,
-) -> Result<Json<RescanResponse>, StatusCode> {
+    ApiQuery(params): ApiQuery<RescanQuery>,
+) -> ApiResult<Json<RescanResponse>> {
+    if state.scans.is_running() {
+        return Err(ApiError::conflict("A scan is already running"));
+    }
+
     let music_path = state.music_path.clone();
     let db = state.db.clone();
+    let counts = state.counts.clone();
+    let scans = state.scans.clone();
+    let webhooks = state.webhooks.clone();
 
     tokio::spawn(async move {
         let scan_config = crate::scanner::ScanConfig {
             music_path,
             show_progress: true,
             batch_size: 100,
-            path_batch_size: 1000,
-            use_optimized_scanning: true,
+            verify_audio: params.deep,
         };
 
-        match crate::scanner::scan_music_library(&db, scan_config).await {
-            Ok(_result) => {
-                // Scan completion is now logged inside the scanner module
-            }
-            Err(e) => {
+        // Scan completion is logged inside the scanner module
+        match scheduler::run_scan(&db, scan_config, "manual", &scans, &counts, &webhooks).await {
+            Some(Ok(_result)) => {}
+            Some(Err(e)) => {
                 error!("Error during rescan: {:?}", e);
             }
+            None => {
+                info!("Skipping rescan, a scan is already running");
+            }
         }
     });
 
@@ -596,3 +1823,278 @@ async fn rescan_library(
         status: "success".to_string(),
     }))
 }
+
+/// Files to re-read: a directory or an album
+#[derive(Deserialize, ToSchema)]
+pub struct RescanPathRequest {
+    /// Directory to re-read with its subdirectories, absolute or relative to MUSIC_PATH
+    pub path: Option<String>,
+    /// Album slug or ID, as for /albums/{id}/tracks, to re-read the album's files
+    pub album: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RescanPathResponse {
+    pub scan_id: String,
+    pub files_scanned: u64,
+    pub tracks_processed: usize,
+    /// Files that couldn't be read, listed by /rescan/errors?scan_id=
+    pub errors_recorded: usize,
+    /// The re-read tracks as they are now
+    pub tracks: Vec<TrackResponse>,
+}
+
+// POST /rescan/path - Re-read one directory or album right away
+#[utoipa::path(
+    post,
+    path = "/rescan/path",
+    tag = "scans",
+    request_body = RescanPathRequest,
+    responses(
+        (status = 200, description = "Files re-read, with the updated tracks", body = RescanPathResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Directory or album not found", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "A scan is already running", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn rescan_path(
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<RescanPathRequest>,
+) -> ApiResult<Json<RescanPathResponse>> {
+    if state.scans.is_running() {
+        return Err(ApiError::conflict("A scan is already running"));
+    }
+
+    let (mut files, known, scope) = match (request.path.as_deref(), request.album.as_deref()) {
+        (Some(path), None) => {
+            let directory = hidden::resolve_directory(&state.music_path, path)
+                .ok_or_else(|| ApiError::invalid_field("path", "must not be empty or contain .. segments"))?;
+            let in_library = storage::library_roots(&state.music_path).iter()
+                .any(|root| paths::same(&directory, root) || paths::strip_root(&directory, root).is_some());
+            if !in_library {
+                return Err(ApiError::invalid_field("path", "must be inside the music library"));
+            }
+
+            let files = match scanner::list_directory(&state.storage, &directory).await {
+                Ok(files) => files,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(ApiError::not_found(format!("Directory {} not found", directory)));
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let scope = hidden::inside_directory(&directory);
+            let known = Track::find().filter(scope.clone()).all(&state.db).await?;
+            (files, known, scope)
+        }
+        (None, Some(album)) => {
+            let album = albums::find_album(&state, album, &Visibility::default()).await?;
+            let ids: Vec<i32> = album.tracks.iter().map(|track| track.id).collect();
+            let mut files = Vec::with_capacity(album.tracks.len());
+            for track in &album.tracks {
+                // Files that are gone are marked missing by the scan
+                if let Ok(entry) = state.storage.stat(&track.path).await {
+                    files.push(entry);
+                }
+            }
+            let known = Track::find().filter(track::Column::Id.is_in(ids.clone())).all(&state.db).await?;
+            (files, known, track::Column::Id.is_in(ids))
+        }
+        _ => return Err(ApiError::invalid_field("path", "give either path or album")),
+    };
+    let in_progress = InProgressFilter::from_env();
+    files.retain(|entry| !in_progress.is_in_progress(entry));
+
+    let result = match scheduler::run_file_scan(&state.db, &state.music_path, files, &known, &state.scans, &state.counts, &state.webhooks).await {
+        Some(Ok(result)) => result,
+        Some(Err(e)) => return Err(ApiError::internal(format!("Rescan failed: {}", e))),
+        None => return Err(ApiError::conflict("A scan is already running")),
+    };
+
+    // New files under the directory are picked up by its path; an album's
+    // tracks keep their IDs even when their album tags changed
+    let tracks = Track::find()
+        .filter(scope)
+        .filter(track::Column::MissingSince.is_null())
+        .order_by_asc(track::Column::Path)
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(TrackResponse::from)
+        .collect();
+
+    Ok(Json(RescanPathResponse {
+        scan_id: result.scan_id,
+        files_scanned: result.files_scanned,
+        tracks_processed: result.tracks_processed,
+        errors_recorded: result.errors_recorded,
+        tracks,
+    }))
+}
+
+// GET /rescan/status - Get the current scan state, last result and next scheduled run
+#[utoipa::path(
+    get,
+    path = "/rescan/status",
+    tag = "scans",
+    responses(
+        (status = 200, description = "Scan state", body = ScanStatus),
+    )
+)]
+async fn get_scan_status(State(state): State<AppState>) -> Json<ScanStatus> {
+    Json(state.scans.status())
+}
+
+// GET /admin/sessions - List active streaming sessions
+#[utoipa::path(
+    get,
+    path = "/admin/sessions",
+    tag = "sessions",
+    responses(
+        (status = 200, description = "Active stream sessions", body = Vec<SessionInfo>),
+    )
+)]
+async fn get_sessions(State(state): State<AppState>) -> Json<Vec<SessionInfo>> {
+    Json(state.sessions.list())
+}
+
+// DELETE /admin/sessions/:id - Terminate an active streaming session
+#[utoipa::path(
+    delete,
+    path = "/admin/sessions/{id}",
+    tag = "sessions",
+    params(("id" = String, Path, description = "Session ID")),
+    responses(
+        (status = 204, description = "Session terminated"),
+        (status = 404, description = "Session not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn terminate_session(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+) -> ApiResult<StatusCode> {
+    if state.sessions.terminate(&id) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::not_found(format!("Session {} not found", id)))
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ScanErrorQuery {
+    pub page: Option<u64>,
+    pub per_page: Option<u64>,
+    pub path: Option<String>,
+    pub kind: Option<String>,
+    pub scan_id: Option<String>,
+    pub since: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ScanErrorResponse {
+    pub id: i32,
+    pub path: String,
+    pub kind: String,
+    pub message: String,
+    pub scan_id: String,
+    pub occurred: DateTime<Utc>,
+    /// Scans in a row the file has failed in
+    pub attempts: i32,
+    /// When a scan reads the file again; null for errors that aren't
+    /// retried, or once the file has failed SCAN_RETRY_ATTEMPTS retries
+    pub next_retry_at: Option<DateTime<Utc>>,
+}
+
+impl From<scan_error::Model> for ScanErrorResponse {
+    fn from(model: scan_error::Model) -> Self {
+        Self {
+            id: model.id,
+            path: model.path,
+            kind: model.kind,
+            message: model.message,
+            scan_id: model.scan_id,
+            occurred: model.occurred,
+            attempts: model.attempts,
+            next_retry_at: model.next_retry_at,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ScanErrorListResponse {
+    pub errors: Vec<ScanErrorResponse>,
+    pub total: u64,
+    pub page: u64,
+    pub per_page: u64,
+    pub total_pages: u64,
+}
+
+// GET /rescan/errors - List files that failed to scan, most recent first
+#[utoipa::path(
+    get,
+    path = "/rescan/errors",
+    tag = "scans",
+    params(ScanErrorQuery),
+    responses(
+        (status = 200, description = "Page of scan errors", body = ScanErrorListResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_scan_errors(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<ScanErrorQuery>,
+) -> ApiResult<Json<ScanErrorListResponse>> {
+    let (page, per_page) = pagination(params.page, params.per_page)?;
+
+    let mut condition = Condition::all();
+    if let Some(path) = params.path {
+        condition = condition.add(scan_error::Column::Path.contains(&path));
+    }
+    if let Some(kind) = params.kind {
+        condition = condition.add(scan_error::Column::Kind.eq(kind));
+    }
+    if let Some(scan_id) = params.scan_id {
+        condition = condition.add(scan_error::Column::ScanId.eq(scan_id));
+    }
+    if let Some(since) = params.since {
+        condition = condition.add(scan_error::Column::Occurred.gte(parse_date_param("since", &since)?));
+    }
+
+    let query = ScanError::find().filter(condition);
+
+    let total = query.clone().count(&state.db).await?;
+    let total_pages = total.div_ceil(per_page);
+
+    let errors = query
+        .order_by_desc(scan_error::Column::Occurred)
+        .order_by_asc(scan_error::Column::Path)
+        .paginate(&state.db, per_page)
+        .fetch_page(page - 1)
+        .await?
+        .into_iter()
+        .map(ScanErrorResponse::from)
+        .collect();
+
+    Ok(Json(ScanErrorListResponse {
+        errors,
+        total,
+        page,
+        per_page,
+        total_pages,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decade_range;
+
+    #[test]
+    fn decade_ranges() {
+        assert_eq!(decade_range(1990).ok(), Some((1990, 1999)));
+        assert_eq!(decade_range(-10).ok(), Some((-10, -1)));
+        assert!(decade_range(1995).is_err());
+        // The last decade that fits, and one whose last year doesn't
+        assert_eq!(decade_range(2147483630).ok(), Some((2147483630, 2147483639)));
+        assert!(decade_range(2147483640).is_err());
+    }
+}
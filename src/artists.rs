@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::env;
+
+use log::info;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, NotSet, QueryFilter, QueryOrder, QuerySelect};
+
+use entity::prelude::{Track, TrackArtist};
+use entity::{track, track_artist};
+
+/// Words and symbols that join collaborating artists, e.g. "A feat. B" or "A & B"
+const DEFAULT_SEPARATORS: &[&str] = &["feat.", "ft.", "featuring", "&"];
+
+/// Names that contain a separator but are a single act
+const DEFAULT_EXCEPTIONS: &[&str] = &[
+    "Simon & Garfunkel",
+    "Earth, Wind & Fire",
+    "Hall & Oates",
+    "Daryl Hall & John Oates",
+    "Belle & Sebastian",
+    "Mumford & Sons",
+    "Echo & the Bunnymen",
+    "Sly & the Family Stone",
+    "Crosby, Stills, Nash & Young",
+    "Peter, Paul & Mary",
+    "Kool & the Gang",
+    "Florence & the Machine",
+];
+
+// Marks split points and masked exceptions while splitting; these private-use
+// characters never appear in tags
+const SPLIT_MARK: char = '\u{e000}';
+const MASK_START: char = '\u{e001}';
+const MASK_END: char = '\u{e002}';
+
+/// Splits artist tags into the individual artists they credit
+pub struct ArtistSplitter {
+    separators: Vec<Vec<char>>,
+    exceptions: Vec<String>,
+}
+
+impl Default for ArtistSplitter {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_SEPARATORS.iter().map(|s| s.to_string()).collect(),
+            DEFAULT_EXCEPTIONS.iter().map(|s| s.to_string()).collect(),
+        )
+    }
+}
+
+fn lowercase(text: &str) -> Vec<char> {
+    text.chars().flat_map(char::to_lowercase).collect()
+}
+
+impl ArtistSplitter {
+    pub fn new(separators: Vec<String>, exceptions: Vec<String>) -> Self {
+        Self {
+            separators: separators.iter()
+                .map(|separator| lowercase(separator.trim()))
+                .filter(|separator| !separator.is_empty())
+                .collect(),
+            exceptions: exceptions.into_iter()
+                .map(|exception| exception.trim().to_string())
+                .filter(|exception| !exception.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Build the splitter from ARTIST_SEPARATORS (space separated) and
+    /// ARTIST_SPLIT_EXCEPTIONS (semicolon separated), keeping defaults for unset values
+    pub fn from_env() -> Self {
+        let mut splitter = Self::default();
+        if let Ok(separators) = env::var("ARTIST_SEPARATORS") {
+            splitter.separators = Self::new(separators.split_whitespace().map(str::to_string).collect(), Vec::new()).separators;
+        }
+        if let Ok(exceptions) = env::var("ARTIST_SPLIT_EXCEPTIONS") {
+            splitter.exceptions = exceptions.split(';')
+                .map(|exception| exception.trim().to_string())
+                .filter(|exception| !exception.is_empty())
+                .collect();
+        }
+        splitter
+    }
+
+    /// Split an artist tag into its credited artists, in order. Tags are always
+    /// split on ";" and NUL (multi-value tags); configured separators must be
+    /// surrounded by spaces, except punctuation which only needs a space after
+    /// it (so "A, B" splits on "," but "AC/DC" never splits on "/"). A
+    /// separator never starts or ends a tag, so "Little Feat" stays whole.
+    pub fn split(&self, artist: &str) -> Vec<String> {
+        let artist = artist.trim();
+        if artist.is_empty() {
+            return Vec::new();
+        }
+        if self.exceptions.iter().any(|exception| exception.eq_ignore_ascii_case(artist)) {
+            return vec![artist.to_string()];
+        }
+
+        let (masked, masks) = self.mask_exceptions(artist);
+        let mut chars: Vec<char> = masked.chars()
+            .map(|c| if c == ';' || c == '\0' { SPLIT_MARK } else { c })
+            .collect();
+        for separator in &self.separators {
+            chars = replace_separator(&chars, separator);
+        }
+
+        let mut artists: Vec<String> = Vec::new();
+        for part in chars.split(|c| *c == SPLIT_MARK) {
+            let part: String = part.iter().collect();
+            let part = unmask(&clean_part(&part), &masks);
+            if !part.is_empty() && !artists.iter().any(|existing| existing.eq_ignore_ascii_case(&part)) {
+                artists.push(part);
+            }
+        }
+        artists
+    }
+
+    // Swap exception names inside a longer tag (e.g. "Simon & Garfunkel feat. X")
+    // for placeholders so their separators are left alone
+    fn mask_exceptions(&self, artist: &str) -> (String, Vec<String>) {
+        let mut masked = artist.to_string();
+        let mut masks = Vec::new();
+        for exception in &self.exceptions {
+            let lower = masked.to_lowercase();
+            // Lowercasing can change byte lengths outside ASCII, where offsets wouldn't line up
+            if lower.len() != masked.len() {
+                break;
+            }
+            if let Some(start) = lower.find(&exception.to_lowercase()) {
+                let end = start + exception.len();
+                masks.push(masked[start..end].to_string());
+                masked = format!("{}{}{}{}{}", &masked[..start], MASK_START, masks.len() - 1, MASK_END, &masked[end..]);
+            }
+        }
+        (masked, masks)
+    }
+}
+
+fn replace_separator(chars: &[char], separator: &[char]) -> Vec<char> {
+    let is_word = separator.iter().any(|c| c.is_alphanumeric());
+    let mut output = Vec::with_capacity(chars.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let end = i + separator.len();
+        let matches = i > 0
+            && end < chars.len()
+            && chars[end].is_whitespace()
+            && chars[i..end].iter().zip(separator).all(|(c, s)| c.to_lowercase().eq(std::iter::once(*s)));
+        // Words need a space (or an opening bracket, as in "Title (feat. B)") before them
+        let standalone = !is_word || (i > 0 && (chars[i - 1].is_whitespace() || matches!(chars[i - 1], '(' | '[')));
+        if matches && standalone {
+            output.push(SPLIT_MARK);
+            i = end;
+        } else {
+            output.push(chars[i]);
+            i += 1;
+        }
+    }
+    output
+}
+
+// Trim a split part, dropping brackets left over from "(feat. B)"
+fn clean_part(part: &str) -> String {
+    let mut part = part.trim();
+    loop {
+        let trimmed = part.trim_end_matches(['(', '[']).trim();
+        let trimmed = if trimmed.ends_with([')', ']']) && !trimmed.contains(['(', '[']) {
+            trimmed[..trimmed.len() - 1].trim()
+        } else {
+            trimmed
+        };
+        if trimmed == part {
+            return part.to_string();
+        }
+        part = trimmed;
+    }
+}
+
+fn unmask(part: &str, masks: &[String]) -> String {
+    let mut output = String::with_capacity(part.len());
+    let mut chars = part.chars();
+    while let Some(c) = chars.next() {
+        if c != MASK_START {
+            output.push(c);
+            continue;
+        }
+        let index: String = chars.by_ref().take_while(|c| *c != MASK_END).collect();
+        if let Some(original) = index.parse::<usize>().ok().and_then(|index| masks.get(index)) {
+            output.push_str(original);
+        }
+    }
+    output
+}
+
+/// Bring the track_artist join table up to date with each track's artist tag
+/// and the configured splitting rules. Only tracks whose credits changed are
+/// rewritten, so this is cheap after the first run.
+pub async fn refresh_track_artists(db: &DatabaseConnection, splitter: &ArtistSplitter) -> Result<usize, DbErr> {
+    let tracks: Vec<(i32, String)> = Track::find()
+        .select_only()
+        .column(track::Column::Id)
+        .column(track::Column::Artist)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let mut current: HashMap<i32, Vec<String>> = HashMap::new();
+    for row in TrackArtist::find()
+        .order_by_asc(track_artist::Column::TrackId)
+        .order_by_asc(track_artist::Column::Position)
+        .all(db)
+        .await?
+    {
+        current.entry(row.track_id).or_default().push(row.artist);
+    }
+
+    let mut changed_ids = Vec::new();
+    let mut new_rows = Vec::new();
+    for (track_id, artist) in tracks {
+        let artists = splitter.split(&artist);
+        if current.get(&track_id).map(Vec::as_slice).unwrap_or_default() == artists.as_slice() {
+            continue;
+        }
+        changed_ids.push(track_id);
+        for (position, artist) in artists.into_iter().enumerate() {
+            new_rows.push(track_artist::ActiveModel {
+                id: NotSet,
+                track_id: Set(track_id),
+                artist: Set(artist),
+                position: Set(position as i32),
+            });
+        }
+    }
+
+    for chunk in changed_ids.chunks(1000) {
+        TrackArtist::delete_many()
+            .filter(track_artist::Column::TrackId.is_in(chunk.iter().copied()))
+            .exec(db)
+            .await?;
+    }
+    for chunk in new_rows.chunks(1000) {
+        TrackArtist::insert_many(chunk.to_vec()).exec(db).await?;
+    }
+
+    if !changed_ids.is_empty() {
+        info!("Updated artist credits for {} tracks", changed_ids.len());
+    }
+    Ok(changed_ids.len())
+}
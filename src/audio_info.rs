@@ -0,0 +1,467 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use axum::{
+    extract::State,
+    response::Json,
+};
+use lofty::ape::ApeProperties;
+use lofty::config::ParseOptions;
+use lofty::error::LoftyError;
+use lofty::file::{AudioFile, TaggedFile};
+use lofty::flac::FlacProperties;
+use lofty::iff::aiff::{AiffCompressionType, AiffProperties};
+use lofty::iff::wav::{WavFormat, WavProperties};
+use lofty::mp4::{AudioObjectType, Mp4Codec, Mp4Properties};
+use lofty::mpeg::{ChannelMode, Emphasis, Layer, MpegProperties, MpegVersion};
+use lofty::ogg::{OpusProperties, VorbisProperties};
+use lofty::properties::ChannelMask;
+use lofty::wavpack::WavPackProperties;
+use sea_orm::{EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use entity::prelude::Track;
+
+use crate::api::AppState;
+use crate::error::{ApiError, ApiPath, ApiResult};
+use crate::hidden::{Visibility, VisibilityQuery};
+
+// Speaker names for the bits of a WAVEFORMATEXTENSIBLE channel mask, lowest first
+const SPEAKERS: &[&str] = &[
+    "FL", "FR", "FC", "LFE", "BL", "BR", "FLC", "FRC", "BC",
+    "SL", "SR", "TC", "TFL", "TFC", "TFR", "TBL", "TBC", "TBR",
+];
+// How far past the ID3v2 tag the first MPEG frame is looked for
+const MPEG_SEARCH_SIZE: u64 = 16 * 1024;
+
+/// Technical details of a file's audio stream beyond the basic properties,
+/// read during the scan. Fields a format doesn't have are null.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct AudioInfo {
+    /// e.g. "MP3", "AAC", "ALAC", "FLAC", "Opus", "PCM"
+    pub codec: Option<String>,
+    /// Codec version or profile, e.g. "MPEG-1 Layer III", "AAC LC", "HE-AAC"
+    pub codec_profile: Option<String>,
+    pub lossless: Option<bool>,
+    /// MPEG channel mode: "stereo", "joint stereo", "dual channel" or "mono"
+    pub channel_mode: Option<String>,
+    /// Speakers the channels are mapped to, e.g. "FL FR FC LFE BL BR"
+    pub channel_layout: Option<String>,
+    /// Encoder named in the tags, or in the LAME header
+    pub encoder: Option<String>,
+    /// Encoder settings named in the tags
+    pub encoder_settings: Option<String>,
+    /// MPEG de-emphasis: "50/15 ms" or "CCIT J.17"
+    pub emphasis: Option<String>,
+    pub copyright: Option<bool>,
+    pub original: Option<bool>,
+    /// Vorbis bitrate bounds from the stream header, in kbps
+    pub nominal_bitrate: Option<i32>,
+    pub min_bitrate: Option<i32>,
+    pub max_bitrate: Option<i32>,
+    /// Sample rate of the audio before Opus encoded it
+    pub input_sample_rate: Option<u32>,
+    pub drm_protected: Option<bool>,
+    /// The Xing/Info and LAME headers of an MP3 file
+    pub lame: Option<LameHeader>,
+}
+
+/// What the encoder recorded in an MP3 file's first frame
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct LameHeader {
+    /// "Xing" for VBR files, "Info" for CBR
+    pub header: String,
+    pub frames: Option<u32>,
+    pub bytes: Option<u32>,
+    /// VBR quality indicator, 0 (best) to 100
+    pub quality: Option<u32>,
+    /// e.g. "LAME3.100"
+    pub encoder: Option<String>,
+    /// "cbr", "abr", "vbr-old", "vbr-mtrh", "vbr-mt", "cbr 2-pass" or "abr 2-pass"
+    pub vbr_method: Option<String>,
+    pub lowpass_hz: Option<u32>,
+    /// Samples of encoder delay at the start and padding at the end, which
+    /// gapless players trim
+    pub encoder_delay: Option<u16>,
+    pub encoder_padding: Option<u16>,
+    /// e.g. "V0", "V2", "extreme", "ABR 192"
+    pub preset: Option<String>,
+}
+
+/// Format-specific audio properties that go into an AudioInfo
+pub trait Describe {
+    fn describe(&self) -> AudioInfo;
+}
+
+/// Read a file of a known format, with what its audio properties say
+pub fn read_described<F, R>(reader: &mut R) -> Result<(TaggedFile, AudioInfo), LoftyError>
+where
+    F: AudioFile + Into<TaggedFile>,
+    F::Properties: Describe,
+    R: Read + Seek,
+{
+    let file = F::read_from(reader, ParseOptions::new())?;
+    let info = file.properties().describe();
+    Ok((file.into(), info))
+}
+
+fn channel_layout(mask: ChannelMask) -> Option<String> {
+    let speakers: Vec<&str> = SPEAKERS.iter()
+        .enumerate()
+        .filter(|(bit, _)| mask.bits() & (1 << bit) != 0)
+        .map(|(_, speaker)| *speaker)
+        .collect();
+    (!speakers.is_empty()).then(|| speakers.join(" "))
+}
+
+// Bitrates Vorbis headers leave unset are 0 or -1
+fn vorbis_bitrate(bps: i32) -> Option<i32> {
+    (bps > 0).then_some(bps / 1000)
+}
+
+impl Describe for MpegProperties {
+    fn describe(&self) -> AudioInfo {
+        let (codec, layer) = match self.layer() {
+            Layer::Layer1 => ("MP1", "I"),
+            Layer::Layer2 => ("MP2", "II"),
+            Layer::Layer3 => ("MP3", "III"),
+        };
+        let version = match self.version() {
+            MpegVersion::V1 => "MPEG-1",
+            MpegVersion::V2 => "MPEG-2",
+            MpegVersion::V2_5 => "MPEG-2.5",
+            MpegVersion::V4 => "MPEG-4",
+        };
+        let channel_mode = match self.channel_mode() {
+            ChannelMode::Stereo => "stereo",
+            ChannelMode::JointStereo => "joint stereo",
+            ChannelMode::DualChannel => "dual channel",
+            ChannelMode::SingleChannel => "mono",
+        };
+        AudioInfo {
+            codec: Some(codec.to_string()),
+            codec_profile: Some(format!("{} Layer {}", version, layer)),
+            lossless: Some(false),
+            channel_mode: Some(channel_mode.to_string()),
+            emphasis: self.emphasis().and_then(|emphasis| match emphasis {
+                Emphasis::MS5015 => Some("50/15 ms".to_string()),
+                Emphasis::CCIT_J17 => Some("CCIT J.17".to_string()),
+                Emphasis::Reserved => None,
+            }),
+            copyright: Some(self.is_copyright()),
+            original: Some(self.is_original()),
+            ..AudioInfo::default()
+        }
+    }
+}
+
+impl Describe for Mp4Properties {
+    fn describe(&self) -> AudioInfo {
+        let (codec, lossless) = match self.codec() {
+            Mp4Codec::AAC => (Some("AAC"), Some(false)),
+            Mp4Codec::ALAC => (Some("ALAC"), Some(true)),
+            Mp4Codec::MP3 => (Some("MP3"), Some(false)),
+            Mp4Codec::FLAC => (Some("FLAC"), Some(true)),
+            _ => (None, None),
+        };
+        let codec_profile = self.audio_object_type().and_then(|object_type| match object_type {
+            AudioObjectType::NULL => None,
+            AudioObjectType::AacMain => Some("AAC Main".to_string()),
+            AudioObjectType::AacLowComplexity => Some("AAC LC".to_string()),
+            AudioObjectType::AacScalableSampleRate => Some("AAC SSR".to_string()),
+            AudioObjectType::AacLongTermPrediction => Some("AAC LTP".to_string()),
+            AudioObjectType::SpectralBandReplication => Some("HE-AAC".to_string()),
+            AudioObjectType::ParametricStereo => Some("HE-AAC v2".to_string()),
+            AudioObjectType::ErrorResilientAacLowDelay => Some("AAC LD".to_string()),
+            AudioObjectType::ErrorResilientAacEnhancedLowDelay => Some("AAC ELD".to_string()),
+            AudioObjectType::UnifiedSpeechAudioCoding => Some("xHE-AAC".to_string()),
+            other => Some(format!("{:?}", other)),
+        });
+        AudioInfo {
+            codec: codec.map(str::to_string),
+            codec_profile,
+            lossless,
+            channel_layout: ChannelMask::from_mp4_channels(self.channels()).and_then(channel_layout),
+            drm_protected: Some(self.is_drm_protected()),
+            ..AudioInfo::default()
+        }
+    }
+}
+
+impl Describe for FlacProperties {
+    fn describe(&self) -> AudioInfo {
+        AudioInfo {
+            codec: Some("FLAC".to_string()),
+            lossless: Some(true),
+            ..AudioInfo::default()
+        }
+    }
+}
+
+impl Describe for OpusProperties {
+    fn describe(&self) -> AudioInfo {
+        AudioInfo {
+            codec: Some("Opus".to_string()),
+            codec_profile: Some(format!("Opus version {}", self.version())),
+            lossless: Some(false),
+            channel_layout: channel_layout(self.channel_mask()),
+            input_sample_rate: Some(self.input_sample_rate()).filter(|rate| *rate > 0),
+            ..AudioInfo::default()
+        }
+    }
+}
+
+impl Describe for VorbisProperties {
+    fn describe(&self) -> AudioInfo {
+        AudioInfo {
+            codec: Some("Vorbis".to_string()),
+            codec_profile: Some(format!("Vorbis version {}", self.version())),
+            lossless: Some(false),
+            nominal_bitrate: vorbis_bitrate(self.bitrate_nominal()),
+            min_bitrate: vorbis_bitrate(self.bitrate_min()),
+            max_bitrate: vorbis_bitrate(self.bitrate_max()),
+            ..AudioInfo::default()
+        }
+    }
+}
+
+impl Describe for WavProperties {
+    fn describe(&self) -> AudioInfo {
+        let (codec, lossless) = match self.format() {
+            WavFormat::PCM => ("PCM".to_string(), Some(true)),
+            WavFormat::IEEE_FLOAT => ("IEEE float".to_string(), Some(true)),
+            WavFormat::Other(tag) => (format!("WAVE format 0x{:04x}", tag), None),
+        };
+        AudioInfo {
+            codec: Some(codec),
+            lossless,
+            channel_layout: self.channel_mask().and_then(channel_layout),
+            ..AudioInfo::default()
+        }
+    }
+}
+
+impl Describe for AiffProperties {
+    fn describe(&self) -> AudioInfo {
+        let (codec, lossless) = match self.compression_type() {
+            None | Some(AiffCompressionType::None) => ("PCM".to_string(), Some(true)),
+            Some(AiffCompressionType::sowt) => ("PCM (little-endian)".to_string(), Some(true)),
+            Some(AiffCompressionType::fl32 | AiffCompressionType::FL32) => ("32-bit float".to_string(), Some(true)),
+            Some(AiffCompressionType::fl64) => ("64-bit float".to_string(), Some(true)),
+            Some(compression) => (compression.compression_name().into_owned(), None),
+        };
+        AudioInfo {
+            codec: Some(codec),
+            lossless,
+            ..AudioInfo::default()
+        }
+    }
+}
+
+impl Describe for ApeProperties {
+    fn describe(&self) -> AudioInfo {
+        AudioInfo {
+            codec: Some("Monkey's Audio".to_string()),
+            // Stored as e.g. 3990 for 3.99
+            codec_profile: Some(format!("version {}.{:02}", self.version() / 1000, self.version() % 1000 / 10)),
+            lossless: Some(true),
+            ..AudioInfo::default()
+        }
+    }
+}
+
+impl Describe for WavPackProperties {
+    fn describe(&self) -> AudioInfo {
+        AudioInfo {
+            codec: Some("WavPack".to_string()),
+            codec_profile: Some(format!("version 0x{:x}", self.version())),
+            // Hybrid mode files without their correction file are lossy
+            lossless: Some(self.is_lossless()),
+            channel_layout: channel_layout(self.channel_mask()),
+            ..AudioInfo::default()
+        }
+    }
+}
+
+/// The Xing/Info and LAME headers of an MP3 file, leaving the reader where
+/// it was
+pub fn read_lame_header<R: Read + Seek>(reader: &mut R) -> std::io::Result<Option<LameHeader>> {
+    let start = reader.stream_position()?;
+    let mut id3 = Vec::new();
+    reader.by_ref().take(10).read_to_end(&mut id3)?;
+    reader.seek(SeekFrom::Start(start + id3v2_size(&id3)))?;
+    let mut data = Vec::new();
+    reader.by_ref().take(MPEG_SEARCH_SIZE).read_to_end(&mut data)?;
+    reader.seek(SeekFrom::Start(start))?;
+    Ok(lame_header(&data))
+}
+
+// Length of the ID3v2 tag at the start of an MP3 file, footer included
+fn id3v2_size(header: &[u8]) -> u64 {
+    if header.len() < 10 || &header[..3] != b"ID3" {
+        return 0;
+    }
+    let size = header[6..10].iter().fold(0u64, |size, byte| (size << 7) | (*byte & 0x7f) as u64);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    10 + size + footer
+}
+
+// Offset of the first MPEG Layer III frame header
+fn first_frame(data: &[u8]) -> Option<usize> {
+    data.windows(4).position(|header| {
+        header[0] == 0xff
+            && header[1] & 0xe0 == 0xe0
+            && (header[1] >> 3) & 0x03 != 0x01
+            && (header[1] >> 1) & 0x03 == 0x01
+            && header[2] >> 4 != 0x0f
+            && (header[2] >> 2) & 0x03 != 0x03
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The Xing/Info header in the first frame of MP3 data following any ID3v2
+/// tag, and the LAME extension after it
+pub fn lame_header(data: &[u8]) -> Option<LameHeader> {
+    let frame = first_frame(data)?;
+    let mpeg1 = (data[frame + 1] >> 3) & 0x03 == 0x03;
+    let mono = data[frame + 3] >> 6 == 0x03;
+    // The header follows the frame's side information
+    let side_info = match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let mut offset = frame + 4 + side_info;
+    let header = match data.get(offset..offset + 4)? {
+        b"Xing" => "Xing",
+        b"Info" => "Info",
+        _ => return None,
+    };
+    let flags = read_u32(data, offset + 4)?;
+    offset += 8;
+
+    let mut lame = LameHeader {
+        header: header.to_string(),
+        ..LameHeader::default()
+    };
+    if flags & 0x01 != 0 {
+        lame.frames = read_u32(data, offset);
+        offset += 4;
+    }
+    if flags & 0x02 != 0 {
+        lame.bytes = read_u32(data, offset);
+        offset += 4;
+    }
+    if flags & 0x04 != 0 {
+        // Seek table
+        offset += 100;
+    }
+    if flags & 0x08 != 0 {
+        lame.quality = read_u32(data, offset);
+        offset += 4;
+    }
+
+    // The LAME extension: a 9 byte encoder version, then the encoding settings
+    let Some(extension) = data.get(offset..offset + 28) else {
+        return Some(lame);
+    };
+    let version = &extension[..9];
+    if !version[0].is_ascii_alphabetic() || !version.iter().all(|byte| *byte == 0 || byte.is_ascii_graphic()) {
+        return Some(lame);
+    }
+    lame.encoder = Some(String::from_utf8_lossy(version).trim_end_matches(['\0', ' ']).to_string());
+    lame.vbr_method = match extension[9] & 0x0f {
+        1 => Some("cbr"),
+        2 => Some("abr"),
+        3 => Some("vbr-old"),
+        4 => Some("vbr-mtrh"),
+        5 => Some("vbr-mt"),
+        8 => Some("cbr 2-pass"),
+        9 => Some("abr 2-pass"),
+        _ => None,
+    }
+    .map(str::to_string);
+    lame.lowpass_hz = Some(extension[10] as u32 * 100).filter(|hz| *hz > 0);
+    lame.encoder_delay = Some(((extension[21] as u16) << 4) | (extension[22] >> 4) as u16);
+    lame.encoder_padding = Some((((extension[22] & 0x0f) as u16) << 8) | extension[23] as u16);
+    lame.preset = preset_name(u16::from_be_bytes([extension[26], extension[27]]) & 0x07ff);
+    Some(lame)
+}
+
+// LAME's preset codes
+fn preset_name(preset: u16) -> Option<String> {
+    match preset {
+        0 => None,
+        8..=320 => Some(format!("ABR {}", preset)),
+        410..=500 if preset.is_multiple_of(10) => Some(format!("V{}", (500 - preset) / 10)),
+        1000 => Some("r3mix".to_string()),
+        1001 => Some("standard".to_string()),
+        1002 => Some("extreme".to_string()),
+        1003 => Some("insane".to_string()),
+        1004 => Some("fast standard".to_string()),
+        1005 => Some("fast extreme".to_string()),
+        1006 => Some("medium".to_string()),
+        1007 => Some("fast medium".to_string()),
+        preset => Some(preset.to_string()),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AudioInfoResponse {
+    pub track_id: i32,
+    pub content_type: Option<String>,
+    pub duration_seconds: i32,
+    pub sample_rate: i32,
+    pub bit_depth: i32,
+    pub channels: i32,
+    pub audio_bitrate: i32,
+    pub overall_bitrate: i32,
+    /// Null for files not read since the server learned to record it; a
+    /// rescan of their folder fills it in
+    pub details: Option<AudioInfo>,
+}
+
+// GET /tracks/:id/audio-info - Technical details of a track's audio stream
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/audio-info",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID"), VisibilityQuery),
+    responses(
+        (status = 200, description = "Audio details", body = AudioInfoResponse),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_audio_info(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    visibility: Visibility,
+) -> ApiResult<Json<AudioInfoResponse>> {
+    let track = Track::find_by_id(id)
+        .filter(visibility.condition())
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", id)))?;
+
+    // Rows from before the column was added hold an empty object
+    let details = track.audio_info
+        .as_object()
+        .filter(|details| !details.is_empty())
+        .and_then(|_| serde_json::from_value(track.audio_info.clone()).ok());
+    Ok(Json(AudioInfoResponse {
+        track_id: track.id,
+        content_type: track.content_type,
+        duration_seconds: track.duration_seconds,
+        sample_rate: track.sample_rate,
+        bit_depth: track.bit_depth,
+        channels: track.channels,
+        audio_bitrate: track.audio_bitrate,
+        overall_bitrate: track.overall_bitrate,
+        details,
+    }))
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::State,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use sea_orm::{ColumnTrait, DbErr, EntityTrait, QueryFilter, QueryOrder};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use entity::prelude::{PlaybackPosition, Track};
+use entity::{playback_position, track};
+
+use crate::albums::{self, folder_album_id, folder_album_key, parse_folder_album_id};
+use crate::api::{AppState, TrackResponse};
+use crate::chapters;
+use crate::error::{ApiError, ApiPath, ApiResult};
+use crate::hidden::{Visibility, VisibilityQuery};
+use crate::media_types::AUDIOBOOK;
+
+/// A chapter of an audiobook: one embedded in a file, or a whole file when
+/// it has none
+#[derive(Serialize, ToSchema)]
+pub struct BookChapter {
+    pub title: String,
+    pub track_id: i32,
+    /// Start and end within the track
+    pub start_ms: i64,
+    pub end_ms: i64,
+    /// Start from the beginning of the book, across its files
+    pub book_start_ms: i64,
+}
+
+/// Where a user left off in an audiobook
+#[derive(Serialize, ToSchema)]
+pub struct ResumePosition {
+    pub track_id: i32,
+    /// Position within the track, to resume playing it from
+    pub position_ms: i64,
+    /// Position from the beginning of the book
+    pub book_position_ms: i64,
+    /// Index of the chapter the position is in
+    pub chapter: Option<usize>,
+    pub client: Option<String>,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AudiobookSummary {
+    pub id: String,
+    pub title: String,
+    pub author: String,
+    pub year: Option<i32>,
+    pub track_count: usize,
+    pub duration_seconds: i64,
+    pub chapter_count: usize,
+    /// Track to fetch cover art from with /tracks/:id/albumart
+    pub art_track_id: Option<i32>,
+    /// Where the requesting user left off, when the request named a user
+    pub resume: Option<ResumePosition>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AudiobookResponse {
+    pub audiobook: AudiobookSummary,
+    pub chapters: Vec<BookChapter>,
+    /// The book's files in playing order
+    pub tracks: Vec<TrackResponse>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AudiobookListResponse {
+    pub audiobooks: Vec<AudiobookSummary>,
+}
+
+// The files of one audiobook folder, in playing order
+struct Book {
+    folder: String,
+    tracks: Vec<track::Model>,
+}
+
+impl Book {
+    fn chapters(&self) -> Vec<BookChapter> {
+        let mut chapters = Vec::new();
+        let mut offset_ms = 0;
+        for track in &self.tracks {
+            let duration_ms = track.duration_seconds as i64 * 1000;
+            let embedded = chapters::of_track(&track.chapters);
+            if embedded.is_empty() {
+                chapters.push(BookChapter {
+                    title: track.title.clone(),
+                    track_id: track.id,
+                    start_ms: 0,
+                    end_ms: duration_ms,
+                    book_start_ms: offset_ms,
+                });
+            }
+            chapters.extend(embedded.into_iter().map(|chapter| BookChapter {
+                title: chapter.title,
+                track_id: track.id,
+                start_ms: chapter.start_ms,
+                end_ms: chapter.end_ms,
+                book_start_ms: offset_ms + chapter.start_ms,
+            }));
+            offset_ms += duration_ms;
+        }
+        chapters
+    }
+
+    // The user's most recent position in any of the book's files
+    fn resume(&self, positions: &HashMap<i32, playback_position::Model>, chapters: &[BookChapter]) -> Option<ResumePosition> {
+        let position = self.tracks.iter()
+            .filter_map(|track| positions.get(&track.id))
+            .max_by_key(|position| position.updated)?;
+        let offset_ms: i64 = self.tracks.iter()
+            .take_while(|track| track.id != position.track_id)
+            .map(|track| track.duration_seconds as i64 * 1000)
+            .sum();
+        let book_position_ms = offset_ms + position.position_ms;
+
+        Some(ResumePosition {
+            track_id: position.track_id,
+            position_ms: position.position_ms,
+            book_position_ms,
+            chapter: chapters.iter().rposition(|chapter| chapter.book_start_ms <= book_position_ms),
+            client: position.client.clone(),
+            updated: position.updated,
+        })
+    }
+
+    fn summary(&self, chapters: &[BookChapter], resume: Option<ResumePosition>) -> AudiobookSummary {
+        let author = self.tracks.iter()
+            .map(|track| track.album_artist.as_str())
+            .find(|artist| !artist.is_empty())
+            .or_else(|| self.tracks.iter().map(|track| track.artist.as_str()).find(|artist| !artist.is_empty()))
+            .unwrap_or_default()
+            .to_string();
+
+        AudiobookSummary {
+            id: folder_album_id(&self.folder),
+            title: albums::folder_album_name(&self.folder, self.tracks.iter().map(|track| track.album.as_str())),
+            author,
+            year: self.tracks.iter().filter_map(|track| track.year).min(),
+            track_count: self.tracks.len(),
+            duration_seconds: self.tracks.iter().map(|track| track.duration_seconds as i64).sum(),
+            chapter_count: chapters.len(),
+            art_track_id: self.tracks.iter()
+                .find(|track| track.album_art_path.is_some())
+                .or(self.tracks.first())
+                .map(|track| track.id),
+            resume,
+        }
+    }
+}
+
+// Visible audiobook files grouped into books by folder, sorted by folder
+async fn load_books(state: &AppState, visibility: &Visibility) -> Result<Vec<Book>, DbErr> {
+    let tracks = Track::find()
+        .filter(track::Column::MediaType.eq(AUDIOBOOK))
+        .filter(visibility.condition())
+        .order_by_asc(track::Column::DiscNumber)
+        .order_by_asc(track::Column::TrackNumber)
+        .order_by_asc(track::Column::Path)
+        .all(&state.db)
+        .await?;
+
+    let mut books: HashMap<String, Vec<track::Model>> = HashMap::new();
+    for track in tracks {
+        let folder = folder_album_key(&track.path, track.library_root.as_deref()).to_string();
+        books.entry(folder).or_default().push(track);
+    }
+    let mut books: Vec<Book> = books.into_iter()
+        .map(|(folder, tracks)| Book { folder, tracks })
+        .collect();
+    books.sort_by(|a, b| a.folder.cmp(&b.folder));
+    Ok(books)
+}
+
+// The user's resume positions in the given tracks, by track
+async fn load_positions(state: &AppState, visibility: &Visibility, track_ids: Vec<i32>) -> Result<HashMap<i32, playback_position::Model>, DbErr> {
+    let Some(user) = visibility.user() else {
+        return Ok(HashMap::new());
+    };
+    Ok(PlaybackPosition::find()
+        .filter(playback_position::Column::User.eq(user))
+        .filter(playback_position::Column::TrackId.is_in(track_ids))
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|position| (position.track_id, position))
+        .collect())
+}
+
+// GET /audiobooks - Audiobooks in the library, with where the user left off
+#[utoipa::path(
+    get,
+    path = "/audiobooks",
+    tag = "audiobooks",
+    params(VisibilityQuery),
+    responses(
+        (status = 200, description = "Audiobooks by folder", body = AudiobookListResponse),
+    )
+)]
+pub async fn get_audiobooks(
+    State(state): State<AppState>,
+    visibility: Visibility,
+) -> ApiResult<Json<AudiobookListResponse>> {
+    let books = load_books(&state, &visibility).await?;
+    let track_ids = books.iter().flat_map(|book| book.tracks.iter().map(|track| track.id)).collect();
+    let positions = load_positions(&state, &visibility, track_ids).await?;
+
+    let audiobooks = books.iter()
+        .map(|book| {
+            let chapters = book.chapters();
+            book.summary(&chapters, book.resume(&positions, &chapters))
+        })
+        .collect();
+    Ok(Json(AudiobookListResponse { audiobooks }))
+}
+
+// GET /audiobooks/:id - An audiobook's chapters and files, with where the user left off
+#[utoipa::path(
+    get,
+    path = "/audiobooks/{id}",
+    tag = "audiobooks",
+    params(("id" = String, Path, description = "Audiobook ID"), VisibilityQuery),
+    responses(
+        (status = 200, description = "Audiobook with chapters and tracks", body = AudiobookResponse),
+        (status = 404, description = "Audiobook not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_audiobook(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+    visibility: Visibility,
+) -> ApiResult<Json<AudiobookResponse>> {
+    let not_found = || ApiError::not_found(format!("Audiobook {} not found", id));
+    let folder = parse_folder_album_id(&id).ok_or_else(not_found)?;
+    let book = load_books(&state, &visibility)
+        .await?
+        .into_iter()
+        .find(|book| book.folder == folder)
+        .ok_or_else(not_found)?;
+
+    let positions = load_positions(&state, &visibility, book.tracks.iter().map(|track| track.id).collect()).await?;
+    let chapters = book.chapters();
+    let audiobook = book.summary(&chapters, book.resume(&positions, &chapters));
+
+    Ok(Json(AudiobookResponse {
+        audiobook,
+        chapters,
+        tracks: book.tracks.into_iter().map(TrackResponse::from).collect(),
+    }))
+}
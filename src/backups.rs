@@ -0,0 +1,439 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement};
+use serde::Serialize;
+use tokio::process::Command;
+use utoipa::ToSchema;
+
+use crate::api::AppState;
+use crate::error::{ApiError, ApiResult};
+
+const DEFAULT_KEEP: usize = 7;
+const BACKUP_PREFIX: &str = "ongaku-";
+const BACKUP_EXTENSION: &str = ".dump";
+// How often the size of a backup being written is sampled for progress
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+// Where sea-orm records the migrations a database has had
+const MIGRATIONS_TABLE: &str = "seaql_migrations";
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupState {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct BackupJob {
+    pub state: BackupState,
+    /// File the backup is written to
+    pub path: String,
+    /// Bytes written so far, or the size of the finished backup
+    pub bytes_written: u64,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BackupFile {
+    pub name: String,
+    pub size_bytes: u64,
+    pub created: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BackupStatusResponse {
+    pub backup_dir: String,
+    /// Backups kept before the oldest are deleted
+    pub keep: usize,
+    /// The running backup, or the last one since the server started
+    pub last: Option<BackupJob>,
+    /// Backups in backup_dir, newest first
+    pub backups: Vec<BackupFile>,
+}
+
+/// Online database backups with pg_dump. pg_dump reads from a single
+/// snapshot, so the server keeps serving and writing while it runs.
+pub struct Backups {
+    dir: Option<PathBuf>,
+    keep: usize,
+    pg_dump: String,
+    pg_restore: String,
+    database_url: String,
+    last: RwLock<Option<BackupJob>>,
+}
+
+impl Backups {
+    /// Backups are written to BACKUP_DIR, keeping the newest BACKUP_KEEP
+    /// (default 7), with pg_dump from PG_DUMP_PATH (default: found on PATH).
+    /// Restores use pg_restore from PG_RESTORE_PATH, or next to pg_dump.
+    pub fn from_env(database_url: &str) -> Self {
+        let pg_dump = env::var("PG_DUMP_PATH").unwrap_or_else(|_| "pg_dump".to_string());
+        let pg_restore = env::var("PG_RESTORE_PATH").unwrap_or_else(|_| {
+            let sibling = Path::new(&pg_dump).with_file_name(format!("pg_restore{}", env::consts::EXE_SUFFIX));
+            match Path::new(&pg_dump).parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => sibling.display().to_string(),
+                _ => "pg_restore".to_string(),
+            }
+        });
+        Self {
+            dir: env::var("BACKUP_DIR").ok().filter(|dir| !dir.trim().is_empty()).map(PathBuf::from),
+            keep: env::var("BACKUP_KEEP")
+                .ok()
+                .and_then(|keep| keep.parse().ok())
+                .filter(|keep| *keep > 0)
+                .unwrap_or(DEFAULT_KEEP),
+            pg_dump,
+            pg_restore,
+            database_url: database_url.to_string(),
+            last: RwLock::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    fn dir(&self) -> ApiResult<&Path> {
+        self.dir.as_deref().ok_or_else(|| {
+            ApiError::new(StatusCode::NOT_IMPLEMENTED, "backups_disabled", "BACKUP_DIR is not configured")
+        })
+    }
+
+    // Connection settings for pg_dump, passed in libpq's environment variables
+    // so the password doesn't show up in the process list
+    fn connection_env(&self) -> Vec<(&'static str, String)> {
+        let Ok(url) = url::Url::parse(&self.database_url) else {
+            return Vec::new();
+        };
+        let mut vars = Vec::new();
+        if let Some(host) = url.host_str() {
+            vars.push(("PGHOST", host.to_string()));
+        }
+        if let Some(port) = url.port() {
+            vars.push(("PGPORT", port.to_string()));
+        }
+        if !url.username().is_empty() {
+            vars.push(("PGUSER", urlencoding::decode(url.username()).map(|user| user.into_owned()).unwrap_or_default()));
+        }
+        if let Some(password) = url.password() {
+            vars.push(("PGPASSWORD", urlencoding::decode(password).map(|password| password.into_owned()).unwrap_or_default()));
+        }
+        let database = url.path().trim_start_matches('/');
+        if !database.is_empty() {
+            vars.push(("PGDATABASE", database.to_string()));
+        }
+        vars
+    }
+
+    fn database_name(&self) -> Option<String> {
+        self.connection_env().into_iter()
+            .find(|(name, _)| *name == "PGDATABASE")
+            .map(|(_, database)| database)
+    }
+
+    fn update(&self, change: impl FnOnce(&mut BackupJob)) {
+        if let Some(job) = self.last.write().unwrap().as_mut() {
+            change(job);
+        }
+    }
+
+    // Run pg_dump into `partial`, reporting its size as it grows
+    async fn dump(&self, partial: &Path, progress: impl Fn(u64)) -> Result<(), String> {
+        let mut child = Command::new(&self.pg_dump)
+            .arg("--format=custom")
+            .arg("--no-password")
+            .arg("--file")
+            .arg(partial)
+            .envs(self.connection_env())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("Failed to run {}: {}", self.pg_dump, e))?;
+
+        let mut stderr = child.stderr.take();
+        let errors = tokio::spawn(async move {
+            let mut output = String::new();
+            if let Some(stderr) = stderr.as_mut() {
+                let _ = tokio::io::AsyncReadExt::read_to_string(stderr, &mut output).await;
+            }
+            output
+        });
+        let status = loop {
+            tokio::select! {
+                status = child.wait() => break status,
+                _ = tokio::time::sleep(PROGRESS_INTERVAL) => {
+                    if let Ok(metadata) = tokio::fs::metadata(partial).await {
+                        progress(metadata.len());
+                    }
+                }
+            }
+        };
+        let errors = errors.await.unwrap_or_default();
+
+        match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(format!("pg_dump exited with {}: {}", status, errors.trim())),
+            Err(e) => Err(format!("Failed to wait for pg_dump: {}", e)),
+        }
+    }
+
+    // Run pg_dump into a partial file, renamed once complete so a crash
+    // never leaves a truncated file among the backups
+    async fn run(self: Arc<Self>, dir: PathBuf, partial: PathBuf, path: PathBuf) {
+        if let Err(e) = self.dump(&partial, |bytes| self.update(|job| job.bytes_written = bytes)).await {
+            return self.fail(&partial, e).await;
+        }
+        if let Err(e) = tokio::fs::rename(&partial, &path).await {
+            return self.fail(&partial, format!("Failed to move the backup into place: {}", e)).await;
+        }
+
+        let size = tokio::fs::metadata(&path).await.map(|metadata| metadata.len()).unwrap_or(0);
+        self.update(|job| {
+            job.state = BackupState::Completed;
+            job.bytes_written = size;
+            job.finished = Some(Utc::now());
+        });
+        info!("Database backup written to {} ({} bytes)", path.display(), size);
+
+        if let Err(e) = self.prune(&dir).await {
+            error!("Failed to delete old backups in {}: {}", dir.display(), e);
+        }
+    }
+
+    /// Write a backup to `path`, as `ongaku-server backup <path>` does.
+    /// Returns its size.
+    pub async fn backup_to(&self, path: &Path) -> Result<u64, String> {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        if let Err(e) = self.dump(&partial, |_| {}).await {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e);
+        }
+        tokio::fs::rename(&partial, path)
+            .await
+            .map_err(|e| format!("Failed to move the backup into place: {}", e))?;
+        Ok(tokio::fs::metadata(path).await.map(|metadata| metadata.len()).unwrap_or(0))
+    }
+
+    /// Restore a backup into the database, as `ongaku-server restore <path>`
+    /// does, then bring it up to date with migrations newer than the backup.
+    /// Backups from a newer server, and restoring over a database that has
+    /// migrations the backup lacks, are refused before anything is changed.
+    pub async fn restore_from(&self, db: &DatabaseConnection, path: &Path) -> Result<(), String> {
+        if !tokio::fs::try_exists(path).await.unwrap_or(false) {
+            return Err(format!("{} doesn't exist", path.display()));
+        }
+        let known: Vec<String> = Migrator::migrations().iter().map(|migration| migration.name().to_string()).collect();
+        let in_backup = self.backup_migrations(path).await?;
+        if in_backup.is_empty() {
+            return Err(format!("{} has no {} table, so it isn't a backup of this server", path.display(), MIGRATIONS_TABLE));
+        }
+        if let Some(newer) = in_backup.iter().find(|version| !known.contains(version)) {
+            return Err(format!("The backup is from a newer server (it has migration {}); upgrade this server before restoring it", newer));
+        }
+        let applied = applied_migrations(db).await.map_err(|e| format!("Failed to read the database's migrations: {}", e))?;
+        if let Some(newer) = applied.iter().find(|version| !in_backup.contains(version)) {
+            return Err(format!(
+                "The database has migration {} that the backup predates; restore into an empty database instead",
+                newer,
+            ));
+        }
+
+        let database = self.database_name().ok_or("DATABASE_URL doesn't name a database")?;
+        info!("Restoring {} into database {} ({} migrations)", path.display(), database, in_backup.len());
+        let output = Command::new(&self.pg_restore)
+            .args(["--clean", "--if-exists", "--no-owner", "--no-privileges", "--single-transaction", "--exit-on-error", "--no-password"])
+            .arg(format!("--dbname={}", database))
+            .arg(path)
+            .envs(self.connection_env())
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| format!("Failed to run {}: {}", self.pg_restore, e))?;
+        if !output.status.success() {
+            return Err(format!("pg_restore exited with {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()));
+        }
+
+        let pending = known.len() - in_backup.len();
+        if pending > 0 {
+            info!("Applying {} migrations newer than the backup", pending);
+        }
+        Migrator::up(db, None).await.map_err(|e| format!("Failed to migrate the restored database: {}", e))
+    }
+
+    // Migrations recorded in a backup, read from its copy of the migrations table
+    async fn backup_migrations(&self, path: &Path) -> Result<Vec<String>, String> {
+        let output = Command::new(&self.pg_restore)
+            .args(["--data-only", "--table", MIGRATIONS_TABLE, "--file", "-"])
+            .arg(path)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| format!("Failed to run {}: {}", self.pg_restore, e))?;
+        if !output.status.success() {
+            return Err(format!("{} isn't a readable backup: {}", path.display(), String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(copied_versions(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    async fn fail(&self, partial: &Path, message: String) {
+        error!("Database backup failed: {}", message);
+        let _ = tokio::fs::remove_file(partial).await;
+        self.update(|job| {
+            job.state = BackupState::Failed;
+            job.finished = Some(Utc::now());
+            job.error = Some(message);
+        });
+    }
+
+    // Delete all but the newest `keep` backups. Names sort by time.
+    async fn prune(&self, dir: &Path) -> std::io::Result<()> {
+        let backups = list_backups(dir).await?;
+        for backup in backups.iter().skip(self.keep) {
+            info!("Deleting old backup {}", backup.name);
+            tokio::fs::remove_file(dir.join(&backup.name)).await?;
+        }
+        Ok(())
+    }
+}
+
+// The first column of the rows of the migrations table in pg_restore's SQL,
+// which come as tab separated lines between "COPY ... FROM stdin;" and "\."
+fn copied_versions(sql: &str) -> Vec<String> {
+    sql.lines()
+        .skip_while(|line| !(line.starts_with("COPY ") && line.contains(MIGRATIONS_TABLE)))
+        .skip(1)
+        .take_while(|line| *line != "\\.")
+        .filter_map(|line| line.split('\t').next())
+        .filter(|version| !version.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Migrations the database has had, none when it has never been migrated
+async fn applied_migrations(db: &DatabaseConnection) -> Result<Vec<String>, sea_orm::DbErr> {
+    let exists = db.query_one(Statement::from_string(
+        db.get_database_backend(),
+        format!("SELECT to_regclass('{}') IS NOT NULL AS present", MIGRATIONS_TABLE),
+    ))
+    .await?
+    .map(|row| row.try_get::<bool>("", "present"))
+    .transpose()?
+    .unwrap_or(false);
+    if !exists {
+        return Ok(Vec::new());
+    }
+    db.query_all(Statement::from_string(db.get_database_backend(), format!("SELECT version FROM {}", MIGRATIONS_TABLE)))
+        .await?
+        .iter()
+        .map(|row| row.try_get::<String>("", "version"))
+        .collect()
+}
+
+// Finished backups in `dir`, newest first
+async fn list_backups(dir: &Path) -> std::io::Result<Vec<BackupFile>> {
+    let mut backups = Vec::new();
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(backups),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(BACKUP_PREFIX) || !name.ends_with(BACKUP_EXTENSION) {
+            continue;
+        }
+        let metadata = entry.metadata().await?;
+        backups.push(BackupFile {
+            name,
+            size_bytes: metadata.len(),
+            created: metadata.modified().ok().map(DateTime::from),
+        });
+    }
+    backups.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(backups)
+}
+
+// POST /admin/backup - Start an online backup of the database
+#[utoipa::path(
+    post,
+    path = "/admin/backup",
+    tag = "backups",
+    responses(
+        (status = 202, description = "Backup started", body = BackupJob),
+        (status = 409, description = "A backup is already running", body = Problem, content_type = "application/problem+json"),
+        (status = 501, description = "BACKUP_DIR is not configured", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn start_backup(State(state): State<AppState>) -> ApiResult<(StatusCode, Json<BackupJob>)> {
+    let backups = state.backups.clone();
+    let dir = backups.dir()?.to_path_buf();
+    tokio::fs::create_dir_all(&dir).await.map_err(|e| {
+        error!("Failed to create backup directory {}: {}", dir.display(), e);
+        ApiError::internal("Failed to create the backup directory")
+    })?;
+
+    let started = Utc::now();
+    let name = format!("{}{}{}", BACKUP_PREFIX, started.format("%Y%m%d-%H%M%S"), BACKUP_EXTENSION);
+    let path = dir.join(&name);
+    let partial = dir.join(format!("{}.partial", name));
+    let job = BackupJob {
+        state: BackupState::Running,
+        path: path.display().to_string(),
+        bytes_written: 0,
+        started,
+        finished: None,
+        error: None,
+    };
+    {
+        let mut last = backups.last.write().unwrap();
+        if last.as_ref().is_some_and(|job| job.state == BackupState::Running) {
+            return Err(ApiError::conflict("A backup is already running"));
+        }
+        *last = Some(job.clone());
+    }
+    info!("Starting database backup to {}", path.display());
+
+    tokio::spawn(backups.run(dir, partial, path));
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+// GET /admin/backup - Progress of the running or last backup, and the backups kept
+#[utoipa::path(
+    get,
+    path = "/admin/backup",
+    tag = "backups",
+    responses(
+        (status = 200, description = "Backup status", body = BackupStatusResponse),
+        (status = 501, description = "BACKUP_DIR is not configured", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_backup_status(State(state): State<AppState>) -> ApiResult<Json<BackupStatusResponse>> {
+    let dir = state.backups.dir()?;
+    let backups = list_backups(dir).await?;
+    let last = state.backups.last.read().unwrap().clone();
+    Ok(Json(BackupStatusResponse {
+        backup_dir: dir.display().to_string(),
+        keep: state.backups.keep,
+        last,
+        backups,
+    }))
+}
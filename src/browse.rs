@@ -0,0 +1,417 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, DatabaseTransaction, DbErr, EntityTrait, JoinType, NotSet,
+    QueryFilter, QueryOrder, QuerySelect, RelationTrait, TransactionTrait,
+};
+use sea_query::Expr;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use entity::prelude::{BrowseAlbum, BrowseArtist, BrowseGenre, Track, TrackArtist};
+use entity::{browse_album, browse_artist, browse_genre, track, track_artist};
+
+use crate::albums::{self, AlbumSummaryRow};
+use crate::formats::FormatSummaryBuilder;
+use crate::hidden::Visibility;
+use crate::media_types::MediaTypes;
+use crate::slugs;
+use crate::text_index::{self, IgnoredArticles, TextNormalization};
+
+// Tracks without an album tag count once, as the singles album
+const ALBUM_COUNT_EXPR: &str = r#"COUNT(DISTINCT "album")"#;
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct GenreCount {
+    pub name: String,
+    pub song_count: i64,
+    pub album_count: i64,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct ArtistCount {
+    pub id: Option<String>,
+    pub name: String,
+    pub album_count: i64,
+    pub song_count: i64,
+    /// When the artist's newest track was added
+    pub added: Option<DateTime<Utc>>,
+}
+
+/// Artists whose names start with the same letter
+#[derive(Serialize, ToSchema)]
+pub struct ArtistIndex {
+    /// A-Z, or # for names starting with anything else
+    pub name: String,
+    pub artists: Vec<ArtistCount>,
+}
+
+/// Per-genre and per-artist aggregates. Those of the default view are read
+/// from the browse tables, which each scan brings up to date, and kept in
+/// memory until the next scan finishes or a track or directory is hidden.
+#[derive(Default)]
+pub struct CountCache {
+    genres: RwLock<Option<Arc<Vec<GenreCount>>>>,
+    artists: RwLock<Option<Arc<Vec<ArtistCount>>>>,
+}
+
+impl CountCache {
+    /// Drop cached counts so the next request reads them again
+    pub fn invalidate(&self) {
+        *self.genres.write().unwrap() = None;
+        *self.artists.write().unwrap() = None;
+    }
+
+    /// Rebuild the browse tables after tracks were hidden or edited outside a
+    /// scan, then drop cached counts
+    pub async fn refresh(&self, db: &DatabaseConnection) {
+        if let Err(e) = refresh_browse_tables(db).await {
+            error!("Failed to update browse tables: {}", e);
+        }
+        self.invalidate();
+    }
+
+    /// Per-genre counts of the tracks of `media_types` that `visibility`
+    /// allows. Only the default view (music, hidden tracks left out, every
+    /// folder) comes from the browse table.
+    pub async fn genres(&self, db: &DatabaseConnection, visibility: &Visibility, media_types: &MediaTypes) -> Result<Arc<Vec<GenreCount>>, DbErr> {
+        let condition = visibility.condition().add(media_types.condition());
+        if !visibility.is_default() || !media_types.is_default() {
+            return genre_counts(db, condition).await.map(Arc::new);
+        }
+        if let Some(genres) = self.genres.read().unwrap().as_ref() {
+            return Ok(genres.clone());
+        }
+
+        let mut genres: Vec<GenreCount> = BrowseGenre::find()
+            .order_by_asc(browse_genre::Column::Genre)
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|row| GenreCount { name: row.genre, song_count: row.song_count, album_count: row.album_count })
+            .collect();
+        // Empty until the first scan after upgrading fills it
+        if genres.is_empty() {
+            genres = genre_counts(db, condition).await?;
+        }
+        let genres = Arc::new(genres);
+        *self.genres.write().unwrap() = Some(genres.clone());
+        Ok(genres)
+    }
+
+    /// Per-artist counts of the tracks of `media_types` that `visibility`
+    /// allows, ordered by name without leading articles and read like genres
+    pub async fn artists(
+        &self,
+        db: &DatabaseConnection,
+        visibility: &Visibility,
+        media_types: &MediaTypes,
+        normalization: TextNormalization,
+        articles: &IgnoredArticles,
+    ) -> Result<Arc<Vec<ArtistCount>>, DbErr> {
+        let condition = visibility.condition().add(media_types.condition());
+        if !visibility.is_default() || !media_types.is_default() {
+            return artist_counts(db, condition, normalization, articles).await.map(Arc::new);
+        }
+        if let Some(artists) = self.artists.read().unwrap().as_ref() {
+            return Ok(artists.clone());
+        }
+
+        let mut rows: Vec<ArtistRow> = BrowseArtist::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|row| (row.artist, row.album_count, row.song_count, row.added))
+            .collect();
+        if rows.is_empty() {
+            rows = artist_rows(db, condition).await?;
+        }
+        let artists = Arc::new(sorted_artists(db, rows, normalization, articles).await?);
+        *self.artists.write().unwrap() = Some(artists.clone());
+        Ok(artists)
+    }
+}
+
+/// Bring the browse tables up to date with the default view (music, hidden
+/// tracks left out, every folder). Only rows whose counts changed are written.
+pub async fn refresh_browse_tables(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let condition = Visibility::load(db, false, None).await?.condition().add(MediaTypes::default().condition());
+    let genres = genre_counts(db, condition.clone()).await?;
+    let artists = artist_rows(db, condition.clone()).await?;
+    let albums = albums::album_summary_rows(db, condition.clone(), Condition::all()).await?;
+    let formats = albums::album_formats(db, condition).await?;
+
+    let txn = db.begin().await?;
+    let changed = refresh_browse_genres(&txn, genres).await?
+        + refresh_browse_artists(&txn, artists).await?
+        + refresh_browse_albums(&txn, albums, &formats).await?;
+    txn.commit().await?;
+    if changed > 0 {
+        info!("Updated {} browse rows", changed);
+    }
+    Ok(())
+}
+
+async fn refresh_browse_genres(txn: &DatabaseTransaction, genres: Vec<GenreCount>) -> Result<usize, DbErr> {
+    let mut existing: HashMap<String, browse_genre::Model> = BrowseGenre::find()
+        .all(txn)
+        .await?
+        .into_iter()
+        .map(|row| (row.genre.clone(), row))
+        .collect();
+    let mut new_rows = Vec::new();
+    let mut changed = 0;
+    for genre in genres {
+        let id = match existing.remove(&genre.name) {
+            Some(row) if row.song_count == genre.song_count && row.album_count == genre.album_count => continue,
+            Some(row) => Set(row.id),
+            None => NotSet,
+        };
+        let row = browse_genre::ActiveModel {
+            id,
+            genre: Set(genre.name),
+            song_count: Set(genre.song_count),
+            album_count: Set(genre.album_count),
+        };
+        if row.id.is_set() {
+            row.update(txn).await?;
+            changed += 1;
+        } else {
+            new_rows.push(row);
+        }
+    }
+
+    let stale: Vec<i32> = existing.into_values().map(|row| row.id).collect();
+    for chunk in stale.chunks(1000) {
+        BrowseGenre::delete_many()
+            .filter(browse_genre::Column::Id.is_in(chunk.iter().copied()))
+            .exec(txn)
+            .await?;
+    }
+    for chunk in new_rows.chunks(1000) {
+        BrowseGenre::insert_many(chunk.to_vec()).exec(txn).await?;
+    }
+    Ok(changed + stale.len() + new_rows.len())
+}
+
+async fn refresh_browse_artists(txn: &DatabaseTransaction, artists: Vec<ArtistRow>) -> Result<usize, DbErr> {
+    let mut existing: HashMap<String, browse_artist::Model> = BrowseArtist::find()
+        .all(txn)
+        .await?
+        .into_iter()
+        .map(|row| (row.artist.clone(), row))
+        .collect();
+    let mut new_rows = Vec::new();
+    let mut changed = 0;
+    for (artist, album_count, song_count, added) in artists {
+        let id = match existing.remove(&artist) {
+            Some(row) if row.album_count == album_count && row.song_count == song_count && row.added == added => continue,
+            Some(row) => Set(row.id),
+            None => NotSet,
+        };
+        let row = browse_artist::ActiveModel {
+            id,
+            artist: Set(artist),
+            album_count: Set(album_count),
+            song_count: Set(song_count),
+            added: Set(added),
+        };
+        if row.id.is_set() {
+            row.update(txn).await?;
+            changed += 1;
+        } else {
+            new_rows.push(row);
+        }
+    }
+
+    let stale: Vec<i32> = existing.into_values().map(|row| row.id).collect();
+    for chunk in stale.chunks(1000) {
+        BrowseArtist::delete_many()
+            .filter(browse_artist::Column::Id.is_in(chunk.iter().copied()))
+            .exec(txn)
+            .await?;
+    }
+    for chunk in new_rows.chunks(1000) {
+        BrowseArtist::insert_many(chunk.to_vec()).exec(txn).await?;
+    }
+    Ok(changed + stale.len() + new_rows.len())
+}
+
+async fn refresh_browse_albums(
+    txn: &DatabaseTransaction,
+    albums: Vec<AlbumSummaryRow>,
+    formats: &HashMap<String, FormatSummaryBuilder>,
+) -> Result<usize, DbErr> {
+    let mut existing: HashMap<(String, String), browse_album::Model> = BrowseAlbum::find()
+        .all(txn)
+        .await?
+        .into_iter()
+        .map(|row| ((row.album_artist.clone(), row.album.clone()), row))
+        .collect();
+    let mut new_rows = Vec::new();
+    let mut changed = 0;
+    for (album_artist, album, year, track_count, duration_seconds, added) in albums {
+        let format = formats.get(&albums::album_key(&album_artist, &album))
+            .map(FormatSummaryBuilder::build)
+            .unwrap_or_default();
+        let format = serde_json::to_value(format).unwrap_or_default();
+        let duration_seconds = duration_seconds.unwrap_or(0);
+        let id = match existing.remove(&(album_artist.clone(), album.clone())) {
+            Some(row) if row.year == year
+                && row.track_count == track_count
+                && row.duration_seconds == duration_seconds
+                && row.format == format
+                && row.added == added => continue,
+            Some(row) => Set(row.id),
+            None => NotSet,
+        };
+        let row = browse_album::ActiveModel {
+            id,
+            album_artist: Set(album_artist),
+            album: Set(album),
+            year: Set(year),
+            track_count: Set(track_count),
+            duration_seconds: Set(duration_seconds),
+            format: Set(format),
+            added: Set(added),
+        };
+        if row.id.is_set() {
+            row.update(txn).await?;
+            changed += 1;
+        } else {
+            new_rows.push(row);
+        }
+    }
+
+    let stale: Vec<i32> = existing.into_values().map(|row| row.id).collect();
+    for chunk in stale.chunks(1000) {
+        BrowseAlbum::delete_many()
+            .filter(browse_album::Column::Id.is_in(chunk.iter().copied()))
+            .exec(txn)
+            .await?;
+    }
+    for chunk in new_rows.chunks(1000) {
+        BrowseAlbum::insert_many(chunk.to_vec()).exec(txn).await?;
+    }
+    Ok(changed + stale.len() + new_rows.len())
+}
+
+async fn genre_counts(db: &DatabaseConnection, condition: Condition) -> Result<Vec<GenreCount>, DbErr> {
+    let genres = Track::find()
+        .select_only()
+        .column(track::Column::Genre)
+        .column_as(Expr::col(track::Column::Id).count(), "song_count")
+        .column_as(Expr::cust(ALBUM_COUNT_EXPR), "album_count")
+        .filter(track::Column::Genre.ne(""))
+        .filter(condition)
+        .group_by(track::Column::Genre)
+        .order_by_asc(track::Column::Genre)
+        .into_tuple::<(String, i64, i64)>()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|(name, song_count, album_count)| GenreCount { name, song_count, album_count })
+        .collect();
+    Ok(genres)
+}
+
+/// Sort artist names the way the index buckets them, ignoring leading articles,
+/// case and accents
+pub fn sort_artist_names(names: &mut [String], normalization: TextNormalization, articles: &IgnoredArticles) {
+    names.sort_by_cached_key(|name| (text_index::artist_sort_key(normalization, articles, name), name.clone()));
+}
+
+/// Artists pinned above the index with INDEX_SHORTCUTS, in the order given.
+/// Names are matched ignoring case; ones without tracks are left out.
+pub fn index_shortcuts(artists: &[ArtistCount]) -> Vec<ArtistCount> {
+    env::var("INDEX_SHORTCUTS").unwrap_or_default()
+        .split(';')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| artists.iter().find(|artist| artist.name.eq_ignore_ascii_case(name)).cloned())
+        .collect()
+}
+
+/// Tracks `condition` allows that sit directly in a library folder rather than
+/// in an artist or album folder, by path, at most `limit` of them
+pub async fn root_tracks(db: &DatabaseConnection, condition: Condition, limit: Option<u64>) -> Result<Vec<track::Model>, DbErr> {
+    // What follows "library_root/" has no further slash
+    let loose = Expr::cust(r#""track"."library_root" IS NOT NULL AND strpos(substr("track"."path", length("track"."library_root") + 2), '/') = 0"#);
+    Track::find()
+        .filter(loose)
+        .filter(condition)
+        .order_by_asc(track::Column::Path)
+        .limit(limit)
+        .all(db)
+        .await
+}
+
+/// Group sorted artists under the first letter of their sort key, with "#" last
+pub fn artist_index(artists: &[ArtistCount], normalization: TextNormalization, articles: &IgnoredArticles) -> Vec<ArtistIndex> {
+    let mut buckets: BTreeMap<(bool, String), Vec<ArtistCount>> = BTreeMap::new();
+    for artist in artists {
+        let letter = text_index::index_letter(&text_index::artist_sort_key(normalization, articles, &artist.name));
+        buckets.entry((letter == "#", letter)).or_default().push(artist.clone());
+    }
+    buckets.into_iter()
+        .map(|((_, name), artists)| ArtistIndex { name, artists })
+        .collect()
+}
+
+// artist, album_count, song_count, added
+type ArtistRow = (String, i64, i64, Option<DateTime<Utc>>);
+
+// Per-artist counts of the tracks `condition` selects, in no particular order
+async fn artist_rows(db: &DatabaseConnection, condition: Condition) -> Result<Vec<ArtistRow>, DbErr> {
+    // Counted through track_artist so collaborations count for every participant
+    TrackArtist::find()
+        .select_only()
+        .column(track_artist::Column::Artist)
+        .column_as(Expr::cust(ALBUM_COUNT_EXPR), "album_count")
+        .column_as(Expr::col((track::Entity, track::Column::Id)).count(), "song_count")
+        .column_as(Expr::col((track::Entity, track::Column::Added)).max(), "added")
+        .join(JoinType::InnerJoin, track_artist::Relation::Track.def())
+        .filter(condition)
+        .group_by(track_artist::Column::Artist)
+        .into_tuple::<ArtistRow>()
+        .all(db)
+        .await
+}
+
+/// Per-artist counts of the tracks `condition` selects, ordered by name
+/// without leading articles
+pub async fn artist_counts(
+    db: &DatabaseConnection,
+    condition: Condition,
+    normalization: TextNormalization,
+    articles: &IgnoredArticles,
+) -> Result<Vec<ArtistCount>, DbErr> {
+    let rows = artist_rows(db, condition).await?;
+    sorted_artists(db, rows, normalization, articles).await
+}
+
+// Artists with their slugs, ordered by name without leading articles
+async fn sorted_artists(
+    db: &DatabaseConnection,
+    rows: Vec<ArtistRow>,
+    normalization: TextNormalization,
+    articles: &IgnoredArticles,
+) -> Result<Vec<ArtistCount>, DbErr> {
+    let slugs = slugs::slugs_by_key(db, slugs::ARTIST).await?;
+    let mut artists: Vec<ArtistCount> = rows.into_iter()
+        .map(|(name, album_count, song_count, added)| ArtistCount {
+            id: slugs.get(&name).cloned(),
+            name,
+            album_count,
+            song_count,
+            added,
+        })
+        .collect();
+    artists.sort_by_cached_key(|artist| (text_index::artist_sort_key(normalization, articles, &artist.name), artist.name.clone()));
+    Ok(artists)
+}
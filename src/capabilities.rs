@@ -0,0 +1,144 @@
+use std::env;
+
+use axum::{
+    extract::State,
+    http::{StatusCode, Uri},
+    response::Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::api::AppState;
+use crate::error::{ApiError, ApiResult};
+
+/// An optional part of the API and the routes it covers
+struct Feature {
+    name: &'static str,
+    description: &'static str,
+    /// Route prefixes under /api/v1
+    paths: &'static [&'static str],
+}
+
+// Features this server has, available when configured
+const FEATURES: &[Feature] = &[
+    Feature { name: "streaming", description: "Streaming with Range requests and stream sessions", paths: &["/tracks/:id/play", "/admin/sessions"] },
+    Feature { name: "transcoding", description: "Transcoding profiles (needs ffmpeg)", paths: &["/transcoding/profiles"] },
+    Feature { name: "signed_streams", description: "Signed, expiring stream URLs (STREAM_SIGNING_KEY)", paths: &["/tracks/:id/stream-url"] },
+    Feature { name: "album_streams", description: "Whole albums as one gapless stream with chapters (needs ffmpeg, experimental)", paths: &["/albums/:id/stream"] },
+    Feature { name: "hls", description: "HLS playlists with synced lyrics as subtitles", paths: &["/tracks/:id/hls"] },
+    Feature { name: "lyrics", description: "Lyrics from files and tags", paths: &["/tracks/:id/lyrics"] },
+    Feature { name: "lyrics_providers", description: "Lyrics from LRCLIB and Genius (LYRICS_PROVIDERS)", paths: &["/admin/lyrics"] },
+    Feature { name: "album_art", description: "Embedded images, folder art and placeholder art", paths: &["/tracks/:id/albumart", "/albums/:id/cover"] },
+    Feature { name: "downloads", description: "Original file downloads, gated by a per-user download role", paths: &["/download", "/download.view"] },
+    Feature { name: "previews", description: "Loudness normalized preview clips (needs ffmpeg)", paths: &["/tracks/:id/preview", "/admin/previews"] },
+    Feature { name: "history", description: "Track change history with revert", paths: &["/tracks/:id/history"] },
+    Feature { name: "audiobooks", description: "Audiobooks with chapters and resume positions", paths: &["/audiobooks"] },
+    Feature { name: "search", description: "Track search, with field filters and boolean operators", paths: &["/tracks/search"] },
+    Feature { name: "scanning", description: "Library rescans, scan status and scan errors", paths: &["/rescan"] },
+    Feature { name: "lastfm", description: "Last.fm scrobbling (LASTFM_API_KEY)", paths: &["/lastfm", "/tracks/:id/scrobble", "/tracks/:id/now-playing"] },
+    Feature { name: "listening", description: "Listening history, annotations and playback progress", paths: &["/tracks/:id/plays", "/tracks/:id/annotation", "/tracks/:id/progress", "/stats/listening"] },
+    Feature { name: "playqueue", description: "Play queue persistence", paths: &["/playqueue"] },
+    Feature { name: "playlists", description: "Playlists and M3U/XSPF import and export", paths: &["/playlists"] },
+    Feature { name: "recommendations", description: "Playlists from Last.fm recommendations (LASTFM_API_KEY)", paths: &["/playlists/recommended", "/playlists/:id/refresh"] },
+    Feature { name: "webhooks", description: "Outgoing event notifications (WEBHOOK_URLS)", paths: &["/admin/webhooks"] },
+    Feature { name: "conversions", description: "Batch audio format conversion", paths: &["/admin/conversions"] },
+    Feature { name: "diagnostics", description: "Diagnostics bundle for bug reports", paths: &["/admin/diagnostics", "/admin/query-log"] },
+    Feature { name: "backups", description: "Online database backups (BACKUP_DIR, needs pg_dump)", paths: &["/admin/backup"] },
+    Feature { name: "folder_permissions", description: "Per-user folder permissions", paths: &["/admin/users"] },
+];
+
+// Features other music servers have that clients probe for. Their routes
+// answer 501 Not Implemented instead of 404 so clients can tell them apart
+// from a broken server.
+const UNSUPPORTED_FEATURES: &[Feature] = &[
+    Feature { name: "internet_radio", description: "Internet radio stations", paths: &["/radio", "/internet-radio"] },
+    Feature { name: "podcasts", description: "Podcast channels and episodes", paths: &["/podcasts"] },
+    Feature { name: "shares", description: "Public share links", paths: &["/shares"] },
+    Feature { name: "jukebox", description: "Server-side jukebox playback", paths: &["/jukebox"] },
+    Feature { name: "chat", description: "Chat messages", paths: &["/chat"] },
+    Feature { name: "bookmarks", description: "Bookmarks", paths: &["/bookmarks"] },
+    Feature { name: "video", description: "Video streaming", paths: &["/videos"] },
+];
+
+#[derive(Serialize, ToSchema)]
+pub struct CapabilityResponse {
+    pub name: String,
+    pub description: String,
+    /// Whether the feature can be used: supported and, where it needs it, configured
+    pub available: bool,
+    /// Route prefixes under /api/v1
+    pub paths: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CapabilitiesResponse {
+    pub server: String,
+    pub version: String,
+    pub api_version: String,
+    pub demo_mode: bool,
+    pub capabilities: Vec<CapabilityResponse>,
+}
+
+fn is_available(state: &AppState, name: &str) -> bool {
+    match name {
+        // Demo mode refuses admin routes, Last.fm and anything that changes state
+        "album_streams" | "backups" | "conversions" | "diagnostics" | "downloads" | "folder_permissions" | "lastfm" | "recommendations" | "webhooks" if state.demo_mode => false,
+        "signed_streams" => state.stream_signer.is_some(),
+        "lyrics_providers" => state.lyrics.is_enabled(),
+        "lastfm" | "recommendations" => env::var("LASTFM_API_KEY").is_ok_and(|key| !key.is_empty()),
+        "webhooks" => state.webhooks.is_enabled(),
+        "backups" => state.backups.is_enabled(),
+        _ => true,
+    }
+}
+
+fn capability(feature: &Feature, available: bool) -> CapabilityResponse {
+    CapabilityResponse {
+        name: feature.name.to_string(),
+        description: feature.description.to_string(),
+        available,
+        paths: feature.paths.iter().map(|path| path.to_string()).collect(),
+    }
+}
+
+// GET /capabilities - List the features this server supports, so clients can detect them
+#[utoipa::path(
+    get,
+    path = "/capabilities",
+    tag = "library",
+    responses(
+        (status = 200, description = "Supported and unsupported features", body = CapabilitiesResponse),
+    )
+)]
+pub async fn get_capabilities(State(state): State<AppState>) -> Json<CapabilitiesResponse> {
+    let capabilities = FEATURES.iter()
+        .map(|feature| capability(feature, is_available(&state, feature.name)))
+        .chain(UNSUPPORTED_FEATURES.iter().map(|feature| capability(feature, false)))
+        .collect();
+
+    Json(CapabilitiesResponse {
+        server: env!("CARGO_PKG_NAME").to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        api_version: "v1".to_string(),
+        demo_mode: state.demo_mode,
+        capabilities,
+    })
+}
+
+fn matches_path(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Fallback for routes that don't exist: a problem response instead of an
+/// empty 404, with 501 Not Implemented for features this server doesn't have
+pub async fn unknown_route(uri: Uri) -> ApiResult<()> {
+    let path = uri.path();
+    if let Some(feature) = UNSUPPORTED_FEATURES.iter().find(|feature| feature.paths.iter().any(|prefix| matches_path(path, prefix))) {
+        return Err(ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "not_implemented",
+            format!("This server doesn't support {}, see /capabilities", feature.description.to_lowercase()),
+        ));
+    }
+    Err(ApiError::new(StatusCode::NOT_FOUND, "unknown_endpoint", format!("No endpoint at {}, see /docs", path)))
+}
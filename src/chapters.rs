@@ -0,0 +1,118 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Boxes walked at each level before giving up on an MP4 file
+const MAX_MP4_BOXES: usize = 64;
+// Nero chapter start times are in units of 100 nanoseconds
+const CHPL_UNITS_PER_MS: u64 = 10_000;
+
+/// A chapter embedded in a file, with times from the start of the file
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Chapter {
+    pub title: String,
+    pub start_ms: i64,
+    pub end_ms: i64,
+}
+
+/// Chapters from the track's stored chapter list
+pub fn of_track(chapters: &serde_json::Value) -> Vec<Chapter> {
+    serde_json::from_value(chapters.clone()).unwrap_or_default()
+}
+
+// Content range of the first box named `name` between `start` and `end`
+fn find_box<R: Read + Seek>(reader: &mut R, start: u64, end: u64, name: &[u8; 4]) -> io::Result<Option<(u64, u64)>> {
+    let mut offset = start;
+    for _ in 0..MAX_MP4_BOXES {
+        if offset + 8 > end {
+            break;
+        }
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let (size, header_size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            // The box runs to the end of its parent
+            0 => (end - offset, 8),
+            // A 64-bit size follows the type
+            1 => {
+                let mut large = [0u8; 8];
+                reader.read_exact(&mut large)?;
+                (u64::from_be_bytes(large), 16)
+            }
+            size => (size as u64, 8),
+        };
+        if size < header_size || offset + size > end {
+            break;
+        }
+        if &header[4..] == name {
+            return Ok(Some((offset + header_size, offset + size)));
+        }
+        offset += size;
+    }
+    Ok(None)
+}
+
+/// Chapter starts and titles from the Nero chapter list (moov/udta/chpl) of an
+/// MP4 or M4B file, as written by ffmpeg and most audiobook tools. Empty for
+/// files without one.
+pub fn read_mp4_starts<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<(i64, String)>> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    let Some((moov, moov_end)) = find_box(reader, 0, end, b"moov")? else {
+        return Ok(Vec::new());
+    };
+    let Some((udta, udta_end)) = find_box(reader, moov, moov_end, b"udta")? else {
+        return Ok(Vec::new());
+    };
+    let Some((chpl, chpl_end)) = find_box(reader, udta, udta_end, b"chpl")? else {
+        return Ok(Vec::new());
+    };
+
+    let mut data = vec![0u8; (chpl_end - chpl) as usize];
+    reader.seek(SeekFrom::Start(chpl))?;
+    reader.read_exact(&mut data)?;
+
+    // Version and flags, then four reserved bytes in version 1
+    let mut offset = if data.first() == Some(&1) { 8 } else { 4 };
+    let Some(&count) = data.get(offset) else {
+        return Ok(Vec::new());
+    };
+    offset += 1;
+
+    let mut starts = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let Some(start) = data.get(offset..offset + 8) else {
+            break;
+        };
+        let start = u64::from_be_bytes(start.try_into().unwrap());
+        let length = data.get(offset + 8).copied().unwrap_or(0) as usize;
+        let Some(title) = data.get(offset + 9..offset + 9 + length) else {
+            break;
+        };
+        starts.push(((start / CHPL_UNITS_PER_MS) as i64, String::from_utf8_lossy(title).trim().to_string()));
+        offset += 9 + length;
+    }
+    Ok(starts)
+}
+
+/// Turn chapter starts into chapters ending where the next begins, the last
+/// at the end of the file. Untitled chapters are numbered.
+pub fn from_starts(mut starts: Vec<(i64, String)>, duration_ms: i64) -> Vec<Chapter> {
+    starts.sort_by_key(|(start, _)| *start);
+    starts.dedup_by_key(|(start, _)| *start);
+    let ends: Vec<i64> = starts.iter()
+        .skip(1)
+        .map(|(start, _)| *start)
+        .chain([duration_ms])
+        .collect();
+
+    starts.into_iter()
+        .zip(ends)
+        .enumerate()
+        .map(|(index, ((start_ms, title), end_ms))| Chapter {
+            title: if title.is_empty() { format!("Chapter {}", index + 1) } else { title },
+            start_ms,
+            end_ms: end_ms.max(start_ms),
+        })
+        .collect()
+}
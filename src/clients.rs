@@ -0,0 +1,194 @@
+use axum::{
+    extract::State,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, DbErr, EntityTrait, NotSet, QueryOrder};
+use sea_query::OnConflict;
+use serde::{Deserialize, Deserializer, Serialize};
+use utoipa::ToSchema;
+
+use entity::{client, track};
+use entity::prelude::Client;
+
+use crate::api::AppState;
+use crate::error::{ApiError, ApiJson, ApiPath, ApiResult};
+use crate::transcoder::{ProfileFormat, TranscodeProfile};
+
+// Long User-Agent strings are cut to keep the registry readable
+const MAX_IDENTIFIER_LENGTH: usize = 512;
+
+fn identifier(value: Option<&str>) -> String {
+    value.map(str::trim).unwrap_or_default().chars().take(MAX_IDENTIFIER_LENGTH).collect()
+}
+
+/// Record a client (its client name and User-Agent) the first time it
+/// streams and update when it was last seen. Returns the client with any
+/// overrides an admin has set, or None for requests that identify neither.
+pub async fn identify(db: &DatabaseConnection, name: Option<&str>, user_agent: Option<&str>) -> Result<Option<client::Model>, DbErr> {
+    let name = identifier(name);
+    let user_agent = identifier(user_agent);
+    if name.is_empty() && user_agent.is_empty() {
+        return Ok(None);
+    }
+
+    let now = Utc::now();
+    let model = Client::insert(client::ActiveModel {
+        id: NotSet,
+        name: Set(name),
+        user_agent: Set(user_agent),
+        first_seen: Set(now),
+        last_seen: Set(now),
+        force_profile: Set(None),
+        max_bitrate: Set(None),
+        disable_chunking: Set(false),
+    })
+    .on_conflict(
+        OnConflict::columns([client::Column::Name, client::Column::UserAgent])
+            .update_column(client::Column::LastSeen)
+            .to_owned(),
+    )
+    .exec_with_returning(db)
+    .await?;
+    Ok(Some(model))
+}
+
+/// Cap a stream at a client's maximum bitrate: profiles above it are lowered
+/// to it, lossless profiles become MP3, and original files above it are
+/// transcoded to MP3. Tracks with an unknown bitrate are left as they are.
+pub fn cap_bitrate(profile: Option<TranscodeProfile>, max_kbps: u32, track: &track::Model) -> Option<TranscodeProfile> {
+    match profile {
+        Some(mut profile) if profile.bitrate_kbps == 0 || profile.bitrate_kbps > max_kbps => {
+            if profile.bitrate_kbps == 0 {
+                profile.format = ProfileFormat::Mp3;
+            }
+            profile.bitrate_kbps = max_kbps;
+            Some(profile)
+        }
+        Some(profile) => Some(profile),
+        None if track.audio_bitrate > 0 && track.audio_bitrate as u32 > max_kbps => Some(TranscodeProfile {
+            name: "max-bitrate".to_string(),
+            format: ProfileFormat::Mp3,
+            bitrate_kbps: max_kbps,
+            channels: None,
+        }),
+        None => None,
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ClientResponse {
+    pub id: i32,
+    /// The `client` parameter the client streams with, empty when it sends none
+    pub name: String,
+    pub user_agent: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Transcoding profile every stream uses, or "original" to never transcode
+    pub force_profile: Option<String>,
+    /// Streams above this bitrate (kbps) are transcoded down to it
+    pub max_bitrate: Option<i32>,
+    /// Ignore Range requests and always send whole files
+    pub disable_chunking: bool,
+}
+
+impl From<client::Model> for ClientResponse {
+    fn from(model: client::Model) -> Self {
+        Self {
+            id: model.id,
+            name: model.name,
+            user_agent: model.user_agent,
+            first_seen: model.first_seen,
+            last_seen: model.last_seen,
+            force_profile: model.force_profile,
+            max_bitrate: model.max_bitrate,
+            disable_chunking: model.disable_chunking,
+        }
+    }
+}
+
+// Tells a field set to null (Some(None)) apart from one left out (None)
+fn nullable<'de, T: Deserialize<'de>, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<T>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// Overrides to change; fields left out stay as they are and null clears them
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateClientRequest {
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub force_profile: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i32>)]
+    pub max_bitrate: Option<Option<i32>>,
+    pub disable_chunking: Option<bool>,
+}
+
+// GET /admin/clients - List every client seen streaming, most recent first
+#[utoipa::path(
+    get,
+    path = "/admin/clients",
+    tag = "clients",
+    responses(
+        (status = 200, description = "Known clients with their overrides", body = Vec<ClientResponse>),
+    )
+)]
+pub async fn get_clients(State(state): State<AppState>) -> ApiResult<Json<Vec<ClientResponse>>> {
+    let clients = Client::find()
+        .order_by_desc(client::Column::LastSeen)
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(ClientResponse::from)
+        .collect();
+    Ok(Json(clients))
+}
+
+// PATCH /admin/clients/:id - Set a client's playback overrides
+#[utoipa::path(
+    patch,
+    path = "/admin/clients/{id}",
+    tag = "clients",
+    params(("id" = i32, Path, description = "Client ID")),
+    request_body = UpdateClientRequest,
+    responses(
+        (status = 200, description = "Updated client", body = ClientResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Client not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn update_client(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    ApiJson(request): ApiJson<UpdateClientRequest>,
+) -> ApiResult<Json<ClientResponse>> {
+    let model = Client::find_by_id(id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Client {} not found", id)))?;
+
+    let mut active: client::ActiveModel = model.into();
+    if let Some(force_profile) = request.force_profile {
+        let force_profile = match force_profile {
+            Some(name) if name.trim().eq_ignore_ascii_case("original") => Some("original".to_string()),
+            Some(name) => match state.transcoding.profile(&name) {
+                Some(profile) => Some(profile.name.clone()),
+                None => return Err(ApiError::invalid_field("force_profile", format!("unknown profile '{}'", name.trim()))),
+            },
+            None => None,
+        };
+        active.force_profile = Set(force_profile);
+    }
+    if let Some(max_bitrate) = request.max_bitrate {
+        if max_bitrate.is_some_and(|max_bitrate| max_bitrate <= 0) {
+            return Err(ApiError::invalid_field("max_bitrate", "must be 1 or greater"));
+        }
+        active.max_bitrate = Set(max_bitrate);
+    }
+    if let Some(disable_chunking) = request.disable_chunking {
+        active.disable_chunking = Set(disable_chunking);
+    }
+
+    Ok(Json(ClientResponse::from(active.update(&state.db).await?)))
+}
@@ -0,0 +1,123 @@
+use std::path::Path;
+
+use lofty::file::FileType;
+
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Content types for the audio and image formats found in music libraries,
+/// keyed by lowercase file extension
+const EXTENSION_TABLE: &[(&str, &str)] = &[
+    ("aac", "audio/aac"),
+    ("aif", "audio/aiff"),
+    ("aifc", "audio/aiff"),
+    ("aiff", "audio/aiff"),
+    ("alac", "audio/mp4"),
+    ("ape", "audio/x-ape"),
+    ("dsf", "audio/x-dsf"),
+    ("flac", "audio/flac"),
+    ("m4a", "audio/mp4"),
+    ("m4b", "audio/mp4"),
+    ("mka", "audio/x-matroska"),
+    ("mp2", "audio/mpeg"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "audio/mp4"),
+    ("mpc", "audio/x-musepack"),
+    ("oga", "audio/ogg"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/ogg; codecs=opus"),
+    ("spx", "audio/ogg; codecs=speex"),
+    ("wav", "audio/wav"),
+    ("weba", "audio/webm"),
+    ("webm", "audio/webm"),
+    ("wma", "audio/x-ms-wma"),
+    ("wv", "audio/x-wavpack"),
+    ("gif", "image/gif"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("png", "image/png"),
+    ("webp", "image/webp"),
+];
+
+/// Look up the content type for a file extension
+pub fn from_extension(extension: &str) -> Option<&'static str> {
+    let extension = extension.to_ascii_lowercase();
+    EXTENSION_TABLE.iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, content_type)| *content_type)
+}
+
+/// Content type for a path based on its extension, falling back to mime_guess
+/// for anything outside the table
+pub fn from_path(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(from_extension)
+        .map(str::to_string)
+        .unwrap_or_else(|| mime_guess::from_path(path).first_or_octet_stream().to_string())
+}
+
+/// Content type for a format detected by lofty while reading tags
+pub fn from_file_type(file_type: &FileType) -> Option<&'static str> {
+    match file_type {
+        FileType::Aac => Some("audio/aac"),
+        FileType::Aiff => Some("audio/aiff"),
+        FileType::Ape => Some("audio/x-ape"),
+        FileType::Flac => Some("audio/flac"),
+        FileType::Mpeg => Some("audio/mpeg"),
+        FileType::Mp4 => Some("audio/mp4"),
+        FileType::Mpc => Some("audio/x-musepack"),
+        FileType::Opus => Some("audio/ogg; codecs=opus"),
+        FileType::Vorbis => Some("audio/ogg"),
+        FileType::Speex => Some("audio/ogg; codecs=speex"),
+        FileType::Wav => Some("audio/wav"),
+        FileType::WavPack => Some("audio/x-wavpack"),
+        _ => None,
+    }
+}
+
+/// Sniff the content type from the first bytes of a file
+pub fn sniff(header: &[u8]) -> Option<&'static str> {
+    let starts_with = |magic: &[u8]| header.starts_with(magic);
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+
+    if starts_with(b"fLaC") {
+        Some("audio/flac")
+    } else if starts_with(b"OggS") {
+        if header.windows(8).any(|window| window == b"OpusHead") {
+            Some("audio/ogg; codecs=opus")
+        } else {
+            Some("audio/ogg")
+        }
+    } else if starts_with(b"ID3") || (header.len() >= 2 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0 && header[1] & 0x06 != 0) {
+        Some("audio/mpeg")
+    } else if header.len() >= 2 && header[0] == 0xFF && header[1] & 0xF6 == 0xF0 {
+        Some("audio/aac")
+    } else if starts_with(b"RIFF") && at(8, b"WAVE") {
+        Some("audio/wav")
+    } else if starts_with(b"RIFF") && at(8, b"WEBP") {
+        Some("image/webp")
+    } else if starts_with(b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+        Some("audio/aiff")
+    } else if at(4, b"ftyp") {
+        Some("audio/mp4")
+    } else if starts_with(&[0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11]) {
+        Some("audio/x-ms-wma")
+    } else if starts_with(b"MAC ") {
+        Some("audio/x-ape")
+    } else if starts_with(b"wvpk") {
+        Some("audio/x-wavpack")
+    } else if starts_with(b"MPCK") || starts_with(b"MP+") {
+        Some("audio/x-musepack")
+    } else if starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        Some("audio/webm")
+    } else if starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if starts_with(&[0x89, b'P', b'N', b'G']) {
+        Some("image/png")
+    } else if starts_with(b"GIF8") {
+        Some("image/gif")
+    } else {
+        None
+    }
+}
@@ -0,0 +1,432 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, NotSet, QueryFilter, QueryOrder};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use entity::prelude::Track;
+use entity::track;
+
+use crate::albums;
+use crate::api::AppState;
+use crate::error::{ApiError, ApiJson, ApiPath, ApiResult};
+use crate::scanner;
+use crate::storage::{FileEntry, LocalStorage};
+use crate::transcoder::{self, TranscodeOptions};
+
+const DEFAULT_TEMPLATE: &str = "{album_artist}/{album}/{disc}-{track} {title}";
+const PLACEHOLDERS: &[&str] = &["artist", "album_artist", "album", "title", "track", "disc", "year", "genre", "filename"];
+const DEFAULT_OPUS_BITRATE: u32 = 160;
+// Finished jobs kept for GET /admin/conversions, and errors kept per job
+const FINISHED_JOB_LIMIT: usize = 20;
+const JOB_ERROR_LIMIT: usize = 100;
+
+#[derive(Clone, Copy, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConversionFormat {
+    Flac,
+    Opus,
+}
+
+impl ConversionFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ConversionFormat::Flac => "flac",
+            ConversionFormat::Opus => "opus",
+        }
+    }
+
+    fn options(self, bitrate_kbps: Option<u32>) -> TranscodeOptions {
+        match self {
+            ConversionFormat::Flac => TranscodeOptions::flac(),
+            ConversionFormat::Opus => TranscodeOptions::opus(bitrate_kbps.unwrap_or(DEFAULT_OPUS_BITRATE)),
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ConversionRequest {
+    /// Convert tracks with this file extension, e.g. "wav"
+    pub extension: Option<String>,
+    /// Convert tracks under this directory
+    pub path_prefix: Option<String>,
+    /// Convert these tracks
+    pub track_ids: Option<Vec<i32>>,
+    pub format: ConversionFormat,
+    /// Opus bitrate (default 160); ignored for FLAC
+    pub bitrate_kbps: Option<u32>,
+    /// Absolute directory the converted files are written under
+    pub target_dir: String,
+    /// Output path relative to target_dir, without extension. Placeholders:
+    /// {artist}, {album_artist}, {album}, {title}, {track}, {disc}, {year},
+    /// {genre} and {filename}
+    pub naming_template: Option<String>,
+    /// Point the library entries at the converted files once written
+    #[serde(default)]
+    pub repoint: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Completed,
+    Cancelled,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct ConversionError {
+    pub track_id: i32,
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct ConversionJob {
+    pub id: String,
+    pub state: JobState,
+    pub format: ConversionFormat,
+    pub target_dir: String,
+    pub repoint: bool,
+    pub total: usize,
+    pub converted: usize,
+    /// Tracks whose output file already existed
+    pub skipped: usize,
+    pub failed: usize,
+    /// Track being converted
+    pub current: Option<String>,
+    pub errors: Vec<ConversionError>,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+}
+
+struct JobEntry {
+    job: RwLock<ConversionJob>,
+    cancelled: AtomicBool,
+}
+
+/// Batch conversion jobs, kept in memory. Running jobs and the most recent
+/// finished ones are listed; a restart forgets them.
+#[derive(Default)]
+pub struct ConversionJobs {
+    jobs: RwLock<Vec<Arc<JobEntry>>>,
+}
+
+impl ConversionJobs {
+    fn start(&self, job: ConversionJob) -> Arc<JobEntry> {
+        let entry = Arc::new(JobEntry {
+            job: RwLock::new(job),
+            cancelled: AtomicBool::new(false),
+        });
+        let mut jobs = self.jobs.write().unwrap();
+        jobs.push(entry.clone());
+
+        let finished: Vec<usize> = jobs.iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.job.read().unwrap().state != JobState::Running)
+            .map(|(index, _)| index)
+            .collect();
+        for index in finished.iter().take(finished.len().saturating_sub(FINISHED_JOB_LIMIT)).rev() {
+            jobs.remove(*index);
+        }
+        entry
+    }
+
+    fn find(&self, id: &str) -> Option<Arc<JobEntry>> {
+        self.jobs.read().unwrap().iter()
+            .find(|entry| entry.job.read().unwrap().id == id)
+            .cloned()
+    }
+
+    pub fn list(&self) -> Vec<ConversionJob> {
+        self.jobs.read().unwrap().iter()
+            .map(|entry| entry.job.read().unwrap().clone())
+            .collect()
+    }
+}
+
+// Replace characters that aren't allowed (or would add directories) in a file name
+fn sanitize(value: &str) -> String {
+    value.trim()
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c })
+        .collect()
+}
+
+// A non-empty path component that can't climb out of the target directory
+fn path_component(value: &str) -> String {
+    let cleaned = sanitize(value);
+    let cleaned = cleaned.trim_matches('.').trim();
+    if cleaned.is_empty() { "Unknown".to_string() } else { cleaned.to_string() }
+}
+
+// Placeholders in a naming template that aren't recognised
+fn unknown_placeholders(template: &str) -> Vec<&str> {
+    template.split('{')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+        .filter(|name| !PLACEHOLDERS.contains(name))
+        .collect()
+}
+
+fn render_template(template: &str, track: &track::Model) -> String {
+    let filename = Path::new(&track.path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let album_artist = if track.album_artist.is_empty() { &track.artist } else { &track.album_artist };
+    let values = [
+        ("{artist}", track.artist.clone()),
+        ("{album_artist}", album_artist.clone()),
+        ("{album}", track.album.clone()),
+        ("{title}", track.title.clone()),
+        ("{track}", track.track_number.map(|n| format!("{:02}", n)).unwrap_or_default()),
+        ("{disc}", track.disc_number.unwrap_or(1).to_string()),
+        ("{year}", track.year.map(|year| year.to_string()).unwrap_or_default()),
+        ("{genre}", track.genre.clone()),
+        ("{filename}", filename),
+    ];
+
+    // Placeholders are filled per path component so tag values can't add directories
+    template.split('/')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let filled = values.iter().fold(part.to_string(), |part, (placeholder, value)| {
+                if part.contains(placeholder) { part.replace(placeholder, &sanitize(value)) } else { part }
+            });
+            path_component(&filled)
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+async fn select_tracks(db: &DatabaseConnection, request: &ConversionRequest) -> Result<Vec<track::Model>, sea_orm::DbErr> {
+    // Missing files can't be converted
+    let mut condition = Condition::all().add(track::Column::MissingSince.is_null());
+    if let Some(extension) = &request.extension {
+        condition = condition.add(track::Column::Extension.eq(extension.trim_start_matches('.').to_lowercase()));
+    }
+    if let Some(prefix) = &request.path_prefix {
+        condition = condition.add(track::Column::Path.starts_with(format!("{}/", prefix.trim_end_matches('/'))));
+    }
+    if let Some(ids) = &request.track_ids {
+        condition = condition.add(track::Column::Id.is_in(ids.clone()));
+    }
+    Track::find()
+        .filter(condition)
+        .order_by_asc(track::Column::Path)
+        .all(db)
+        .await
+}
+
+// Point a track at its converted file, refreshing audio properties from it.
+// The track keeps its ID, so playlists, queues and history still refer to it.
+async fn repoint_track(db: &DatabaseConnection, track: &track::Model, output: &Path) -> Result<(), String> {
+    let output = output.to_string_lossy().to_string();
+    let metadata = tokio::fs::metadata(&output).await.map_err(|e| e.to_string())?;
+    let entry = FileEntry::from_metadata(output.clone(), &metadata).map_err(|e| e.to_string())?;
+    let storage = LocalStorage::new(Path::new(&output).parent().unwrap_or(Path::new("/")));
+
+    let mut model = scanner::read_track(&storage, &entry).await?;
+    model.id = Set(track.id);
+    model.created = NotSet;
+    // Audio-only output drops embedded art, so keep the art found in the original
+    if matches!(model.album_art_path, Set(None)) {
+        model.album_art_path = NotSet;
+        model.album_art_mime_type = NotSet;
+        model.album_art_size = NotSet;
+        model.images = NotSet;
+    }
+    model.update(db).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+async fn run_job(state: AppState, entry: Arc<JobEntry>, tracks: Vec<track::Model>, request: ConversionRequest) {
+    let options = request.format.options(request.bitrate_kbps);
+    let template = request.naming_template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+    let target_dir = PathBuf::from(&request.target_dir);
+
+    for track in tracks {
+        if entry.cancelled.load(Ordering::SeqCst) {
+            break;
+        }
+        entry.job.write().unwrap().current = Some(track.path.clone());
+
+        let output = target_dir.join(format!("{}.{}", render_template(template, &track), request.format.extension()));
+        if tokio::fs::try_exists(&output).await.unwrap_or(false) {
+            entry.job.write().unwrap().skipped += 1;
+            continue;
+        }
+
+        let result = async {
+            if let Some(parent) = output.parent() {
+                tokio::fs::create_dir_all(parent).await.map_err(|e| e.to_string())?;
+            }
+            transcoder::convert_to_file(state.storage.as_ref(), &track.path, &options, &output)
+                .await
+                .map_err(|e| e.to_string())?;
+            if request.repoint {
+                repoint_track(&state.db, &track, &output).await?;
+            }
+            Ok::<(), String>(())
+        }
+        .await;
+
+        let mut job = entry.job.write().unwrap();
+        match result {
+            Ok(()) => job.converted += 1,
+            Err(message) => {
+                error!("Failed to convert {}: {}", track.path, message);
+                job.failed += 1;
+                if job.errors.len() < JOB_ERROR_LIMIT {
+                    job.errors.push(ConversionError { track_id: track.id, path: track.path.clone(), message });
+                }
+            }
+        }
+    }
+
+    if request.repoint {
+        state.counts.refresh(&state.db).await;
+        // Repointed tracks may have gained or lost embedded art
+        if let Err(e) = albums::refresh_albums(&state.db).await {
+            error!("Failed to update albums: {}", e);
+        }
+    }
+    let mut job = entry.job.write().unwrap();
+    job.current = None;
+    job.finished = Some(Utc::now());
+    job.state = if entry.cancelled.load(Ordering::SeqCst) { JobState::Cancelled } else { JobState::Completed };
+    info!("Conversion job {} finished: {} converted, {} skipped, {} failed", job.id, job.converted, job.skipped, job.failed);
+}
+
+// POST /admin/conversions - Start converting a selection of tracks to FLAC or Opus
+#[utoipa::path(
+    post,
+    path = "/admin/conversions",
+    tag = "conversions",
+    request_body = ConversionRequest,
+    responses(
+        (status = 202, description = "Conversion job started", body = ConversionJob),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn start_conversion(
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<ConversionRequest>,
+) -> ApiResult<(StatusCode, Json<ConversionJob>)> {
+    if request.extension.is_none() && request.path_prefix.is_none() && request.track_ids.is_none() {
+        return Err(ApiError::invalid_field("extension", "one of extension, path_prefix or track_ids is required"));
+    }
+    if !Path::new(&request.target_dir).is_absolute() {
+        return Err(ApiError::invalid_field("target_dir", "must be an absolute path"));
+    }
+    if let Some(bitrate) = request.bitrate_kbps {
+        if !(6..=510).contains(&bitrate) {
+            return Err(ApiError::invalid_field("bitrate_kbps", "must be between 6 and 510"));
+        }
+    }
+    if let Some(template) = &request.naming_template {
+        if template.trim_matches('/').is_empty() {
+            return Err(ApiError::invalid_field("naming_template", "must not be empty"));
+        }
+        let unknown = unknown_placeholders(template);
+        if !unknown.is_empty() {
+            return Err(ApiError::invalid_field("naming_template", format!("unknown placeholders: {}", unknown.join(", "))));
+        }
+    }
+    // Converted files are always local, which only a local library can stream
+    if request.repoint && state.storage.local_path(&state.music_path).is_none() {
+        return Err(ApiError::invalid_field("repoint", "requires a local music library"));
+    }
+    tokio::fs::create_dir_all(&request.target_dir)
+        .await
+        .map_err(|e| ApiError::invalid_field("target_dir", format!("can't be created: {}", e)))?;
+
+    let tracks = select_tracks(&state.db, &request).await?;
+    let job = ConversionJob {
+        id: uuid::Uuid::new_v4().to_string(),
+        state: JobState::Running,
+        format: request.format,
+        target_dir: request.target_dir.clone(),
+        repoint: request.repoint,
+        total: tracks.len(),
+        converted: 0,
+        skipped: 0,
+        failed: 0,
+        current: None,
+        errors: Vec::new(),
+        started: Utc::now(),
+        finished: None,
+    };
+    info!("Starting conversion job {} for {} tracks", job.id, job.total);
+
+    let entry = state.conversions.start(job.clone());
+    tokio::spawn(run_job(state.clone(), entry, tracks, request));
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+// GET /admin/conversions - List running and recently finished conversion jobs
+#[utoipa::path(
+    get,
+    path = "/admin/conversions",
+    tag = "conversions",
+    responses(
+        (status = 200, description = "Conversion jobs", body = [ConversionJob]),
+    )
+)]
+pub async fn get_conversions(State(state): State<AppState>) -> Json<Vec<ConversionJob>> {
+    Json(state.conversions.list())
+}
+
+// GET /admin/conversions/:id - Get a conversion job's progress
+#[utoipa::path(
+    get,
+    path = "/admin/conversions/{id}",
+    tag = "conversions",
+    params(("id" = String, Path, description = "Conversion job ID")),
+    responses(
+        (status = 200, description = "Conversion job", body = ConversionJob),
+        (status = 404, description = "Conversion job not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_conversion(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+) -> ApiResult<Json<ConversionJob>> {
+    let entry = state.conversions.find(&id)
+        .ok_or_else(|| ApiError::not_found(format!("Conversion job {} not found", id)))?;
+    let job = entry.job.read().unwrap().clone();
+    Ok(Json(job))
+}
+
+// DELETE /admin/conversions/:id - Cancel a running conversion job after its current track
+#[utoipa::path(
+    delete,
+    path = "/admin/conversions/{id}",
+    tag = "conversions",
+    params(("id" = String, Path, description = "Conversion job ID")),
+    responses(
+        (status = 204, description = "Job will stop after the current track"),
+        (status = 404, description = "Conversion job not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn cancel_conversion(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<String>,
+) -> ApiResult<StatusCode> {
+    let entry = state.conversions.find(&id)
+        .ok_or_else(|| ApiError::not_found(format!("Conversion job {} not found", id)))?;
+    entry.cancelled.store(true, Ordering::SeqCst);
+    Ok(StatusCode::NO_CONTENT)
+}
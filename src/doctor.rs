@@ -0,0 +1,255 @@
+// `ongaku-server doctor`: checks that the configuration can work before the
+// server is started with it. Each check says what is wrong and which setting
+// to change, so a server that starts but finds no music, can't transcode or
+// can't bind its port is caught up front.
+
+use std::env;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::Duration;
+
+use sea_orm::{ConnectOptions, ConnectionTrait, Database, DatabaseConnection, DbBackend, Statement};
+use tokio::net::TcpListener;
+use tokio::process::Command;
+
+use migration::{Migrator, MigratorTrait};
+
+use crate::config::Config;
+use crate::lastfm::LastfmClient;
+use crate::scheduler;
+use crate::storage;
+use crate::transcoder::{self, TranscodeProfile};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Works, but something is likely not as intended
+    Warning,
+    /// The server won't work as configured
+    Error,
+}
+
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn ok(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { name: name.into(), status: Status::Ok, detail: detail.into() }
+    }
+
+    fn warning(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { name: name.into(), status: Status::Warning, detail: detail.into() }
+    }
+
+    fn error(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { name: name.into(), status: Status::Error, detail: detail.into() }
+    }
+}
+
+fn is_set(var: &str) -> bool {
+    env::var(var).is_ok_and(|value| !value.trim().is_empty())
+}
+
+fn is_true(var: &str) -> bool {
+    env::var(var).is_ok_and(|value| value == "true" || value == "1")
+}
+
+// Settings that are read leniently elsewhere, falling back to defaults
+fn settings(checks: &mut Vec<Check>) {
+    if let Ok(port) = env::var("API_PORT") {
+        if port.trim().parse::<u16>().is_err() {
+            checks.push(Check::error("API_PORT", format!("{:?} is not a port number; the server would fall back to 4000", port)));
+        }
+    }
+    if let Ok(expression) = env::var("SCAN_SCHEDULE") {
+        if !expression.trim().is_empty() {
+            match scheduler::parse_schedule(&expression) {
+                Ok(_) => checks.push(Check::ok("SCAN_SCHEDULE", format!("scans run on {:?}", expression))),
+                Err(e) => checks.push(Check::error("SCAN_SCHEDULE", format!("{:?} is not a valid cron expression ({}); scheduled scans won't run. Use six fields, seconds first, e.g. \"0 0 4 * * *\"", expression, e))),
+            }
+        }
+    }
+    for entry in env::var("TRANSCODING_PROFILES").unwrap_or_default().split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        if let Err(e) = entry.parse::<TranscodeProfile>() {
+            checks.push(Check::error("TRANSCODING_PROFILES", format!("{:?} is skipped: {}. Profiles are name=format/bitrate[/channels], e.g. mobile=opus/96/2", entry, e)));
+        }
+    }
+    if is_true("REQUIRE_SIGNED_STREAMS") && !is_set("STREAM_SIGNING_KEY") {
+        checks.push(Check::error("REQUIRE_SIGNED_STREAMS", "has no effect without STREAM_SIGNING_KEY; set a signing key or unset it"));
+    }
+}
+
+// Every library folder exists and can be listed
+async fn music_folders(config: &Config, checks: &mut Vec<Check>) {
+    for root in storage::library_roots(&config.music_path) {
+        let name = format!("Music folder {}", root);
+        if root.starts_with(storage::S3_SCHEME) {
+            let storage = storage::for_music_path(&root);
+            match storage.list().await {
+                Ok(entries) => checks.push(Check::ok(name, format!("bucket listed, {} files", entries.len()))),
+                Err(e) => checks.push(Check::error(name, format!("can't list the bucket: {}. Check S3_ENDPOINT, S3_REGION and the access keys", e))),
+            }
+            continue;
+        }
+        match tokio::fs::read_dir(&root).await {
+            Ok(mut entries) => match entries.next_entry().await {
+                Ok(Some(_)) => checks.push(Check::ok(name, "readable")),
+                Ok(None) => checks.push(Check::warning(name, "is empty; is the library mounted? Scans will find no music")),
+                Err(e) => checks.push(Check::error(name, format!("can't be listed: {}", e))),
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => checks.push(Check::error(name, "doesn't exist. Set MUSIC_PATH (and EXTRA_MUSIC_PATHS) to folders holding your music")),
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => checks.push(Check::error(name, "can't be read by the user the server runs as; grant it read access")),
+            Err(e) => checks.push(Check::error(name, format!("can't be opened: {}", e))),
+        }
+    }
+}
+
+// The server can connect, write and migrate
+async fn database(config: &Config, checks: &mut Vec<Check>) {
+    let mut options = ConnectOptions::new(&config.database_url);
+    options.max_connections(1)
+        .connect_timeout(Duration::from_secs(5))
+        .sqlx_logging(false);
+    let db: DatabaseConnection = match Database::connect(options).await {
+        Ok(db) => db,
+        Err(e) => {
+            checks.push(Check::error("Database", format!("can't connect: {}. Check DATABASE_URL and that PostgreSQL is running", e)));
+            return;
+        }
+    };
+
+    let writable = db.query_one(Statement::from_string(
+        DbBackend::Postgres,
+        "SELECT NOT pg_is_in_recovery() AND has_database_privilege(current_database(), 'CREATE') AS writable",
+    ))
+    .await
+    .ok()
+    .flatten()
+    .and_then(|row| row.try_get::<bool>("", "writable").ok());
+    match writable {
+        Some(true) => checks.push(Check::ok("Database", "connected and writable")),
+        Some(false) => checks.push(Check::error("Database", "is read-only for this user, or a standby; the server needs to create tables and write to them")),
+        None => checks.push(Check::warning("Database", "connected, but whether it is writable couldn't be checked")),
+    }
+
+    match Migrator::get_pending_migrations(&db).await {
+        Ok(pending) if pending.is_empty() => checks.push(Check::ok("Migrations", "up to date")),
+        Ok(pending) => checks.push(Check::ok("Migrations", format!("{} pending, applied when the server starts", pending.len()))),
+        Err(e) => checks.push(Check::warning("Migrations", format!("couldn't be read: {}", e))),
+    }
+}
+
+// First line of a program's version output
+async fn version(program: &str, flag: &str) -> std::io::Result<String> {
+    let output = Command::new(program).arg(flag).output().await?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!("exited with {}", output.status)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().next().unwrap_or_default().trim().to_string())
+}
+
+// External programs the configured features run
+async fn programs(checks: &mut Vec<Check>) {
+    let ffmpeg = transcoder::ffmpeg_path();
+    let needed_by: Vec<&str> = [
+        ("TRANSCODING_PROFILES", is_set("TRANSCODING_PROFILES")),
+        ("DEMO_MODE", is_true("DEMO_MODE")),
+        ("PREVIEW_PREGENERATE", is_true("PREVIEW_PREGENERATE")),
+        ("SCAN_VERIFY_AUDIO", is_true("SCAN_VERIFY_AUDIO")),
+    ]
+    .into_iter()
+    .filter_map(|(setting, enabled)| enabled.then_some(setting))
+    .collect();
+    match version(&ffmpeg, "-version").await {
+        Ok(version) => checks.push(Check::ok("ffmpeg", version)),
+        Err(e) if needed_by.is_empty() => checks.push(Check::warning("ffmpeg", format!("{} can't be run ({}); previews, album streams and conversions won't work. Install ffmpeg or set FFMPEG_PATH", ffmpeg, e))),
+        Err(e) => checks.push(Check::error("ffmpeg", format!("{} can't be run ({}), but {} needs it. Install ffmpeg or set FFMPEG_PATH", ffmpeg, e, needed_by.join(", ")))),
+    }
+
+    if is_set("BACKUP_DIR") {
+        let pg_dump = env::var("PG_DUMP_PATH").unwrap_or_else(|_| "pg_dump".to_string());
+        match version(&pg_dump, "--version").await {
+            Ok(version) => checks.push(Check::ok("pg_dump", version)),
+            Err(e) => checks.push(Check::error("pg_dump", format!("{} can't be run ({}), but BACKUP_DIR needs it. Install the PostgreSQL client tools or set PG_DUMP_PATH", pg_dump, e))),
+        }
+    }
+}
+
+// Directories the server writes to can be created and written
+async fn writable_dirs(checks: &mut Vec<Check>) {
+    for setting in ["STREAM_CACHE_DIR", "PREVIEW_CACHE_DIR", "PLAYLIST_COVER_CACHE_DIR", "BACKUP_DIR"] {
+        let Some(dir) = env::var(setting).ok().filter(|dir| !dir.trim().is_empty()) else {
+            continue;
+        };
+        let probe = Path::new(&dir).join(".ongaku-doctor");
+        let result = match tokio::fs::create_dir_all(&dir).await {
+            Ok(()) => tokio::fs::write(&probe, b"").await,
+            Err(e) => Err(e),
+        };
+        let _ = tokio::fs::remove_file(&probe).await;
+        match result {
+            Ok(()) => checks.push(Check::ok(setting, format!("{} is writable", dir))),
+            Err(e) => checks.push(Check::error(setting, format!("{} can't be written: {}", dir, e))),
+        }
+    }
+}
+
+// The Last.fm key and secret are accepted by Last.fm
+async fn lastfm(checks: &mut Vec<Check>) {
+    if !is_set("LASTFM_API_KEY") {
+        return;
+    }
+    let client = match LastfmClient::new() {
+        Ok(client) => client,
+        Err(e) => {
+            checks.push(Check::error("Last.fm", format!("{}; scrobbling needs both the API key and shared secret", e)));
+            return;
+        }
+    };
+    match client.get_token().await {
+        Ok(_) => checks.push(Check::ok("Last.fm", "API key and shared secret accepted")),
+        Err(e) => checks.push(Check::error("Last.fm", format!("{}. Check LASTFM_API_KEY and LASTFM_SHARED_SECRET against your Last.fm API account", e))),
+    }
+}
+
+// Nothing else is listening on the API address
+async fn port(config: &Config, checks: &mut Vec<Check>) {
+    let address = config.bind_address();
+    match TcpListener::bind(&address).await {
+        Ok(_) => checks.push(Check::ok("API address", format!("{} is free", address))),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => checks.push(Check::error("API address", format!("{} is already in use; stop what is listening there (another ongaku-server?) or set API_PORT", address))),
+        Err(e) => checks.push(Check::error("API address", format!("can't listen on {}: {}. Check API_HOST and API_PORT", address, e))),
+    }
+}
+
+/// Run every check against the configuration in the environment
+pub async fn run(config: &Config) -> Vec<Check> {
+    let mut checks = Vec::new();
+    settings(&mut checks);
+    music_folders(config, &mut checks).await;
+    database(config, &mut checks).await;
+    programs(&mut checks).await;
+    writable_dirs(&mut checks).await;
+    lastfm(&mut checks).await;
+    port(config, &mut checks).await;
+    checks
+}
+
+/// Print the checks, one per line. Returns whether none of them failed.
+pub fn report(checks: &[Check]) -> bool {
+    for check in checks {
+        let label = match check.status {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Error => "error",
+        };
+        println!("[{:<7}] {}: {}", label, check.name, check.detail);
+    }
+    let errors = checks.iter().filter(|check| check.status == Status::Error).count();
+    let warnings = checks.iter().filter(|check| check.status == Status::Warning).count();
+    println!("{} errors, {} warnings", errors, warnings);
+    errors == 0
+}
@@ -0,0 +1,183 @@
+use std::env;
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, request::Parts, StatusCode},
+    response::Response,
+};
+use sea_orm::{EntityTrait, QueryFilter};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use entity::prelude::Track;
+use entity::track;
+
+use crate::api::{self, AppState};
+use crate::content_type;
+use crate::error::{self, ApiError, ApiQuery, ApiResult};
+use crate::hidden::Visibility;
+use crate::permissions;
+use crate::stream_metrics::METRICS;
+use crate::streaming;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadQuery {
+    /// Track ID. IDs that aren't numeric get 404 like unknown ones.
+    pub id: String,
+    /// User downloading, as Subsonic clients send it
+    pub u: Option<String>,
+    /// User downloading, when `u` isn't given
+    pub user: Option<String>,
+    /// Signed token from /tracks/{id}/stream-url, as for /tracks/{id}/play
+    pub token: Option<String>,
+}
+
+// Longest name a template makes, in bytes before the extension, well under
+// the 255 most filesystems allow
+const MAX_FILE_NAME_BYTES: usize = 200;
+// Device names Windows won't create files under, whatever the extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// How downloaded files are named: after DOWNLOAD_FILENAME_TEMPLATE, e.g.
+/// "{artist} - {album} ({year}) - {track} {title}", or their own name
+pub struct FilenameTemplate(Option<String>);
+
+impl FilenameTemplate {
+    pub fn from_env() -> Self {
+        Self(env::var("DOWNLOAD_FILENAME_TEMPLATE").ok().filter(|template| !template.trim().is_empty()))
+    }
+
+    /// The name to download a track's file under, with its extension
+    pub fn file_name(&self, track: &track::Model) -> String {
+        let own_name = track.path.rsplit('/').next().unwrap_or(&track.path);
+        let Some(template) = &self.0 else {
+            return own_name.to_string();
+        };
+        let stem = own_name.rsplit_once('.').map_or(own_name, |(stem, _)| stem);
+        let number = |number: Option<i32>| number.map(|number| format!("{:02}", number)).unwrap_or_default();
+        let name = template
+            .replace("{artist}", &track.artist)
+            .replace("{album_artist}", &track.album_artist)
+            .replace("{album}", &track.album)
+            .replace("{title}", &track.title)
+            .replace("{genre}", &track.genre)
+            .replace("{year}", &track.year.map(|year| year.to_string()).unwrap_or_default())
+            .replace("{track}", &number(track.track_number))
+            .replace("{disc}", &number(track.disc_number))
+            .replace("{filename}", stem);
+        let name = sanitize(&name);
+        if name.is_empty() {
+            return own_name.to_string();
+        }
+        if track.extension.is_empty() {
+            name
+        } else {
+            format!("{}.{}", name, track.extension)
+        }
+    }
+}
+
+/// A file name made safe for every filesystem: no path separators, control
+/// characters or characters Windows forbids, no empty brackets or dangling
+/// separators left by missing tags, and no reserved device names
+pub fn sanitize(name: &str) -> String {
+    let replaced: String = name.chars()
+        .map(|c| if c.is_control() || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') { '_' } else { c })
+        .collect();
+    let mut name = replaced.replace("()", "").replace("[]", "");
+    name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    // Separators next to a tag that was empty, e.g. "Artist -  - Title"
+    while name.contains(" - - ") {
+        name = name.replace(" - - ", " - ");
+    }
+    let mut name = name.trim_matches(|c: char| c.is_whitespace() || c == '.' || c == '-').to_string();
+
+    if name.len() > MAX_FILE_NAME_BYTES {
+        let mut end = MAX_FILE_NAME_BYTES;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+        name = name.trim_end_matches(|c: char| c.is_whitespace() || c == '.').to_string();
+    }
+    let device = name.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(device)) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// Content-Disposition for downloading a file under its own name, with an
+/// ASCII fallback for clients that don't understand RFC 5987 `filename*`
+fn attachment(file_name: &str) -> String {
+    let fallback: String = file_name.chars()
+        .map(|c| if c == ' ' || (c.is_ascii_graphic() && !matches!(c, '"' | '\\')) { c } else { '_' })
+        .collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, urlencoding::encode(file_name))
+}
+
+// GET /download and /download.view - Download a track's original file, as Subsonic's download
+#[utoipa::path(
+    get,
+    path = "/download",
+    tag = "tracks",
+    params(DownloadQuery),
+    responses(
+        (status = 200, description = "The original, untranscoded file as an attachment", content_type = "application/octet-stream"),
+        (status = 401, description = "A signed stream token is required", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The user may not download files, or the stream token is invalid", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn download(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<DownloadQuery>,
+    parts: Parts,
+) -> ApiResult<Response> {
+    // Demo visitors only ever get previews
+    if state.demo_mode {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "demo_mode", "Not available in demo mode"));
+    }
+    let id: i32 = params.id.trim().parse().map_err(|_| error::unknown_id(&parts, &params.id))?;
+    api::check_stream_token(&state, id, params.token.as_deref())?;
+
+    let user = params.u.as_deref().or(params.user.as_deref()).map(str::trim).filter(|user| !user.is_empty());
+    // The role is advisory: users are named, not authenticated
+    if let Some(user) = user {
+        if !permissions::can_download(&state.db, user).await? {
+            return Err(ApiError::new(StatusCode::FORBIDDEN, "download_not_allowed", format!("User {} may not download files", user)));
+        }
+    }
+
+    let visibility = Visibility::load(&state.db, false, user).await?;
+    let track = Track::find_by_id(id)
+        .filter(visibility.condition())
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", id)))?;
+
+    let file_size = state.storage.stat(&track.path).await?.size;
+    let body = match file_size.checked_sub(1) {
+        Some(end) => Body::from_stream(streaming::open(state.storage.clone(), &track.path, 0, end).await?),
+        None => Body::empty(),
+    };
+    METRICS.original_request(&track.extension);
+    let file_name = FilenameTemplate::from_env().file_name(&track);
+    let mime_type = track.content_type.clone()
+        .unwrap_or_else(|| content_type::from_path(&track.path));
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, mime_type)
+        .header(header::CONTENT_LENGTH, file_size.to_string())
+        .header(header::CONTENT_DISPOSITION, attachment(&file_name))
+        .header(header::CACHE_CONTROL, "private, max-age=3600")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(body)?)
+}
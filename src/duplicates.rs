@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use sea_orm::{DatabaseConnection, DbErr, QuerySelect, Select};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use entity::prelude::Track;
+use entity::track;
+
+// Copies of a song in different formats are grouped when their lengths are
+// this close: encoders pad and trim a few hundred milliseconds differently
+const DURATION_TOLERANCE_SECONDS: i32 = 2;
+
+/// Another copy of a track, in a format the grouped search result didn't prefer
+#[derive(Serialize, ToSchema)]
+pub struct TrackAlternate {
+    pub id: i32,
+    pub path: String,
+    pub extension: String,
+    pub content_type: Option<String>,
+    pub duration_seconds: i32,
+    pub audio_bitrate: i32,
+    pub sample_rate: i32,
+    pub bit_depth: i32,
+}
+
+impl From<&track::Model> for TrackAlternate {
+    fn from(model: &track::Model) -> Self {
+        Self {
+            id: model.id,
+            path: model.path.clone(),
+            extension: model.extension.clone(),
+            content_type: model.content_type.clone(),
+            duration_seconds: model.duration_seconds,
+            audio_bitrate: model.audio_bitrate,
+            sample_rate: model.sample_rate,
+            bit_depth: model.bit_depth,
+        }
+    }
+}
+
+/// Copies of one song: the one to list and the others
+pub struct DuplicateGroup {
+    pub preferred: i32,
+    pub alternates: Vec<i32>,
+}
+
+/// Which copy of a song a grouped listing shows: the first of the preferred
+/// extensions, then the highest bit depth, sample rate and bitrate
+pub struct FormatPreference(Vec<String>);
+
+impl FormatPreference {
+    /// From a comma separated list of extensions such as "mp3,m4a"
+    pub fn parse(value: Option<&str>) -> Self {
+        Self(value.unwrap_or_default()
+            .split(',')
+            .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+            .filter(|extension| !extension.is_empty())
+            .collect())
+    }
+
+    fn rank(&self, copy: &TrackCopy) -> (usize, i32, i32, i32, i32) {
+        let position = self.0.iter()
+            .position(|extension| extension.eq_ignore_ascii_case(&copy.extension))
+            .unwrap_or(self.0.len());
+        (position, -copy.bit_depth, -copy.sample_rate, -copy.audio_bitrate, copy.id)
+    }
+}
+
+type CopyRow = (i32, String, String, i32, String, i32, i32, i32);
+
+struct TrackCopy {
+    id: i32,
+    extension: String,
+    duration_seconds: i32,
+    bit_depth: i32,
+    sample_rate: i32,
+    audio_bitrate: i32,
+}
+
+/// Group the tracks a query selects by artist, title and length, in the
+/// order the query lists them
+pub async fn group_tracks(db: &DatabaseConnection, query: Select<Track>, preference: &FormatPreference) -> Result<Vec<DuplicateGroup>, DbErr> {
+    let rows: Vec<CopyRow> = query
+        .select_only()
+        .columns([
+            track::Column::Id,
+            track::Column::Artist,
+            track::Column::Title,
+            track::Column::DurationSeconds,
+            track::Column::Extension,
+            track::Column::BitDepth,
+            track::Column::SampleRate,
+            track::Column::AudioBitrate,
+        ])
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    // Groups in the order of their first copy, and the groups each song's
+    // artist and title have, which only differ in length
+    let mut groups: Vec<Vec<TrackCopy>> = Vec::new();
+    let mut by_song: HashMap<(String, String), Vec<usize>> = HashMap::new();
+    for (id, artist, title, duration_seconds, extension, bit_depth, sample_rate, audio_bitrate) in rows {
+        let copy = TrackCopy { id, extension, duration_seconds, bit_depth, sample_rate, audio_bitrate };
+        // Untitled tracks aren't copies of each other
+        if title.trim().is_empty() {
+            groups.push(vec![copy]);
+            continue;
+        }
+        let song = (artist.trim().to_lowercase(), title.trim().to_lowercase());
+        let candidates = by_song.entry(song).or_default();
+        let same_length = candidates.iter()
+            .copied()
+            .find(|&group| (groups[group][0].duration_seconds - duration_seconds).abs() <= DURATION_TOLERANCE_SECONDS);
+        match same_length {
+            Some(group) => groups[group].push(copy),
+            None => {
+                candidates.push(groups.len());
+                groups.push(vec![copy]);
+            }
+        }
+    }
+
+    Ok(groups.into_iter()
+        .map(|mut copies| {
+            copies.sort_by_key(|copy| preference.rank(copy));
+            DuplicateGroup {
+                preferred: copies[0].id,
+                alternates: copies[1..].iter().map(|copy| copy.id).collect(),
+            }
+        })
+        .collect())
+}
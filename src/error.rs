@@ -0,0 +1,232 @@
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{path::ErrorKind, rejection::PathRejection, FromRequest, FromRequestParts, Request},
+    http::{header, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use log::{error, warn};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// A problem with a single request field, such as a query parameter that
+/// doesn't parse
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Error returned by API handlers, rendered as an RFC 7807 problem details
+/// body with a stable machine-readable `code`
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub detail: String,
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[schema(as = Problem)]
+pub(crate) struct ProblemDetails<'a> {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'a str,
+    status: u16,
+    detail: &'a str,
+    code: &'static str,
+    #[serde(skip_serializing_if = "<[FieldError]>::is_empty")]
+    errors: &'a [FieldError],
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            detail: detail.into(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn bad_request(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", detail)
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", detail)
+    }
+
+    pub fn conflict(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, "conflict", detail)
+    }
+
+    pub fn internal(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", detail)
+    }
+
+    /// A 400 response pointing at one invalid field
+    pub fn invalid_field(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "validation_failed", "Request validation failed")
+            .with_field(field, message)
+    }
+
+    pub fn with_field(mut self, field: impl Into<String>, message: impl Into<String>) -> Self {
+        self.errors.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+        self
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ProblemDetails {
+            problem_type: "about:blank",
+            title: self.status.canonical_reason().unwrap_or("Error"),
+            status: self.status.as_u16(),
+            detail: &self.detail,
+            code: self.code,
+            errors: &self.errors,
+        };
+        let body = serde_json::to_vec(&body).unwrap_or_default();
+
+        (self.status, [(header::CONTENT_TYPE, PROBLEM_JSON)], body).into_response()
+    }
+}
+
+impl From<sea_orm::DbErr> for ApiError {
+    fn from(e: sea_orm::DbErr) -> Self {
+        error!("Database error: {}", e);
+        Self::internal("Database error")
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => Self::not_found("File not found"),
+            _ => {
+                error!("I/O error: {}", e);
+                Self::internal("Failed to read file")
+            }
+        }
+    }
+}
+
+impl From<axum::http::Error> for ApiError {
+    fn from(e: axum::http::Error) -> Self {
+        error!("Failed to build response: {}", e);
+        Self::internal("Failed to build response")
+    }
+}
+
+// serde_path_to_error reports "." when the problem isn't tied to one field,
+// such as a missing required field
+fn field_error<E: std::fmt::Display>(e: serde_path_to_error::Error<E>, fallback: &str) -> ApiError {
+    let path = e.path().to_string();
+    let message = e.into_inner().to_string();
+    let field = if path == "." {
+        message.split('`').nth(1).unwrap_or(fallback).to_string()
+    } else {
+        path
+    };
+    ApiError::invalid_field(field, message)
+}
+
+/// Query string extractor that reports the offending parameter on failure
+pub struct ApiQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer = serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        serde_path_to_error::deserialize(deserializer)
+            .map(ApiQuery)
+            .map_err(|e| field_error(e, "query"))
+    }
+}
+
+/// JSON body extractor that reports the offending field on failure
+pub struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| ApiError::bad_request(e.body_text()))?;
+        let mut deserializer = serde_json::Deserializer::from_slice(&body);
+        serde_path_to_error::deserialize(&mut deserializer)
+            .map(ApiJson)
+            .map_err(|e| field_error(e, "body"))
+    }
+}
+
+/// Path parameter extractor with problem details rejections
+pub struct ApiPath<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiPath<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        axum::extract::Path::<T>::from_request_parts(parts, state)
+            .await
+            .map(|axum::extract::Path(value)| ApiPath(value))
+            .map_err(|e: PathRejection| match &e {
+                // An ID of the wrong kind, e.g. "artist-Foo" where a track ID belongs, names nothing
+                PathRejection::FailedToDeserializePathParams(inner) => match inner.kind() {
+                    ErrorKind::ParseErrorAtKey { value, .. }
+                    | ErrorKind::ParseErrorAtIndex { value, .. }
+                    | ErrorKind::ParseError { value, .. } => unknown_id(parts, value),
+                    _ => ApiError::invalid_field("path", e.body_text()),
+                },
+                _ => ApiError::invalid_field("path", e.body_text()),
+            })
+    }
+}
+
+/// 404 for an ID that can't name anything, such as "artist-Foo" sent by a
+/// client where a track ID belongs. Logged with the client that sent it, since
+/// it usually means a client bug rather than a deleted track.
+pub fn unknown_id(parts: &Parts, id: &str) -> ApiError {
+    let query = parts.uri.query().unwrap_or_default();
+    // Subsonic clients name themselves with c, others with client
+    let client = form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == "client" || key == "c")
+        .map(|(_, name)| name.into_owned())
+        .unwrap_or_else(|| "unknown".to_string());
+    let user_agent = parts.headers.get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("unknown");
+    warn!(
+        "{} {} has ID {:?}, which can't name anything (client {}, User-Agent {})",
+        parts.method, parts.uri.path(), id, client, user_agent,
+    );
+    ApiError::not_found(format!("Nothing has ID {:?}", id))
+}
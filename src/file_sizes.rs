@@ -0,0 +1,74 @@
+// File sizes of tracks recorded before the file_size column existed. Columns
+// added by a migration start out NULL; rather than waiting for every file to
+// be read again, the scan fills them from a stat of each file, a batch of rows
+// at a time so an upgraded library isn't held up by one long transaction.
+
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait};
+use sea_query::Expr;
+
+use entity::prelude::Track;
+use entity::track;
+
+use crate::storage::Storage;
+
+const BACKFILL_BATCH: u64 = 1_000;
+const STAT_CONCURRENCY: usize = 16;
+
+/// Record the size of every present track without one. Returns the number of
+/// tracks updated; files that can't be stat'ed are left for a later scan.
+pub async fn backfill_file_sizes(db: &DatabaseConnection, storage: &dyn Storage) -> Result<usize, DbErr> {
+    let mut updated = 0;
+    let mut after = 0;
+    loop {
+        // Keyset paging, so rows left NULL by failed stats aren't read again
+        let batch: Vec<(i32, String)> = Track::find()
+            .select_only()
+            .column(track::Column::Id)
+            .column(track::Column::Path)
+            .filter(track::Column::FileSize.is_null())
+            .filter(track::Column::MissingSince.is_null())
+            .filter(track::Column::Id.gt(after))
+            .order_by_asc(track::Column::Id)
+            .limit(BACKFILL_BATCH)
+            .into_tuple()
+            .all(db)
+            .await?;
+        let Some(&(last, _)) = batch.last() else {
+            break;
+        };
+        after = last;
+
+        let sizes: Vec<(i32, i64)> = stream::iter(batch)
+            .map(|(id, path)| async move {
+                match storage.stat(&path).await {
+                    Ok(entry) => Some((id, entry.size as i64)),
+                    Err(e) => {
+                        warn!("Failed to read the size of {}: {}", path, e);
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(STAT_CONCURRENCY)
+            .filter_map(|size| async move { size })
+            .collect()
+            .await;
+
+        let txn = db.begin().await?;
+        for (id, size) in &sizes {
+            Track::update_many()
+                .col_expr(track::Column::FileSize, Expr::value(*size))
+                .filter(track::Column::Id.eq(*id))
+                .exec(&txn)
+                .await?;
+        }
+        txn.commit().await?;
+        updated += sizes.len();
+    }
+
+    if updated > 0 {
+        info!("Recorded file sizes of {} tracks", updated);
+    }
+    Ok(updated)
+}
@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use entity::track;
+
+/// Tracks of an album or artist in one format. Lossless tracks are grouped by
+/// bit depth and sample rate, lossy ones by codec with their average bitrate.
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct FormatGroup {
+    /// Codec, e.g. "FLAC", "MP3" or "AAC"
+    pub codec: String,
+    pub lossless: bool,
+    /// Bit depth of lossless tracks
+    pub bit_depth: Option<i32>,
+    /// Sample rate in Hz of lossless tracks
+    pub sample_rate: Option<i32>,
+    /// Average audio bitrate of lossy tracks
+    pub bitrate_kbps: Option<i32>,
+    pub track_count: usize,
+    /// e.g. "FLAC 16/44.1" or "MP3 256k"
+    pub label: String,
+}
+
+/// The formats of an album's or artist's tracks, so lossy tracks in a lossless
+/// collection stand out
+#[derive(Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct FormatSummary {
+    /// The formats joined with "+", most tracks first, e.g. "FLAC 16/44.1 + MP3 320k"
+    pub label: String,
+    /// Every track is lossless
+    pub lossless: bool,
+    /// Lossless and lossy tracks are mixed
+    pub mixed: bool,
+    pub formats: Vec<FormatGroup>,
+}
+
+// Codec name and whether it is lossless, from the extension. MP4 files hold
+// either ALAC or AAC; only ALAC has a bit depth.
+fn codec(extension: &str, bit_depth: i32) -> (String, bool) {
+    let extension = extension.to_lowercase();
+    let (codec, lossless) = match extension.as_str() {
+        "flac" => ("FLAC", true),
+        "m4a" | "mp4" | "alac" if bit_depth > 0 => ("ALAC", true),
+        "m4a" | "mp4" | "aac" => ("AAC", false),
+        "wav" => ("WAV", true),
+        "aif" | "aiff" => ("AIFF", true),
+        "ape" => ("APE", true),
+        "wv" => ("WavPack", true),
+        "dsf" | "dff" => ("DSD", true),
+        "mp3" => ("MP3", false),
+        "ogg" | "oga" => ("Vorbis", false),
+        "opus" => ("Opus", false),
+        "wma" => ("WMA", false),
+        _ => return (extension.to_uppercase(), false),
+    };
+    (codec.to_string(), lossless)
+}
+
+// Sample rate in kHz as written on releases: 44.1, 48, 96
+fn khz(sample_rate: i32) -> String {
+    if sample_rate % 1000 == 0 {
+        (sample_rate / 1000).to_string()
+    } else {
+        format!("{:.1}", sample_rate as f64 / 1000.0)
+    }
+}
+
+struct Group {
+    codec: String,
+    lossless: bool,
+    bit_depth: i32,
+    sample_rate: i32,
+    bitrate_total: i64,
+    track_count: usize,
+}
+
+/// Collects tracks, or counts of tracks per format, into a FormatSummary
+#[derive(Default)]
+pub struct FormatSummaryBuilder {
+    groups: Vec<Group>,
+}
+
+impl FormatSummaryBuilder {
+    /// Count `track_count` tracks with these properties, whose audio bitrates add up to `bitrate_total`
+    pub fn add(&mut self, extension: &str, bit_depth: i32, sample_rate: i32, bitrate_total: i64, track_count: usize) {
+        let (codec, lossless) = codec(extension, bit_depth);
+        // Lossy tracks are one group per codec whatever their sample rate
+        let (bit_depth, sample_rate) = if lossless { (bit_depth, sample_rate) } else { (0, 0) };
+        self.insert(Group { codec, lossless, bit_depth, sample_rate, bitrate_total, track_count });
+    }
+
+    pub fn add_track(&mut self, track: &track::Model) {
+        self.add(&track.extension, track.bit_depth, track.sample_rate, track.audio_bitrate as i64, 1);
+    }
+
+    /// Add everything another builder collected, e.g. an album's tracks to its artist's
+    pub fn merge(&mut self, other: &FormatSummaryBuilder) {
+        for group in &other.groups {
+            self.insert(Group { codec: group.codec.clone(), ..*group });
+        }
+    }
+
+    fn insert(&mut self, group: Group) {
+        let existing = self.groups.iter_mut().find(|own| {
+            own.codec == group.codec && own.bit_depth == group.bit_depth && own.sample_rate == group.sample_rate
+        });
+        match existing {
+            Some(own) => {
+                own.bitrate_total += group.bitrate_total;
+                own.track_count += group.track_count;
+            }
+            None => self.groups.push(group),
+        }
+    }
+
+    pub fn build(&self) -> FormatSummary {
+        let mut formats: Vec<FormatGroup> = self.groups.iter()
+            .map(|group| {
+                let known = |value: i32| (value > 0).then_some(value);
+                let bitrate_kbps = (!group.lossless && group.track_count > 0)
+                    .then(|| (group.bitrate_total / group.track_count as i64) as i32)
+                    .and_then(known);
+                let label = match (group.lossless, known(group.bit_depth), known(group.sample_rate), bitrate_kbps) {
+                    (true, Some(bit_depth), Some(sample_rate), _) => format!("{} {}/{}", group.codec, bit_depth, khz(sample_rate)),
+                    (true, None, Some(sample_rate), _) => format!("{} {}", group.codec, khz(sample_rate)),
+                    (false, _, _, Some(bitrate)) => format!("{} {}k", group.codec, bitrate),
+                    _ => group.codec.clone(),
+                };
+                FormatGroup {
+                    codec: group.codec.clone(),
+                    lossless: group.lossless,
+                    bit_depth: known(group.bit_depth).filter(|_| group.lossless),
+                    sample_rate: known(group.sample_rate).filter(|_| group.lossless),
+                    bitrate_kbps,
+                    track_count: group.track_count,
+                    label,
+                }
+            })
+            .collect();
+        formats.sort_by(|a, b| b.track_count.cmp(&a.track_count).then_with(|| a.label.cmp(&b.label)));
+
+        let lossless = !formats.is_empty() && formats.iter().all(|format| format.lossless);
+        let mixed = formats.iter().any(|format| format.lossless) && formats.iter().any(|format| !format.lossless);
+        FormatSummary {
+            label: formats.iter().map(|format| format.label.as_str()).collect::<Vec<_>>().join(" + "),
+            lossless,
+            mixed,
+            formats,
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{request::Parts, StatusCode},
+    response::Json,
+};
+use chrono::Utc;
+use log::info;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, NotSet, QueryFilter, QueryOrder, TransactionTrait};
+use sea_query::{Expr, SimpleExpr};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use entity::prelude::{HiddenDirectory, Track};
+use entity::{hidden_directory, track};
+
+use crate::api::{AppState, TrackResponse};
+use crate::error::{ApiError, ApiJson, ApiPath, ApiQuery, ApiResult};
+use crate::history::{self, ChangeSource};
+use crate::paths;
+use crate::permissions;
+use crate::search_query;
+use crate::text_index;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VisibilityQuery {
+    /// Include hidden tracks and tracks in hidden directories (ignored in demo mode)
+    pub include_hidden: Option<bool>,
+    /// User making the request. Users limited to some folders only get tracks in
+    /// them, and users whose content filter hides explicit tracks don't get
+    /// those; these are preferences clients opt into, not access control.
+    pub user: Option<String>,
+}
+
+/// Which tracks a request may see. Hidden tracks and everything under a hidden
+/// directory are left out unless the request passes include_hidden=true,
+/// users limited to some folders only see the tracks in them, and users whose
+/// content filter hides explicit tracks don't see those.
+/// Tracks whose files have gone missing are always left out.
+#[derive(Clone, Default)]
+pub struct Visibility {
+    // None when hidden tracks are included
+    hidden_directories: Option<Vec<String>>,
+    // None when the user may see every folder
+    folders: Option<Vec<String>>,
+    hide_explicit: bool,
+    user: Option<String>,
+}
+
+impl Visibility {
+    pub async fn load(db: &DatabaseConnection, include_hidden: bool, user: Option<&str>) -> Result<Self, DbErr> {
+        let user = user.map(str::trim).filter(|user| !user.is_empty());
+        let (folders, hide_explicit) = match user {
+            Some(user) => (permissions::user_folders(db, user).await?, permissions::hides_explicit(db, user).await?),
+            None => (Vec::new(), false),
+        };
+        let mut visibility = Self {
+            hidden_directories: None,
+            folders: (!folders.is_empty()).then_some(folders),
+            hide_explicit,
+            user: user.map(str::to_string),
+        };
+        if include_hidden {
+            return Ok(visibility);
+        }
+        let hidden_directories = HiddenDirectory::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|directory| directory.path)
+            .collect();
+        visibility.hidden_directories = Some(hidden_directories);
+        Ok(visibility)
+    }
+
+    pub fn includes_hidden(&self) -> bool {
+        self.hidden_directories.is_none()
+    }
+
+    /// Whether this is the view most requests get (hidden tracks left out,
+    /// every folder, explicit tracks included), which counts can be cached for
+    pub fn is_default(&self) -> bool {
+        !self.includes_hidden() && self.folders.is_none() && !self.hide_explicit
+    }
+
+    /// The user the request was made for, if it named one
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Condition on track columns selecting the visible tracks
+    pub fn condition(&self) -> Condition {
+        let mut condition = Condition::all().add(track::Column::MissingSince.is_null());
+        if let Some(folders) = &self.folders {
+            condition = condition.add(folders.iter().fold(Condition::any(), |any, folder| {
+                any.add(inside_directory(folder))
+            }));
+        }
+        if self.hide_explicit {
+            // Tracks without an advisory tag count as clean
+            condition = condition.add(
+                Condition::any()
+                    .add(track::Column::Explicit.is_null())
+                    .add(track::Column::Explicit.eq(false)),
+            );
+        }
+        let Some(directories) = &self.hidden_directories else {
+            return condition;
+        };
+        directories.iter().fold(
+            condition.add(track::Column::IsHidden.eq(false)),
+            |condition, directory| condition.add(outside_directory(directory)),
+        )
+    }
+}
+
+// LIKE pattern matching everything under a directory
+fn directory_pattern(directory: &str) -> String {
+    format!("{}/%", search_query::escape_like(directory))
+}
+
+/// Tracks under a directory, ignoring case where the filesystem does
+pub fn inside_directory(directory: &str) -> SimpleExpr {
+    if paths::CASE_INSENSITIVE {
+        Expr::cust_with_values(r#""track"."path" ILIKE $1"#, [directory_pattern(directory)])
+    } else {
+        track::Column::Path.like(directory_pattern(directory))
+    }
+}
+
+// Tracks not under a directory, ignoring case where the filesystem does
+fn outside_directory(directory: &str) -> SimpleExpr {
+    if paths::CASE_INSENSITIVE {
+        Expr::cust_with_values(r#""track"."path" NOT ILIKE $1"#, [directory_pattern(directory)])
+    } else {
+        track::Column::Path.not_like(directory_pattern(directory))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Visibility {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let ApiQuery(params) = ApiQuery::<VisibilityQuery>::from_request_parts(parts, state).await?;
+        // Demo visitors never see hidden tracks
+        let include_hidden = params.include_hidden.unwrap_or(false) && !state.demo_mode;
+        Ok(Self::load(&state.db, include_hidden, params.user.as_deref()).await?)
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateTrackRequest {
+    pub is_hidden: Option<bool>,
+    /// New comment; an empty one clears it. Kept until the file's tags change.
+    pub comment: Option<String>,
+}
+
+// PATCH /tracks/:id - Update a track's flags and comment; hidden tracks are left out of client responses
+#[utoipa::path(
+    patch,
+    path = "/tracks/{id}",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID")),
+    request_body = UpdateTrackRequest,
+    responses(
+        (status = 200, description = "Updated track", body = TrackResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn update_track(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    ApiJson(request): ApiJson<UpdateTrackRequest>,
+) -> ApiResult<Json<TrackResponse>> {
+    let track = Track::find_by_id(id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", id)))?;
+
+    let is_hidden = request.is_hidden.filter(|is_hidden| *is_hidden != track.is_hidden);
+    let comment = request.comment
+        .map(|comment| Some(comment.trim().to_string()).filter(|comment| !comment.is_empty()))
+        .filter(|comment| *comment != track.comment);
+    if is_hidden.is_none() && comment.is_none() {
+        return Ok(Json(TrackResponse::from(track)));
+    }
+
+    let mut active = track::ActiveModel {
+        id: Set(id),
+        ..Default::default()
+    };
+    if let Some(is_hidden) = is_hidden {
+        active.is_hidden = Set(is_hidden);
+    }
+    if let Some(comment) = &comment {
+        // The comment is part of the search text
+        let edited = track::Model { comment: comment.clone(), ..track.clone() };
+        text_index::set_text_columns(&mut active, state.text_normalization, &state.ignored_articles, &edited);
+        active.comment = Set(comment.clone());
+    }
+
+    let txn = state.db.begin().await?;
+    let updated = active.update(&txn).await?;
+    history::record(&txn, ChangeSource::Edit, vec![track]).await?;
+    txn.commit().await?;
+    if is_hidden.is_some() {
+        state.counts.refresh(&state.db).await;
+    }
+
+    if let Some(is_hidden) = is_hidden {
+        info!("Track {} is now {}", id, if is_hidden { "hidden" } else { "visible" });
+    }
+    if comment.is_some() {
+        info!("Updated the comment of track {}", id);
+    }
+    Ok(Json(TrackResponse::from(updated)))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct HideDirectoryRequest {
+    /// Directory to hide, absolute or relative to the music library
+    pub path: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HiddenDirectoryResponse {
+    pub id: i32,
+    pub path: String,
+    pub created: chrono::DateTime<Utc>,
+}
+
+impl From<hidden_directory::Model> for HiddenDirectoryResponse {
+    fn from(model: hidden_directory::Model) -> Self {
+        Self {
+            id: model.id,
+            path: model.path,
+            created: model.created,
+        }
+    }
+}
+
+// GET /admin/hidden-directories - List directories hidden from clients
+#[utoipa::path(
+    get,
+    path = "/admin/hidden-directories",
+    tag = "library",
+    responses(
+        (status = 200, description = "Hidden directories", body = [HiddenDirectoryResponse]),
+    )
+)]
+pub async fn get_hidden_directories(State(state): State<AppState>) -> ApiResult<Json<Vec<HiddenDirectoryResponse>>> {
+    let directories = HiddenDirectory::find()
+        .order_by_asc(hidden_directory::Column::Path)
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(HiddenDirectoryResponse::from)
+        .collect();
+
+    Ok(Json(directories))
+}
+
+// Track paths are stored under the music path, so relative directories are
+// resolved against it. Trailing slashes are dropped to match the LIKE prefix,
+// `.` segments are dropped, and paths with `..` segments are refused (None)
+// since they could climb out of the library.
+pub fn resolve_directory(music_path: &str, path: &str) -> Option<String> {
+    let path = paths::normalize(path.trim());
+    let segments: Vec<&str> = path.split('/').filter(|segment| *segment != ".").collect();
+    if segments.contains(&"..") {
+        return None;
+    }
+    let path = segments.join("/");
+    let path = path.trim_end_matches('/');
+    if path.is_empty() {
+        return None;
+    }
+    let music_path = music_path.trim_end_matches('/');
+    if paths::is_absolute(path) || paths::same(path, music_path) || paths::strip_root(path, music_path).is_some() {
+        return Some(path.to_string());
+    }
+    Some(format!("{}/{}", music_path, path))
+}
+
+// POST /admin/hidden-directories - Hide every track under a directory
+#[utoipa::path(
+    post,
+    path = "/admin/hidden-directories",
+    tag = "library",
+    request_body = HideDirectoryRequest,
+    responses(
+        (status = 201, description = "Directory hidden", body = HiddenDirectoryResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Directory is already hidden", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn hide_directory(
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<HideDirectoryRequest>,
+) -> ApiResult<(StatusCode, Json<HiddenDirectoryResponse>)> {
+    let path = resolve_directory(&state.music_path, &request.path)
+        .ok_or_else(|| ApiError::invalid_field("path", "must not be empty or contain .. segments"))?;
+
+    let existing = HiddenDirectory::find()
+        .filter(hidden_directory::Column::Path.eq(path.as_str()))
+        .one(&state.db)
+        .await?;
+    if existing.is_some() {
+        return Err(ApiError::new(StatusCode::CONFLICT, "already_hidden", format!("{} is already hidden", path)));
+    }
+
+    let directory = hidden_directory::ActiveModel {
+        id: NotSet,
+        path: Set(path),
+        created: Set(Utc::now()),
+    }
+    .insert(&state.db)
+    .await?;
+    state.counts.refresh(&state.db).await;
+
+    info!("Hid directory {}", directory.path);
+    Ok((StatusCode::CREATED, Json(HiddenDirectoryResponse::from(directory))))
+}
+
+// DELETE /admin/hidden-directories/:id - Show a hidden directory's tracks again
+#[utoipa::path(
+    delete,
+    path = "/admin/hidden-directories/{id}",
+    tag = "library",
+    params(("id" = i32, Path, description = "Hidden directory ID")),
+    responses(
+        (status = 204, description = "Directory is visible again"),
+        (status = 404, description = "Hidden directory not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn unhide_directory(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+) -> ApiResult<StatusCode> {
+    let result = HiddenDirectory::delete_by_id(id)
+        .exec(&state.db)
+        .await?;
+    if result.rows_affected == 0 {
+        return Err(ApiError::not_found(format!("Hidden directory {} not found", id)));
+    }
+    state.counts.refresh(&state.db).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve_directory;
+
+    #[test]
+    fn resolves_directories_against_music_path() {
+        assert_eq!(resolve_directory("/music", "Family/").as_deref(), Some("/music/Family"));
+        assert_eq!(resolve_directory("/music/", "/music/Family").as_deref(), Some("/music/Family"));
+        assert_eq!(resolve_directory("/music", "/other/Family").as_deref(), Some("/other/Family"));
+        assert_eq!(resolve_directory("/music", "  "), None);
+    }
+
+    #[test]
+    fn drops_dot_segments_and_refuses_parent_segments() {
+        assert_eq!(resolve_directory("/music", "./Family/./Kids").as_deref(), Some("/music/Family/Kids"));
+        assert_eq!(resolve_directory("/music", "/music/./Family").as_deref(), Some("/music/Family"));
+        assert_eq!(resolve_directory("/music", "."), None);
+        assert_eq!(resolve_directory("/music", "/music/../etc"), None);
+        assert_eq!(resolve_directory("/music", "../etc"), None);
+        assert_eq!(resolve_directory("/music", "Family/.."), None);
+        // Names that only start with dots are ordinary folders
+        assert_eq!(resolve_directory("/music", "...and more").as_deref(), Some("/music/...and more"));
+    }
+}
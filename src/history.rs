@@ -0,0 +1,260 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use log::info;
+use sea_orm::ActiveValue::Set;
+use sea_orm::sea_query::IntoCondition;
+use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, IdenStatic, Iterable, NotSet, QueryFilter, QueryOrder, QuerySelect, TransactionTrait};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use entity::prelude::{Track, TrackChange};
+use entity::{track, track_change};
+
+use crate::api::{AppState, TrackResponse};
+use crate::error::{ApiError, ApiPath, ApiResult};
+use crate::hidden::{Visibility, VisibilityQuery};
+use crate::text_index;
+
+/// Track columns whose changes are recorded
+const AUDITED_FIELDS: &[&str] = &[
+    "title", "artist", "album", "album_artist", "disc_number", "track_number", "year", "genre",
+    "publisher", "catalog_number", "isrc", "barcode", "explicit", "comment",
+    "duration_seconds", "audio_bitrate", "sample_rate", "bit_depth", "channels",
+    "is_hidden", "missing_since", "audio_md5_status",
+];
+
+/// Audited columns a revert may set back. Whether a file is missing and
+/// whether its audio checks out are facts about the file, not edits to undo.
+const REVERTIBLE_FIELDS: &[&str] = &[
+    "title", "artist", "album", "album_artist", "disc_number", "track_number", "year", "genre",
+    "publisher", "catalog_number", "isrc", "barcode", "explicit", "comment", "is_hidden",
+];
+
+/// What changed a track
+#[derive(Clone, Copy, Debug)]
+pub enum ChangeSource {
+    /// A scan read new tags or audio properties from the file
+    Scan,
+    /// Changed through the API, e.g. hidden with PATCH /tracks/:id
+    Edit,
+    /// The file went missing or came back
+    Missing,
+    /// Audio checksum verification
+    Verification,
+    /// An earlier change was reverted
+    Revert,
+}
+
+impl ChangeSource {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChangeSource::Scan => "scan",
+            ChangeSource::Edit => "edit",
+            ChangeSource::Missing => "missing",
+            ChangeSource::Verification => "verification",
+            ChangeSource::Revert => "revert",
+        }
+    }
+}
+
+/// A column's value before and after a change
+#[derive(Serialize, ToSchema)]
+pub struct FieldChange {
+    #[schema(value_type = Object)]
+    pub before: Value,
+    #[schema(value_type = Object)]
+    pub after: Value,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TrackChangeResponse {
+    pub id: i32,
+    pub track_id: i32,
+    /// scan, edit, missing, verification or revert
+    pub source: String,
+    /// Changed columns, as named in track responses
+    pub changes: BTreeMap<String, FieldChange>,
+    pub created: DateTime<Utc>,
+}
+
+impl From<track_change::Model> for TrackChangeResponse {
+    fn from(model: track_change::Model) -> Self {
+        Self {
+            changes: changes_of(&model),
+            id: model.id,
+            track_id: model.track_id,
+            source: model.source,
+            created: model.created,
+        }
+    }
+}
+
+fn changes_of(model: &track_change::Model) -> BTreeMap<String, FieldChange> {
+    let Value::Object(changes) = &model.changes else {
+        return BTreeMap::new();
+    };
+    changes.iter()
+        .map(|(field, change)| (field.clone(), FieldChange {
+            before: change["before"].clone(),
+            after: change["after"].clone(),
+        }))
+        .collect()
+}
+
+// The audited columns that differ between two versions of a track
+fn diff(before: &track::Model, after: &track::Model) -> serde_json::Map<String, Value> {
+    let (Ok(before), Ok(after)) = (serde_json::to_value(before), serde_json::to_value(after)) else {
+        return serde_json::Map::new();
+    };
+    AUDITED_FIELDS.iter()
+        .filter(|field| before[**field] != after[**field])
+        .map(|field| (field.to_string(), serde_json::json!({ "before": before[*field], "after": after[*field] })))
+        .collect()
+}
+
+/// Tracks as they are before a change, to hand to `record` afterwards
+pub async fn snapshot<C: ConnectionTrait>(db: &C, condition: impl IntoCondition) -> Result<Vec<track::Model>, DbErr> {
+    Track::find().filter(condition).all(db).await
+}
+
+/// Record how the tracks in `before` have changed since it was taken. Tracks
+/// that didn't change, or were deleted, get no entry. Returns how many did.
+pub async fn record<C: ConnectionTrait>(db: &C, source: ChangeSource, before: Vec<track::Model>) -> Result<usize, DbErr> {
+    if before.is_empty() {
+        return Ok(0);
+    }
+    let after: HashMap<i32, track::Model> = Track::find()
+        .filter(track::Column::Id.is_in(before.iter().map(|track| track.id)))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|track| (track.id, track))
+        .collect();
+
+    let now = Utc::now();
+    let changes: Vec<track_change::ActiveModel> = before.iter()
+        .filter_map(|old| {
+            let changes = diff(old, after.get(&old.id)?);
+            (!changes.is_empty()).then(|| track_change::ActiveModel {
+                id: NotSet,
+                track_id: Set(old.id),
+                source: Set(source.as_str().to_string()),
+                changes: Set(Value::Object(changes)),
+                created: Set(now),
+            })
+        })
+        .collect();
+
+    let recorded = changes.len();
+    if recorded > 0 {
+        TrackChange::insert_many(changes).exec(db).await?;
+    }
+    Ok(recorded)
+}
+
+// GET /tracks/:id/history - Recorded changes to a track's metadata, newest first
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/history",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID"), VisibilityQuery),
+    responses(
+        (status = 200, description = "Changes to the track", body = Vec<TrackChangeResponse>),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_track_history(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    visibility: Visibility,
+) -> ApiResult<Json<Vec<TrackChangeResponse>>> {
+    Track::find_by_id(id)
+        .filter(visibility.condition())
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", id)))?;
+
+    let changes = TrackChange::find()
+        .filter(track_change::Column::TrackId.eq(id))
+        .order_by_desc(track_change::Column::Created)
+        .order_by_desc(track_change::Column::Id)
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(TrackChangeResponse::from)
+        .collect();
+
+    Ok(Json(changes))
+}
+
+// POST /tracks/:id/history/:change_id/revert - Set the columns a change touched back to their earlier values
+#[utoipa::path(
+    post,
+    path = "/tracks/{id}/history/{change_id}/revert",
+    tag = "tracks",
+    params(
+        ("id" = i32, Path, description = "Track ID"),
+        ("change_id" = i32, Path, description = "Change ID from the track's history"),
+    ),
+    responses(
+        (status = 200, description = "Reverted track", body = TrackResponse),
+        (status = 404, description = "Track or change not found", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The columns have changed again since, or the change can't be reverted", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn revert_change(
+    State(state): State<AppState>,
+    ApiPath((id, change_id)): ApiPath<(i32, i32)>,
+) -> ApiResult<Json<TrackResponse>> {
+    let change = TrackChange::find_by_id(change_id)
+        .filter(track_change::Column::TrackId.eq(id))
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Change {} of track {} not found", change_id, id)))?;
+    let changes = changes_of(&change);
+    let reverted: Vec<&str> = REVERTIBLE_FIELDS.iter()
+        .copied()
+        .filter(|field| changes.contains_key(*field))
+        .collect();
+    if reverted.is_empty() {
+        return Err(ApiError::new(StatusCode::CONFLICT, "not_revertible", format!("Change {} only touched columns that can't be reverted", change_id)));
+    }
+
+    // Lock the track so a scan can't write it between the check and the revert
+    let txn = state.db.begin().await?;
+    let current = Track::find_by_id(id)
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", id)))?;
+
+    // Reverting over a later change would silently undo that one too
+    let mut values = serde_json::to_value(&current).map_err(|e| ApiError::internal(e.to_string()))?;
+    if let Some(field) = reverted.iter().find(|field| values[**field] != changes[**field].after) {
+        return Err(ApiError::conflict(format!("{} has changed since change {}", field, change_id)));
+    }
+    for field in &reverted {
+        values[*field] = changes[*field].before.clone();
+    }
+    let model: track::Model = serde_json::from_value(values).map_err(|e| ApiError::internal(e.to_string()))?;
+
+    let mut active = track::ActiveModel::from(model.clone());
+    for column in track::Column::iter().filter(|column| reverted.contains(&column.as_str())) {
+        active.reset(column);
+    }
+    text_index::set_text_columns(&mut active, state.text_normalization, &state.ignored_articles, &model);
+    let track = active.update(&txn).await?;
+    record(&txn, ChangeSource::Revert, vec![current]).await?;
+    txn.commit().await?;
+    state.counts.refresh(&state.db).await;
+
+    info!("Reverted change {} of track {} ({})", change_id, id, reverted.join(", "));
+    Ok(Json(TrackResponse::from(track)))
+}
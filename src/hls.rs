@@ -0,0 +1,267 @@
+use axum::{
+    body::Body,
+    extract::{RawQuery, State},
+    http::{header, HeaderMap, StatusCode},
+    response::Response,
+};
+use serde::Deserialize;
+use sea_orm::{EntityTrait, QueryFilter};
+use utoipa::IntoParams;
+
+use entity::prelude::Track;
+use entity::track;
+
+use crate::api::{self, AppState};
+use crate::error::{ApiError, ApiPath, ApiQuery, ApiResult};
+use crate::hidden::{Visibility, VisibilityQuery};
+use crate::proxy::RequestOrigin;
+use crate::sessions::NewSession;
+use crate::stream_metrics::METRICS;
+use crate::transcoder::{ProfileFormat, TranscodeOptions};
+use crate::{lyric_providers, lyrics};
+
+const PLAYLIST_CONTENT_TYPE: &str = "application/vnd.apple.mpegurl";
+const WEBVTT_CONTENT_TYPE: &str = "text/vtt; charset=utf-8";
+// Bitrate tracks HLS can't play as they are are transcoded to AAC at
+const SEGMENT_BITRATE: u32 = 256;
+
+async fn find_track(state: &AppState, id: i32, visibility: &Visibility) -> ApiResult<track::Model> {
+    Track::find_by_id(id)
+        .filter(visibility.condition())
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", id)))
+}
+
+fn text_response(content_type: &str, body: String) -> ApiResult<Response> {
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(body))?)
+}
+
+// The manifest's query string (token, profile, user, ...) is passed on to
+// every URL it references, so they play the same way
+fn with_query(uri: &str, query: &Option<String>) -> String {
+    match query.as_deref().filter(|query| !query.is_empty()) {
+        Some(query) => format!("{}?{}", uri, query),
+        None => uri.to_string(),
+    }
+}
+
+// The whole track is one segment, so the target duration is its length
+fn media_playlist(track: &track::Model, uri: &str) -> String {
+    let duration = track.duration_seconds.max(1);
+    format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:0\n#EXTINF:{}.000,\n{}\n#EXT-X-ENDLIST\n",
+        duration, duration, uri,
+    )
+}
+
+// GET /tracks/:id/hls/index.m3u8 - HLS master playlist with the lyrics as a subtitle track
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/hls/index.m3u8",
+    tag = "tracks",
+    params(
+        ("id" = i32, Path, description = "Track ID"),
+        ("profile" = Option<String>, Query, description = "Transcoding profile for the audio, as for /tracks/{id}/play"),
+        ("token" = Option<String>, Query, description = "Signed stream token, as for /tracks/{id}/play"),
+        VisibilityQuery,
+    ),
+    responses(
+        (status = 200, description = "HLS master playlist", content_type = "application/vnd.apple.mpegurl"),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_master_playlist(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    RawQuery(query): RawQuery,
+    visibility: Visibility,
+) -> ApiResult<Response> {
+    let track = find_track(&state, id, &visibility).await?;
+    let has_lyrics = lyric_providers::synced_lyrics(&state, &track).await.is_some();
+
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+    if has_lyrics {
+        playlist.push_str(&format!(
+            "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"lyrics\",NAME=\"Lyrics\",DEFAULT=YES,AUTOSELECT=YES,FORCED=NO,URI=\"{}\"\n",
+            with_query("lyrics.m3u8", &query),
+        ));
+    }
+    let bandwidth = track.overall_bitrate.max(track.audio_bitrate).max(1) as i64 * 1000;
+    playlist.push_str(&format!("#EXT-X-STREAM-INF:BANDWIDTH={}", bandwidth));
+    if has_lyrics {
+        playlist.push_str(",SUBTITLES=\"lyrics\"");
+    }
+    playlist.push_str(&format!("\n{}\n", with_query("audio.m3u8", &query)));
+
+    text_response(PLAYLIST_CONTENT_TYPE, playlist)
+}
+
+// HLS only plays audio as packed MP3, AAC or AC-3 streams, or in MPEG-TS or
+// fragmented MP4 segments. Plain MP4 (.m4a) files aren't fragmented, so AAC
+// in them is transcoded like any other format.
+fn is_hls_compatible(track: &track::Model) -> bool {
+    let codec = track.codec.as_deref().unwrap_or_default();
+    match track.extension.to_lowercase().as_str() {
+        "mp3" => codec.is_empty() || codec == "MP3",
+        "aac" | "ac3" | "eac3" | "ec3" | "ts" => true,
+        _ => false,
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AudioPlaylistQuery {
+    /// Transcoding profile for the audio, as for /tracks/{id}/play. Only mp3 and aac profiles can be played through HLS.
+    pub profile: Option<String>,
+}
+
+// GET /tracks/:id/hls/audio.m3u8 - HLS media playlist with the track as a single segment
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/hls/audio.m3u8",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID"), AudioPlaylistQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "HLS media playlist", content_type = "application/vnd.apple.mpegurl"),
+        (status = 400, description = "Unknown profile, or one that doesn't transcode to mp3 or aac", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_audio_playlist(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    ApiQuery(params): ApiQuery<AudioPlaylistQuery>,
+    RawQuery(query): RawQuery,
+    visibility: Visibility,
+) -> ApiResult<Response> {
+    let track = find_track(&state, id, &visibility).await?;
+
+    // The segment is /tracks/:id/play when it can serve something HLS plays:
+    // a requested mp3 or aac profile, the original file when it is in a
+    // format HLS plays, or a preview clip in demo mode. Anything else is
+    // transcoded to AAC by audio.aac.
+    let segment = match params.profile.as_deref().map(str::trim) {
+        Some(name) if !name.eq_ignore_ascii_case("original") => {
+            let profile = state.transcoding
+                .profile(name)
+                .ok_or_else(|| ApiError::invalid_field("profile", format!("unknown profile '{}'", name)))?;
+            if !matches!(profile.format, ProfileFormat::Mp3 | ProfileFormat::Aac) {
+                return Err(ApiError::invalid_field("profile", "HLS can only play mp3 and aac profiles"));
+            }
+            with_query("../play", &query)
+        }
+        _ if state.demo_mode => with_query("../play", &query),
+        Some(_) if is_hls_compatible(&track) => with_query("../play", &query),
+        // Asking for the original keeps a profile assigned to the client or
+        // user from transcoding it to a format HLS can't play
+        None if is_hls_compatible(&track) => {
+            let query = match query.as_deref().filter(|query| !query.is_empty()) {
+                Some(query) => format!("{}&profile=original", query),
+                None => "profile=original".to_string(),
+            };
+            with_query("../play", &Some(query))
+        }
+        _ => with_query("audio.aac", &query),
+    };
+    text_response(PLAYLIST_CONTENT_TYPE, media_playlist(&track, &segment))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AudioSegmentQuery {
+    pub client: Option<String>,
+    pub token: Option<String>,
+}
+
+// GET /tracks/:id/hls/audio.aac - The track transcoded to AAC, for HLS playlists of tracks in formats HLS can't play
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/hls/audio.aac",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID"), AudioSegmentQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "The track as AAC", content_type = "audio/aac"),
+        (status = 401, description = "A signed stream token is required", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "The stream token is invalid or expired, or demo mode is enabled", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_audio_segment(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    ApiQuery(params): ApiQuery<AudioSegmentQuery>,
+    visibility: Visibility,
+    origin: RequestOrigin,
+    headers: HeaderMap,
+) -> ApiResult<Response<Body>> {
+    if state.demo_mode {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "demo_mode", "Not available in demo mode"));
+    }
+    api::check_stream_token(&state, id, params.token.as_deref())?;
+    let track = find_track(&state, id, &visibility).await?;
+
+    let user_agent = headers.get(header::USER_AGENT).and_then(|value| value.to_str().ok());
+    let new_session = NewSession {
+        user: visibility.user().map(str::to_string),
+        client: params.client.or_else(|| user_agent.map(str::to_string)),
+        address: origin.client_ip.map(|ip| ip.to_string()),
+        track_id: track.id,
+        title: track.title.clone(),
+        artist: track.artist.clone(),
+        bitrate: SEGMENT_BITRATE as i32,
+        total_bytes: 0,
+    };
+    METRICS.transcoded_request("aac");
+    api::transcoded_response(&state, &track.path, TranscodeOptions::aac(SEGMENT_BITRATE), new_session).await
+}
+
+// GET /tracks/:id/hls/lyrics.m3u8 - HLS subtitle playlist for the track's synced lyrics
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/hls/lyrics.m3u8",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID"), VisibilityQuery),
+    responses(
+        (status = 200, description = "HLS subtitle playlist", content_type = "application/vnd.apple.mpegurl"),
+        (status = 404, description = "Track not found or has no synced lyrics", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_lyrics_playlist(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    RawQuery(query): RawQuery,
+    visibility: Visibility,
+) -> ApiResult<Response> {
+    let track = find_track(&state, id, &visibility).await?;
+    if lyric_providers::synced_lyrics(&state, &track).await.is_none() {
+        return Err(ApiError::not_found(format!("Track {} has no synced lyrics", id)));
+    }
+    text_response(PLAYLIST_CONTENT_TYPE, media_playlist(&track, &with_query("lyrics.vtt", &query)))
+}
+
+// GET /tracks/:id/hls/lyrics.vtt - Synced lyrics as WebVTT
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/hls/lyrics.vtt",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID"), VisibilityQuery),
+    responses(
+        (status = 200, description = "Lyrics as WebVTT cues", content_type = "text/vtt"),
+        (status = 404, description = "Track not found or has no synced lyrics", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_lyrics_vtt(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    visibility: Visibility,
+) -> ApiResult<Response> {
+    let track = find_track(&state, id, &visibility).await?;
+    let lines = lyric_providers::synced_lyrics(&state, &track)
+        .await
+        .ok_or_else(|| ApiError::not_found(format!("Track {} has no synced lyrics", id)))?;
+    text_response(WEBVTT_CONTENT_TYPE, lyrics::to_webvtt(&lines, track.duration_seconds as i64 * 1000))
+}
@@ -0,0 +1,52 @@
+use lofty::picture::PictureType;
+use serde::{Deserialize, Serialize};
+
+use entity::track;
+
+/// Front cover, the album art
+pub const FRONT: &str = "front";
+pub const BACK: &str = "back";
+/// The disc or other media
+pub const DISC: &str = "disc";
+/// The artist, lead artist or band
+pub const ARTIST: &str = "artist";
+/// Any other picture, including ones without a type
+pub const OTHER: &str = "other";
+
+pub const IMAGE_TYPES: [&str; 5] = [FRONT, BACK, DISC, ARTIST, OTHER];
+
+/// The image type an embedded picture is stored as
+pub fn image_type(picture_type: PictureType) -> &'static str {
+    match picture_type {
+        PictureType::CoverFront => FRONT,
+        PictureType::CoverBack => BACK,
+        PictureType::Media => DISC,
+        PictureType::LeadArtist | PictureType::Artist | PictureType::Band => ARTIST,
+        _ => OTHER,
+    }
+}
+
+/// An embedded image extracted from a track, as stored in its `images` column
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoredImage {
+    #[serde(rename = "type")]
+    pub image_type: String,
+    pub path: String,
+    pub mime_type: Option<String>,
+    pub size: i32,
+}
+
+/// The images extracted from a track, one per type
+pub fn stored_images(track: &track::Model) -> Vec<StoredImage> {
+    serde_json::from_value(track.images.clone()).unwrap_or_default()
+}
+
+/// The types of image a track has, in the order they were found
+pub fn image_types(track: &track::Model) -> Vec<String> {
+    stored_images(track).into_iter().map(|image| image.image_type).collect()
+}
+
+/// A track's image of the given type
+pub fn find_image(track: &track::Model, image_type: &str) -> Option<StoredImage> {
+    stored_images(track).into_iter().find(|image| image.image_type == image_type)
+}
@@ -0,0 +1,649 @@
+use std::collections::HashMap;
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use log::info;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, NotSet, QueryFilter, QueryOrder, QuerySelect};
+use sea_query::{Expr, OnConflict};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use entity::prelude::{Playlist, Track, TrackAnnotation};
+use entity::{playlist, track, track_annotation};
+
+use crate::api::AppState;
+use crate::error::{ApiError, ApiQuery, ApiResult};
+use crate::storage;
+
+/// iTunes libraries regularly run to tens of megabytes
+pub const IMPORT_BODY_LIMIT: usize = 512 * 1024 * 1024;
+// Unmatched tracks listed in the response, the rest are only counted
+const UNMATCHED_LIMIT: usize = 100;
+
+/// Export formats the importer understands
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// iTunes / Music.app "Library.xml"
+    Itunes,
+    /// Subsonic API XML responses, as served by Navidrome and Airsonic
+    Subsonic,
+}
+
+impl ImportFormat {
+    fn label(self) -> &'static str {
+        match self {
+            ImportFormat::Itunes => "iTunes",
+            ImportFormat::Subsonic => "Subsonic",
+        }
+    }
+}
+
+/// A track as the other server knows it
+#[derive(Default)]
+struct ImportedTrack {
+    location: Option<String>,
+    title: String,
+    artist: String,
+    album: String,
+    rating: Option<i16>,
+    starred: Option<DateTime<Utc>>,
+    play_count: i32,
+    last_played: Option<DateTime<Utc>>,
+}
+
+impl ImportedTrack {
+    fn describe(&self) -> String {
+        match &self.location {
+            Some(location) => location.clone(),
+            None => format!("{} - {}", self.artist, self.title),
+        }
+    }
+}
+
+struct ImportedPlaylist {
+    name: String,
+    // Indexes into ImportedLibrary::tracks
+    entries: Vec<usize>,
+}
+
+struct ImportedLibrary {
+    format: ImportFormat,
+    tracks: Vec<ImportedTrack>,
+    playlists: Vec<ImportedPlaylist>,
+}
+
+/// Collect the text content of the current element, up to its end tag.
+/// Entity references arrive as separate events, so text is never trimmed per event.
+fn read_text(reader: &mut Reader<&[u8]>) -> Result<String, String> {
+    let mut text = String::new();
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Text(content) => text.push_str(&content.decode().map_err(|e| e.to_string())?),
+            Event::CData(content) => text.push_str(&content.decode().map_err(|e| e.to_string())?),
+            Event::GeneralRef(reference) => {
+                if let Some(c) = reference.resolve_char_ref().map_err(|e| e.to_string())? {
+                    text.push(c);
+                } else {
+                    let name = reference.decode().map_err(|e| e.to_string())?;
+                    let resolved = quick_xml::escape::resolve_predefined_entity(&name)
+                        .ok_or_else(|| format!("unknown entity &{};", name))?;
+                    text.push_str(resolved);
+                }
+            }
+            Event::End(_) => return Ok(text.trim().to_string()),
+            Event::Eof => return Err("unexpected end of file".to_string()),
+            _ => {}
+        }
+    }
+}
+
+/// A value from an Apple property list
+enum Plist {
+    Dict(Vec<(String, Plist)>),
+    Array(Vec<Plist>),
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    // Dates are kept as their ISO 8601 text
+    Date(String),
+    Other,
+}
+
+impl Plist {
+    fn get(&self, key: &str) -> Option<&Plist> {
+        match self {
+            Plist::Dict(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn str(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            Plist::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn int(&self, key: &str) -> Option<i64> {
+        match self.get(key)? {
+            Plist::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn flag(&self, key: &str) -> bool {
+        matches!(self.get(key), Some(Plist::Bool(true)))
+    }
+
+    fn date(&self, key: &str) -> Option<DateTime<Utc>> {
+        match self.get(key)? {
+            Plist::Date(value) => parse_date(value),
+            _ => None,
+        }
+    }
+}
+
+/// Parse the plist value whose start tag was just read
+fn read_plist_value(reader: &mut Reader<&[u8]>, tag: &[u8]) -> Result<Plist, String> {
+    match tag {
+        b"dict" => {
+            let mut entries = Vec::new();
+            let mut key = None;
+            loop {
+                match reader.read_event().map_err(|e| e.to_string())? {
+                    Event::Start(start) if start.name().as_ref() == b"key" => key = Some(read_text(reader)?),
+                    Event::Start(start) => {
+                        let value = read_plist_value(reader, start.name().as_ref())?;
+                        entries.push((key.take().unwrap_or_default(), value));
+                    }
+                    Event::End(_) => return Ok(Plist::Dict(entries)),
+                    Event::Eof => return Err("unexpected end of file".to_string()),
+                    _ => {}
+                }
+            }
+        }
+        b"array" => {
+            let mut items = Vec::new();
+            loop {
+                match reader.read_event().map_err(|e| e.to_string())? {
+                    Event::Start(start) => items.push(read_plist_value(reader, start.name().as_ref())?),
+                    Event::End(_) => return Ok(Plist::Array(items)),
+                    Event::Eof => return Err("unexpected end of file".to_string()),
+                    _ => {}
+                }
+            }
+        }
+        b"string" => Ok(Plist::String(read_text(reader)?)),
+        b"integer" => Ok(read_text(reader)?.parse().map(Plist::Integer).unwrap_or(Plist::Other)),
+        b"date" => Ok(Plist::Date(read_text(reader)?)),
+        b"true" | b"false" => {
+            read_text(reader)?;
+            Ok(Plist::Bool(tag == b"true"))
+        }
+        _ => {
+            reader.read_to_end(quick_xml::name::QName(tag)).map_err(|e| e.to_string())?;
+            Ok(Plist::Other)
+        }
+    }
+}
+
+fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value).ok().map(|date| date.with_timezone(&Utc))
+}
+
+/// iTunes locations are file:// URLs, percent-encoded and often with a
+/// "localhost" host
+fn location_to_path(location: &str) -> String {
+    let path = location.strip_prefix("file://localhost")
+        .or_else(|| location.strip_prefix("file://"))
+        .unwrap_or(location);
+    urlencoding::decode(path).map(|path| path.into_owned()).unwrap_or_else(|_| path.to_string())
+}
+
+fn parse_itunes(reader: &mut Reader<&[u8]>) -> Result<ImportedLibrary, String> {
+    let root = loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(start) if start.name().as_ref() == b"dict" => break read_plist_value(reader, b"dict")?,
+            Event::Eof => return Err("no library dictionary found".to_string()),
+            _ => {}
+        }
+    };
+
+    let mut tracks = Vec::new();
+    let mut index_by_id = HashMap::new();
+    if let Some(Plist::Dict(entries)) = root.get("Tracks") {
+        for (_, entry) in entries {
+            // Podcasts, audiobooks and videos aren't part of a music library
+            if entry.flag("Podcast") || entry.flag("Movie") || entry.flag("TV Show") || entry.flag("Music Video") {
+                continue;
+            }
+            let Some(id) = entry.int("Track ID") else {
+                continue;
+            };
+            // Computed ratings are inherited from the album, not given to the track
+            let rating = if entry.flag("Rating Computed") { None } else { entry.int("Rating") };
+            index_by_id.insert(id, tracks.len());
+            tracks.push(ImportedTrack {
+                location: entry.str("Location").map(location_to_path),
+                title: entry.str("Name").unwrap_or_default().to_string(),
+                artist: entry.str("Artist").unwrap_or_default().to_string(),
+                album: entry.str("Album").unwrap_or_default().to_string(),
+                // 0-100, 20 per star
+                rating: rating.map(|rating| ((rating + 10) / 20).clamp(0, 5) as i16).filter(|&stars| stars > 0),
+                starred: (entry.flag("Loved") || entry.flag("Favorited"))
+                    .then(|| entry.date("Date Added").unwrap_or_else(Utc::now)),
+                play_count: entry.int("Play Count").unwrap_or(0).clamp(0, i32::MAX as i64) as i32,
+                last_played: entry.date("Play Date UTC"),
+            });
+        }
+    }
+
+    let mut playlists = Vec::new();
+    if let Some(Plist::Array(items)) = root.get("Playlists") {
+        for item in items {
+            // The whole library, built-in lists (Music, Podcasts, ...), folders and smart playlists
+            let built_in = item.flag("Master") || item.get("Distinguished Kind").is_some() || item.flag("Folder");
+            if built_in || item.get("Smart Info").is_some() {
+                continue;
+            }
+            let entries = match item.get("Playlist Items") {
+                Some(Plist::Array(entries)) => entries.iter()
+                    .filter_map(|entry| entry.int("Track ID"))
+                    .filter_map(|id| index_by_id.get(&id).copied())
+                    .collect(),
+                _ => Vec::new(),
+            };
+            if let Some(name) = item.str("Name") {
+                playlists.push(ImportedPlaylist { name: name.to_string(), entries });
+            }
+        }
+    }
+
+    Ok(ImportedLibrary { format: ImportFormat::Itunes, tracks, playlists })
+}
+
+fn attributes_of(start: &quick_xml::events::BytesStart) -> Result<HashMap<String, String>, String> {
+    let mut attributes = HashMap::new();
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        let key = String::from_utf8_lossy(attribute.key.local_name().as_ref()).into_owned();
+        let value = attribute.unescape_value().map_err(|e| e.to_string())?.into_owned();
+        attributes.insert(key, value);
+    }
+    Ok(attributes)
+}
+
+/// Songs can appear in any Subsonic response (getStarred, getAlbum,
+/// getPlaylist, ...) as <song>, <entry> or <child>; entries inside a
+/// <playlist> also make up that playlist
+fn parse_subsonic(reader: &mut Reader<&[u8]>) -> Result<ImportedLibrary, String> {
+    let mut tracks: Vec<ImportedTrack> = Vec::new();
+    let mut index_by_id: HashMap<String, usize> = HashMap::new();
+    let mut playlists = Vec::new();
+    let mut current_playlist: Option<ImportedPlaylist> = None;
+
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(start) => match start.local_name().as_ref() {
+                b"playlist" => {
+                    let attributes = attributes_of(&start)?;
+                    current_playlist = Some(ImportedPlaylist {
+                        name: attributes.get("name").cloned().unwrap_or_default(),
+                        entries: Vec::new(),
+                    });
+                }
+                b"song" | b"entry" | b"child" => {
+                    let attributes = attributes_of(&start)?;
+                    if attributes.get("isDir").is_some_and(|dir| dir == "true") {
+                        continue;
+                    }
+                    let imported = ImportedTrack {
+                        location: attributes.get("path").cloned(),
+                        title: attributes.get("title").cloned().unwrap_or_default(),
+                        artist: attributes.get("artist").cloned().unwrap_or_default(),
+                        album: attributes.get("album").cloned().unwrap_or_default(),
+                        rating: attributes.get("userRating")
+                            .and_then(|rating| rating.parse::<i16>().ok())
+                            .filter(|rating| (1..=5).contains(rating)),
+                        starred: attributes.get("starred").and_then(|date| parse_date(date)),
+                        play_count: attributes.get("playCount").and_then(|count| count.parse().ok()).unwrap_or(0),
+                        last_played: attributes.get("played").and_then(|date| parse_date(date)),
+                    };
+                    // The same song shows up in several lists; keep the first
+                    // copy and fill in whatever it was missing
+                    let index = match attributes.get("id").and_then(|id| index_by_id.get(id)) {
+                        Some(&index) => {
+                            let existing = &mut tracks[index];
+                            existing.rating = existing.rating.or(imported.rating);
+                            existing.starred = existing.starred.or(imported.starred);
+                            existing.play_count = existing.play_count.max(imported.play_count);
+                            existing.last_played = existing.last_played.max(imported.last_played);
+                            index
+                        }
+                        None => {
+                            if let Some(id) = attributes.get("id") {
+                                index_by_id.insert(id.clone(), tracks.len());
+                            }
+                            tracks.push(imported);
+                            tracks.len() - 1
+                        }
+                    };
+                    if let Some(playlist) = current_playlist.as_mut() {
+                        playlist.entries.push(index);
+                    }
+                }
+                _ => {}
+            },
+            Event::End(end) if end.local_name().as_ref() == b"playlist" => {
+                if let Some(playlist) = current_playlist.take().filter(|playlist| !playlist.entries.is_empty()) {
+                    playlists.push(playlist);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(ImportedLibrary { format: ImportFormat::Subsonic, tracks, playlists })
+}
+
+/// Parse an export, telling the format apart by its root element
+fn parse_library(content: &str) -> Result<ImportedLibrary, String> {
+    let mut reader = Reader::from_str(content.trim_start_matches('\u{feff}'));
+    reader.config_mut().expand_empty_elements = true;
+
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(start) => {
+                return match start.local_name().as_ref() {
+                    b"plist" => parse_itunes(&mut reader),
+                    b"subsonic-response" => parse_subsonic(&mut reader),
+                    other => Err(format!(
+                        "unrecognized export with root element <{}>, expected an iTunes library or a Subsonic response",
+                        String::from_utf8_lossy(other),
+                    )),
+                };
+            }
+            Event::Eof => return Err("document is empty".to_string()),
+            _ => {}
+        }
+    }
+}
+
+/// Library tracks indexed for matching imported ones
+struct TrackMatcher {
+    roots: Vec<String>,
+    by_path: HashMap<String, i32>,
+    // Lowercased "folder/file name", with the full paths of the tracks there
+    by_tail: HashMap<String, Vec<(i32, String)>>,
+    by_metadata: HashMap<(String, String, String), i32>,
+    by_artist_title: HashMap<(String, String), i32>,
+}
+
+fn path_tail(path: &str) -> Option<String> {
+    let mut components = path.rsplit(['/', '\\']).filter(|component| !component.is_empty());
+    let file = components.next()?;
+    let folder = components.next()?;
+    Some(format!("{}/{}", folder, file).to_lowercase())
+}
+
+// Number of trailing path components two paths share, ignoring case
+fn shared_components(a: &str, b: &str) -> usize {
+    a.rsplit(['/', '\\'])
+        .zip(b.rsplit(['/', '\\']))
+        .take_while(|(a, b)| !a.is_empty() && a.eq_ignore_ascii_case(b))
+        .count()
+}
+
+impl TrackMatcher {
+    async fn load(db: &DatabaseConnection, music_path: &str) -> Result<Self, DbErr> {
+        let tracks: Vec<(i32, String, String, String, String)> = Track::find()
+            .select_only()
+            .column(track::Column::Id)
+            .column(track::Column::Path)
+            .column(track::Column::Title)
+            .column(track::Column::Artist)
+            .column(track::Column::Album)
+            .filter(track::Column::MissingSince.is_null())
+            .order_by_asc(track::Column::Path)
+            .into_tuple()
+            .all(db)
+            .await?;
+
+        let mut matcher = TrackMatcher {
+            roots: storage::library_roots(music_path),
+            by_path: HashMap::new(),
+            by_tail: HashMap::new(),
+            by_metadata: HashMap::new(),
+            by_artist_title: HashMap::new(),
+        };
+        for (id, path, title, artist, album) in tracks {
+            let (title, artist, album) = (title.to_lowercase(), artist.to_lowercase(), album.to_lowercase());
+            matcher.by_metadata.entry((artist.clone(), title.clone(), album)).or_insert(id);
+            matcher.by_artist_title.entry((artist, title)).or_insert(id);
+            if let Some(tail) = path_tail(&path) {
+                matcher.by_tail.entry(tail).or_default().push((id, path.clone()));
+            }
+            matcher.by_path.insert(path, id);
+        }
+        Ok(matcher)
+    }
+
+    /// Match on the exact path, the path relative to a library folder, the
+    /// longest shared trailing path (at least folder and file name), then
+    /// artist, title and album, then artist and title
+    fn resolve(&self, imported: &ImportedTrack) -> Option<i32> {
+        if let Some(location) = imported.location.as_deref() {
+            if let Some(&id) = self.by_path.get(location) {
+                return Some(id);
+            }
+            let relative = location.trim_start_matches("./");
+            for root in &self.roots {
+                if let Some(&id) = self.by_path.get(&format!("{}/{}", root, relative)) {
+                    return Some(id);
+                }
+            }
+            let by_tail = path_tail(location)
+                .and_then(|tail| self.by_tail.get(&tail))
+                .and_then(|candidates| candidates.iter().max_by_key(|(_, path)| shared_components(path, location)));
+            if let Some((id, _)) = by_tail {
+                return Some(*id);
+            }
+        }
+
+        let artist = imported.artist.to_lowercase();
+        let title = imported.title.to_lowercase();
+        if title.is_empty() {
+            return None;
+        }
+        self.by_metadata.get(&(artist.clone(), title.clone(), imported.album.to_lowercase()))
+            .or_else(|| self.by_artist_title.get(&(artist, title)))
+            .copied()
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportLibraryQuery {
+    /// User the ratings, stars and play counts belong to
+    pub user: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportLibraryResponse {
+    pub format: ImportFormat,
+    pub tracks_in_file: usize,
+    pub matched: usize,
+    pub ratings: usize,
+    pub starred: usize,
+    pub play_counts: usize,
+    pub playlists_created: Vec<String>,
+    /// Playlists left alone because one with the same name already exists
+    pub playlists_skipped: Vec<String>,
+    pub unmatched_count: usize,
+    /// Paths (or "artist - title") of the first 100 unmatched tracks
+    pub unmatched: Vec<String>,
+}
+
+// Merge two annotations of the same library track
+fn merge(into: &mut track_annotation::Model, imported: &ImportedTrack) {
+    into.rating = into.rating.max(imported.rating);
+    into.starred = match (into.starred, imported.starred) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    into.play_count = into.play_count.saturating_add(imported.play_count);
+    into.last_played = into.last_played.max(imported.last_played);
+}
+
+async fn save_annotations(db: &DatabaseConnection, annotations: Vec<track_annotation::Model>) -> Result<(), DbErr> {
+    // Importing the same export twice changes nothing: ratings are replaced
+    // when the export has one, and play counts and dates only move forward
+    let on_conflict = OnConflict::columns([track_annotation::Column::User, track_annotation::Column::TrackId])
+        .value(track_annotation::Column::Rating, Expr::cust("COALESCE(excluded.rating, track_annotation.rating)"))
+        .value(track_annotation::Column::Starred, Expr::cust("LEAST(excluded.starred, track_annotation.starred)"))
+        .value(track_annotation::Column::PlayCount, Expr::cust("GREATEST(excluded.play_count, track_annotation.play_count)"))
+        .value(track_annotation::Column::LastPlayed, Expr::cust("GREATEST(excluded.last_played, track_annotation.last_played)"))
+        .update_column(track_annotation::Column::Updated)
+        .to_owned();
+
+    let models: Vec<track_annotation::ActiveModel> = annotations.into_iter()
+        .map(|annotation| track_annotation::ActiveModel {
+            id: NotSet,
+            user: Set(annotation.user),
+            track_id: Set(annotation.track_id),
+            rating: Set(annotation.rating),
+            starred: Set(annotation.starred),
+            play_count: Set(annotation.play_count),
+            last_played: Set(annotation.last_played),
+            updated: Set(annotation.updated),
+        })
+        .collect();
+    for chunk in models.chunks(1000) {
+        TrackAnnotation::insert_many(chunk.to_vec())
+            .on_conflict(on_conflict.clone())
+            .exec(db)
+            .await?;
+    }
+    Ok(())
+}
+
+// POST /admin/import - Import ratings, stars, play counts and playlists from another server's export
+#[utoipa::path(
+    post,
+    path = "/admin/import",
+    tag = "listening",
+    params(ImportLibraryQuery),
+    request_body(content = String, content_type = "application/xml", description = "iTunes Library.xml or Subsonic API XML responses"),
+    responses(
+        (status = 200, description = "Import summary", body = ImportLibraryResponse),
+        (status = 400, description = "Invalid parameters or unreadable export", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn import_library(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<ImportLibraryQuery>,
+    body: Bytes,
+) -> ApiResult<Json<ImportLibraryResponse>> {
+    if params.user.trim().is_empty() {
+        return Err(ApiError::invalid_field("user", "must not be empty"));
+    }
+    let content = String::from_utf8_lossy(&body);
+    let library = parse_library(&content).map_err(|e| ApiError::invalid_field("body", e))?;
+
+    let matcher = TrackMatcher::load(&state.db, &state.music_path).await?;
+    let now = Utc::now();
+    let mut track_ids: Vec<Option<i32>> = Vec::with_capacity(library.tracks.len());
+    let mut annotations: HashMap<i32, track_annotation::Model> = HashMap::new();
+    let mut unmatched = Vec::new();
+    let mut unmatched_count = 0;
+    for imported in &library.tracks {
+        let track_id = matcher.resolve(imported);
+        track_ids.push(track_id);
+        let Some(track_id) = track_id else {
+            unmatched_count += 1;
+            if unmatched.len() < UNMATCHED_LIMIT {
+                unmatched.push(imported.describe());
+            }
+            continue;
+        };
+        if imported.rating.is_none() && imported.starred.is_none() && imported.play_count == 0 {
+            continue;
+        }
+        let annotation = annotations.entry(track_id).or_insert_with(|| track_annotation::Model {
+            id: 0,
+            user: params.user.clone(),
+            track_id,
+            rating: None,
+            starred: None,
+            play_count: 0,
+            last_played: None,
+            updated: now,
+        });
+        merge(annotation, imported);
+    }
+
+    let matched = track_ids.iter().filter(|id| id.is_some()).count();
+    let ratings = annotations.values().filter(|annotation| annotation.rating.is_some()).count();
+    let starred = annotations.values().filter(|annotation| annotation.starred.is_some()).count();
+    let play_counts = annotations.values().filter(|annotation| annotation.play_count > 0).count();
+    save_annotations(&state.db, annotations.into_values().collect()).await?;
+
+    let existing: Vec<String> = Playlist::find()
+        .select_only()
+        .column(playlist::Column::Name)
+        .into_tuple()
+        .all(&state.db)
+        .await?;
+    let mut playlists_created = Vec::new();
+    let mut playlists_skipped = Vec::new();
+    for imported in library.playlists {
+        let ids: Vec<i32> = imported.entries.iter().filter_map(|&index| track_ids[index]).collect();
+        if imported.name.trim().is_empty() || ids.is_empty() {
+            continue;
+        }
+        if existing.contains(&imported.name) || playlists_created.contains(&imported.name) {
+            playlists_skipped.push(imported.name);
+            continue;
+        }
+        Playlist::insert(playlist::ActiveModel {
+            id: NotSet,
+            name: Set(imported.name.clone()),
+            comment: Set(format!("Imported from {}", library.format.label())),
+            track_ids: Set(serde_json::json!(ids)),
+            public: Set(false),
+            created: Set(now),
+            changed: Set(now),
+            lastfm_user: Set(None),
+            mix_size: Set(None),
+            refreshed: Set(None),
+        })
+        .exec(&state.db)
+        .await?;
+        playlists_created.push(imported.name);
+    }
+
+    info!("Imported {} export for {}: {} of {} tracks matched, {} playlists created",
+          library.format.label(), params.user, matched, library.tracks.len(), playlists_created.len());
+
+    Ok(Json(ImportLibraryResponse {
+        format: library.format,
+        tracks_in_file: library.tracks.len(),
+        matched,
+        ratings,
+        starred,
+        play_counts,
+        playlists_created,
+        playlists_skipped,
+        unmatched_count,
+        unmatched,
+    }))
+}
@@ -0,0 +1,83 @@
+use std::env;
+use std::path::Path;
+
+use chrono::{Duration, Utc};
+
+use crate::storage::FileEntry;
+
+/// Suffixes download clients use for files that are still being written
+/// (slskd, qBittorrent, uTorrent, Transmission, browsers)
+const DEFAULT_PARTIAL_SUFFIXES: &[&str] = &[".part", ".partial", ".!ut", ".!qb", ".crdownload", ".tmp", ".downloading"];
+
+/// Directory names download clients keep unfinished transfers in
+const DEFAULT_INCOMPLETE_DIRS: &[&str] = &["incomplete", ".incomplete"];
+
+const DEFAULT_SETTLE_SECONDS: i64 = 60;
+
+/// Detects files that are still being downloaded so the scanner can leave
+/// them for a later scan instead of recording junk tracks or scan errors
+pub struct InProgressFilter {
+    partial_suffixes: Vec<String>,
+    incomplete_dirs: Vec<String>,
+    settle_delay: Duration,
+}
+
+impl Default for InProgressFilter {
+    fn default() -> Self {
+        Self {
+            partial_suffixes: DEFAULT_PARTIAL_SUFFIXES.iter().map(|s| s.to_string()).collect(),
+            incomplete_dirs: DEFAULT_INCOMPLETE_DIRS.iter().map(|s| s.to_string()).collect(),
+            settle_delay: Duration::seconds(DEFAULT_SETTLE_SECONDS),
+        }
+    }
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(|item| item.trim().to_lowercase())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+impl InProgressFilter {
+    /// Build the filter from SCAN_PARTIAL_SUFFIXES, SCAN_INCOMPLETE_DIRS
+    /// (comma separated) and SCAN_SETTLE_SECONDS, keeping defaults for unset values
+    pub fn from_env() -> Self {
+        let mut filter = Self::default();
+        if let Ok(suffixes) = env::var("SCAN_PARTIAL_SUFFIXES") {
+            filter.partial_suffixes = parse_list(&suffixes);
+        }
+        if let Ok(dirs) = env::var("SCAN_INCOMPLETE_DIRS") {
+            filter.incomplete_dirs = parse_list(&dirs);
+        }
+        if let Some(seconds) = env::var("SCAN_SETTLE_SECONDS").ok().and_then(|s| s.parse().ok()) {
+            filter.settle_delay = Duration::seconds(seconds);
+        }
+        filter
+    }
+
+    /// Whether a directory is one download clients keep unfinished transfers in
+    pub fn is_incomplete_dir(&self, path: &Path) -> bool {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| self.incomplete_dirs.contains(&name.to_lowercase()))
+            .unwrap_or(false)
+    }
+
+    /// Whether a file looks like an unfinished download: a partial suffix, a
+    /// parent directory named like an incomplete folder, or a write within the
+    /// settle delay
+    pub fn is_in_progress(&self, entry: &FileEntry) -> bool {
+        let lower = entry.path.to_lowercase();
+        if self.partial_suffixes.iter().any(|suffix| lower.ends_with(suffix.as_str())) {
+            return true;
+        }
+
+        let path = Path::new(&entry.path);
+        if path.ancestors().skip(1).any(|ancestor| self.is_incomplete_dir(ancestor)) {
+            return true;
+        }
+
+        Utc::now().signed_duration_since(entry.modified) < self.settle_delay
+    }
+}
@@ -0,0 +1,216 @@
+use std::env;
+use std::io;
+
+use axum::{
+    extract::State,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use log::{error, info, warn};
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait};
+use sea_query::Expr;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use entity::prelude::Track;
+use entity::track;
+
+use crate::api::AppState;
+use crate::error::ApiResult;
+use crate::history::{self, ChangeSource};
+use crate::storage::Storage;
+use crate::transcoder;
+
+/// The decoded audio matches the checksum in STREAMINFO
+pub const STATUS_OK: &str = "ok";
+/// The decoded audio differs from the checksum: the file is damaged or truncated
+pub const STATUS_MISMATCH: &str = "mismatch";
+/// ffmpeg couldn't decode the file at all
+pub const STATUS_UNREADABLE: &str = "unreadable";
+
+// Files decoded at once during verification
+const VERIFY_CONCURRENCY: usize = 4;
+// Problem tracks listed in the quality report
+const PROBLEM_LIMIT: u64 = 500;
+
+/// Whether every scan verifies FLAC checksums, with SCAN_VERIFY_AUDIO=true
+pub fn verify_from_env() -> bool {
+    env::var("SCAN_VERIFY_AUDIO").map(|s| s == "true" || s == "1").unwrap_or(false)
+}
+
+/// The MD5 of the decoded audio recorded in a FLAC file's STREAMINFO block,
+/// from at least the first 42 bytes of the file. None for other files and
+/// for encoders that leave it unset (all zeros).
+pub fn flac_audio_md5(header: &[u8]) -> Option<String> {
+    // "fLaC", a 4 byte block header, then STREAMINFO with the MD5 in its last 16 bytes
+    if header.len() < 42 || &header[..4] != b"fLaC" || header[4] & 0x7f != 0 {
+        return None;
+    }
+    let md5 = &header[26..42];
+    if md5.iter().all(|&byte| byte == 0) {
+        return None;
+    }
+    Some(hex::encode(md5))
+}
+
+// FLAC hashes samples as little-endian signed integers of the stream's own
+// width, which ffmpeg can reproduce for whole-byte sample sizes
+fn pcm_codec(bit_depth: i32) -> Option<&'static str> {
+    match bit_depth {
+        8 => Some("pcm_s8"),
+        16 => Some("pcm_s16le"),
+        24 => Some("pcm_s24le"),
+        32 => Some("pcm_s32le"),
+        _ => None,
+    }
+}
+
+async fn check_track(storage: &dyn Storage, path: &str, expected: &str, codec: &'static str) -> io::Result<&'static str> {
+    match transcoder::decoded_md5(storage, path, codec).await {
+        Ok(md5) if md5.eq_ignore_ascii_case(expected) => Ok(STATUS_OK),
+        Ok(_) => Ok(STATUS_MISMATCH),
+        // Without ffmpeg nothing can be checked, so stop instead of flagging every file
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(e),
+        Err(_) => Ok(STATUS_UNREADABLE),
+    }
+}
+
+/// Decode every FLAC track with a checksum that hasn't been checked since it
+/// was last scanned and record whether its audio matches. Returns the number
+/// of tracks checked.
+pub async fn verify_flac_checksums(db: &DatabaseConnection, storage: &dyn Storage) -> Result<usize, DbErr> {
+    let pending: Vec<(i32, String, String, i32)> = Track::find()
+        .select_only()
+        .column(track::Column::Id)
+        .column(track::Column::Path)
+        .column(track::Column::AudioMd5)
+        .column(track::Column::BitDepth)
+        .filter(track::Column::AudioMd5.is_not_null())
+        .filter(track::Column::AudioMd5Status.is_null())
+        .filter(track::Column::MissingSince.is_null())
+        .order_by_asc(track::Column::Path)
+        .into_tuple()
+        .all(db)
+        .await?;
+    if pending.is_empty() {
+        return Ok(0);
+    }
+    info!("Verifying audio checksums of {} FLAC files", pending.len());
+
+    // Other sample sizes are left unverified
+    let checkable: Vec<(i32, String, String, &'static str)> = pending.into_iter()
+        .filter_map(|(id, path, expected, bit_depth)| Some((id, path, expected, pcm_codec(bit_depth)?)))
+        .collect();
+    let checks: Vec<_> = checkable.into_iter()
+        .map(|(id, path, expected, codec)| async move {
+            let status = check_track(storage, &path, &expected, codec).await;
+            (id, path, status)
+        })
+        .collect();
+    let mut results = stream::iter(checks).buffer_unordered(VERIFY_CONCURRENCY);
+
+    let mut checked = 0;
+    let mut damaged = 0;
+    while let Some((id, path, status)) = results.next().await {
+        let status = match status {
+            Ok(status) => status,
+            Err(e) => {
+                error!("Can't verify audio checksums, ffmpeg is not available: {}", e);
+                break;
+            }
+        };
+        if status != STATUS_OK {
+            warn!("Audio checksum {} for {}", status, path);
+            damaged += 1;
+        }
+        let txn = db.begin().await?;
+        let before = history::snapshot(&txn, track::Column::Id.eq(id)).await?;
+        Track::update_many()
+            .col_expr(track::Column::AudioMd5Status, Expr::value(status))
+            .col_expr(track::Column::AudioMd5Checked, Expr::value(Utc::now()))
+            .filter(track::Column::Id.eq(id))
+            .exec(&txn)
+            .await?;
+        history::record(&txn, ChangeSource::Verification, before).await?;
+        txn.commit().await?;
+        checked += 1;
+    }
+
+    info!("Verified {} FLAC files, {} damaged or unreadable", checked, damaged);
+    Ok(checked)
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct QualityProblem {
+    pub track_id: i32,
+    pub path: String,
+    /// mismatch or unreadable
+    pub status: String,
+    pub checked: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct QualityReport {
+    pub flac_tracks: u64,
+    /// FLAC files whose encoder left the audio checksum unset, which can't be verified
+    pub without_checksum: u64,
+    pub unverified: u64,
+    pub verified: u64,
+    pub mismatched: u64,
+    pub unreadable: u64,
+    /// Damaged and unreadable files, up to 500
+    pub problems: Vec<QualityProblem>,
+}
+
+// GET /admin/quality-report - Library integrity: FLAC files whose audio doesn't match its checksum
+#[utoipa::path(
+    get,
+    path = "/admin/quality-report",
+    tag = "scans",
+    responses(
+        (status = 200, description = "Audio checksum verification summary", body = QualityReport),
+    )
+)]
+pub async fn get_quality_report(State(state): State<AppState>) -> ApiResult<Json<QualityReport>> {
+    let flac = || {
+        Track::find()
+            .filter(track::Column::MissingSince.is_null())
+            .filter(track::Column::ContentType.eq("audio/flac"))
+    };
+    let flac_tracks = flac().count(&state.db).await?;
+    let without_checksum = flac().filter(track::Column::AudioMd5.is_null()).count(&state.db).await?;
+    let unverified = flac()
+        .filter(track::Column::AudioMd5.is_not_null())
+        .filter(track::Column::AudioMd5Status.is_null())
+        .count(&state.db)
+        .await?;
+    let verified = flac().filter(track::Column::AudioMd5Status.eq(STATUS_OK)).count(&state.db).await?;
+    let mismatched = flac().filter(track::Column::AudioMd5Status.eq(STATUS_MISMATCH)).count(&state.db).await?;
+    let unreadable = flac().filter(track::Column::AudioMd5Status.eq(STATUS_UNREADABLE)).count(&state.db).await?;
+
+    let problems = flac()
+        .filter(track::Column::AudioMd5Status.is_in([STATUS_MISMATCH, STATUS_UNREADABLE]))
+        .order_by_asc(track::Column::Path)
+        .limit(PROBLEM_LIMIT)
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|track| QualityProblem {
+            track_id: track.id,
+            path: track.path,
+            status: track.audio_md5_status.unwrap_or_default(),
+            checked: track.audio_md5_checked,
+        })
+        .collect();
+
+    Ok(Json(QualityReport {
+        flac_tracks,
+        without_checksum,
+        unverified,
+        verified,
+        mismatched,
+        unreadable,
+        problems,
+    }))
+}
@@ -3,21 +3,22 @@ use std::env;
 use std::fs;
 use std::path::PathBuf;
 use axum::{
-    extract::{Path, State, Query},
+    extract::State,
     http::StatusCode,
     response::{Json, Html},
 };
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use log::{debug, error, warn};
 use rustfm_scrobble_proxy::{Scrobbler, Scrobble};
-use md5;
 
 use entity::prelude::Track;
 use entity::track;
 use sea_orm::EntityTrait;
 
 use crate::api::AppState;
+use crate::error::{ApiError, ApiJson, ApiPath, ApiQuery, ApiResult};
 
 const LASTFM_API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
 const LASTFM_AUTH_URL: &str = "https://www.last.fm/api/auth";
@@ -42,7 +43,37 @@ struct LastfmTokenResponse {
     message: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize)]
+struct LastfmErrorResponse {
+    error: i32,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct LastfmArtist {
+    name: String,
+    // Similarity to the artist asked about, from 0 to 1, in artist.getSimilar
+    #[serde(rename = "match", default)]
+    similarity: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LastfmArtistList {
+    #[serde(default)]
+    artist: Vec<LastfmArtist>,
+}
+
+#[derive(Deserialize)]
+struct LastfmTopArtistsResponse {
+    topartists: LastfmArtistList,
+}
+
+#[derive(Deserialize)]
+struct LastfmSimilarArtistsResponse {
+    similarartists: LastfmArtistList,
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct LastfmAuthResponse {
     pub auth_url: String,
     pub token: String,
@@ -52,43 +83,44 @@ pub struct LastfmAuthResponse {
 #[derive(Deserialize)]
 pub struct AuthUrlQuery {}
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct CallbackQuery {
     pub token: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct LastfmSessionRequest {
     pub token: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct LastfmSessionResponse {
     pub session_key: String,
     pub username: String,
     pub message: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct ScrobbleRequest {
     pub session_key: String,
     pub timestamp: i64,
     pub album_artist: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct NowPlayingRequest {
     pub session_key: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ScrobbleResponse {
     pub success: bool,
     pub message: String,
     pub scrobble_id: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct NowPlayingResponse {
     pub success: bool,
     pub message: String,
@@ -225,7 +257,7 @@ impl LastfmClient {
 
         // Build the parameter vector for URL encoding
         // No signature needed for user-facing auth URLs
-        let url_params = vec![
+        let url_params = [
             ("api_key", self.api_key.as_str()),
             ("token", token),
         ];
@@ -244,7 +276,51 @@ impl LastfmClient {
         auth_url
     }
 
+    /// Call a read-only API method that needs no session or signature
+    async fn get_json<T: for<'de> Deserialize<'de>>(&self, method: &str, params: &[(&str, &str)]) -> Result<T, String> {
+        let response = self.client
+            .get(LASTFM_API_URL)
+            .query(&[("method", method), ("api_key", self.api_key.as_str()), ("format", "json")])
+            .query(params)
+            .send()
+            .await
+            .map_err(|e| format!("HTTP request failed: {}", e))?;
+        let body: serde_json::Value = response
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse JSON response: {}", e))?;
+
+        if let Ok(error) = serde_json::from_value::<LastfmErrorResponse>(body.clone()) {
+            return Err(format!("Last.fm API error {}: {}", error.error, error.message));
+        }
+        serde_json::from_value(body).map_err(|e| format!("Unexpected {} response: {}", method, e))
+    }
+
+    /// A user's most played artists over a period (7day, 1month, 3month, 6month, 12month or overall)
+    pub async fn get_top_artists(&self, user: &str, period: &str, limit: usize) -> Result<Vec<String>, String> {
+        let limit = limit.to_string();
+        let response: LastfmTopArtistsResponse = self
+            .get_json("user.gettopartists", &[("user", user), ("period", period), ("limit", &limit)])
+            .await?;
+        Ok(response.topartists.artist.into_iter().map(|artist| artist.name).collect())
+    }
+
+    /// Artists similar to one artist, with how similar they are from 0 to 1
+    pub async fn get_similar_artists(&self, artist: &str, limit: usize) -> Result<Vec<(String, f64)>, String> {
+        let limit = limit.to_string();
+        let response: LastfmSimilarArtistsResponse = self
+            .get_json("artist.getsimilar", &[("artist", artist), ("limit", &limit), ("autocorrect", "1")])
+            .await?;
+        Ok(response.similarartists.artist.into_iter()
+            .map(|artist| {
+                let similarity = artist.similarity.and_then(|s| s.parse().ok()).unwrap_or(0.0);
+                (artist.name, similarity)
+            })
+            .collect())
+    }
+
     /// Load existing session from file if available
+    #[allow(dead_code)]
     pub fn load_existing_session(&self) -> Option<String> {
         get_session_file_path()
             .ok()
@@ -275,15 +351,32 @@ impl LastfmClient {
 
 // API handlers
 
+pub fn lastfm_unavailable() -> ApiError {
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "lastfm_unavailable", "Last.fm client is not configured")
+}
+
+pub fn lastfm_error(detail: &str) -> ApiError {
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "lastfm_error", detail)
+}
+
+#[utoipa::path(
+    get,
+    path = "/lastfm/auth",
+    tag = "lastfm",
+    responses(
+        (status = 200, description = "Last.fm authorization URL", body = LastfmAuthResponse),
+        (status = 500, description = "Last.fm is unavailable", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 pub async fn get_auth_url(
     State(_state): State<AppState>,
-    Query(_query): Query<AuthUrlQuery>,
-) -> Result<Json<LastfmAuthResponse>, StatusCode> {
+    ApiQuery(_query): ApiQuery<AuthUrlQuery>,
+) -> ApiResult<Json<LastfmAuthResponse>> {
     let client = match LastfmClient::new() {
         Ok(client) => client,
         Err(e) => {
             error!("Failed to create Last.fm client: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(lastfm_unavailable());
         }
     };
 
@@ -291,7 +384,7 @@ pub async fn get_auth_url(
         Ok(token) => token,
         Err(e) => {
             error!("Failed to get Last.fm token: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(lastfm_error("Failed to get a Last.fm token"));
         }
     };
 
@@ -300,15 +393,25 @@ pub async fn get_auth_url(
     Ok(Json(LastfmAuthResponse { auth_url, token }))
 }
 
+#[utoipa::path(
+    post,
+    path = "/lastfm/session",
+    tag = "lastfm",
+    request_body = LastfmSessionRequest,
+    responses(
+        (status = 200, description = "Session created", body = LastfmSessionResponse),
+        (status = 400, description = "The token could not be exchanged for a session", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 pub async fn create_session(
     State(_state): State<AppState>,
-    Json(request): Json<LastfmSessionRequest>,
-) -> Result<Json<LastfmSessionResponse>, StatusCode> {
+    ApiJson(request): ApiJson<LastfmSessionRequest>,
+) -> ApiResult<Json<LastfmSessionResponse>> {
     let client = match LastfmClient::new() {
         Ok(client) => client,
         Err(e) => {
             error!("Failed to create Last.fm client: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(lastfm_unavailable());
         }
     };
 
@@ -316,7 +419,7 @@ pub async fn create_session(
         Ok(session) => session,
         Err(e) => {
             warn!("Failed to create Last.fm session: {}", e);
-            return Err(StatusCode::BAD_REQUEST);
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "lastfm_session_failed", e.to_string()));
         }
     };
 
@@ -327,24 +430,34 @@ pub async fn create_session(
     }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/lastfm/callback",
+    tag = "lastfm",
+    params(CallbackQuery),
+    responses(
+        (status = 200, description = "Page showing the new session key", content_type = "text/html"),
+        (status = 500, description = "Last.fm is unavailable", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 pub async fn auth_callback(
     State(_state): State<AppState>,
-    Query(query): Query<CallbackQuery>,
-) -> Result<Html<String>, StatusCode> {
+    ApiQuery(query): ApiQuery<CallbackQuery>,
+) -> ApiResult<Html<String>> {
     let client = match LastfmClient::new() {
         Ok(client) => client,
         Err(e) => {
             error!("Failed to create Last.fm client: {}", e);
-            return Ok(Html(format!(
+            return Ok(Html(
                 r#"<!DOCTYPE html>
 <html>
 <head>
     <title>Last.fm Authorization Failed</title>
     <style>
-        body {{ font-family: Arial, sans-serif; margin: 40px; background-color: #f5f5f5; }}
-        .container {{ max-width: 600px; margin: 0 auto; background: white; padding: 30px; border-radius: 8px; box-shadow: 0 2px 10px rgba(0,0,0,0.1); }}
-        .error {{ color: #d32f2f; }}
-        h1 {{ color: #333; }}
+        body { font-family: Arial, sans-serif; margin: 40px; background-color: #f5f5f5; }
+        .container { max-width: 600px; margin: 0 auto; background: white; padding: 30px; border-radius: 8px; box-shadow: 0 2px 10px rgba(0,0,0,0.1); }
+        .error { color: #d32f2f; }
+        h1 { color: #333; }
     </style>
 </head>
 <body>
@@ -354,23 +467,23 @@ pub async fn auth_callback(
         <p>Please try again later.</p>
     </div>
 </body>
-</html>"#
-            )));
+</html>"#.to_string()
+            ));
         }
     };
 
     // Validate token
     if query.token.trim().is_empty() {
-        return Ok(Html(format!(
+        return Ok(Html(
             r#"<!DOCTYPE html>
 <html>
 <head>
     <title>Last.fm Authorization Failed</title>
     <style>
-        body {{ font-family: Arial, sans-serif; margin: 40px; background-color: #f5f5f5; }}
-        .container {{ max-width: 600px; margin: 0 auto; background: white; padding: 30px; border-radius: 8px; box-shadow: 0 2px 10px rgba(0,0,0,0.1); }}
-        .error {{ color: #d32f2f; }}
-        h1 {{ color: #333; }}
+        body { font-family: Arial, sans-serif; margin: 40px; background-color: #f5f5f5; }
+        .container { max-width: 600px; margin: 0 auto; background: white; padding: 30px; border-radius: 8px; box-shadow: 0 2px 10px rgba(0,0,0,0.1); }
+        .error { color: #d32f2f; }
+        h1 { color: #333; }
     </style>
 </head>
 <body>
@@ -380,8 +493,8 @@ pub async fn auth_callback(
         <p>Please restart the authorization process.</p>
     </div>
 </body>
-</html>"#
-        )));
+</html>"#.to_string()
+        ));
     }
 
     // Create session from the token
@@ -466,26 +579,34 @@ pub async fn auth_callback(
     )))
 }
 
+#[utoipa::path(
+    post,
+    path = "/tracks/{id}/scrobble",
+    tag = "lastfm",
+    params(("id" = i32, Path, description = "Track ID")),
+    request_body = ScrobbleRequest,
+    responses(
+        (status = 200, description = "Track scrobbled", body = ScrobbleResponse),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+        (status = 500, description = "Last.fm is unavailable", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 pub async fn scrobble_track(
     State(state): State<AppState>,
-    Path(track_id): Path<i32>,
-    Json(request): Json<ScrobbleRequest>,
-) -> Result<Json<ScrobbleResponse>, StatusCode> {
+    ApiPath(track_id): ApiPath<i32>,
+    ApiJson(request): ApiJson<ScrobbleRequest>,
+) -> ApiResult<Json<ScrobbleResponse>> {
     // Get track from database
-    let track = match Track::find_by_id(track_id).one(&state.db).await {
-        Ok(Some(track)) => track,
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(e) => {
-            error!("Database error: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    };
+    let track = Track::find_by_id(track_id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", track_id)))?;
 
     let client = match LastfmClient::new() {
         Ok(client) => client,
         Err(e) => {
             error!("Failed to create Last.fm client: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(lastfm_unavailable());
         }
     };
 
@@ -498,7 +619,7 @@ pub async fn scrobble_track(
         Ok(id) => id,
         Err(e) => {
             error!("Failed to scrobble track: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(lastfm_error("Failed to scrobble track"));
         }
     };
 
@@ -509,26 +630,34 @@ pub async fn scrobble_track(
     }))
 }
 
+#[utoipa::path(
+    post,
+    path = "/tracks/{id}/now-playing",
+    tag = "lastfm",
+    params(("id" = i32, Path, description = "Track ID")),
+    request_body = NowPlayingRequest,
+    responses(
+        (status = 200, description = "Now playing updated", body = NowPlayingResponse),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+        (status = 500, description = "Last.fm is unavailable", body = Problem, content_type = "application/problem+json"),
+    )
+)]
 pub async fn update_now_playing(
     State(state): State<AppState>,
-    Path(track_id): Path<i32>,
-    Json(request): Json<NowPlayingRequest>,
-) -> Result<Json<NowPlayingResponse>, StatusCode> {
+    ApiPath(track_id): ApiPath<i32>,
+    ApiJson(request): ApiJson<NowPlayingRequest>,
+) -> ApiResult<Json<NowPlayingResponse>> {
     // Get track from database
-    let track = match Track::find_by_id(track_id).one(&state.db).await {
-        Ok(Some(track)) => track,
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(e) => {
-            error!("Database error: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    };
+    let track = Track::find_by_id(track_id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", track_id)))?;
 
     let client = match LastfmClient::new() {
         Ok(client) => client,
         Err(e) => {
             error!("Failed to create Last.fm client: {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(lastfm_unavailable());
         }
     };
 
@@ -539,7 +668,7 @@ pub async fn update_now_playing(
         })),
         Err(e) => {
             error!("Failed to update now playing: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            Err(lastfm_error("Failed to update now playing"))
         }
     }
 }
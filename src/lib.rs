@@ -0,0 +1,68 @@
+pub mod api;
+pub mod config;
+pub mod scanner;
+pub mod processor;
+pub mod lastfm;
+pub mod albums;
+pub mod audiobooks;
+pub mod chapters;
+pub mod media_types;
+pub mod release_types;
+pub mod storage;
+pub mod playqueue;
+pub mod playlists;
+pub mod browse;
+pub mod content_type;
+pub mod in_progress;
+pub mod scheduler;
+pub mod error;
+pub mod sessions;
+pub mod transcoder;
+pub mod stream_token;
+pub mod streaming;
+pub mod slugs;
+pub mod text_index;
+pub mod scan_profile;
+pub mod read_cache;
+pub mod openapi;
+pub mod artists;
+pub mod hidden;
+pub mod history;
+pub mod conversion;
+pub mod listening;
+pub mod playback;
+pub mod missing;
+pub mod webhooks;
+pub mod import;
+pub mod shuffle;
+pub mod lyrics;
+pub mod lyric_providers;
+pub mod hls;
+pub mod integrity;
+pub mod clients;
+pub mod images;
+pub mod permissions;
+pub mod tag_region;
+pub mod placeholder;
+pub mod formats;
+pub mod proxy;
+pub mod recommendations;
+pub mod capabilities;
+pub mod previews;
+pub mod downloads;
+pub mod diagnostics;
+pub mod backups;
+pub mod query_log;
+pub mod paths;
+pub mod album_artists;
+pub mod audio_info;
+pub mod search_query;
+pub mod duplicates;
+pub mod write_queue;
+pub mod stream_metrics;
+pub mod music_folders;
+pub mod playlist_covers;
+pub mod poll_watcher;
+pub mod musical_keys;
+pub mod file_sizes;
+pub mod doctor;
@@ -0,0 +1,500 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{
+    ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, JoinType, NotSet, QueryFilter,
+    QueryOrder, QuerySelect, RelationTrait, Select,
+};
+use sea_query::Expr;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use entity::prelude::{PlayHistory, Track, TrackAnnotation};
+use entity::{play_history, track, track_annotation, track_artist};
+
+use crate::albums;
+use crate::api::AppState;
+use crate::error::{ApiError, ApiJson, ApiPath, ApiQuery, ApiResult};
+
+const DEFAULT_LIMIT: u64 = 10;
+const MAX_LIMIT: u64 = 100;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    Year,
+    All,
+}
+
+impl FromStr for Period {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "day" => Ok(Period::Day),
+            "week" => Ok(Period::Week),
+            "month" => Ok(Period::Month),
+            "year" => Ok(Period::Year),
+            "all" => Ok(Period::All),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Period {
+    /// The calendar period (UTC) containing `date`, end exclusive. Weeks start on Monday.
+    fn range(self, date: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+        let (start, end) = match self {
+            Period::Day => (date, date + Duration::days(1)),
+            Period::Week => {
+                let start = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+                (start, start + Duration::days(7))
+            }
+            Period::Month => {
+                let start = date.with_day(1)?;
+                let end = if date.month() == 12 {
+                    NaiveDate::from_ymd_opt(date.year() + 1, 1, 1)?
+                } else {
+                    NaiveDate::from_ymd_opt(date.year(), date.month() + 1, 1)?
+                };
+                (start, end)
+            }
+            Period::Year => (NaiveDate::from_ymd_opt(date.year(), 1, 1)?, NaiveDate::from_ymd_opt(date.year() + 1, 1, 1)?),
+            Period::All => return None,
+        };
+        Some((start, end))
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RecordPlayRequest {
+    pub user: String,
+    pub client: Option<String>,
+    /// When playback started (default now)
+    pub played_at: Option<DateTime<Utc>>,
+    /// Seconds actually listened (default the track's duration)
+    pub seconds_played: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PlayResponse {
+    pub id: i32,
+    pub user: String,
+    pub client: Option<String>,
+    pub track_id: i32,
+    pub played_at: DateTime<Utc>,
+    pub seconds_played: i32,
+}
+
+impl From<play_history::Model> for PlayResponse {
+    fn from(model: play_history::Model) -> Self {
+        Self {
+            id: model.id,
+            user: model.user,
+            client: model.client,
+            track_id: model.track_id,
+            played_at: model.played_at,
+            seconds_played: model.seconds_played,
+        }
+    }
+}
+
+/// Add a play to a user's listening history
+pub async fn record_play(
+    db: &DatabaseConnection,
+    user: &str,
+    client: Option<String>,
+    track_id: i32,
+    played_at: DateTime<Utc>,
+    seconds_played: i32,
+) -> Result<play_history::Model, DbErr> {
+    let play = play_history::ActiveModel {
+        id: NotSet,
+        user: Set(user.to_string()),
+        client: Set(client),
+        track_id: Set(track_id),
+        played_at: Set(played_at),
+        seconds_played: Set(seconds_played),
+    };
+    PlayHistory::insert(play).exec_with_returning(db).await
+}
+
+// POST /tracks/:id/plays - Record a finished play in a user's listening history
+#[utoipa::path(
+    post,
+    path = "/tracks/{id}/plays",
+    tag = "listening",
+    params(("id" = i32, Path, description = "Track ID")),
+    request_body = RecordPlayRequest,
+    responses(
+        (status = 201, description = "Play recorded", body = PlayResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn create_play(
+    State(state): State<AppState>,
+    ApiPath(track_id): ApiPath<i32>,
+    ApiJson(request): ApiJson<RecordPlayRequest>,
+) -> ApiResult<(StatusCode, Json<PlayResponse>)> {
+    if request.user.trim().is_empty() {
+        return Err(ApiError::invalid_field("user", "must not be empty"));
+    }
+    if request.seconds_played.is_some_and(|seconds| seconds < 0) {
+        return Err(ApiError::invalid_field("seconds_played", "must not be negative"));
+    }
+    if request.played_at.is_some_and(|played_at| played_at > Utc::now() + Duration::minutes(5)) {
+        return Err(ApiError::invalid_field("played_at", "must not be in the future"));
+    }
+
+    let track = Track::find_by_id(track_id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", track_id)))?;
+
+    let play = record_play(
+        &state.db,
+        &request.user,
+        request.client,
+        track.id,
+        request.played_at.unwrap_or_else(Utc::now),
+        request.seconds_played.unwrap_or(track.duration_seconds),
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(PlayResponse::from(play))))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AnnotationQuery {
+    pub user: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TrackAnnotationResponse {
+    pub track_id: i32,
+    /// 1 to 5 stars
+    pub rating: Option<i16>,
+    pub starred: Option<DateTime<Utc>>,
+    /// Plays imported from another server; plays recorded here are in the listening history
+    pub play_count: i32,
+    pub last_played: Option<DateTime<Utc>>,
+}
+
+// GET /tracks/:id/annotation - A user's rating, star and imported play count for a track
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/annotation",
+    tag = "listening",
+    params(("id" = i32, Path, description = "Track ID"), AnnotationQuery),
+    responses(
+        (status = 200, description = "Rating, star and play count", body = TrackAnnotationResponse),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_annotation(
+    State(state): State<AppState>,
+    ApiPath(track_id): ApiPath<i32>,
+    ApiQuery(params): ApiQuery<AnnotationQuery>,
+) -> ApiResult<Json<TrackAnnotationResponse>> {
+    let track = Track::find_by_id(track_id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", track_id)))?;
+
+    let annotation = TrackAnnotation::find()
+        .filter(track_annotation::Column::User.eq(params.user.as_str()))
+        .filter(track_annotation::Column::TrackId.eq(track.id))
+        .one(&state.db)
+        .await?;
+
+    Ok(Json(TrackAnnotationResponse {
+        track_id: track.id,
+        rating: annotation.as_ref().and_then(|annotation| annotation.rating),
+        starred: annotation.as_ref().and_then(|annotation| annotation.starred),
+        play_count: annotation.as_ref().map_or(0, |annotation| annotation.play_count),
+        last_played: annotation.and_then(|annotation| annotation.last_played),
+    }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListeningQuery {
+    pub user: String,
+    /// day, week, month, year or all (default year)
+    pub period: Option<String>,
+    /// A date (YYYY-MM-DD) in the period to summarize (default today, UTC)
+    pub date: Option<String>,
+    /// Entries in each top list (default 10, max 100)
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TopArtist {
+    pub name: String,
+    pub plays: i64,
+    pub seconds: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TopAlbum {
+    pub name: String,
+    pub album_artist: String,
+    pub plays: i64,
+    pub seconds: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TopTrack {
+    pub track_id: i32,
+    pub title: String,
+    pub artist: String,
+    pub plays: i64,
+    pub seconds: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TopGenre {
+    pub name: String,
+    pub plays: i64,
+    pub seconds: i64,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct DailyListening {
+    pub date: NaiveDate,
+    pub plays: i64,
+    pub seconds: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListeningStatsResponse {
+    pub user: String,
+    pub period: Period,
+    /// First day of the period; null for all time
+    pub from: Option<NaiveDate>,
+    /// Last day of the period; null for all time
+    pub to: Option<NaiveDate>,
+    pub total_plays: i64,
+    pub total_seconds: i64,
+    pub unique_tracks: i64,
+    pub unique_artists: i64,
+    /// Days with at least one play
+    pub active_days: usize,
+    pub longest_streak_days: usize,
+    pub busiest_day: Option<DailyListening>,
+    pub top_artists: Vec<TopArtist>,
+    pub top_albums: Vec<TopAlbum>,
+    pub top_tracks: Vec<TopTrack>,
+    pub top_genres: Vec<TopGenre>,
+    pub daily: Vec<DailyListening>,
+}
+
+// Plays in the period joined to their tracks
+fn plays(condition: &Condition) -> Select<PlayHistory> {
+    PlayHistory::find()
+        .select_only()
+        .join(JoinType::InnerJoin, play_history::Relation::Track.def())
+        .filter(condition.clone())
+}
+
+fn plays_expr() -> sea_query::SimpleExpr {
+    Expr::col((play_history::Entity, play_history::Column::Id)).count()
+}
+
+fn seconds_expr() -> sea_query::SimpleExpr {
+    Expr::col((play_history::Entity, play_history::Column::SecondsPlayed)).sum()
+}
+
+fn longest_streak(daily: &[DailyListening]) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for day in daily {
+        current = match previous {
+            Some(previous) if day.date - previous == Duration::days(1) => current + 1,
+            _ => 1,
+        };
+        longest = longest.max(current);
+        previous = Some(day.date);
+    }
+    longest
+}
+
+// GET /stats/listening - Summarize a user's listening over a calendar period
+#[utoipa::path(
+    get,
+    path = "/stats/listening",
+    tag = "listening",
+    params(ListeningQuery),
+    responses(
+        (status = 200, description = "Listening statistics", body = ListeningStatsResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_listening_stats(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<ListeningQuery>,
+) -> ApiResult<Json<ListeningStatsResponse>> {
+    let period = match params.period.as_deref() {
+        Some(period) => Period::from_str(period)
+            .map_err(|_| ApiError::invalid_field("period", "must be one of day, week, month, year or all"))?,
+        None => Period::Year,
+    };
+    let date = match params.date.as_deref() {
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| ApiError::invalid_field("date", "must be a date like 2024-06-01"))?,
+        None => Utc::now().date_naive(),
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(ApiError::invalid_field("limit", "must be between 1 and 100"));
+    }
+
+    let range = period.range(date);
+    let mut condition = Condition::all().add(play_history::Column::User.eq(params.user.as_str()));
+    if let Some((start, end)) = range {
+        condition = condition
+            .add(play_history::Column::PlayedAt.gte(start.and_time(chrono::NaiveTime::MIN).and_utc()))
+            .add(play_history::Column::PlayedAt.lt(end.and_time(chrono::NaiveTime::MIN).and_utc()));
+    }
+
+    let (total_plays, total_seconds, unique_tracks): (i64, Option<i64>, i64) = plays(&condition)
+        .column_as(plays_expr(), "plays")
+        .column_as(seconds_expr(), "seconds")
+        .column_as(Expr::col((play_history::Entity, play_history::Column::TrackId)).count_distinct(), "tracks")
+        .into_tuple()
+        .one(&state.db)
+        .await?
+        .unwrap_or((0, None, 0));
+
+    // Credited artists, so a collaboration counts for every participant
+    let artists = || plays(&condition).join(JoinType::InnerJoin, track_artist::Relation::Track.def().rev());
+    let unique_artists: Option<i64> = artists()
+        .column_as(Expr::col((track_artist::Entity, track_artist::Column::Artist)).count_distinct(), "artists")
+        .into_tuple()
+        .one(&state.db)
+        .await?;
+
+    let top_artists = artists()
+        .column(track_artist::Column::Artist)
+        .column_as(plays_expr(), "plays")
+        .column_as(seconds_expr(), "seconds")
+        .group_by(track_artist::Column::Artist)
+        .order_by_desc(Expr::cust("plays"))
+        .order_by_desc(Expr::cust("seconds"))
+        .order_by_asc(track_artist::Column::Artist)
+        .limit(limit)
+        .into_tuple::<(String, i64, i64)>()
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|(name, plays, seconds)| TopArtist { name, plays, seconds })
+        .collect();
+
+    let top_albums = plays(&condition)
+        .column(track::Column::Album)
+        .column(track::Column::AlbumArtist)
+        .column_as(plays_expr(), "plays")
+        .column_as(seconds_expr(), "seconds")
+        .group_by(track::Column::Album)
+        .group_by(track::Column::AlbumArtist)
+        .order_by_desc(Expr::cust("plays"))
+        .order_by_desc(Expr::cust("seconds"))
+        .order_by_asc(track::Column::Album)
+        .limit(limit)
+        .into_tuple::<(String, String, i64, i64)>()
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|(name, album_artist, plays, seconds)| TopAlbum {
+            name: albums::album_name(&name).to_string(),
+            album_artist,
+            plays,
+            seconds,
+        })
+        .collect();
+
+    let top_tracks = plays(&condition)
+        .column(play_history::Column::TrackId)
+        .column(track::Column::Title)
+        .column(track::Column::Artist)
+        .column_as(plays_expr(), "plays")
+        .column_as(seconds_expr(), "seconds")
+        .group_by(play_history::Column::TrackId)
+        .group_by(track::Column::Title)
+        .group_by(track::Column::Artist)
+        .order_by_desc(Expr::cust("plays"))
+        .order_by_desc(Expr::cust("seconds"))
+        .order_by_asc(track::Column::Title)
+        .limit(limit)
+        .into_tuple::<(i32, String, String, i64, i64)>()
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|(track_id, title, artist, plays, seconds)| TopTrack { track_id, title, artist, plays, seconds })
+        .collect();
+
+    let top_genres = plays(&condition)
+        .column(track::Column::Genre)
+        .column_as(plays_expr(), "plays")
+        .column_as(seconds_expr(), "seconds")
+        .filter(track::Column::Genre.ne(""))
+        .group_by(track::Column::Genre)
+        .order_by_desc(Expr::cust("plays"))
+        .order_by_desc(Expr::cust("seconds"))
+        .order_by_asc(track::Column::Genre)
+        .limit(limit)
+        .into_tuple::<(String, i64, i64)>()
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|(name, plays, seconds)| TopGenre { name, plays, seconds })
+        .collect();
+
+    let daily: Vec<DailyListening> = plays(&condition)
+        .column_as(Expr::cust(r#"DATE("play_history"."played_at" AT TIME ZONE 'UTC')"#), "day")
+        .column_as(plays_expr(), "plays")
+        .column_as(seconds_expr(), "seconds")
+        .group_by(Expr::cust("day"))
+        .order_by_asc(Expr::cust("day"))
+        .into_tuple::<(NaiveDate, i64, i64)>()
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|(date, plays, seconds)| DailyListening { date, plays, seconds })
+        .collect();
+
+    let busiest_day = daily.iter()
+        .max_by_key(|day| (day.seconds, day.plays))
+        .cloned();
+
+    Ok(Json(ListeningStatsResponse {
+        user: params.user,
+        period,
+        from: range.map(|(start, _)| start),
+        to: range.map(|(_, end)| end - Duration::days(1)),
+        total_plays,
+        total_seconds: total_seconds.unwrap_or(0),
+        unique_tracks,
+        unique_artists: unique_artists.unwrap_or(0),
+        active_days: daily.len(),
+        longest_streak_days: longest_streak(&daily),
+        busiest_day,
+        top_artists,
+        top_albums,
+        top_tracks,
+        top_genres,
+        daily,
+    }))
+}
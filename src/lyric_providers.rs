@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::Json,
+};
+use chrono::Utc;
+use log::{error, info, warn};
+use reqwest::Client;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, NotSet, QueryFilter, QueryOrder};
+use sea_query::OnConflict;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use entity::prelude::{Track, TrackLyrics};
+use entity::{track, track_lyrics};
+
+use crate::api::AppState;
+use crate::error::{ApiError, ApiPath, ApiResult};
+use crate::hidden::{Visibility, VisibilityQuery};
+use crate::lyrics::{self, LyricLine};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Tracks no provider had lyrics for are looked up again after this long
+const RETRY_AFTER_DAYS: i64 = 30;
+// Pause between tracks in a batch fetch, to stay well within rate limits
+const BATCH_DELAY: Duration = Duration::from_secs(1);
+const USER_AGENT: &str = concat!("ongaku-server/", env!("CARGO_PKG_VERSION"), " (https://github.com/kramerc/ongaku-server)");
+const LRCLIB_URL: &str = "https://lrclib.net";
+const GENIUS_API_URL: &str = "https://api.genius.com";
+
+/// An external source of lyrics, tried in the order LYRICS_PROVIDERS lists them
+enum Provider {
+    /// lrclib.net, an open database of synced and plain lyrics
+    Lrclib,
+    /// genius.com, plain lyrics found through its API and read from the song page
+    Genius { access_token: String },
+}
+
+impl Provider {
+    fn name(&self) -> &'static str {
+        match self {
+            Provider::Lrclib => "lrclib",
+            Provider::Genius { .. } => "genius",
+        }
+    }
+
+    // The terms the lyrics are served under, recorded with each one
+    fn license(&self) -> &'static str {
+        match self {
+            Provider::Lrclib => "Copyright of the rights holders, provided by LRCLIB",
+            Provider::Genius { .. } => "Copyright of the rights holders, provided by Genius for personal use",
+        }
+    }
+}
+
+/// Lyrics a provider found for a track
+struct Fetched {
+    synced: bool,
+    content: String,
+    source_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LrclibTrack {
+    id: i64,
+    plain_lyrics: Option<String>,
+    synced_lyrics: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GeniusSearch {
+    response: GeniusHits,
+}
+
+#[derive(Deserialize)]
+struct GeniusHits {
+    hits: Vec<GeniusHit>,
+}
+
+#[derive(Deserialize)]
+struct GeniusHit {
+    result: GeniusSong,
+}
+
+#[derive(Deserialize)]
+struct GeniusSong {
+    url: String,
+    primary_artist: GeniusArtist,
+}
+
+#[derive(Deserialize)]
+struct GeniusArtist {
+    name: String,
+}
+
+fn non_blank(text: Option<String>) -> Option<String> {
+    text.filter(|text| !text.trim().is_empty())
+}
+
+// Decode the character references Genius uses in lyrics text
+fn decode_entities(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "nbsp" => ' ',
+                _ => {
+                    let code = match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => entity.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                output.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// The lyrics on a Genius song page: the text of its lyrics containers, with
+/// `<br>` as line breaks and every other tag dropped
+fn genius_page_lyrics(html: &str) -> Option<String> {
+    const CONTAINER: &str = "data-lyrics-container=\"true\"";
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find(CONTAINER) {
+        let Some(open_end) = rest[start..].find('>') else {
+            break;
+        };
+        rest = &rest[start + open_end + 1..];
+        // Containers nest other divs, so track depth to find the closing one
+        let mut depth = 1;
+        while depth > 0 {
+            let Some(tag_start) = rest.find('<') else {
+                rest = "";
+                break;
+            };
+            text.push_str(&decode_entities(&rest[..tag_start]));
+            let Some(tag_end) = rest[tag_start..].find('>') else {
+                rest = "";
+                break;
+            };
+            let tag = &rest[tag_start + 1..tag_start + tag_end];
+            rest = &rest[tag_start + tag_end + 1..];
+            if tag.starts_with("br") {
+                text.push('\n');
+            } else if tag.starts_with("/div") {
+                depth -= 1;
+            } else if tag.starts_with("div") && !tag.ends_with('/') {
+                depth += 1;
+            }
+        }
+        text.push('\n');
+    }
+    let text = text.lines().map(str::trim_end).collect::<Vec<_>>().join("\n");
+    non_blank(Some(text.trim().to_string()))
+}
+
+/// Looks up lyrics for tracks that have none of their own from the providers
+/// in LYRICS_PROVIDERS, and runs batch lookups over the whole library
+pub struct LyricsFetcher {
+    client: Client,
+    providers: Vec<Provider>,
+    batch_running: AtomicBool,
+}
+
+impl Default for LyricsFetcher {
+    fn default() -> Self {
+        Self {
+            client: Client::new(),
+            providers: Vec::new(),
+            batch_running: AtomicBool::new(false),
+        }
+    }
+}
+
+impl LyricsFetcher {
+    /// Read LYRICS_PROVIDERS (comma separated, in order: lrclib, genius) and
+    /// GENIUS_ACCESS_TOKEN, which Genius needs
+    pub fn from_env() -> Self {
+        let genius_token = env::var("GENIUS_ACCESS_TOKEN").ok().filter(|token| !token.trim().is_empty());
+        let providers = env::var("LYRICS_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| match name.as_str() {
+                "lrclib" => Some(Provider::Lrclib),
+                "genius" => match &genius_token {
+                    Some(token) => Some(Provider::Genius { access_token: token.trim().to_string() }),
+                    None => {
+                        error!("Ignoring the genius lyrics provider, GENIUS_ACCESS_TOKEN is not set");
+                        None
+                    }
+                },
+                _ => {
+                    error!("Ignoring unknown lyrics provider '{}', expected lrclib or genius", name);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            client: Client::builder().timeout(REQUEST_TIMEOUT).user_agent(USER_AGENT).build().unwrap_or_default(),
+            providers,
+            batch_running: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.providers.is_empty()
+    }
+
+    async fn lrclib(&self, track: &track::Model) -> Result<Option<Fetched>, String> {
+        let mut query = vec![("artist_name", track.artist.clone()), ("track_name", track.title.clone())];
+        // An exact lookup needs the album and duration; without them search instead
+        let exact = !track.album.is_empty() && track.duration_seconds > 0;
+        if exact {
+            query.push(("album_name", track.album.clone()));
+            query.push(("duration", track.duration_seconds.to_string()));
+        }
+        let url = format!("{}/api/{}", LRCLIB_URL, if exact { "get" } else { "search" });
+        let response = self.client.get(url).query(&query).send().await.map_err(|e| e.to_string())?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+
+        let found = if exact {
+            Some(response.json::<LrclibTrack>().await.map_err(|e| e.to_string())?)
+        } else {
+            response.json::<Vec<LrclibTrack>>().await.map_err(|e| e.to_string())?.into_iter().next()
+        };
+        let Some(found) = found else {
+            return Ok(None);
+        };
+        let source_url = Some(format!("{}/api/get/{}", LRCLIB_URL, found.id));
+        // Synced lyrics are only kept when they parse, so they can be shown line by line
+        if let Some(synced) = non_blank(found.synced_lyrics).filter(|synced| !lyrics::parse_lrc(synced).is_empty()) {
+            return Ok(Some(Fetched { synced: true, content: synced, source_url }));
+        }
+        Ok(non_blank(found.plain_lyrics).map(|plain| Fetched { synced: false, content: plain, source_url }))
+    }
+
+    async fn genius(&self, access_token: &str, track: &track::Model) -> Result<Option<Fetched>, String> {
+        let response = self.client.get(format!("{}/search", GENIUS_API_URL))
+            .bearer_auth(access_token)
+            .query(&[("q", format!("{} {}", track.artist, track.title))])
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        let search: GeniusSearch = response.json().await.map_err(|e| e.to_string())?;
+
+        // Search matches loosely, so only take a song by the same artist
+        let artist = track.artist.to_lowercase();
+        let Some(song) = search.response.hits.into_iter()
+            .map(|hit| hit.result)
+            .find(|song| {
+                let name = song.primary_artist.name.to_lowercase();
+                artist.contains(&name) || name.contains(&artist)
+            })
+        else {
+            return Ok(None);
+        };
+
+        let response = self.client.get(&song.url).send().await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        let page = response.text().await.map_err(|e| e.to_string())?;
+        Ok(genius_page_lyrics(&page).map(|content| Fetched { synced: false, content, source_url: Some(song.url) }))
+    }
+
+    // Ask each provider in turn. An error from one doesn't stop the others,
+    // but is returned when nobody had lyrics so the miss isn't cached.
+    async fn fetch(&self, track: &track::Model) -> Result<Option<(&Provider, Fetched)>, String> {
+        let mut last_error = None;
+        for provider in &self.providers {
+            let result = match provider {
+                Provider::Lrclib => self.lrclib(track).await,
+                Provider::Genius { access_token } => self.genius(access_token, track).await,
+            };
+            match result {
+                Ok(Some(fetched)) => return Ok(Some((provider, fetched))),
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Failed to fetch lyrics for track {} from {}: {}", track.id, provider.name(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    // Fetch lyrics for a track and cache what was found, or that nothing was
+    async fn fetch_and_store(&self, db: &DatabaseConnection, track: &track::Model) -> Result<Option<track_lyrics::Model>, DbErr> {
+        let fetched = match self.fetch(track).await {
+            Ok(fetched) => fetched,
+            Err(_) => return Ok(None),
+        };
+        let (provider, synced, content, source_url, license) = match fetched {
+            Some((provider, fetched)) => (
+                Some(provider.name().to_string()),
+                fetched.synced,
+                Some(fetched.content),
+                fetched.source_url,
+                Some(provider.license().to_string()),
+            ),
+            None => (None, false, None, None, None),
+        };
+
+        let model = TrackLyrics::insert(track_lyrics::ActiveModel {
+            id: NotSet,
+            track_id: Set(track.id),
+            provider: Set(provider),
+            synced: Set(synced),
+            content: Set(content),
+            source_url: Set(source_url),
+            license: Set(license),
+            fetched: Set(Utc::now()),
+        })
+        .on_conflict(
+            OnConflict::column(track_lyrics::Column::TrackId)
+                .update_columns([
+                    track_lyrics::Column::Provider,
+                    track_lyrics::Column::Synced,
+                    track_lyrics::Column::Content,
+                    track_lyrics::Column::SourceUrl,
+                    track_lyrics::Column::License,
+                    track_lyrics::Column::Fetched,
+                ])
+                .to_owned(),
+        )
+        .exec_with_returning(db)
+        .await?;
+        Ok(model.content.is_some().then_some(model))
+    }
+}
+
+fn should_retry(cached: &track_lyrics::Model) -> bool {
+    cached.content.is_none() && cached.fetched < Utc::now() - chrono::Duration::days(RETRY_AFTER_DAYS)
+}
+
+/// Lyrics a provider supplied for a track, from the cache or fetched now when
+/// it has none yet. None when no provider has them.
+pub async fn provider_lyrics(state: &AppState, track: &track::Model) -> Result<Option<track_lyrics::Model>, DbErr> {
+    let cached = TrackLyrics::find()
+        .filter(track_lyrics::Column::TrackId.eq(track.id))
+        .one(&state.db)
+        .await?;
+    match cached {
+        Some(cached) if !should_retry(&cached) || !state.lyrics.is_enabled() => Ok(cached.content.is_some().then_some(cached)),
+        _ if state.lyrics.is_enabled() => state.lyrics.fetch_and_store(&state.db, track).await,
+        _ => Ok(None),
+    }
+}
+
+/// Synced lyrics for a track from its own files or tags or, failing that, a provider
+pub async fn synced_lyrics(state: &AppState, track: &track::Model) -> Option<Vec<LyricLine>> {
+    if let Some(lines) = lyrics::synced_lyrics(state.storage.as_ref(), track).await {
+        return Some(lines);
+    }
+    let fetched = provider_lyrics(state, track).await.unwrap_or_else(|e| {
+        error!("Failed to look up lyrics for track {}: {}", track.id, e);
+        None
+    })?;
+    let lines = lyrics::parse_lrc(fetched.content.as_deref().unwrap_or_default());
+    (fetched.synced && !lines.is_empty()).then_some(lines)
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LyricLineResponse {
+    pub start_ms: i64,
+    pub text: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LyricsResponse {
+    pub track_id: i32,
+    /// Whether the lyrics are timed, with a start time for each line
+    pub synced: bool,
+    /// Timed lines of synced lyrics, empty for plain lyrics
+    pub lines: Vec<LyricLineResponse>,
+    /// The lyrics as plain text
+    pub text: String,
+    /// Where the lyrics came from: file (an .lrc next to the track), tags, or the provider (lrclib, genius)
+    pub source: String,
+    pub source_url: Option<String>,
+    pub license: Option<String>,
+}
+
+impl LyricsResponse {
+    fn new(track_id: i32, lines: Vec<LyricLine>, text: String, source: &str) -> Self {
+        let text = if lines.is_empty() {
+            text
+        } else {
+            lines.iter().map(|line| line.text.as_str()).collect::<Vec<_>>().join("\n")
+        };
+        Self {
+            track_id,
+            synced: !lines.is_empty(),
+            lines: lines.into_iter().map(|line| LyricLineResponse { start_ms: line.start_ms, text: line.text }).collect(),
+            text,
+            source: source.to_string(),
+            source_url: None,
+            license: None,
+        }
+    }
+}
+
+// GET /tracks/:id/lyrics - Get a track's lyrics, fetching them from a provider when it has none
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/lyrics",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID"), VisibilityQuery),
+    responses(
+        (status = 200, description = "The track's lyrics", body = LyricsResponse),
+        (status = 404, description = "Track not found or has no lyrics", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_lyrics(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    visibility: Visibility,
+) -> ApiResult<Json<LyricsResponse>> {
+    let track = Track::find_by_id(id)
+        .filter(visibility.condition())
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", id)))?;
+
+    if let Some(lines) = lyrics::sidecar_lyrics(state.storage.as_ref(), &track).await {
+        return Ok(Json(LyricsResponse::new(id, lines, String::new(), "file")));
+    }
+    if let Some(embedded) = lyrics::embedded_lyrics(&track) {
+        return Ok(Json(LyricsResponse::new(id, lyrics::parse_lrc(embedded), embedded.trim().to_string(), "tags")));
+    }
+
+    let fetched = provider_lyrics(&state, &track)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} has no lyrics", id)))?;
+    let content = fetched.content.unwrap_or_default();
+    let lines = if fetched.synced { lyrics::parse_lrc(&content) } else { Vec::new() };
+    let mut response = LyricsResponse::new(id, lines, content.trim().to_string(), fetched.provider.as_deref().unwrap_or_default());
+    response.source_url = fetched.source_url;
+    response.license = fetched.license;
+    Ok(Json(response))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LyricsBatchResponse {
+    /// Tracks without cached lyrics that will be checked, one per second
+    pub tracks: usize,
+}
+
+// Tracks that are present and have no cached lyrics, or a miss old enough to retry
+async fn tracks_without_lyrics(db: &DatabaseConnection) -> Result<Vec<track::Model>, DbErr> {
+    let cached: HashMap<i32, track_lyrics::Model> = TrackLyrics::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|cached| (cached.track_id, cached))
+        .collect();
+    let tracks = Track::find()
+        .filter(track::Column::MissingSince.is_null())
+        .filter(track::Column::Title.ne(""))
+        .filter(track::Column::Artist.ne(""))
+        .order_by_asc(track::Column::Path)
+        .all(db)
+        .await?;
+    Ok(tracks.into_iter()
+        .filter(|track| cached.get(&track.id).is_none_or(should_retry))
+        .filter(|track| lyrics::embedded_lyrics(track).is_none())
+        .collect())
+}
+
+// POST /admin/lyrics/fetch - Fetch lyrics for every track that has none, in the background
+#[utoipa::path(
+    post,
+    path = "/admin/lyrics/fetch",
+    tag = "library",
+    responses(
+        (status = 202, description = "Batch fetch started", body = LyricsBatchResponse),
+        (status = 409, description = "A batch fetch is already running", body = Problem, content_type = "application/problem+json"),
+        (status = 501, description = "No lyrics providers are configured", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn fetch_missing_lyrics(State(state): State<AppState>) -> ApiResult<(StatusCode, Json<LyricsBatchResponse>)> {
+    if !state.lyrics.is_enabled() {
+        return Err(ApiError::new(StatusCode::NOT_IMPLEMENTED, "lyrics_disabled", "LYRICS_PROVIDERS is not configured"));
+    }
+    if state.lyrics.batch_running.swap(true, Ordering::SeqCst) {
+        return Err(ApiError::conflict("A lyrics fetch is already running"));
+    }
+    let pending = match tracks_without_lyrics(&state.db).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            state.lyrics.batch_running.store(false, Ordering::SeqCst);
+            return Err(e.into());
+        }
+    };
+
+    let tracks = pending.len();
+    info!("Fetching lyrics for up to {} tracks", tracks);
+    tokio::spawn(async move {
+        let mut found = 0;
+        for track in &pending {
+            // Tracks with an .lrc file next to them don't need a lookup
+            let sidecar = Path::new(&track.path).with_extension("lrc");
+            if let Some(sidecar) = sidecar.to_str() {
+                if state.storage.stat(sidecar).await.is_ok() {
+                    continue;
+                }
+            }
+            match state.lyrics.fetch_and_store(&state.db, track).await {
+                Ok(Some(_)) => found += 1,
+                Ok(None) => {}
+                Err(e) => error!("Failed to store lyrics for track {}: {}", track.id, e),
+            }
+            tokio::time::sleep(BATCH_DELAY).await;
+        }
+        info!("Lyrics fetch finished, found lyrics for {} of {} tracks", found, pending.len());
+        state.lyrics.batch_running.store(false, Ordering::SeqCst);
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(LyricsBatchResponse { tracks })))
+}
@@ -0,0 +1,138 @@
+use std::path::Path;
+
+use entity::track;
+
+use crate::storage::Storage;
+
+// How long the last line stays up when the track's duration is unknown
+const LAST_LINE_MS: i64 = 5_000;
+
+/// One line of synced lyrics
+pub struct LyricLine {
+    pub start_ms: i64,
+    pub text: String,
+}
+
+// "mm:ss", "mm:ss.xx" or "mm:ss.xxx" to milliseconds
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    let (minutes, seconds) = timestamp.split_once(':')?;
+    let minutes: i64 = minutes.trim().parse().ok()?;
+    let (seconds, fraction) = seconds.split_once('.').unwrap_or((seconds, "0"));
+    let seconds: i64 = seconds.trim().parse().ok()?;
+    if !(0..60).contains(&seconds) || fraction.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    // Scale hundredths and tenths up to milliseconds
+    let fraction: String = fraction.chars().chain("00".chars()).take(3).collect();
+    Some((minutes * 60 + seconds) * 1000 + fraction.parse::<i64>().ok()?)
+}
+
+/// Parse LRC lyrics into lines ordered by time. Lines can carry several
+/// timestamps (`[00:12.00][01:30.00]Chorus`), `[offset:+500]` shifts every
+/// line earlier by that many milliseconds and enhanced LRC word timings
+/// (`<00:12.50>`) are dropped. Returns nothing for unsynced lyrics.
+pub fn parse_lrc(content: &str) -> Vec<LyricLine> {
+    let mut offset_ms = 0;
+    let mut lines = Vec::new();
+
+    for line in content.trim_start_matches('\u{feff}').lines() {
+        let mut rest = line.trim();
+        let mut starts = Vec::new();
+        while let Some(tag) = rest.strip_prefix('[') {
+            let Some((tag, after)) = tag.split_once(']') else {
+                break;
+            };
+            rest = after;
+            match parse_timestamp(tag) {
+                Some(start_ms) => starts.push(start_ms),
+                None => {
+                    if let Some(offset) = tag.strip_prefix("offset:") {
+                        offset_ms = offset.trim().parse().unwrap_or(0);
+                    }
+                }
+            }
+        }
+        if starts.is_empty() {
+            continue;
+        }
+
+        let mut text = String::new();
+        let mut remaining = rest;
+        while let Some((before, after)) = remaining.split_once('<') {
+            text.push_str(before);
+            remaining = match after.split_once('>') {
+                Some((timing, after)) if parse_timestamp(timing).is_some() => after,
+                _ => {
+                    text.push('<');
+                    after
+                }
+            };
+        }
+        text.push_str(remaining);
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        for start_ms in starts {
+            lines.push(LyricLine { start_ms: (start_ms - offset_ms).max(0), text: text.clone() });
+        }
+    }
+
+    lines.sort_by_key(|line| line.start_ms);
+    lines
+}
+
+/// Synced lyrics from an .lrc file next to a track
+pub async fn sidecar_lyrics(storage: &dyn Storage, track: &track::Model) -> Option<Vec<LyricLine>> {
+    let sidecar = Path::new(&track.path).with_extension("lrc");
+    let content = storage.read(sidecar.to_str()?).await.ok()?;
+    let lines = parse_lrc(&String::from_utf8_lossy(&content));
+    (!lines.is_empty()).then_some(lines)
+}
+
+/// Lyrics embedded in a track's tags, synced or not
+pub fn embedded_lyrics(track: &track::Model) -> Option<&str> {
+    track.tags.get("Lyrics")
+        .and_then(|lyrics| lyrics.as_str())
+        .filter(|lyrics| !lyrics.trim().is_empty())
+}
+
+/// Synced lyrics for a track, from an .lrc file next to it or, failing that,
+/// LRC-formatted lyrics embedded in its tags
+pub async fn synced_lyrics(storage: &dyn Storage, track: &track::Model) -> Option<Vec<LyricLine>> {
+    if let Some(lines) = sidecar_lyrics(storage, track).await {
+        return Some(lines);
+    }
+
+    let embedded = embedded_lyrics(track)
+        .map(parse_lrc)
+        .unwrap_or_default();
+    (!embedded.is_empty()).then_some(embedded)
+}
+
+fn vtt_timestamp(ms: i64) -> String {
+    format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+/// Render lyrics as WebVTT cues, each shown until the next line starts.
+/// Blank lines (instrumental breaks) end the previous cue without showing anything.
+pub fn to_webvtt(lines: &[LyricLine], duration_ms: i64) -> String {
+    // Lines are timed from the start of the audio
+    let mut output = String::from("WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:0,LOCAL:00:00:00.000\n");
+    let mut cue = 0;
+    for (index, line) in lines.iter().enumerate() {
+        if line.text.is_empty() {
+            continue;
+        }
+        let end_ms = match lines.get(index + 1) {
+            Some(next) => next.start_ms,
+            None if duration_ms > line.start_ms => duration_ms,
+            None => line.start_ms + LAST_LINE_MS,
+        };
+        if end_ms <= line.start_ms {
+            continue;
+        }
+        let text = line.text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+        cue += 1;
+        output.push_str(&format!("\n{}\n{} --> {}\n{}\n", cue, vtt_timestamp(line.start_ms), vtt_timestamp(end_ms), text));
+    }
+    output
+}
@@ -1,4 +1,7 @@
+use std::future::IntoFuture;
+use std::net::SocketAddr;
 use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
 
 use axum::Router;
@@ -9,11 +12,9 @@ use tower_http::cors::CorsLayer;
 
 use migration::{Migrator, MigratorTrait};
 
+use ongaku_server::{api, backups, browse, config, conversion, doctor, lyric_providers, playback, playlist_covers, poll_watcher, previews, query_log, read_cache, scanner, scheduler, sessions, storage, stream_metrics, stream_token, webhooks, write_queue};
+
 mod logger;
-mod api;
-mod config;
-mod scanner;
-mod lastfm;
 
 #[tokio::main]
 async fn main() -> Result<(), DbErr> {
@@ -21,9 +22,25 @@ async fn main() -> Result<(), DbErr> {
     dotenv::dotenv().ok();
 
     logger::init().unwrap();
+    stream_metrics::METRICS.start();
 
     let config = config::Config::from_env();
 
+    // `doctor` checks the configuration, including the database connection,
+    // so it runs before connecting
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|command| command == "doctor") {
+        if args.len() > 1 {
+            error!("{}", USAGE);
+            std::process::exit(2);
+        }
+        let checks = doctor::run(&config).await;
+        if !doctor::report(&checks) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let mut opt = ConnectOptions::new(&config.database_url);
     opt.max_connections(150)       // Increased from 100 for better concurrency
         .min_connections(10)       // Increased from 5 to maintain ready connections
@@ -31,8 +48,18 @@ async fn main() -> Result<(), DbErr> {
         .acquire_timeout(Duration::from_secs(10))  // Slightly increased
         .idle_timeout(Duration::from_secs(300))    // Increased from 8 seconds
         .max_lifetime(Duration::from_secs(1800))   // Increased from 8 seconds
-        .sqlx_logging(false); // Disable SQL logging to clean up progress bar display
-    let db: DatabaseConnection = Database::connect(opt).await?;
+        .sqlx_logging(false); // Statements are logged by the query log below instead
+    let mut db: DatabaseConnection = Database::connect(opt).await?;
+    // Log statements while SQL_LOG or an admin switches it on, and slow ones always
+    let query_log = Arc::new(query_log::QueryLog::from_env());
+    let metric_query_log = query_log.clone();
+    db.set_metric_callback(move |query| metric_query_log.record(query));
+
+    // `backup <path>` and `restore <path>` run before migrating, since a
+    // restore checks the database's migrations against the backup's
+    if !args.is_empty() {
+        return run_command(&args, &config.database_url, &db).await;
+    }
     Migrator::up(&db, None).await?;
 
     // Clone database connections for API server and scanner
@@ -40,6 +67,51 @@ async fn main() -> Result<(), DbErr> {
     let scan_db = db.clone();
     let bind_address = config.bind_address();
     let music_path_str = config.music_path.clone();
+    let counts = Arc::new(browse::CountCache::default());
+    let scans = Arc::new(scheduler::ScanTracker::default());
+    let scan_counts = counts.clone();
+    let scan_tracker = scans.clone();
+    let webhooks = Arc::new(webhooks::Webhooks::from_env());
+    let scan_webhooks = webhooks.clone();
+
+    if let Some(expression) = config.scan_schedule.clone() {
+        match scheduler::parse_schedule(&expression) {
+            Ok(schedule) => scheduler::spawn_scheduler(
+                db.clone(),
+                config.music_path.clone(),
+                expression,
+                schedule,
+                scans.clone(),
+                counts.clone(),
+                webhooks.clone(),
+            ),
+            Err(e) => error!("Invalid SCAN_SCHEDULE '{}': {}", expression, e),
+        }
+    }
+
+    let folder_schedules = scheduler::folder_schedules_from_env(&config.music_path);
+    if !folder_schedules.is_empty() {
+        scheduler::spawn_folder_scheduler(
+            db.clone(),
+            config.music_path.clone(),
+            folder_schedules,
+            scans.clone(),
+            counts.clone(),
+            webhooks.clone(),
+        );
+    }
+
+    let poll_watches = poll_watcher::poll_watches_from_env(&config.music_path);
+    if !poll_watches.is_empty() {
+        poll_watcher::spawn_poll_watchers(
+            db.clone(),
+            config.music_path.clone(),
+            poll_watches,
+            scans.clone(),
+            counts.clone(),
+            webhooks.clone(),
+        );
+    }
 
     // Start initial music library scan in background
     let _scan_handle = tokio::spawn(async move {
@@ -51,24 +123,26 @@ async fn main() -> Result<(), DbErr> {
             music_path: music_path_str,
             show_progress: true,
             batch_size: 100,         // Smaller batches for consistency
-            path_batch_size: 2500,   // Balanced query efficiency
-            use_optimized_scanning: true,
+            verify_audio: false,
         };
 
-        match scanner::scan_music_library(&scan_db, scan_config).await {
-            Ok(result) => {
-                info!("Initial scan completed: {} files scanned, {} tracks processed",
-                      result.files_scanned, result.tracks_processed);
+        match scheduler::run_scan(&scan_db, scan_config, "startup", &scan_tracker, &scan_counts, &scan_webhooks).await {
+            Some(Ok(result)) => {
+                info!("Initial scan {} completed: {} files scanned, {} tracks processed, {} errors recorded",
+                      result.scan_id, result.files_scanned, result.tracks_processed, result.errors_recorded);
             }
-            Err(e) => {
+            Some(Err(e)) => {
                 error!("Error during initial scan: {}", e);
             }
+            None => {
+                info!("Skipping initial scan, a scan is already running");
+            }
         }
     });
 
     // Start API server (this will run indefinitely)
     let api_handle = tokio::spawn(async move {
-        if let Err(e) = start_api_server(api_db, bind_address).await {
+        if let Err(e) = start_api_server(api_db, counts, scans, webhooks, query_log, bind_address).await {
             error!("API server failed to start: {}", e);
         }
     });
@@ -83,15 +157,74 @@ async fn main() -> Result<(), DbErr> {
     Ok(())
 }
 
-async fn start_api_server(db: DatabaseConnection, bind_address: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+// How long a shutdown waits for open requests to finish
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+const USAGE: &str = "Usage: ongaku-server [backup <path> | restore <path> | doctor]";
+
+// Back the database up to a file or restore it from one, then exit
+async fn run_command(args: &[String], database_url: &str, db: &DatabaseConnection) -> Result<(), DbErr> {
+    let backups = backups::Backups::from_env(database_url);
+    let result = match (args[0].as_str(), args.get(1), args.len()) {
+        ("backup", Some(path), 2) => backups.backup_to(Path::new(path)).await
+            .map(|size| info!("Database backup written to {} ({} bytes)", path, size)),
+        ("restore", Some(path), 2) => backups.restore_from(db, Path::new(path)).await
+            .map(|()| info!("Database restored from {}", path)),
+        _ => Err(USAGE.to_string()),
+    };
+    result.map_err(|e| {
+        error!("{}", e);
+        DbErr::Custom(e)
+    })
+}
+
+async fn start_api_server(db: DatabaseConnection, counts: Arc<browse::CountCache>, scans: Arc<scheduler::ScanTracker>, webhooks: Arc<webhooks::Webhooks>, query_log: Arc<query_log::QueryLog>, bind_address: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let config = config::Config::from_env();
+
+    // Optionally keep recently streamed chunks on local disk in front of slow storage
+    let mut music_storage = storage::for_library(&config.music_path);
+    if let Some(cache_dir) = &config.stream_cache_dir {
+        let max_bytes = config.stream_cache_max_mb * 1024 * 1024;
+        match read_cache::ReadCache::new(music_storage.clone(), cache_dir, max_bytes) {
+            Ok(cache) => music_storage = Arc::new(cache),
+            Err(e) => error!("Failed to open stream cache at {}, streaming without it: {}", cache_dir, e),
+        }
+    }
+
+    let previews = Arc::new(previews::PreviewClips::from_env());
+    previews.clone().spawn_pregeneration(db.clone(), music_storage.clone(), scans.clone());
+
+    let writes = Arc::new(write_queue::WriteQueue::spawn(db.clone()));
+
     let state = api::AppState {
         db,
+        storage: music_storage,
         music_path: config.music_path,
+        counts,
+        scans,
+        album_mode: config.album_mode,
+        text_normalization: config.text_normalization,
+        ignored_articles: Arc::new(config.ignored_articles),
+        sessions: Arc::new(sessions::SessionRegistry::default()),
+        conversions: Arc::new(conversion::ConversionJobs::default()),
+        playback: Arc::new(playback::PlaybackTracker::default()),
+        demo_mode: config.demo_mode,
+        previews: previews.clone(),
+        playlist_covers: Arc::new(playlist_covers::PlaylistCovers::from_env()),
+        transcoding: Arc::new(config.transcoding),
+        stream_signer: config.stream_signing_key.as_deref()
+            .map(|key| stream_token::StreamSigner::new(key, config.require_signed_streams)),
+        webhooks,
+        lyrics: Arc::new(lyric_providers::LyricsFetcher::from_env()),
+        proxy: Arc::new(config.proxy.clone()),
+        backups: Arc::new(backups::Backups::from_env(&config.database_url)),
+        query_log,
+        writes: writes.clone(),
     };
 
+    let api_path = config.proxy.api_path();
     let app = Router::new()
-        .nest("/api/v1", api::create_router(state))
+        .nest(&api_path, api::create_router(state))
         .layer(CorsLayer::permissive());
 
     let listener = match TcpListener::bind(&bind_address).await {
@@ -102,32 +235,156 @@ async fn start_api_server(db: DatabaseConnection, bind_address: String) -> Resul
         }
     };
 
+    if config.require_signed_streams && config.stream_signing_key.is_none() {
+        error!("REQUIRE_SIGNED_STREAMS is set without STREAM_SIGNING_KEY, streams will not require tokens");
+    }
+    if config.demo_mode {
+        info!("Demo mode enabled: read-only, streams limited to {} second previews", previews.length_seconds());
+    }
+
     const PUBLIC_ADDRESS: &str = "ongaku-dev.m3r.dev";
 
-    info!("API server starting on https://{}", PUBLIC_ADDRESS);
+    info!("API server starting on https://{}{}", PUBLIC_ADDRESS, config.proxy.base_path);
     info!("API endpoints available at:");
     info!("  GET /api/v1/tracks - List tracks with pagination");
     info!("  GET /api/v1/tracks/:id - Get track by ID");
+    info!("  POST /api/v1/tracks/batch - Get up to 500 tracks by ID or path");
+    info!("  PATCH /api/v1/tracks/:id - Hide or unhide a track");
+    info!("  GET /api/v1/tracks/:id/history - List changes to a track's metadata");
+    info!("  POST /api/v1/tracks/:id/history/:change_id/revert - Revert a change to a track");
+    info!("  GET /api/v1/tracks/:id/albumart - Get album art or another embedded image (?type=back)");
     info!("  GET /api/v1/tracks/:id/play - Stream audio file");
+    info!("  GET /api/v1/download?id=123 - Download a track's original file (also /download.view)");
+    info!("  GET /api/v1/tracks/:id/preview - Get a short, loudness normalized preview clip");
+    info!("  GET /api/v1/tracks/:id/audio-info - Get technical details of a track's audio stream");
+    info!("  GET /api/v1/tracks/:id/stream-url - Get a signed, expiring stream URL");
+    info!("  GET /api/v1/tracks/:id/lyrics - Get a track's lyrics, from its files or a lyrics provider");
+    info!("  GET /api/v1/tracks/:id/hls/index.m3u8 - HLS playlist with synced lyrics as subtitles");
+    info!("  GET /api/v1/transcoding/profiles - List transcoding profiles for ?profile=");
     info!("  GET /api/v1/tracks/search?q=query - Search tracks");
+    info!("  GET /api/v1/tracks/shuffle?seed=seed&count=50 - Reproducible random order of tracks");
     info!("  GET /api/v1/stats - Get database statistics");
+    info!("  GET /api/v1/capabilities - List supported features for client feature detection");
+    info!("  GET /api/v1/stats/listening?user=name&period=year - Get a user's listening summary");
     info!("  GET /api/v1/artists - Get list of artists");
-    info!("  GET /api/v1/albums - Get list of albums");
+    info!("  GET /api/v1/indexes - Get artists grouped by first letter");
+    info!("  GET /api/v1/music-folders - List library folders, or a folder's artists and loose files by ID");
+    info!("  GET /api/v1/albums - Get list of albums (?include=tracks for nested tracks, ?include=summary without)");
+    info!("  GET /api/v1/albums/by-decade - Get decades with album counts, or a decade's albums (?decade=1990)");
+    info!("  GET /api/v1/albums/:id/tracks - Get an album with its tracks");
+    info!("  GET /api/v1/albums/:id/cover - Get an album's cover, or placeholder art without one");
+    info!("  GET /api/v1/albums/:id/stream - Stream a whole album as one gapless stream with chapters (experimental)");
+    info!("  GET /api/v1/audiobooks - List audiobooks with resume positions (?user=name)");
+    info!("  GET /api/v1/audiobooks/:id - Get an audiobook's chapters and tracks");
     info!("  GET /api/v1/genres - Get list of genres");
     info!("  POST /api/v1/rescan - Trigger music library rescan");
+    info!("  POST /api/v1/rescan/path - Re-read one directory or album and return its tracks");
+    info!("  GET /api/v1/rescan/status - Get scan status and next scheduled run");
+    info!("  GET /api/v1/rescan/errors - List files that failed to scan");
     info!("  GET /api/v1/lastfm/auth - Get Last.fm authentication URL");
     info!("  POST /api/v1/lastfm/session - Create Last.fm session");
     info!("  POST /api/v1/tracks/:id/scrobble - Scrobble track to Last.fm");
     info!("  POST /api/v1/tracks/:id/now-playing - Update Last.fm now playing");
+    info!("  POST /api/v1/tracks/:id/plays - Record a play in a user's listening history");
+    info!("  GET /api/v1/tracks/:id/annotation - Get a user's rating, star and imported play count for a track");
+    info!("  GET/POST /api/v1/tracks/:id/progress - Get or report playback position");
+    info!("  GET /api/v1/admin/clients - List known clients and their playback overrides");
+    info!("  PATCH /api/v1/admin/clients/:id - Force a profile, cap the bitrate or disable range requests for a client");
+    info!("  GET /api/v1/admin/quality-report - Summarize FLAC audio checksum verification");
+    info!("  GET /api/v1/admin/diagnostics - Diagnostics bundle for bug reports (?anonymize=true)");
+    info!("  POST /api/v1/admin/backup - Start an online database backup to BACKUP_DIR");
+    info!("  GET /api/v1/admin/backup - Get backup progress and the backups kept");
+    info!("  GET/PUT /api/v1/admin/query-log - Get or switch logging of every SQL statement");
+    info!("  GET /api/v1/admin/streaming-metrics - Get transcoding load, cache hit rates and streamed formats");
+    info!("  POST /api/v1/admin/lyrics/fetch - Fetch lyrics from providers for tracks without any");
+    info!("  POST /api/v1/admin/previews/generate - Generate preview clips for tracks without one");
+    info!("  GET /api/v1/admin/now-playing - List what each listener is playing");
+    info!("  POST /api/v1/admin/webhooks/test - Send a test event to the configured webhooks");
+    info!("  POST /api/v1/admin/import - Import ratings, play counts and playlists from an iTunes or Subsonic export");
+    info!("  GET /api/v1/admin/sessions - List active streaming sessions");
+    info!("  DELETE /api/v1/admin/sessions/:id - Terminate a streaming session");
+    info!("  GET/POST /api/v1/admin/hidden-directories - List or hide directories");
+    info!("  DELETE /api/v1/admin/hidden-directories/:id - Unhide a directory");
+    info!("  GET /api/v1/admin/users - List users limited to some folders, with a content filter or without a role");
+    info!("  GET/PUT /api/v1/admin/users/:user/folders - Get or set the folders a user can see");
+    info!("  GET/PUT /api/v1/admin/users/:user/content-filter - Get or set whether explicit tracks are hidden from a user");
+    info!("  GET/PUT /api/v1/admin/users/:user/roles - Get or set whether a user may download files");
+    info!("  GET/POST /api/v1/admin/conversions - List or start batch format conversions");
+    info!("  GET/DELETE /api/v1/admin/conversions/:id - Get or cancel a conversion job");
+    info!("  GET /api/v1/playqueue - Get saved play queue");
+    info!("  POST /api/v1/playqueue - Save play queue");
+    info!("  GET /api/v1/playlists - List playlists");
+    info!("  GET /api/v1/playlists/:id - Get playlist as JSON, M3U8 or XSPF");
+    info!("  PATCH /api/v1/playlists/:id - Rename a playlist or remove and add tracks");
+    info!("  POST /api/v1/playlists/import - Import an M3U8 playlist");
+    info!("  POST /api/v1/playlists/recommended - Create a playlist from a user's Last.fm recommendations");
+    info!("  POST /api/v1/playlists/:id/refresh - Rebuild a recommended playlist");
+    info!("  GET /api/v1/playlists/:id/cover - Get a playlist's cover, a mosaic of its albums' art");
     info!("");
     info!("📖 API Documentation available at:");
-    info!("  https://{}/api/v1/docs - Interactive Swagger UI", PUBLIC_ADDRESS);
-    info!("  https://{}/api/v1/openapi.yaml - OpenAPI 3.0 specification", PUBLIC_ADDRESS);
+    info!("  https://{}{}/docs - Interactive Swagger UI", PUBLIC_ADDRESS, api_path);
+    info!("  https://{}{}/openapi.yaml - OpenAPI 3.0 specification (also /openapi.json)", PUBLIC_ADDRESS, api_path);
 
-    if let Err(e) = axum::serve(listener, app).await {
+    // Peer addresses identify clients when there's no trusted proxy in front
+    let shutting_down = Arc::new(tokio::sync::Notify::new());
+    let signalled = shutting_down.clone();
+    let server = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            signalled.notify_one();
+        });
+    // Streams can stay open for as long as a track or album plays, so open
+    // requests only get a while to finish
+    let served = tokio::select! {
+        served = server.into_future() => served,
+        _ = async {
+            shutting_down.notified().await;
+            tokio::time::sleep(SHUTDOWN_GRACE_PERIOD).await;
+        } => {
+            info!("Requests still open after {} seconds, exiting anyway", SHUTDOWN_GRACE_PERIOD.as_secs());
+            Ok(())
+        }
+    };
+
+    // Writes queued so far are kept; requests cut off by the grace period
+    // can't queue more once the queue is closed
+    info!("Writing queued plays and positions before exiting");
+    writes.close().await;
+
+    if let Err(e) = served {
         error!("Server error: {}", e);
         return Err(Box::new(e));
     }
-
     Ok(())
 }
+
+// Ctrl+C, or SIGTERM from a service manager or `docker stop`
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    info!("Shutting down, waiting for open requests to finish");
+}
@@ -0,0 +1,173 @@
+use std::env;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::request::Parts,
+};
+use sea_orm::sea_query::{CaseStatement, Expr, SimpleExpr};
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use entity::prelude::Track;
+use entity::track;
+
+use crate::api::AppState;
+use crate::error::{ApiError, ApiQuery};
+use crate::paths;
+
+pub const MUSIC: &str = "music";
+pub const PODCAST: &str = "podcast";
+pub const AUDIOBOOK: &str = "audiobook";
+/// Sound effects, samples and the like
+pub const SFX: &str = "sfx";
+pub const MEDIA_TYPES: &[&str] = &[MUSIC, PODCAST, AUDIOBOOK, SFX];
+
+// Tag values hinting at a media type, compared lowercased: ITUNESMEDIATYPE as
+// a name or iTunes media kind number, then the genre
+const MEDIA_KIND_HINTS: &[(&str, &[&str])] = &[
+    (MUSIC, &["normal", "music", "1"]),
+    (AUDIOBOOK, &["audiobook", "2"]),
+    (PODCAST, &["podcast", "21"]),
+    (SFX, &["ringtone", "14"]),
+];
+const GENRE_HINTS: &[(&str, &[&str])] = &[
+    (PODCAST, &["podcast", "podcasts"]),
+    (AUDIOBOOK, &["audiobook", "audiobooks", "audio book", "hörbuch"]),
+    (SFX, &["sfx", "sound effect", "sound effects"]),
+];
+
+/// Folders whose tracks are of a media type, from the semicolon separated
+/// `folder=type` pairs of MEDIA_TYPE_PATHS and the folders of AUDIOBOOK_PATHS.
+/// Relative folders are looked for in every library folder. Deepest first, so
+/// a folder nested in another takes its own type.
+pub fn path_rules(library_roots: &[String]) -> Vec<(String, &'static str)> {
+    let media_type_paths = env::var("MEDIA_TYPE_PATHS").unwrap_or_default();
+    let audiobook_paths = env::var("AUDIOBOOK_PATHS").unwrap_or_default();
+    let rules = media_type_paths.split(';')
+        .filter_map(|rule| {
+            let (folder, media_type) = rule.rsplit_once('=')?;
+            let media_type = MEDIA_TYPES.iter().find(|known| known.eq_ignore_ascii_case(media_type.trim()));
+            if media_type.is_none() {
+                log::warn!("Ignoring MEDIA_TYPE_PATHS rule {:?}: the type must be one of {}", rule, MEDIA_TYPES.join(", "));
+            }
+            Some((folder, *media_type?))
+        })
+        .chain(audiobook_paths.split(';').map(|folder| (folder, AUDIOBOOK)));
+
+    let mut resolved = Vec::new();
+    for (folder, media_type) in rules {
+        let folder = paths::normalize(folder.trim());
+        let folder = folder.trim_end_matches('/');
+        if folder.is_empty() {
+            continue;
+        }
+        if paths::is_absolute(folder) || folder.contains("://") {
+            resolved.push((folder.to_string(), media_type));
+        } else {
+            let folder = folder.trim_start_matches('/');
+            resolved.extend(library_roots.iter().map(|root| (format!("{}/{}", root, folder), media_type)));
+        }
+    }
+    resolved.sort_by_key(|(folder, _)| std::cmp::Reverse(folder.len()));
+    resolved
+}
+
+fn lowercase_in(expr: &str, values: &[&str]) -> SimpleExpr {
+    Expr::cust_with_values(
+        format!("lower({}) IN ({})", expr, (1..=values.len()).map(|n| format!("${}", n)).collect::<Vec<_>>().join(", ")),
+        values.iter().map(|value| value.to_string()),
+    )
+}
+
+// A track's media type: its folder's type, then the type its tags hint at,
+// then audiobook for M4B files and music for everything else
+fn classification(rules: &[(String, &'static str)]) -> CaseStatement {
+    let mut case = CaseStatement::new();
+    for (folder, media_type) in rules {
+        case = case.case(track::Column::Path.starts_with(format!("{}/", folder)), Expr::val(*media_type));
+    }
+    for (media_type, values) in MEDIA_KIND_HINTS {
+        case = case.case(lowercase_in("\"track\".\"tags\"->>'ITUNESMEDIATYPE'", values), Expr::val(*media_type));
+    }
+    for (media_type, values) in GENRE_HINTS {
+        case = case.case(lowercase_in("\"track\".\"genre\"", values), Expr::val(*media_type));
+    }
+    case.case(lowercase_in("\"track\".\"extension\"", &["m4b"]), Expr::val(AUDIOBOOK))
+        .finally(Expr::val(MUSIC))
+}
+
+/// Classify every track by its folder and tags, also unchanged files when
+/// MEDIA_TYPE_PATHS or AUDIOBOOK_PATHS change
+pub async fn assign_media_types(db: &DatabaseConnection, library_roots: &[String]) -> Result<(), DbErr> {
+    let media_type = SimpleExpr::Case(Box::new(classification(&path_rules(library_roots))));
+    Track::update_many()
+        .col_expr(track::Column::MediaType, media_type.clone())
+        .filter(Expr::col(track::Column::MediaType).ne(media_type))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MediaTypeQuery {
+    /// Comma separated media types to include (music, podcast, audiobook,
+    /// sfx), or "all" (default: music)
+    pub media_type: Option<String>,
+}
+
+/// Which media types a browse or search request lists. Music unless the
+/// request asks for other types with media_type, so podcasts, audiobooks and
+/// sound effects stay out of music listings.
+pub struct MediaTypes(Option<Vec<&'static str>>);
+
+impl Default for MediaTypes {
+    fn default() -> Self {
+        Self(Some(vec![MUSIC]))
+    }
+}
+
+impl MediaTypes {
+    pub fn parse(value: Option<&str>) -> Result<Self, ApiError> {
+        let Some(value) = value.map(str::trim).filter(|value| !value.is_empty()) else {
+            return Ok(Self::default());
+        };
+        if value.eq_ignore_ascii_case("all") {
+            return Ok(Self(None));
+        }
+        let types = value.split(',')
+            .map(|name| {
+                MEDIA_TYPES.iter()
+                    .find(|known| known.eq_ignore_ascii_case(name.trim()))
+                    .copied()
+                    .ok_or_else(|| ApiError::invalid_field("media_type", format!("must be \"all\" or any of {}", MEDIA_TYPES.join(", "))))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self(Some(types)))
+    }
+
+    /// Whether this is the music-only view most requests get, which counts can be cached for
+    pub fn is_default(&self) -> bool {
+        self.0.as_deref() == Some(&[MUSIC])
+    }
+
+    /// Condition on track columns selecting the listed media types
+    pub fn condition(&self) -> Condition {
+        match &self.0 {
+            Some(types) => Condition::all().add(track::Column::MediaType.is_in(types.iter().copied())),
+            None => Condition::all(),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for MediaTypes {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let ApiQuery(params) = ApiQuery::<MediaTypeQuery>::from_request_parts(parts, state).await?;
+        Self::parse(params.media_type.as_deref())
+    }
+}
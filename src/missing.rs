@@ -0,0 +1,165 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait};
+use sea_query::Expr;
+
+use entity::prelude::Track;
+use entity::track;
+
+use crate::history::{self, ChangeSource};
+use crate::storage;
+
+/// Highest track ID before a scan, so tracks the scan adds can be told apart
+pub async fn last_track_id(db: &DatabaseConnection) -> Result<i32, DbErr> {
+    let id: Option<Option<i32>> = Track::find()
+        .select_only()
+        .column_as(track::Column::Id.max(), "id")
+        .into_tuple()
+        .one(db)
+        .await?;
+    Ok(id.flatten().unwrap_or(0))
+}
+
+/// Mark tracks whose files are gone as missing and restore the ones that came
+/// back. `present` is the scan's listing of the library. A track added by this
+/// scan (ID above `last_track_id`) with the same content hash as a missing
+/// track is a moved file: the missing track takes over its path, keeping its
+/// ID and everything attached to it. Tracks under a library folder the listing
+/// found no files in, like an unmounted drive, are left as they are.
+pub async fn reconcile_missing(db: &DatabaseConnection, roots: &[String], present: &HashSet<String>, last_track_id: i32) -> Result<(), DbErr> {
+    let unavailable = unavailable_roots(roots, present);
+    for root in &unavailable {
+        warn!("Library folder {} can't be read or is empty; not checking its tracks for missing files", root);
+    }
+
+    let tracks: Vec<(i32, String, Option<DateTime<Utc>>)> = Track::find()
+        .select_only()
+        .column(track::Column::Id)
+        .column(track::Column::Path)
+        .column(track::Column::MissingSince)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let mut gone = Vec::new();
+    let mut returned = Vec::new();
+    for (id, path, missing_since) in tracks {
+        if storage::library_root_of(roots, &path).is_some_and(|root| unavailable.contains(root)) {
+            continue;
+        }
+        match (present.contains(&path), missing_since.is_some()) {
+            (false, false) => gone.push(id),
+            (true, true) => returned.push(id),
+            _ => {}
+        }
+    }
+
+    update_missing(db, &gone, &returned, last_track_id).await
+}
+
+// Library folders the listing found no files in. A whole folder disappearing
+// at once is far more likely a drive or share that isn't mounted than music
+// that was deleted.
+fn unavailable_roots<'a>(roots: &'a [String], present: &HashSet<String>) -> HashSet<&'a str> {
+    let listed: HashSet<&str> = present.iter()
+        .filter_map(|path| storage::library_root_of(roots, path))
+        .collect();
+    roots.iter()
+        .map(String::as_str)
+        .filter(|root| !listed.contains(root))
+        .collect()
+}
+
+/// Like reconcile_missing for only some tracks, e.g. those under a directory
+/// that was re-read, given the files that are present among them
+pub async fn reconcile_tracks(db: &DatabaseConnection, tracks: &[track::Model], present: &HashSet<String>, last_track_id: i32) -> Result<(), DbErr> {
+    let mut gone = Vec::new();
+    let mut returned = Vec::new();
+    for track in tracks {
+        match (present.contains(&track.path), track.missing_since.is_some()) {
+            (false, false) => gone.push(track.id),
+            (true, true) => returned.push(track.id),
+            _ => {}
+        }
+    }
+    update_missing(db, &gone, &returned, last_track_id).await
+}
+
+async fn update_missing(db: &DatabaseConnection, gone: &[i32], returned: &[i32], last_track_id: i32) -> Result<(), DbErr> {
+    let now = Utc::now();
+    for chunk in gone.chunks(1000) {
+        set_missing_since(db, chunk, Some(now)).await?;
+    }
+    for chunk in returned.chunks(1000) {
+        set_missing_since(db, chunk, None).await?;
+    }
+    let moved = restore_moved(db, last_track_id).await?;
+
+    if !gone.is_empty() || !returned.is_empty() || moved > 0 {
+        info!("{} tracks went missing, {} came back, {} were found at a new path", gone.len(), returned.len(), moved);
+    }
+    Ok(())
+}
+
+async fn set_missing_since(db: &DatabaseConnection, ids: &[i32], missing_since: Option<DateTime<Utc>>) -> Result<(), DbErr> {
+    let txn = db.begin().await?;
+    let before = history::snapshot(&txn, track::Column::Id.is_in(ids.iter().copied())).await?;
+    Track::update_many()
+        .col_expr(track::Column::MissingSince, Expr::value(missing_since))
+        .filter(track::Column::Id.is_in(ids.iter().copied()))
+        .exec(&txn)
+        .await?;
+    history::record(&txn, ChangeSource::Missing, before).await?;
+    txn.commit().await
+}
+
+async fn restore_moved(db: &DatabaseConnection, last_track_id: i32) -> Result<usize, DbErr> {
+    let added = Track::find()
+        .filter(track::Column::Id.gt(last_track_id))
+        .filter(track::Column::ContentHash.is_not_null())
+        .all(db)
+        .await?;
+    if added.is_empty() {
+        return Ok(0);
+    }
+
+    let hashes: Vec<String> = added.iter().filter_map(|track| track.content_hash.clone()).collect();
+    let mut missing_by_hash: HashMap<String, track::Model> = HashMap::new();
+    for chunk in hashes.chunks(1000) {
+        let missing = Track::find()
+            .filter(track::Column::MissingSince.is_not_null())
+            .filter(track::Column::ContentHash.is_in(chunk.iter().cloned()))
+            .order_by_asc(track::Column::Id)
+            .all(db)
+            .await?;
+        for track in missing {
+            if let Some(hash) = track.content_hash.clone() {
+                missing_by_hash.entry(hash).or_insert(track);
+            }
+        }
+    }
+
+    let mut moved = 0;
+    for added in added {
+        let Some(old) = added.content_hash.as_ref().and_then(|hash| missing_by_hash.remove(hash)) else {
+            continue;
+        };
+
+        // The new row was only just inserted, so nothing refers to it yet
+        let txn = db.begin().await?;
+        Track::delete_by_id(added.id).exec(&txn).await?;
+        let mut restored = track::ActiveModel::from(added).reset_all();
+        restored.id = Set(old.id);
+        restored.is_hidden = Set(old.is_hidden);
+        restored.missing_since = Set(None);
+        restored.update(&txn).await?;
+        // Recorded as a scan, since the tags were read from the file at its new path
+        history::record(&txn, ChangeSource::Scan, vec![old]).await?;
+        txn.commit().await?;
+        moved += 1;
+    }
+    Ok(moved)
+}
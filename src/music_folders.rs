@@ -0,0 +1,103 @@
+// Library folders as numbered music folders, the way Subsonic clients browse
+// them: 1 for MUSIC_PATH, then each of EXTRA_MUSIC_PATHS in order. Clients
+// that open a folder by its ID get the artists with tracks in it and the
+// files directly in it.
+
+use axum::{
+    extract::State,
+    response::Json,
+};
+use sea_orm::ColumnTrait;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use entity::track;
+
+use crate::api::{AppState, TrackResponse};
+use crate::browse::{self, ArtistCount};
+use crate::error::{ApiError, ApiPath, ApiResult};
+use crate::hidden::{Visibility, VisibilityQuery};
+use crate::media_types::{MediaTypeQuery, MediaTypes};
+use crate::storage;
+
+#[derive(Serialize, ToSchema)]
+pub struct MusicFolder {
+    pub id: usize,
+    /// The folder's own name
+    pub name: String,
+    pub path: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MusicFolderResponse {
+    pub id: usize,
+    pub name: String,
+    pub path: String,
+    /// Artists with tracks in the folder, sorted as in /artists
+    pub artists: Vec<ArtistCount>,
+    /// Files directly in the folder, not in any artist or album folder
+    pub children: Vec<TrackResponse>,
+}
+
+fn music_folders(music_path: &str) -> Vec<MusicFolder> {
+    storage::library_roots(music_path)
+        .into_iter()
+        .enumerate()
+        .map(|(index, path)| MusicFolder {
+            id: index + 1,
+            name: path.rsplit('/').find(|part| !part.is_empty()).unwrap_or(&path).to_string(),
+            path,
+        })
+        .collect()
+}
+
+// GET /music-folders - The library folders with their IDs
+#[utoipa::path(
+    get,
+    path = "/music-folders",
+    tag = "library",
+    responses(
+        (status = 200, description = "Library folders, MUSIC_PATH first", body = [MusicFolder]),
+    )
+)]
+pub async fn get_music_folders(State(state): State<AppState>) -> Json<Vec<MusicFolder>> {
+    Json(music_folders(&state.music_path))
+}
+
+// GET /music-folders/:id - A library folder's artists and loose files
+#[utoipa::path(
+    get,
+    path = "/music-folders/{id}",
+    tag = "library",
+    params(("id" = usize, Path, description = "Music folder ID, 1 for MUSIC_PATH"), MediaTypeQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "The folder's artists and the files directly in it", body = MusicFolderResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "No music folder has this ID", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_music_folder(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<usize>,
+    media_types: MediaTypes,
+    visibility: Visibility,
+) -> ApiResult<Json<MusicFolderResponse>> {
+    let folder = music_folders(&state.music_path)
+        .into_iter()
+        .find(|folder| folder.id == id)
+        .ok_or_else(|| ApiError::not_found(format!("Music folder {} not found", id)))?;
+
+    let condition = visibility.condition()
+        .add(media_types.condition())
+        .add(track::Column::LibraryRoot.eq(folder.path.as_str()));
+    let artists = browse::artist_counts(&state.db, condition.clone(), state.text_normalization, &state.ignored_articles).await?;
+    let children = browse::root_tracks(&state.db, condition, None).await?;
+
+    Ok(Json(MusicFolderResponse {
+        id: folder.id,
+        name: folder.name,
+        path: folder.path,
+        artists,
+        children: children.into_iter().map(TrackResponse::from).collect(),
+    }))
+}
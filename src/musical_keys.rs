@@ -0,0 +1,104 @@
+// Musical keys from INITIALKEY tags. DJ software writes them in several
+// notations: standard ("Am", "F#", "Db minor"), Camelot ("8A") as used by
+// Mixed In Key, and Open Key ("1m") as used by Traktor. Keys are stored in
+// standard notation, spelled the way the Camelot wheel spells them, and
+// listed in wheel order so harmonically compatible keys sort together.
+
+// Keys in Camelot wheel order: 1A, 1B, 2A, 2B, ... 12A, 12B. Each is the
+// standard name and the pitch class of its tonic, 0 for C.
+const WHEEL: [(&str, u8); 24] = [
+    ("Abm", 8), ("B", 11),
+    ("Ebm", 3), ("F#", 6),
+    ("Bbm", 10), ("Db", 1),
+    ("Fm", 5), ("Ab", 8),
+    ("Cm", 0), ("Eb", 3),
+    ("Gm", 7), ("Bb", 10),
+    ("Dm", 2), ("F", 5),
+    ("Am", 9), ("C", 0),
+    ("Em", 4), ("G", 7),
+    ("Bm", 11), ("D", 2),
+    ("F#m", 6), ("A", 9),
+    ("C#m", 1), ("E", 4),
+];
+
+// Position on the wheel of a Camelot number (1 to 12) and mode
+fn wheel_index(number: u32, minor: bool) -> Option<usize> {
+    (1..=12).contains(&number).then(|| (number as usize - 1) * 2 + if minor { 0 } else { 1 })
+}
+
+// "8A" (minor) or "8B" (major)
+fn camelot(value: &str) -> Option<usize> {
+    let (number, mode) = value.split_at(value.len().checked_sub(1)?);
+    let minor = match mode {
+        "A" | "a" => true,
+        "B" | "b" => false,
+        _ => return None,
+    };
+    wheel_index(number.parse().ok()?, minor)
+}
+
+// "1m" (minor) or "1d" (major); Open Key 1 is Camelot 8
+fn open_key(value: &str) -> Option<usize> {
+    let (number, mode) = value.split_at(value.len().checked_sub(1)?);
+    let minor = match mode {
+        "m" | "M" => true,
+        "d" | "D" => false,
+        _ => return None,
+    };
+    let number: u32 = number.parse().ok()?;
+    if !(1..=12).contains(&number) {
+        return None;
+    }
+    wheel_index((number + 6) % 12 + 1, minor)
+}
+
+// "Am", "A minor", "F#", "Gb major", "Bbmin"
+fn standard(value: &str) -> Option<usize> {
+    let mut chars = value.chars();
+    let tonic = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (pitch, rest) = if let Some(rest) = rest.strip_prefix(['#', '♯']) {
+        (tonic + 1, rest)
+    } else if let Some(rest) = rest.strip_prefix(['b', '♭']) {
+        (tonic + 11, rest)
+    } else {
+        (tonic, rest)
+    };
+    let minor = match rest.trim() {
+        "" => false,
+        "m" => true,
+        mode if mode.eq_ignore_ascii_case("maj") || mode.eq_ignore_ascii_case("major") => false,
+        mode if mode.eq_ignore_ascii_case("min") || mode.eq_ignore_ascii_case("minor") => true,
+        _ => return None,
+    };
+    let pitch = pitch % 12;
+    (0..WHEEL.len()).find(|&index| WHEEL[index].1 == pitch && (index % 2 == 0) == minor)
+}
+
+/// Normalize a key in standard, Camelot or Open Key notation to standard
+/// notation as spelled on the Camelot wheel, e.g. "8A", "1m" and "A minor" to
+/// "Am". Values that aren't keys, like Mixed In Key's "o" for atonal tracks,
+/// give None.
+pub fn normalize_key(value: &str) -> Option<String> {
+    let value = value.trim();
+    camelot(value)
+        .or_else(|| open_key(value))
+        .or_else(|| standard(value))
+        .map(|index| WHEEL[index].0.to_string())
+}
+
+/// SQL ordering tracks by the Camelot wheel position of their key, tracks
+/// without one last
+pub fn wheel_order_expr() -> String {
+    let keys: Vec<String> = WHEEL.iter().map(|(key, _)| format!("'{}'", key)).collect();
+    format!(r#"array_position(ARRAY[{}]::text[], "track"."initial_key")"#, keys.join(", "))
+}
@@ -0,0 +1,281 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use utoipa::openapi::server::Server;
+use utoipa::OpenApi;
+
+use crate::api::AppState;
+use crate::{albums, api, audio_info, audiobooks, backups, browse, capabilities, clients, conversion, diagnostics, downloads, duplicates, error, formats, hidden, history, hls, import, integrity, lastfm, lyric_providers, listening, music_folders, previews, permissions, query_log, recommendations, playback, playlist_covers, playlists, playqueue, scan_profile, scheduler, sessions, shuffle, stream_metrics, transcoder, webhooks, write_queue};
+
+/// OpenAPI document generated from the handler and type annotations, so it
+/// can't drift from the routes actually served
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Ongaku Music Server API",
+        description = "Music library server: browsing, streaming, scanning, playlists and Last.fm scrobbling.",
+    ),
+    servers((url = "/api/v1")),
+    paths(
+        api::get_tracks,
+        api::get_track_by_id,
+        api::get_tracks_batch,
+        hidden::update_track,
+        history::get_track_history,
+        history::revert_change,
+        api::play_track,
+        api::get_album_art,
+        api::get_stream_url,
+        lyric_providers::get_lyrics,
+        previews::get_preview,
+        audio_info::get_audio_info,
+        downloads::download,
+        hls::get_master_playlist,
+        hls::get_audio_playlist,
+        hls::get_audio_segment,
+        hls::get_lyrics_playlist,
+        hls::get_lyrics_vtt,
+        api::get_transcoding_profiles,
+        api::search_tracks,
+        shuffle::shuffle_tracks,
+        api::get_stats,
+        capabilities::get_capabilities,
+        listening::get_listening_stats,
+        listening::create_play,
+        listening::get_annotation,
+        import::import_library,
+        playback::update_progress,
+        playback::get_progress,
+        playback::get_now_playing,
+        api::get_artists,
+        api::get_artist_indexes,
+        music_folders::get_music_folders,
+        music_folders::get_music_folder,
+        api::get_artist,
+        albums::get_albums,
+        albums::get_albums_by_decade,
+        albums::get_album_tracks,
+        albums::get_album_cover,
+        albums::get_album_stream,
+        audiobooks::get_audiobooks,
+        audiobooks::get_audiobook,
+        api::get_genres,
+        api::rescan_library,
+        api::rescan_path,
+        api::get_scan_status,
+        api::get_scan_errors,
+        integrity::get_quality_report,
+        diagnostics::get_diagnostics,
+        backups::start_backup,
+        backups::get_backup_status,
+        query_log::get_query_log,
+        query_log::update_query_log,
+        stream_metrics::get_streaming_metrics,
+        lyric_providers::fetch_missing_lyrics,
+        previews::generate_previews,
+        clients::get_clients,
+        clients::update_client,
+        lastfm::get_auth_url,
+        lastfm::auth_callback,
+        lastfm::create_session,
+        lastfm::scrobble_track,
+        lastfm::update_now_playing,
+        api::get_sessions,
+        api::terminate_session,
+        hidden::get_hidden_directories,
+        hidden::hide_directory,
+        hidden::unhide_directory,
+        permissions::get_users,
+        permissions::get_user_folders,
+        permissions::update_user_folders,
+        permissions::get_content_filter,
+        permissions::update_content_filter,
+        permissions::get_user_roles,
+        permissions::update_user_roles,
+        conversion::start_conversion,
+        conversion::get_conversions,
+        conversion::get_conversion,
+        conversion::cancel_conversion,
+        playqueue::get_play_queue,
+        playqueue::save_play_queue,
+        playlists::get_playlists,
+        playlists::import_playlist,
+        playlists::get_playlist,
+        playlists::update_playlist,
+        recommendations::create_recommended_playlist,
+        recommendations::refresh_playlist,
+        playlist_covers::get_playlist_cover,
+        webhooks::test_webhooks,
+    ),
+    components(schemas(
+        api::TrackResponse,
+        duplicates::TrackAlternate,
+        api::TrackBatchRequest,
+        api::TrackBatchResponse,
+        api::TrackListResponse,
+        api::DecadeFacet,
+        api::LanguageFacet,
+        api::CountryFacet,
+        api::TrackStatsResponse,
+        shuffle::ShuffleResponse,
+        api::StreamUrlResponse,
+        transcoder::TranscodeProfile,
+        transcoder::ProfileFormat,
+        api::ArtistListResponse,
+        api::ArtistPage,
+        api::GenreListResponse,
+        api::ArtistAlbum,
+        api::ArtistResponse,
+        api::RescanResponse,
+        api::RescanPathRequest,
+        api::RescanPathResponse,
+        api::ScanErrorResponse,
+        api::ScanErrorListResponse,
+        albums::AlbumResponse,
+        formats::FormatSummary,
+        formats::FormatGroup,
+        albums::AlbumListResponse,
+        albums::AlbumPage,
+        albums::DecadeCount,
+        albums::DecadeListResponse,
+        audiobooks::AudiobookSummary,
+        audiobooks::AudiobookResponse,
+        audiobooks::AudiobookListResponse,
+        audiobooks::BookChapter,
+        audiobooks::ResumePosition,
+        browse::ArtistCount,
+        browse::ArtistIndex,
+        api::ArtistIndexResponse,
+        music_folders::MusicFolder,
+        music_folders::MusicFolderResponse,
+        browse::GenreCount,
+        scheduler::ScanStatus,
+        scheduler::LastScan,
+        scheduler::FolderScanStatus,
+        scheduler::ScanPriority,
+        lyric_providers::LyricsResponse,
+        lyric_providers::LyricLineResponse,
+        lyric_providers::LyricsBatchResponse,
+        previews::PreviewBatchResponse,
+        audio_info::AudioInfoResponse,
+        audio_info::AudioInfo,
+        audio_info::LameHeader,
+        capabilities::CapabilitiesResponse,
+        capabilities::CapabilityResponse,
+        integrity::QualityReport,
+        integrity::QualityProblem,
+        diagnostics::DiagnosticsResponse,
+        diagnostics::DatabaseStats,
+        diagnostics::TableStats,
+        diagnostics::ScanErrorSummary,
+        diagnostics::SlowQuery,
+        backups::BackupJob,
+        backups::BackupState,
+        backups::BackupFile,
+        backups::BackupStatusResponse,
+        query_log::RecordedQuery,
+        write_queue::WriteQueueStats,
+        query_log::QueryLogSettings,
+        query_log::QueryLogUpdate,
+        stream_metrics::StreamingMetricsResponse,
+        stream_metrics::TranscodeMetrics,
+        stream_metrics::CacheMetrics,
+        clients::ClientResponse,
+        clients::UpdateClientRequest,
+        scan_profile::ScanProfileSummary,
+        scan_profile::PhaseSummary,
+        scan_profile::SlowPath,
+        sessions::SessionInfo,
+        hidden::UpdateTrackRequest,
+        history::TrackChangeResponse,
+        history::FieldChange,
+        hidden::HideDirectoryRequest,
+        hidden::HiddenDirectoryResponse,
+        permissions::UserResponse,
+        permissions::UserFoldersResponse,
+        permissions::ContentFilter,
+        permissions::UserRoles,
+        permissions::UpdateUserFoldersRequest,
+        conversion::ConversionRequest,
+        conversion::ConversionFormat,
+        conversion::ConversionJob,
+        conversion::ConversionError,
+        conversion::JobState,
+        listening::Period,
+        listening::RecordPlayRequest,
+        listening::PlayResponse,
+        listening::TrackAnnotationResponse,
+        import::ImportFormat,
+        import::ImportLibraryResponse,
+        listening::ListeningStatsResponse,
+        listening::TopArtist,
+        listening::TopAlbum,
+        listening::TopTrack,
+        listening::TopGenre,
+        listening::DailyListening,
+        playback::ProgressRequest,
+        playback::ProgressResponse,
+        playback::PlaybackPositionResponse,
+        playback::NowPlayingEntry,
+        lastfm::LastfmAuthResponse,
+        lastfm::LastfmSessionRequest,
+        lastfm::LastfmSessionResponse,
+        lastfm::ScrobbleRequest,
+        lastfm::ScrobbleResponse,
+        lastfm::NowPlayingRequest,
+        lastfm::NowPlayingResponse,
+        playqueue::SavePlayQueueRequest,
+        playqueue::PlayQueueResponse,
+        playlists::PlaylistSummary,
+        playlists::PlaylistResponse,
+        playlists::ImportResponse,
+        playlists::UpdatePlaylistRequest,
+        recommendations::RecommendedPlaylistRequest,
+        recommendations::RecommendedPlaylistResponse,
+        webhooks::WebhookTestResult,
+        error::ProblemDetails,
+        error::FieldError,
+    )),
+    tags(
+        (name = "tracks", description = "Track listing, search and streaming"),
+        (name = "library", description = "Artists, albums, genres and statistics"),
+        (name = "scans", description = "Library scanning"),
+        (name = "lastfm", description = "Last.fm authentication and scrobbling"),
+        (name = "sessions", description = "Active stream sessions"),
+        (name = "playqueue", description = "Play queue persistence"),
+        (name = "playlists", description = "Playlists and M3U/XSPF import and export"),
+        (name = "conversions", description = "Batch audio format conversion"),
+        (name = "audiobooks", description = "Audiobooks with chapters and resume positions"),
+        (name = "backups", description = "Online database backups"),
+        (name = "listening", description = "Listening history, playback progress and statistics"),
+        (name = "webhooks", description = "Outgoing event notifications"),
+        (name = "clients", description = "Known clients and their playback overrides"),
+        (name = "users", description = "Per-user folder permissions and content filters"),
+    )
+)]
+pub struct ApiDoc;
+
+// The document with its server at the API's path, which moves with BASE_PATH
+fn document(state: &AppState) -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    doc.servers = Some(vec![Server::new(state.proxy.api_path())]);
+    doc
+}
+
+// GET /openapi.yaml - OpenAPI specification as YAML
+pub async fn openapi_yaml(State(state): State<AppState>) -> Response {
+    match document(&state).to_yaml() {
+        Ok(yaml) => ([(header::CONTENT_TYPE, "application/yaml")], yaml).into_response(),
+        Err(e) => {
+            log::error!("Failed to render OpenAPI document: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// GET /openapi.json - OpenAPI specification as JSON
+pub async fn openapi_json(State(state): State<AppState>) -> Json<utoipa::openapi::OpenApi> {
+    Json(document(&state))
+}
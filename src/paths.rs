@@ -0,0 +1,197 @@
+// Paths as the database stores them: always with forward slashes, so that
+// Windows drive paths (`C:/Music`) and UNC shares (`//nas/Music`) are
+// split, joined and prefix-matched like Unix paths. Files are opened through
+// `to_native`, which turns them back into what the platform expects.
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+// Windows' extended-length prefixes, which lift the 260 character MAX_PATH limit
+const VERBATIM_PREFIX: &str = r"\\?\";
+const VERBATIM_UNC_PREFIX: &str = r"\\?\UNC\";
+const MAX_PATH: usize = 260;
+
+/// Whether paths differ only in case name the same file, as on Windows
+pub const CASE_INSENSITIVE: bool = cfg!(windows);
+
+/// MUSIC_PATH when it isn't set: the user's Music folder on Windows
+pub fn default_music_path() -> String {
+    if cfg!(windows) {
+        if let Some(music) = dirs::audio_dir().and_then(|dir| to_stored(&dir)) {
+            return music;
+        }
+    }
+    "/mnt/shucked/Music".to_string()
+}
+
+// C:\Music\a.flac -> C:/Music/a.flac, \\nas\Music -> //nas/Music, dropping
+// extended-length prefixes
+fn normalize_windows(path: &str) -> String {
+    if let Some(share) = path.strip_prefix(VERBATIM_UNC_PREFIX) {
+        return format!("//{}", share.replace('\\', "/"));
+    }
+    path.strip_prefix(VERBATIM_PREFIX).unwrap_or(path).replace('\\', "/")
+}
+
+/// A configured or listed path in stored form. Unchanged except on Windows.
+pub fn normalize(path: &str) -> String {
+    if cfg!(windows) {
+        normalize_windows(path)
+    } else {
+        path.to_string()
+    }
+}
+
+/// A path on disk in stored form, None when it isn't valid Unicode
+pub fn to_stored(path: &Path) -> Option<String> {
+    path.to_str().map(normalize)
+}
+
+/// A path on disk in stored form, with anything that isn't valid Unicode
+/// replaced, for messages and scan errors
+pub fn to_stored_lossy(path: &Path) -> String {
+    normalize(&path.to_string_lossy())
+}
+
+/// Whether a stored path starts at a root: `/`, a drive (`C:/`) or a UNC share
+pub fn is_absolute(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    let is_drive = bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && matches!(bytes[2], b'/' | b'\\');
+    path.starts_with('/') || path.starts_with('\\') || is_drive
+}
+
+// C:/Music/a.flac -> C:\Music\a.flac, adding the extended-length prefix to
+// absolute paths too long for MAX_PATH
+fn to_native_windows(path: &str) -> String {
+    let native = path.replace('/', "\\");
+    if native.len() < MAX_PATH || !is_absolute(path) || native.starts_with(VERBATIM_PREFIX) {
+        return native;
+    }
+    match native.strip_prefix(r"\\") {
+        Some(share) => format!("{}{}", VERBATIM_UNC_PREFIX, share),
+        None => format!("{}{}", VERBATIM_PREFIX, native),
+    }
+}
+
+/// The path to open a stored path with. On Windows, absolute paths too long
+/// for MAX_PATH get the extended-length prefix.
+pub fn to_native(path: &str) -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from(to_native_windows(path))
+    } else {
+        PathBuf::from(path)
+    }
+}
+
+fn fold_case(path: &str, case_insensitive: bool) -> Cow<'_, str> {
+    if case_insensitive {
+        Cow::Owned(path.to_lowercase())
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+fn strip_root_folding<'a>(path: &'a str, root: &str, case_insensitive: bool) -> Option<&'a str> {
+    let (head, rest) = (path.get(..root.len())?, &path[root.len()..]);
+    if fold_case(head, case_insensitive) != fold_case(root, case_insensitive) {
+        return None;
+    }
+    rest.strip_prefix('/')
+}
+
+/// A path to compare or index stored paths by, lowercased where case
+/// doesn't matter
+pub fn key(path: &str) -> Cow<'_, str> {
+    fold_case(path, CASE_INSENSITIVE)
+}
+
+/// Whether two stored paths name the same file
+pub fn same(a: &str, b: &str) -> bool {
+    key(a) == key(b)
+}
+
+/// What follows `root/` in a path below the folder `root`
+pub fn strip_root<'a>(path: &'a str, root: &str) -> Option<&'a str> {
+    strip_root_folding(path, root, CASE_INSENSITIVE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fold_case, is_absolute, normalize_windows, strip_root_folding, to_native_windows, MAX_PATH};
+
+    #[test]
+    fn stores_windows_paths_with_forward_slashes() {
+        assert_eq!(normalize_windows(r"C:\Music\Artist\a.flac"), "C:/Music/Artist/a.flac");
+        assert_eq!(normalize_windows(r"\\nas\Music\a.flac"), "//nas/Music/a.flac");
+        assert_eq!(normalize_windows(r"C:\Music/Artist\a.flac"), "C:/Music/Artist/a.flac");
+        assert_eq!(normalize_windows("C:/Music/a.flac"), "C:/Music/a.flac");
+    }
+
+    #[test]
+    fn drops_extended_length_prefixes() {
+        assert_eq!(normalize_windows(r"\\?\C:\Music\a.flac"), "C:/Music/a.flac");
+        assert_eq!(normalize_windows(r"\\?\UNC\nas\Music\a.flac"), "//nas/Music/a.flac");
+    }
+
+    #[test]
+    fn recognizes_absolute_paths() {
+        assert!(is_absolute("C:/Music"));
+        assert!(is_absolute("c:\\Music"));
+        assert!(is_absolute("//nas/Music"));
+        assert!(is_absolute("/mnt/music"));
+        assert!(!is_absolute("Music/Artist"));
+        assert!(!is_absolute("C:Music"));
+    }
+
+    #[test]
+    fn opens_short_paths_without_prefix() {
+        assert_eq!(to_native_windows("C:/Music/a.flac"), r"C:\Music\a.flac");
+        assert_eq!(to_native_windows("//nas/Music/a.flac"), r"\\nas\Music\a.flac");
+        assert_eq!(to_native_windows("Artist/a.flac"), r"Artist\a.flac");
+    }
+
+    #[test]
+    fn prefixes_long_paths() {
+        let name = "a".repeat(MAX_PATH);
+        assert_eq!(to_native_windows(&format!("C:/Music/{}", name)), format!(r"\\?\C:\Music\{}", name));
+        assert_eq!(to_native_windows(&format!("//nas/Music/{}", name)), format!(r"\\?\UNC\nas\Music\{}", name));
+        // Relative paths can't take the prefix
+        assert_eq!(to_native_windows(&format!("Music/{}", name)), format!(r"Music\{}", name));
+        // Nor get it twice
+        let verbatim = format!(r"\\?\C:\Music\{}", name);
+        assert_eq!(to_native_windows(&verbatim), verbatim);
+    }
+
+    #[test]
+    fn round_trips_stored_paths() {
+        let long = format!("C:/Music/{}/b.flac", "é".repeat(MAX_PATH));
+        for stored in ["C:/Music/Björk/a.flac", "//nas/Music/a.flac", "Artist/a.flac", long.as_str(), "//nas/Music/日本/a.flac"] {
+            assert_eq!(normalize_windows(&to_native_windows(stored)), stored);
+        }
+        for native in [r"C:\Music\a.flac", r"\\nas\Music\a.flac"] {
+            assert_eq!(to_native_windows(&normalize_windows(native)), native);
+        }
+    }
+
+    #[test]
+    fn folds_case_where_it_doesnt_matter() {
+        assert_eq!(fold_case("C:/Music/ÄB", true), "c:/music/äb");
+        assert_eq!(fold_case("C:/Music/ÄB", false), "C:/Music/ÄB");
+        assert_eq!(fold_case("c:/music", true), fold_case("C:/MUSIC", true));
+        assert_ne!(fold_case("c:/music", false), fold_case("C:/MUSIC", false));
+    }
+
+    #[test]
+    fn strips_roots() {
+        assert_eq!(strip_root_folding("C:/Music/a.flac", "C:/Music", false), Some("a.flac"));
+        assert_eq!(strip_root_folding("c:/music/a.flac", "C:/Music", true), Some("a.flac"));
+        assert_eq!(strip_root_folding("c:/music/a.flac", "C:/Music", false), None);
+        // A sibling sharing the prefix isn't under the root
+        assert_eq!(strip_root_folding("C:/Music2/a.flac", "C:/Music", true), None);
+        assert_eq!(strip_root_folding("C:/Music", "C:/Music", true), None);
+        assert_eq!(strip_root_folding("C:/Mu", "C:/Music", true), None);
+        assert_eq!(strip_root_folding("//NAS/Music/a.flac", "//nas/Music", true), Some("a.flac"));
+        // Cuts inside a multi-byte character don't match
+        assert_eq!(strip_root_folding("C:/Mé/a.flac", "C:/M.", true), None);
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::State,
+    response::Json,
+};
+use chrono::Utc;
+use log::info;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, NotSet, QueryFilter, QueryOrder, TransactionTrait};
+use sea_query::OnConflict;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use entity::prelude::{UserContentFilter, UserFolder, UserRole};
+use entity::{user_content_filter, user_folder, user_role};
+
+use crate::api::AppState;
+use crate::error::{ApiError, ApiJson, ApiPath, ApiResult};
+use crate::hidden;
+
+/// The folders a user is limited to, empty when they may see every folder
+pub async fn user_folders(db: &DatabaseConnection, user: &str) -> Result<Vec<String>, DbErr> {
+    let folders = UserFolder::find()
+        .filter(user_folder::Column::User.eq(user))
+        .order_by_asc(user_folder::Column::Path)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|folder| folder.path)
+        .collect();
+    Ok(folders)
+}
+
+/// Whether a user's content filter hides explicit tracks
+pub async fn hides_explicit(db: &DatabaseConnection, user: &str) -> Result<bool, DbErr> {
+    let filter = UserContentFilter::find()
+        .filter(user_content_filter::Column::User.eq(user))
+        .one(db)
+        .await?;
+    Ok(filter.is_some_and(|filter| filter.hide_explicit))
+}
+
+/// Whether a user may download original files. Users without roles set may.
+pub async fn can_download(db: &DatabaseConnection, user: &str) -> Result<bool, DbErr> {
+    let role = UserRole::find()
+        .filter(user_role::Column::User.eq(user))
+        .one(db)
+        .await?;
+    Ok(role.is_none_or(|role| role.download))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserResponse {
+    pub user: String,
+    /// Folders the user is limited to; empty when they see the whole library
+    pub folders: Vec<String>,
+    /// Whether explicit tracks are hidden from the user
+    pub hide_explicit: bool,
+    /// Whether the user may download original files
+    pub download: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserFoldersResponse {
+    pub user: String,
+    /// Folders the user is limited to; empty when they see the whole library
+    pub folders: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateUserFoldersRequest {
+    /// Folders to limit the user to, absolute or relative to the music
+    /// library. An empty list lets them see every folder again.
+    pub folders: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UserRoles {
+    /// Download original files with /download, as Subsonic's downloadRole
+    pub download: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ContentFilter {
+    /// Hide tracks tagged as explicit. Untagged tracks are still shown.
+    pub hide_explicit: bool,
+}
+
+// A user in the /admin/users listing, with no restrictions until they're added
+fn user_entry(users: &mut BTreeMap<String, UserResponse>, user: String) -> &mut UserResponse {
+    users.entry(user.clone()).or_insert_with(|| UserResponse {
+        user,
+        folders: Vec::new(),
+        hide_explicit: false,
+        download: true,
+    })
+}
+
+// GET /admin/users - List users limited to some folders, with a content filter or without a role
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    tag = "users",
+    responses(
+        (status = 200, description = "Users with folder permissions, content filters or roles taken away", body = Vec<UserResponse>),
+    )
+)]
+pub async fn get_users(State(state): State<AppState>) -> ApiResult<Json<Vec<UserResponse>>> {
+    let mut users: BTreeMap<String, UserResponse> = BTreeMap::new();
+    for folder in UserFolder::find().order_by_asc(user_folder::Column::Path).all(&state.db).await? {
+        user_entry(&mut users, folder.user).folders.push(folder.path);
+    }
+    for filter in UserContentFilter::find().filter(user_content_filter::Column::HideExplicit.eq(true)).all(&state.db).await? {
+        user_entry(&mut users, filter.user).hide_explicit = true;
+    }
+    for role in UserRole::find().filter(user_role::Column::Download.eq(false)).all(&state.db).await? {
+        user_entry(&mut users, role.user).download = false;
+    }
+    let users = users.into_values().collect();
+    Ok(Json(users))
+}
+
+// GET /admin/users/:user/folders - Get the folders a user is limited to
+#[utoipa::path(
+    get,
+    path = "/admin/users/{user}/folders",
+    tag = "users",
+    params(("user" = String, Path, description = "User name")),
+    responses(
+        (status = 200, description = "The user's folders", body = UserFoldersResponse),
+    )
+)]
+pub async fn get_user_folders(
+    State(state): State<AppState>,
+    ApiPath(user): ApiPath<String>,
+) -> ApiResult<Json<UserFoldersResponse>> {
+    let folders = user_folders(&state.db, &user).await?;
+    Ok(Json(UserFoldersResponse { user, folders }))
+}
+
+// PUT /admin/users/:user/folders - Limit a user to some folders, replacing their current ones
+#[utoipa::path(
+    put,
+    path = "/admin/users/{user}/folders",
+    tag = "users",
+    params(("user" = String, Path, description = "User name")),
+    request_body = UpdateUserFoldersRequest,
+    responses(
+        (status = 200, description = "The user's new folders", body = UserFoldersResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn update_user_folders(
+    State(state): State<AppState>,
+    ApiPath(user): ApiPath<String>,
+    ApiJson(request): ApiJson<UpdateUserFoldersRequest>,
+) -> ApiResult<Json<UserFoldersResponse>> {
+    let user = user.trim().to_string();
+    if user.is_empty() {
+        return Err(ApiError::invalid_field("user", "must not be empty"));
+    }
+    let mut folders = Vec::with_capacity(request.folders.len());
+    for folder in &request.folders {
+        let folder = hidden::resolve_directory(&state.music_path, folder)
+            .ok_or_else(|| ApiError::invalid_field("folders", "must not contain empty paths or .. segments"))?;
+        if !folders.contains(&folder) {
+            folders.push(folder);
+        }
+    }
+    folders.sort();
+
+    let txn = state.db.begin().await?;
+    UserFolder::delete_many()
+        .filter(user_folder::Column::User.eq(user.as_str()))
+        .exec(&txn)
+        .await?;
+    if !folders.is_empty() {
+        let created = Utc::now();
+        UserFolder::insert_many(folders.iter().map(|path| user_folder::ActiveModel {
+            id: NotSet,
+            user: Set(user.clone()),
+            path: Set(path.clone()),
+            created: Set(created),
+        }))
+        .exec(&txn)
+        .await?;
+    }
+    txn.commit().await?;
+
+    if folders.is_empty() {
+        info!("User {} can see every folder", user);
+    } else {
+        info!("User {} is limited to {}", user, folders.join(", "));
+    }
+    Ok(Json(UserFoldersResponse { user, folders }))
+}
+
+// GET /admin/users/:user/content-filter - Get what a user's content filter hides
+#[utoipa::path(
+    get,
+    path = "/admin/users/{user}/content-filter",
+    tag = "users",
+    params(("user" = String, Path, description = "User name")),
+    responses(
+        (status = 200, description = "The user's content filter", body = ContentFilter),
+    )
+)]
+pub async fn get_content_filter(
+    State(state): State<AppState>,
+    ApiPath(user): ApiPath<String>,
+) -> ApiResult<Json<ContentFilter>> {
+    Ok(Json(ContentFilter { hide_explicit: hides_explicit(&state.db, user.trim()).await? }))
+}
+
+// PUT /admin/users/:user/content-filter - Restrict what a user sees, e.g. hide explicit tracks
+#[utoipa::path(
+    put,
+    path = "/admin/users/{user}/content-filter",
+    tag = "users",
+    params(("user" = String, Path, description = "User name")),
+    request_body = ContentFilter,
+    responses(
+        (status = 200, description = "The user's new content filter", body = ContentFilter),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn update_content_filter(
+    State(state): State<AppState>,
+    ApiPath(user): ApiPath<String>,
+    ApiJson(request): ApiJson<ContentFilter>,
+) -> ApiResult<Json<ContentFilter>> {
+    let user = user.trim().to_string();
+    if user.is_empty() {
+        return Err(ApiError::invalid_field("user", "must not be empty"));
+    }
+
+    UserContentFilter::insert(user_content_filter::ActiveModel {
+        id: NotSet,
+        user: Set(user.clone()),
+        hide_explicit: Set(request.hide_explicit),
+        modified: Set(Utc::now()),
+    })
+    .on_conflict(
+        OnConflict::column(user_content_filter::Column::User)
+            .update_columns([user_content_filter::Column::HideExplicit, user_content_filter::Column::Modified])
+            .to_owned(),
+    )
+    .exec(&state.db)
+    .await?;
+
+    if request.hide_explicit {
+        info!("Explicit tracks are hidden from user {}", user);
+    } else {
+        info!("User {} can see explicit tracks", user);
+    }
+    Ok(Json(request))
+}
+
+// GET /admin/users/:user/roles - Get what a user may do beyond browsing and streaming
+#[utoipa::path(
+    get,
+    path = "/admin/users/{user}/roles",
+    tag = "users",
+    params(("user" = String, Path, description = "User name")),
+    responses(
+        (status = 200, description = "The user's roles", body = UserRoles),
+    )
+)]
+pub async fn get_user_roles(
+    State(state): State<AppState>,
+    ApiPath(user): ApiPath<String>,
+) -> ApiResult<Json<UserRoles>> {
+    Ok(Json(UserRoles { download: can_download(&state.db, user.trim()).await? }))
+}
+
+// PUT /admin/users/:user/roles - Give or take away a user's roles, e.g. downloading
+#[utoipa::path(
+    put,
+    path = "/admin/users/{user}/roles",
+    tag = "users",
+    params(("user" = String, Path, description = "User name")),
+    request_body = UserRoles,
+    responses(
+        (status = 200, description = "The user's new roles", body = UserRoles),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn update_user_roles(
+    State(state): State<AppState>,
+    ApiPath(user): ApiPath<String>,
+    ApiJson(request): ApiJson<UserRoles>,
+) -> ApiResult<Json<UserRoles>> {
+    let user = user.trim().to_string();
+    if user.is_empty() {
+        return Err(ApiError::invalid_field("user", "must not be empty"));
+    }
+
+    UserRole::insert(user_role::ActiveModel {
+        id: NotSet,
+        user: Set(user.clone()),
+        download: Set(request.download),
+        modified: Set(Utc::now()),
+    })
+    .on_conflict(
+        OnConflict::column(user_role::Column::User)
+            .update_columns([user_role::Column::Download, user_role::Column::Modified])
+            .to_owned(),
+    )
+    .exec(&state.db)
+    .await?;
+
+    if request.download {
+        info!("User {} can download files", user);
+    } else {
+        info!("User {} can no longer download files", user);
+    }
+    Ok(Json(request))
+}
@@ -0,0 +1,46 @@
+pub const CONTENT_TYPE: &str = "image/svg+xml";
+
+// Placeholders are square, drawn at the size of a typical cover
+const SIZE: u32 = 600;
+
+/// Up to two initials from the first words of an album name that start with a
+/// letter or digit, uppercased. A music note for names without any.
+fn initials(name: &str) -> String {
+    let initials: String = name.split_whitespace()
+        .filter_map(|word| word.chars().find(|c| c.is_alphanumeric()))
+        .take(2)
+        .flat_map(char::to_uppercase)
+        .collect();
+    if initials.is_empty() {
+        "♪".to_string()
+    } else {
+        initials
+    }
+}
+
+// A hue from a hash of the name, so an album always gets the same colour
+fn hue(name: &str) -> u32 {
+    let digest = md5::compute(name.trim().to_lowercase());
+    u16::from_be_bytes([digest[0], digest[1]]) as u32 % 360
+}
+
+/// Placeholder art for an album without any: its initials on a background
+/// colour derived from its name, as an SVG image. The same name always gives
+/// the same image.
+pub fn placeholder_svg(name: &str) -> String {
+    let initials = initials(name);
+    let font_size = if initials.chars().count() > 1 { SIZE * 2 / 5 } else { SIZE / 2 };
+    format!(
+        concat!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{size}\" height=\"{size}\" viewBox=\"0 0 {size} {size}\">",
+            "<rect width=\"{size}\" height=\"{size}\" fill=\"hsl({hue}, 45%, 40%)\"/>",
+            "<text x=\"50%\" y=\"50%\" dy=\"0.35em\" text-anchor=\"middle\" fill=\"#ffffff\" fill-opacity=\"0.9\" ",
+            "font-family=\"Helvetica, Arial, sans-serif\" font-size=\"{font_size}\" font-weight=\"bold\">{initials}</text>",
+            "</svg>",
+        ),
+        size = SIZE,
+        hue = hue(name),
+        font_size = font_size,
+        initials = initials,
+    )
+}
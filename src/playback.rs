@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use axum::{
+    extract::State,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use entity::prelude::{PlaybackPosition, Track};
+use entity::{playback_position, track};
+
+use crate::api::AppState;
+use crate::error::{ApiError, ApiJson, ApiPath, ApiQuery, ApiResult};
+use crate::lastfm::LastfmClient;
+use crate::webhooks::Event;
+use crate::write_queue::Write;
+
+// Last.fm's rule: a play counts after half the track or four minutes,
+// whichever comes first, and only for tracks longer than 30 seconds
+const MIN_COUNTED_DURATION_SECONDS: i64 = 30;
+const MAX_COUNT_THRESHOLD_SECONDS: i64 = 240;
+// Listeners drop out of now playing after this long without a heartbeat, and
+// are forgotten after PLAYBACK_EXPIRY_SECONDS
+const NOW_PLAYING_TIMEOUT_SECONDS: i64 = 90;
+const PLAYBACK_EXPIRY_SECONDS: i64 = 30 * 60;
+// Slack for heartbeats arriving a little late or position clocks drifting
+const HEARTBEAT_SLACK_MS: i64 = 2_000;
+// A resume position this close to the end means the track was finished
+const FINISHED_MARGIN_MS: i64 = 10_000;
+// Starting over from here after a counted play is a repeat, not a seek
+const REPEAT_START_MS: i64 = 5_000;
+
+/// Seconds of listening after which a play counts, or None for tracks too short to count
+pub fn count_threshold_seconds(duration_seconds: i32) -> Option<i64> {
+    let duration = duration_seconds as i64;
+    if duration <= MIN_COUNTED_DURATION_SECONDS {
+        return None;
+    }
+    Some((duration / 2).min(MAX_COUNT_THRESHOLD_SECONDS))
+}
+
+struct Playback {
+    track_id: i32,
+    title: String,
+    artist: String,
+    started: DateTime<Utc>,
+    position_ms: i64,
+    listened_ms: i64,
+    paused: bool,
+    counted: bool,
+    updated: DateTime<Utc>,
+}
+
+impl Playback {
+    fn new(track: &track::Model, position_ms: i64, paused: bool, now: DateTime<Utc>) -> Self {
+        Self {
+            track_id: track.id,
+            title: track.title.clone(),
+            artist: track.artist.clone(),
+            started: now,
+            position_ms,
+            listened_ms: 0,
+            paused,
+            counted: false,
+            updated: now,
+        }
+    }
+}
+
+/// What a heartbeat changed
+pub struct PlaybackUpdate {
+    /// When the current play started
+    pub started: DateTime<Utc>,
+    pub listened_ms: i64,
+    /// The heartbeat started a new play: a different track or a repeat
+    pub started_play: bool,
+    /// The play just passed the count threshold and should be recorded and scrobbled
+    pub reached_threshold: bool,
+    pub counted: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NowPlayingEntry {
+    pub user: String,
+    pub client: String,
+    pub track_id: i32,
+    pub title: String,
+    pub artist: String,
+    pub position_ms: i64,
+    pub paused: bool,
+    pub started: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+/// What each user and client is playing, kept from playback heartbeats.
+/// Listening time only advances as far as wall-clock time between
+/// heartbeats allows, so seeking ahead doesn't count as listening.
+#[derive(Default)]
+pub struct PlaybackTracker {
+    playing: Mutex<HashMap<(String, String), Playback>>,
+}
+
+impl PlaybackTracker {
+    pub fn heartbeat(&self, user: &str, client: &str, track: &track::Model, position_ms: i64, paused: bool) -> PlaybackUpdate {
+        let now = Utc::now();
+        let mut playing = self.playing.lock().unwrap();
+        playing.retain(|_, playback| now.signed_duration_since(playback.updated).num_seconds() < PLAYBACK_EXPIRY_SECONDS);
+
+        let key = (user.to_string(), client.to_string());
+        let mut started_play = false;
+        let playback = playing.entry(key)
+            .and_modify(|playback| {
+                let repeat = playback.counted && position_ms < REPEAT_START_MS && position_ms < playback.position_ms;
+                if playback.track_id != track.id || repeat {
+                    *playback = Playback::new(track, position_ms, paused, now);
+                    started_play = true;
+                    return;
+                }
+
+                let elapsed_ms = now.signed_duration_since(playback.updated).num_milliseconds();
+                let advanced_ms = position_ms - playback.position_ms;
+                if !playback.paused && advanced_ms > 0 {
+                    playback.listened_ms += advanced_ms.min(elapsed_ms + HEARTBEAT_SLACK_MS);
+                }
+                playback.position_ms = position_ms;
+                playback.paused = paused;
+                playback.updated = now;
+            })
+            .or_insert_with(|| {
+                started_play = true;
+                Playback::new(track, position_ms, paused, now)
+            });
+
+        let reached_threshold = !playback.counted
+            && count_threshold_seconds(track.duration_seconds)
+                .is_some_and(|threshold| playback.listened_ms >= threshold * 1000);
+        playback.counted |= reached_threshold;
+
+        PlaybackUpdate {
+            started: playback.started,
+            listened_ms: playback.listened_ms,
+            started_play,
+            reached_threshold,
+            counted: playback.counted,
+        }
+    }
+
+    /// Listeners heard from recently, most recent first
+    pub fn now_playing(&self) -> Vec<NowPlayingEntry> {
+        let now = Utc::now();
+        let mut entries: Vec<NowPlayingEntry> = self.playing.lock().unwrap()
+            .iter()
+            .filter(|(_, playback)| now.signed_duration_since(playback.updated).num_seconds() < NOW_PLAYING_TIMEOUT_SECONDS)
+            .map(|((user, client), playback)| NowPlayingEntry {
+                user: user.clone(),
+                client: client.clone(),
+                track_id: playback.track_id,
+                title: playback.title.clone(),
+                artist: playback.artist.clone(),
+                position_ms: playback.position_ms,
+                paused: playback.paused,
+                started: playback.started,
+                updated: playback.updated,
+            })
+            .collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.updated));
+        entries
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ProgressRequest {
+    pub user: String,
+    pub client: String,
+    pub position_ms: i64,
+    #[serde(default)]
+    pub paused: bool,
+    /// Last.fm session key; when set, now playing and scrobbles are sent to Last.fm
+    pub session_key: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ProgressResponse {
+    pub track_id: i32,
+    pub position_ms: i64,
+    pub listened_seconds: i64,
+    /// Listening needed before the play counts, null for tracks too short to count
+    pub count_threshold_seconds: Option<i64>,
+    /// Whether this play has been recorded (and scrobbled when a session key was given)
+    pub counted: bool,
+}
+
+// Last.fm calls run in the background so a slow Last.fm doesn't hold up heartbeats
+fn spawn_lastfm_update(session_key: String, track: track::Model, scrobble_at: Option<DateTime<Utc>>) {
+    tokio::spawn(async move {
+        let client = match LastfmClient::new() {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Failed to create Last.fm client: {}", e);
+                return;
+            }
+        };
+        let result = match scrobble_at {
+            Some(started) => client.scrobble_track(&session_key, &track, started.timestamp(), None).await.map(|_| ()),
+            None => client.update_now_playing(&session_key, &track).await,
+        };
+        if let Err(e) = result {
+            error!("Failed to update Last.fm for track {}: {}", track.id, e);
+        }
+    });
+}
+
+// POST /tracks/:id/progress - Report the playback position of a track. Clients
+// send this every few seconds while playing; it drives now playing, resume
+// positions and when a play is recorded and scrobbled.
+#[utoipa::path(
+    post,
+    path = "/tracks/{id}/progress",
+    tag = "listening",
+    params(("id" = i32, Path, description = "Track ID")),
+    request_body = ProgressRequest,
+    responses(
+        (status = 200, description = "Progress recorded", body = ProgressResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn update_progress(
+    State(state): State<AppState>,
+    ApiPath(track_id): ApiPath<i32>,
+    ApiJson(request): ApiJson<ProgressRequest>,
+) -> ApiResult<Json<ProgressResponse>> {
+    if request.user.trim().is_empty() {
+        return Err(ApiError::invalid_field("user", "must not be empty"));
+    }
+    if request.client.trim().is_empty() {
+        return Err(ApiError::invalid_field("client", "must not be empty"));
+    }
+    if request.position_ms < 0 {
+        return Err(ApiError::invalid_field("position_ms", "must not be negative"));
+    }
+
+    let track = Track::find_by_id(track_id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", track_id)))?;
+
+    let update = state.playback.heartbeat(&request.user, &request.client, &track, request.position_ms, request.paused);
+
+    if update.reached_threshold {
+        let seconds_played = (update.listened_ms / 1000) as i32;
+        state.writes.push(Write::Play {
+            user: request.user.clone(),
+            client: Some(request.client.clone()),
+            track_id: track.id,
+            played_at: update.started,
+            seconds_played,
+        }).await;
+        info!("Counted play of track {} for {}", track.id, request.user);
+    }
+    if update.started_play && !request.paused {
+        state.webhooks.send(Event::NowPlaying {
+            user: request.user.clone(),
+            client: request.client.clone(),
+            track_id: track.id,
+            title: track.title.clone(),
+            artist: track.artist.clone(),
+            album: track.album.clone(),
+        });
+    }
+    if let Some(session_key) = request.session_key.filter(|key| !key.is_empty()) {
+        if update.reached_threshold {
+            spawn_lastfm_update(session_key, track.clone(), Some(update.started));
+        } else if update.started_play && !request.paused {
+            spawn_lastfm_update(session_key, track.clone(), None);
+        }
+    }
+
+    // Finished tracks start from the beginning next time
+    let finished = track.duration_seconds > 0
+        && request.position_ms >= track.duration_seconds as i64 * 1000 - FINISHED_MARGIN_MS;
+    let position = if finished {
+        Write::ClearPosition { user: request.user.clone(), track_id: track.id }
+    } else {
+        Write::Position {
+            user: request.user.clone(),
+            client: Some(request.client.clone()),
+            track_id: track.id,
+            position_ms: request.position_ms,
+            updated: Utc::now(),
+        }
+    };
+    state.writes.push(position).await;
+
+    Ok(Json(ProgressResponse {
+        track_id: track.id,
+        position_ms: request.position_ms,
+        listened_seconds: update.listened_ms / 1000,
+        count_threshold_seconds: count_threshold_seconds(track.duration_seconds),
+        counted: update.counted,
+    }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ProgressQuery {
+    pub user: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PlaybackPositionResponse {
+    pub track_id: i32,
+    pub position_ms: i64,
+    pub client: Option<String>,
+    pub updated: DateTime<Utc>,
+}
+
+// GET /tracks/:id/progress - Where a user left off in a track
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/progress",
+    tag = "listening",
+    params(("id" = i32, Path, description = "Track ID"), ProgressQuery),
+    responses(
+        (status = 200, description = "Resume position", body = PlaybackPositionResponse),
+        (status = 404, description = "No resume position for this track", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_progress(
+    State(state): State<AppState>,
+    ApiPath(track_id): ApiPath<i32>,
+    ApiQuery(params): ApiQuery<ProgressQuery>,
+) -> ApiResult<Json<PlaybackPositionResponse>> {
+    let position = PlaybackPosition::find()
+        .filter(playback_position::Column::User.eq(params.user.as_str()))
+        .filter(playback_position::Column::TrackId.eq(track_id))
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("No resume position for track {}", track_id)))?;
+
+    Ok(Json(PlaybackPositionResponse {
+        track_id: position.track_id,
+        position_ms: position.position_ms,
+        client: position.client,
+        updated: position.updated,
+    }))
+}
+
+// GET /admin/now-playing - What each listener is playing, from progress heartbeats
+#[utoipa::path(
+    get,
+    path = "/admin/now-playing",
+    tag = "listening",
+    responses(
+        (status = 200, description = "Listeners heard from in the last 90 seconds", body = [NowPlayingEntry]),
+    )
+)]
+pub async fn get_now_playing(State(state): State<AppState>) -> Json<Vec<NowPlayingEntry>> {
+    Json(state.playback.now_playing())
+}
@@ -0,0 +1,174 @@
+// Playlist cover images: a 2x2 mosaic of the art of the first albums in a
+// playlist, rendered with ffmpeg and kept on disk. A cover is keyed by the art
+// files it is made of, so changes to the playlist that bring other albums to
+// the front render a new one, and the old one is removed.
+
+use std::collections::HashSet;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use axum::{
+    body::Body,
+    extract::State,
+    response::Response,
+};
+use log::warn;
+use sea_orm::EntityTrait;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use utoipa::IntoParams;
+
+use entity::prelude::Playlist;
+
+use crate::albums::{self, AlbumMode};
+use crate::api::{self, AppState};
+use crate::error::{ApiError, ApiPath, ApiQuery, ApiResult};
+use crate::hidden::{Visibility, VisibilityQuery};
+use crate::playlists;
+use crate::transcoder;
+
+// Pixels per side of each of the four tiles
+const TILE_SIZE: u32 = 300;
+const CONTENT_TYPE: &str = "image/jpeg";
+// Covers change with the playlist, so clients check back within the hour
+const MAX_AGE_SECONDS: u32 = 3600;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PlaylistCoverQuery {
+    /// Return generated placeholder art when no track has album art, instead of 404 Not Found (default: true)
+    pub placeholder: Option<bool>,
+}
+
+pub struct PlaylistCovers {
+    dir: PathBuf,
+    // One ffmpeg at a time, so requests for the same cover don't both write it
+    generating: Mutex<()>,
+}
+
+impl PlaylistCovers {
+    /// Read PLAYLIST_COVER_CACHE_DIR
+    pub fn from_env() -> Self {
+        let dir = env::var("PLAYLIST_COVER_CACHE_DIR")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| dirs::cache_dir().map(|dir| dir.join("ongaku-server").join("playlist-covers")))
+            .unwrap_or_else(|| env::temp_dir().join("ongaku-server-playlist-covers"));
+
+        Self { dir, generating: Mutex::new(()) }
+    }
+
+    fn cover_path(&self, playlist_id: i32, tiles: &[&Path; 4]) -> PathBuf {
+        let key: Vec<String> = tiles.iter().map(|tile| tile.to_string_lossy().into_owned()).collect();
+        self.dir.join(format!("{}-{:x}.jpg", playlist_id, md5::compute(key.join("\n").as_bytes())))
+    }
+
+    /// A playlist's mosaic of two to four album art files, rendered now if it
+    /// isn't cached yet. Fewer than four are repeated to fill the tiles.
+    pub async fn mosaic(&self, playlist_id: i32, art: &[PathBuf]) -> io::Result<Vec<u8>> {
+        let tiles: [&Path; 4] = match art {
+            [a, b] => [a, b, b, a],
+            [a, b, c] => [a, b, c, a],
+            [a, b, c, d, ..] => [a, b, c, d],
+            _ => return Err(io::Error::other("a mosaic needs at least two images")),
+        }
+        .map(PathBuf::as_path);
+        let path = self.cover_path(playlist_id, &tiles);
+        if let Ok(cover) = tokio::fs::read(&path).await {
+            return Ok(cover);
+        }
+
+        let _guard = self.generating.lock().await;
+        if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            transcoder::image_mosaic(&tiles, TILE_SIZE, &path).await?;
+            self.remove_stale(playlist_id, &path).await;
+        }
+        tokio::fs::read(&path).await
+    }
+
+    // Remove a playlist's earlier covers once a new one is in place
+    async fn remove_stale(&self, playlist_id: i32, current: &Path) {
+        let Ok(mut entries) = tokio::fs::read_dir(&self.dir).await else {
+            return;
+        };
+        let prefix = format!("{}-", playlist_id);
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let stale = path != current
+                && entry.file_name().to_str().is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".jpg"));
+            if stale {
+                if let Err(e) = tokio::fs::remove_file(&path).await {
+                    warn!("Failed to remove old playlist cover {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+// The album art of the first four albums among `tracks`, in playlist order
+fn first_album_art(state: &AppState, tracks: &[entity::track::Model]) -> Vec<(PathBuf, Option<String>)> {
+    let mut seen_albums = HashSet::new();
+    let mut seen_files = HashSet::new();
+    let mut art = Vec::new();
+    for track in tracks {
+        let album = match state.album_mode {
+            AlbumMode::Tags => albums::album_key(&track.album_artist, &track.album),
+            AlbumMode::Folder => albums::folder_album_key(&track.path, track.library_root.as_deref()).to_string(),
+        };
+        if !seen_albums.insert(album) {
+            continue;
+        }
+        if let Some((file_path, mime_type)) = api::album_art_file(state, track, None) {
+            if seen_files.insert(file_path.clone()) {
+                art.push((file_path, mime_type));
+            }
+        }
+        if art.len() == 4 {
+            break;
+        }
+    }
+    art
+}
+
+// GET /playlists/:id/cover - Get a playlist's cover, a mosaic of the art of its first four albums
+#[utoipa::path(
+    get,
+    path = "/playlists/{id}/cover",
+    tag = "playlists",
+    params(("id" = i32, Path, description = "Playlist ID"), PlaylistCoverQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Cover image: a JPEG mosaic, the album art of a playlist with one album, or placeholder art (image/svg+xml) when no track has art", content_type = "image/*"),
+        (status = 404, description = "Playlist or cover not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_playlist_cover(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    ApiQuery(params): ApiQuery<PlaylistCoverQuery>,
+    visibility: Visibility,
+) -> ApiResult<Response<Body>> {
+    let model = Playlist::find_by_id(id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Playlist {} not found", id)))?;
+    let tracks = playlists::playlist_tracks(&state, &visibility, &playlists::track_ids_of(&model)).await?;
+
+    let mut art = first_album_art(&state, &tracks);
+    match art.len() {
+        0 if params.placeholder.unwrap_or(true) => api::placeholder_response(&model.name),
+        0 => Err(ApiError::not_found(format!("Playlist {} has no album art", id))),
+        1 => {
+            let (file_path, mime_type) = art.remove(0);
+            api::album_art_response(file_path, mime_type).await
+        }
+        _ => {
+            let files: Vec<PathBuf> = art.into_iter().map(|(file_path, _)| file_path).collect();
+            let cover = state.playlist_covers.mosaic(id, &files).await
+                .map_err(|e| ApiError::internal(format!("Failed to render cover of playlist {}: {}", id, e)))?;
+            api::image_response(cover, CONTENT_TYPE, MAX_AGE_SECONDS)
+        }
+    }
+}
@@ -0,0 +1,625 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, NotSet, QueryFilter, QueryOrder, QuerySelect, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use entity::prelude::{Playlist, Track};
+use entity::{playlist, track};
+
+use crate::api::{AppState, TrackResponse};
+use crate::error::{ApiError, ApiJson, ApiPath, ApiQuery, ApiResult};
+use crate::hidden::{Visibility, VisibilityQuery};
+
+const M3U8_CONTENT_TYPE: &str = "audio/x-mpegurl; charset=utf-8";
+const XSPF_CONTENT_TYPE: &str = "application/xspf+xml";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    pub format: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PlaylistSummary {
+    pub id: i32,
+    pub name: String,
+    pub comment: String,
+    pub public: bool,
+    pub track_count: usize,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub changed: chrono::DateTime<chrono::Utc>,
+    /// Last.fm user a recommended playlist is built for; refresh it with /playlists/:id/refresh
+    pub lastfm_user: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PlaylistResponse {
+    pub id: i32,
+    pub name: String,
+    pub comment: String,
+    pub public: bool,
+    pub track_count: usize,
+    pub duration_seconds: i64,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub changed: chrono::DateTime<chrono::Utc>,
+    /// Last.fm user a recommended playlist is built for
+    pub lastfm_user: Option<String>,
+    /// When a recommended playlist was last rebuilt
+    pub refreshed: Option<chrono::DateTime<chrono::Utc>>,
+    pub tracks: Vec<TrackResponse>,
+}
+
+/// Changes to a playlist, applied together or not at all
+#[derive(Deserialize, ToSchema)]
+pub struct UpdatePlaylistRequest {
+    pub name: Option<String>,
+    pub comment: Option<String>,
+    pub public: Option<bool>,
+    /// Positions of tracks to remove, counted from 0 in the playlist as it
+    /// was before this update
+    #[serde(default)]
+    pub remove_indexes: Vec<usize>,
+    /// Tracks to append, in order, after the removals
+    #[serde(default)]
+    pub add_track_ids: Vec<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportResponse {
+    pub playlist: PlaylistResponse,
+    pub unresolved: Vec<String>,
+}
+
+/// Playlist file formats available for export
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    Json,
+    M3u8,
+    Xspf,
+}
+
+impl ExportFormat {
+    fn from_param(format: &str) -> Option<Self> {
+        match format.to_lowercase().as_str() {
+            "json" => Some(ExportFormat::Json),
+            "m3u" | "m3u8" => Some(ExportFormat::M3u8),
+            "xspf" => Some(ExportFormat::Xspf),
+            _ => None,
+        }
+    }
+
+    fn from_accept(headers: &HeaderMap) -> Self {
+        let accept = headers.get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_lowercase();
+
+        if accept.contains("mpegurl") {
+            ExportFormat::M3u8
+        } else if accept.contains("xspf") {
+            ExportFormat::Xspf
+        } else {
+            ExportFormat::Json
+        }
+    }
+}
+
+/// One entry from an M3U/M3U8 file
+struct M3uEntry {
+    location: String,
+    // Artist and title from a preceding #EXTINF line, used when the path can't be matched
+    artist_title: Option<(String, String)>,
+}
+
+/// Parse an M3U/M3U8 playlist into its entries and optional #PLAYLIST name
+fn parse_m3u(content: &str) -> (Option<String>, Vec<M3uEntry>) {
+    let mut name = None;
+    let mut entries = Vec::new();
+    let mut pending_info: Option<(String, String)> = None;
+
+    for line in content.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:<duration>,<artist> - <title>
+            pending_info = info.split_once(',')
+                .and_then(|(_, display)| display.split_once(" - "))
+                .map(|(artist, title)| (artist.trim().to_string(), title.trim().to_string()));
+        } else if let Some(playlist_name) = line.strip_prefix("#PLAYLIST:") {
+            name = Some(playlist_name.trim().to_string());
+        } else if !line.starts_with('#') {
+            entries.push(M3uEntry {
+                location: line.strip_prefix("file://").unwrap_or(line).replace('\\', "/"),
+                artist_title: pending_info.take(),
+            });
+        }
+    }
+
+    (name, entries)
+}
+
+/// Resolve a playlist entry to a track, trying the exact path, the path
+/// relative to the music folder, a trailing path match and finally the
+/// artist and title from #EXTINF
+async fn resolve_entry(state: &AppState, entry: &M3uEntry) -> ApiResult<Option<i32>> {
+    let relative = entry.location.trim_start_matches("./");
+    let candidates = [
+        entry.location.clone(),
+        format!("{}/{}", state.music_path.trim_end_matches('/'), relative),
+    ];
+
+    let exact = Track::find()
+        .filter(track::Column::Path.is_in(candidates))
+        .one(&state.db)
+        .await?;
+    if let Some(track) = exact {
+        return Ok(Some(track.id));
+    }
+
+    // Playlists exported elsewhere often use a different library root, so
+    // match on the trailing components instead
+    let suffix = relative.trim_start_matches("../").trim_start_matches('/');
+    if !suffix.is_empty() {
+        let by_suffix = Track::find()
+            .filter(track::Column::Path.ends_with(format!("/{}", suffix)))
+            .order_by_asc(track::Column::Path)
+            .one(&state.db)
+            .await?;
+        if let Some(track) = by_suffix {
+            return Ok(Some(track.id));
+        }
+    }
+
+    if let Some((artist, title)) = &entry.artist_title {
+        let by_metadata = Track::find()
+            .filter(track::Column::Artist.eq(artist.as_str()))
+            .filter(track::Column::Title.eq(title.as_str()))
+            .order_by_asc(track::Column::Path)
+            .one(&state.db)
+            .await?;
+        if let Some(track) = by_metadata {
+            return Ok(Some(track.id));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Load a playlist's tracks in playlist order. Tracks removed from the
+/// library since the playlist was saved, or hidden from `visibility`, are skipped.
+pub async fn playlist_tracks(state: &AppState, visibility: &Visibility, track_ids: &[i32]) -> ApiResult<Vec<track::Model>> {
+    let tracks_by_id: HashMap<i32, track::Model> = Track::find()
+        .filter(track::Column::Id.is_in(track_ids.to_vec()))
+        .filter(visibility.condition())
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|track| (track.id, track))
+        .collect();
+
+    Ok(track_ids.iter()
+        .filter_map(|id| tracks_by_id.get(id).cloned())
+        .collect())
+}
+
+pub fn playlist_response(model: playlist::Model, tracks: Vec<track::Model>) -> PlaylistResponse {
+    PlaylistResponse {
+        id: model.id,
+        name: model.name,
+        comment: model.comment,
+        public: model.public,
+        track_count: tracks.len(),
+        duration_seconds: tracks.iter().map(|track| track.duration_seconds as i64).sum(),
+        created: model.created,
+        changed: model.changed,
+        lastfm_user: model.lastfm_user,
+        refreshed: model.refreshed,
+        tracks: tracks.into_iter().map(TrackResponse::from).collect(),
+    }
+}
+
+pub fn track_ids_of(model: &playlist::Model) -> Vec<i32> {
+    serde_json::from_value(model.track_ids.clone()).unwrap_or_default()
+}
+
+fn to_m3u8(name: &str, tracks: &[track::Model]) -> String {
+    let mut output = format!("#EXTM3U\n#PLAYLIST:{}\n", name);
+    for track in tracks {
+        output.push_str(&format!("#EXTINF:{},{} - {}\n{}\n", track.duration_seconds, track.artist, track.title, track.path));
+    }
+    output
+}
+
+// An XSPF document. Serialized by quick-xml, which escapes every value.
+#[derive(Serialize)]
+#[serde(rename = "playlist")]
+struct Xspf<'a> {
+    #[serde(rename = "@version")]
+    version: u8,
+    #[serde(rename = "@xmlns")]
+    xmlns: &'static str,
+    title: &'a str,
+    #[serde(rename = "trackList")]
+    track_list: XspfTrackList<'a>,
+}
+
+#[derive(Serialize)]
+struct XspfTrackList<'a> {
+    track: Vec<XspfTrack<'a>>,
+}
+
+#[derive(Serialize)]
+struct XspfTrack<'a> {
+    location: String,
+    title: &'a str,
+    creator: &'a str,
+    album: &'a str,
+    #[serde(rename = "trackNum", skip_serializing_if = "Option::is_none")]
+    track_num: Option<i32>,
+    /// Milliseconds
+    duration: i64,
+}
+
+fn to_xspf(name: &str, tracks: &[track::Model]) -> ApiResult<String> {
+    let playlist = Xspf {
+        version: 1,
+        xmlns: "http://xspf.org/ns/0/",
+        title: name,
+        track_list: XspfTrackList {
+            track: tracks.iter()
+                .map(|track| XspfTrack {
+                    location: url::Url::from_file_path(&track.path)
+                        .map(|url| url.to_string())
+                        .unwrap_or_else(|_| track.path.clone()),
+                    title: &track.title,
+                    creator: &track.artist,
+                    album: &track.album,
+                    track_num: track.track_number,
+                    duration: track.duration_seconds as i64 * 1000,
+                })
+                .collect(),
+        },
+    };
+
+    let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let mut serializer = quick_xml::se::Serializer::new(&mut output);
+    serializer.indent(' ', 2);
+    playlist.serialize(serializer)
+        .map_err(|e| ApiError::internal(format!("Failed to write XSPF: {}", e)))?;
+    output.push('\n');
+    Ok(output)
+}
+
+fn playlist_file(name: &str, extension: &str, content_type: &str, body: String) -> ApiResult<Response> {
+    // Keep the download filename to characters every client handles
+    let filename: String = name.chars()
+        .map(|c| if c.is_alphanumeric() || c == ' ' || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.{}\"", filename, extension))
+        .body(Body::from(body))?)
+}
+
+// GET /playlists - List playlists
+#[utoipa::path(
+    get,
+    path = "/playlists",
+    tag = "playlists",
+    responses(
+        (status = 200, description = "Playlists", body = Vec<PlaylistSummary>),
+    )
+)]
+pub async fn get_playlists(
+    State(state): State<AppState>,
+) -> ApiResult<Json<Vec<PlaylistSummary>>> {
+    let playlists = Playlist::find()
+        .order_by_asc(playlist::Column::Name)
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|model| PlaylistSummary {
+            track_count: track_ids_of(&model).len(),
+            id: model.id,
+            name: model.name,
+            comment: model.comment,
+            public: model.public,
+            created: model.created,
+            changed: model.changed,
+            lastfm_user: model.lastfm_user,
+        })
+        .collect();
+
+    Ok(Json(playlists))
+}
+
+// GET /playlists/:id - Get a playlist as JSON, M3U8 or XSPF (?format= or Accept header)
+#[utoipa::path(
+    get,
+    path = "/playlists/{id}",
+    tag = "playlists",
+    params(("id" = i32, Path, description = "Playlist ID"), ExportQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Playlist as JSON, M3U8 or XSPF", body = PlaylistResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Playlist not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_playlist(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    ApiQuery(params): ApiQuery<ExportQuery>,
+    visibility: Visibility,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    let format = match params.format.as_deref() {
+        Some(format) => ExportFormat::from_param(format)
+            .ok_or_else(|| ApiError::invalid_field("format", "must be one of json, m3u8 or xspf"))?,
+        None => ExportFormat::from_accept(&headers),
+    };
+
+    let model = Playlist::find_by_id(id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Playlist {} not found", id)))?;
+    let tracks = playlist_tracks(&state, &visibility, &track_ids_of(&model)).await?;
+
+    match format {
+        ExportFormat::Json => Ok(Json(playlist_response(model, tracks)).into_response()),
+        ExportFormat::M3u8 => playlist_file(&model.name, "m3u8", M3U8_CONTENT_TYPE, to_m3u8(&model.name, &tracks)),
+        ExportFormat::Xspf => playlist_file(&model.name, "xspf", XSPF_CONTENT_TYPE, to_xspf(&model.name, &tracks)?),
+    }
+}
+
+// PATCH /playlists/:id - Rename a playlist, change its comment or public flag, and remove and add tracks
+#[utoipa::path(
+    patch,
+    path = "/playlists/{id}",
+    tag = "playlists",
+    params(("id" = i32, Path, description = "Playlist ID"), VisibilityQuery),
+    request_body = UpdatePlaylistRequest,
+    responses(
+        (status = 200, description = "Updated playlist", body = PlaylistResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Playlist not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn update_playlist(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    visibility: Visibility,
+    ApiJson(request): ApiJson<UpdatePlaylistRequest>,
+) -> ApiResult<Json<PlaylistResponse>> {
+    if request.name.as_ref().is_some_and(|name| name.trim().is_empty()) {
+        return Err(ApiError::invalid_field("name", "must not be empty"));
+    }
+
+    // Lock the playlist so concurrent edits apply one after the other
+    // instead of removing by indexes the other edit has already shifted
+    let txn = state.db.begin().await?;
+    let model = Playlist::find_by_id(id)
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Playlist {} not found", id)))?;
+    let stored = track_ids_of(&model);
+
+    // Indexes count the tracks the client was shown, which leaves out
+    // tracks removed from the library or hidden from it
+    let shown: HashSet<i32> = Track::find()
+        .select_only()
+        .column(track::Column::Id)
+        .filter(track::Column::Id.is_in(stored.clone()))
+        .filter(visibility.condition())
+        .into_tuple()
+        .all(&txn)
+        .await?
+        .into_iter()
+        .collect();
+    let shown_positions: Vec<usize> = stored.iter()
+        .enumerate()
+        .filter(|(_, id)| shown.contains(id))
+        .map(|(position, _)| position)
+        .collect();
+    let mut removed = HashSet::new();
+    for &index in &request.remove_indexes {
+        let position = shown_positions.get(index).ok_or_else(|| ApiError::invalid_field(
+            "remove_indexes",
+            format!("{} is past the end of the playlist ({} tracks)", index, shown_positions.len()),
+        ))?;
+        removed.insert(*position);
+    }
+
+    if !request.add_track_ids.is_empty() {
+        let found: HashSet<i32> = Track::find()
+            .select_only()
+            .column(track::Column::Id)
+            .filter(track::Column::Id.is_in(request.add_track_ids.clone()))
+            .filter(visibility.condition())
+            .into_tuple()
+            .all(&txn)
+            .await?
+            .into_iter()
+            .collect();
+        if let Some(unknown) = request.add_track_ids.iter().find(|id| !found.contains(id)) {
+            return Err(ApiError::invalid_field("add_track_ids", format!("track {} not found", unknown)));
+        }
+    }
+
+    let track_ids: Vec<i32> = stored.into_iter()
+        .enumerate()
+        .filter(|(position, _)| !removed.contains(position))
+        .map(|(_, id)| id)
+        .chain(request.add_track_ids)
+        .collect();
+
+    let mut active: playlist::ActiveModel = model.into();
+    if let Some(name) = request.name {
+        active.name = Set(name.trim().to_string());
+    }
+    if let Some(comment) = request.comment {
+        active.comment = Set(comment);
+    }
+    if let Some(public) = request.public {
+        active.public = Set(public);
+    }
+    active.track_ids = Set(serde_json::json!(track_ids));
+    active.changed = Set(chrono::Utc::now());
+    let model = active.update(&txn).await?;
+    txn.commit().await?;
+
+    let tracks = playlist_tracks(&state, &visibility, &track_ids).await?;
+    Ok(Json(playlist_response(model, tracks)))
+}
+
+// POST /playlists/import - Create a playlist from an M3U/M3U8 request body
+#[utoipa::path(
+    post,
+    path = "/playlists/import",
+    tag = "playlists",
+    params(ImportQuery, VisibilityQuery),
+    request_body(content = String, content_type = "audio/x-mpegurl", description = "M3U/M3U8 playlist"),
+    responses(
+        (status = 201, description = "Playlist created", body = ImportResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn import_playlist(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<ImportQuery>,
+    visibility: Visibility,
+    body: Bytes,
+) -> ApiResult<(StatusCode, Json<ImportResponse>)> {
+    let content = String::from_utf8_lossy(&body);
+    let (file_name, entries) = parse_m3u(&content);
+    if entries.is_empty() {
+        return Err(ApiError::invalid_field("body", "playlist has no entries"));
+    }
+
+    let name = params.name
+        .or(file_name)
+        .filter(|name| !name.trim().is_empty())
+        .ok_or_else(|| ApiError::invalid_field("name", "required when the playlist has no #PLAYLIST line"))?;
+
+    let mut track_ids = Vec::with_capacity(entries.len());
+    let mut unresolved = Vec::new();
+    for entry in &entries {
+        match resolve_entry(&state, entry).await? {
+            Some(id) => track_ids.push(id),
+            None => unresolved.push(entry.location.clone()),
+        }
+    }
+
+    let now = chrono::Utc::now();
+    let model = Playlist::insert(playlist::ActiveModel {
+        id: NotSet,
+        name: Set(name),
+        comment: Set(String::new()),
+        track_ids: Set(serde_json::json!(track_ids)),
+        public: Set(false),
+        created: Set(now),
+        changed: Set(now),
+        lastfm_user: Set(None),
+        mix_size: Set(None),
+        refreshed: Set(None),
+    })
+    .exec_with_returning(&state.db)
+    .await?;
+
+    let tracks = playlist_tracks(&state, &visibility, &track_ids).await?;
+
+    Ok((StatusCode::CREATED, Json(ImportResponse {
+        playlist: playlist_response(model, tracks),
+        unresolved,
+    })))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde::Deserialize;
+
+    use entity::track;
+
+    use super::to_xspf;
+
+    #[derive(Deserialize)]
+    struct Playlist {
+        title: String,
+        #[serde(rename = "trackList")]
+        track_list: TrackList,
+    }
+
+    #[derive(Deserialize)]
+    struct TrackList {
+        track: Vec<Track>,
+    }
+
+    #[derive(Deserialize)]
+    struct Track {
+        location: String,
+        title: String,
+        creator: String,
+        album: String,
+        #[serde(rename = "trackNum")]
+        track_num: Option<i32>,
+        duration: i64,
+    }
+
+    fn track(path: &str, title: &str, artist: &str, album: &str, track_number: Option<i32>) -> track::Model {
+        serde_json::from_value(serde_json::json!({
+            "id": 1, "path": path, "extension": "flac", "title": title, "artist": artist, "album": album,
+            "track_number": track_number, "genre": "", "album_artist": artist, "album_artist_tag": artist,
+            "publisher": "", "catalog_number": "", "duration_seconds": 215, "audio_bitrate": 0,
+            "overall_bitrate": 0, "sample_rate": 44100, "bit_depth": 16, "channels": 2, "tags": {},
+            "images": [], "is_hidden": false, "media_type": "music", "chapters": [], "audio_info": {},
+            "added": "2026-10-17T00:00:00Z", "created": "2026-10-17T00:00:00Z", "modified": "2026-10-17T00:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trips_xspf() {
+        let tracks = vec![
+            track("/music/Simon & Garfunkel/<1> \"Live\".flac", "Mrs. Robinson <Live> & \"More\"", "Simon & Garfunkel", "A <B> & 'C'", Some(3)),
+            track("/music/Sigur Rós/Ágætis byrjun/01 Intro.flac", "Intro 日本語", "Sigur Rós", "Ágætis byrjun", None),
+        ];
+        let output = to_xspf("Mix & <Match> \"2026\" ☕", &tracks).unwrap();
+        let playlist: Playlist = quick_xml::de::from_str(&output).unwrap();
+
+        assert_eq!(playlist.title, "Mix & <Match> \"2026\" ☕");
+        assert_eq!(playlist.track_list.track.len(), tracks.len());
+        for (parsed, track) in playlist.track_list.track.iter().zip(&tracks) {
+            let location = url::Url::parse(&parsed.location).unwrap().to_file_path().unwrap();
+            assert_eq!(location, PathBuf::from(&track.path));
+            assert_eq!(parsed.title, track.title);
+            assert_eq!(parsed.creator, track.artist);
+            assert_eq!(parsed.album, track.album);
+            assert_eq!(parsed.track_num, track.track_number);
+            assert_eq!(parsed.duration, 215_000);
+        }
+        // Tracks without a number leave trackNum out rather than writing an empty one
+        assert_eq!(output.matches("<trackNum>").count(), 1);
+    }
+}
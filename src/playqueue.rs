@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::Json,
+};
+use log::error;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, EntityTrait, NotSet, QueryFilter, QueryOrder};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use entity::prelude::{PlayQueue, Track};
+use entity::{play_queue, track};
+
+use crate::api::{AppState, TrackResponse};
+use crate::error::{ApiError, ApiJson, ApiQuery, ApiResult};
+use crate::hidden::{Visibility, VisibilityQuery};
+
+#[derive(Deserialize, ToSchema)]
+pub struct SavePlayQueueRequest {
+    pub user: String,
+    pub client: String,
+    pub track_ids: Vec<i32>,
+    pub current_track_id: Option<i32>,
+    #[serde(default)]
+    pub position_ms: i64,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PlayQueueQuery {
+    pub user: String,
+    pub client: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PlayQueueResponse {
+    pub user: String,
+    pub client: String,
+    pub current_track_id: Option<i32>,
+    pub position_ms: i64,
+    pub changed: chrono::DateTime<chrono::Utc>,
+    pub tracks: Vec<TrackResponse>,
+}
+
+// POST /playqueue - Save the current play queue for a user and client
+#[utoipa::path(
+    post,
+    path = "/playqueue",
+    tag = "playqueue",
+    request_body = SavePlayQueueRequest,
+    responses(
+        (status = 204, description = "Play queue saved"),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn save_play_queue(
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<SavePlayQueueRequest>,
+) -> ApiResult<StatusCode> {
+    if request.user.trim().is_empty() {
+        return Err(ApiError::invalid_field("user", "must not be empty"));
+    }
+    if request.client.trim().is_empty() {
+        return Err(ApiError::invalid_field("client", "must not be empty"));
+    }
+    if request.position_ms < 0 {
+        return Err(ApiError::invalid_field("position_ms", "must not be negative"));
+    }
+    if let Some(current) = request.current_track_id {
+        if !request.track_ids.contains(&current) {
+            return Err(ApiError::invalid_field("current_track_id", "must be one of track_ids"));
+        }
+    }
+
+    let queue = play_queue::ActiveModel {
+        id: NotSet,
+        user: Set(request.user),
+        client: Set(request.client),
+        track_ids: Set(serde_json::json!(request.track_ids)),
+        current_track_id: Set(request.current_track_id),
+        position_ms: Set(request.position_ms),
+        changed: Set(chrono::Utc::now()),
+    };
+
+    let on_conflict = sea_query::OnConflict::columns([play_queue::Column::User, play_queue::Column::Client])
+        .update_columns([
+            play_queue::Column::TrackIds,
+            play_queue::Column::CurrentTrackId,
+            play_queue::Column::PositionMs,
+            play_queue::Column::Changed,
+        ])
+        .to_owned();
+
+    PlayQueue::insert(queue)
+        .on_conflict(on_conflict)
+        .exec(&state.db)
+        .await
+        .map_err(|e| {
+            error!("Failed to save play queue: {}", e);
+            ApiError::internal("Failed to save play queue")
+        })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// GET /playqueue - Get the most recently saved play queue for a user,
+// optionally limited to one client
+#[utoipa::path(
+    get,
+    path = "/playqueue",
+    tag = "playqueue",
+    params(PlayQueueQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Most recently saved play queue", body = PlayQueueResponse),
+        (status = 404, description = "Play queue not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_play_queue(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<PlayQueueQuery>,
+    visibility: Visibility,
+) -> ApiResult<Json<PlayQueueResponse>> {
+    let mut query = PlayQueue::find().filter(play_queue::Column::User.eq(params.user));
+    if let Some(client) = params.client {
+        query = query.filter(play_queue::Column::Client.eq(client));
+    }
+
+    let queue = query
+        .order_by_desc(play_queue::Column::Changed)
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found("No saved play queue"))?;
+
+    let track_ids: Vec<i32> = serde_json::from_value(queue.track_ids).unwrap_or_default();
+
+    // Resolve all queued tracks in one query, then restore queue order.
+    // Tracks removed from the library or hidden since the queue was saved are skipped.
+    let tracks_by_id: HashMap<i32, track::Model> = Track::find()
+        .filter(track::Column::Id.is_in(track_ids.clone()))
+        .filter(visibility.condition())
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(|track| (track.id, track))
+        .collect();
+
+    let tracks = track_ids.iter()
+        .filter_map(|id| tracks_by_id.get(id).cloned())
+        .map(TrackResponse::from)
+        .collect();
+
+    Ok(Json(PlayQueueResponse {
+        user: queue.user,
+        client: queue.client,
+        current_track_id: queue.current_track_id,
+        position_ms: queue.position_ms,
+        changed: queue.changed,
+        tracks,
+    }))
+}
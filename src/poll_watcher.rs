@@ -0,0 +1,164 @@
+// Polling watcher for folders where file system notifications don't arrive,
+// such as NFS and SMB mounts. Each watched folder's directory tree is walked
+// on an interval and the modification times of its directories compared with
+// the previous walk. A directory's time changes when files in it are added,
+// removed or renamed into place, which is how most tools save; the directories
+// that changed get a folder scan, which re-reads only new and changed files.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use log::{error, info, warn};
+use sea_orm::DatabaseConnection;
+
+use crate::browse::CountCache;
+use crate::hidden;
+use crate::paths;
+use crate::scheduler::{self, ScanTracker, BUSY_RETRY_INTERVAL};
+use crate::storage;
+use crate::webhooks::Webhooks;
+
+const DEFAULT_INTERVAL_SECONDS: u64 = 60;
+
+/// A folder checked for changes on an interval
+pub struct PollWatch {
+    pub folder: String,
+    pub interval: Duration,
+}
+
+/// `folder` or `folder=seconds` rules of WATCH_POLL_FOLDERS, polled every
+/// WATCH_POLL_INTERVAL_SECONDS (default 60) unless a rule gives its own
+/// interval. Relative folders are under MUSIC_PATH. Invalid rules are logged
+/// and skipped.
+pub fn poll_watches_from_env(music_path: &str) -> Vec<PollWatch> {
+    let default_seconds = env::var("WATCH_POLL_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.trim().parse().ok())
+        .filter(|&seconds: &u64| seconds > 0)
+        .unwrap_or(DEFAULT_INTERVAL_SECONDS);
+    let rules = env::var("WATCH_POLL_FOLDERS").unwrap_or_default();
+    rules.split(';')
+        .filter(|rule| !rule.trim().is_empty())
+        .filter_map(|rule| {
+            let (folder, seconds) = match rule.rsplit_once('=') {
+                Some((folder, seconds)) => match seconds.trim().parse::<u64>() {
+                    Ok(seconds) if seconds > 0 => (folder, seconds),
+                    _ => {
+                        warn!("Ignoring WATCH_POLL_FOLDERS rule {:?}: interval must be a positive number of seconds", rule);
+                        return None;
+                    }
+                },
+                None => (rule, default_seconds),
+            };
+            let folder = hidden::resolve_directory(music_path, folder)?;
+            if folder.starts_with(storage::S3_SCHEME) {
+                warn!("Ignoring WATCH_POLL_FOLDERS rule {:?}: only local and mounted folders can be polled", rule);
+                return None;
+            }
+            Some(PollWatch { folder, interval: Duration::from_secs(seconds) })
+        })
+        .collect()
+}
+
+// Modification time of every directory under `root`, `root` included.
+// Symlinked directories aren't followed.
+fn directory_times(root: &Path) -> HashMap<PathBuf, SystemTime> {
+    let mut times = HashMap::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(directory) = pending.pop() {
+        let modified = match std::fs::metadata(&directory).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                warn!("Failed to read directory {} while polling for changes: {}", directory.display(), e);
+                continue;
+            }
+        };
+        if let Ok(entries) = std::fs::read_dir(&directory) {
+            for entry in entries.flatten() {
+                if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                    pending.push(entry.path());
+                }
+            }
+        }
+        times.insert(directory, modified);
+    }
+    times
+}
+
+// Directories to scan for what changed between two walks: those modified or
+// new, and the parents of those removed, without any below another of them
+// since a folder scan covers everything below it
+fn changed_directories(before: &HashMap<PathBuf, SystemTime>, after: &HashMap<PathBuf, SystemTime>) -> Vec<PathBuf> {
+    let mut changed: HashSet<&Path> = after.iter()
+        .filter(|(directory, modified)| before.get(*directory) != Some(modified))
+        .map(|(directory, _)| directory.as_path())
+        .collect();
+    changed.extend(before.keys()
+        .filter(|directory| !after.contains_key(*directory))
+        .filter_map(|directory| directory.parent()));
+
+    let mut directories: Vec<&Path> = changed.iter()
+        .copied()
+        .filter(|directory| !directory.ancestors().skip(1).any(|ancestor| changed.contains(ancestor)))
+        .collect();
+    directories.sort();
+    directories.into_iter().map(Path::to_path_buf).collect()
+}
+
+async fn walk(folder: &str) -> HashMap<PathBuf, SystemTime> {
+    let root = paths::to_native(folder);
+    tokio::task::spawn_blocking(move || directory_times(&root))
+        .await
+        .unwrap_or_default()
+}
+
+/// Poll each watched folder on its own task, scanning the directories that
+/// changed since the previous walk. The first walk only records the folder as
+/// it is; the startup scan has read it.
+pub fn spawn_poll_watchers(
+    db: DatabaseConnection,
+    music_path: String,
+    watches: Vec<PollWatch>,
+    tracker: Arc<ScanTracker>,
+    counts: Arc<CountCache>,
+    webhooks: Arc<Webhooks>,
+) {
+    for watch in watches {
+        let db = db.clone();
+        let music_path = music_path.clone();
+        let tracker = tracker.clone();
+        let counts = counts.clone();
+        let webhooks = webhooks.clone();
+        tokio::spawn(async move {
+            info!("Polling {} for changes every {} seconds", watch.folder, watch.interval.as_secs());
+            let mut times = walk(&watch.folder).await;
+            loop {
+                tokio::time::sleep(watch.interval).await;
+                let current = walk(&watch.folder).await;
+                // An unreachable mount reads as empty; keep the last walk until it is back
+                if current.is_empty() {
+                    continue;
+                }
+                for directory in changed_directories(&times, &current) {
+                    let directory = paths::normalize(&directory.to_string_lossy());
+                    loop {
+                        match scheduler::run_folder_scan(&db, &music_path, &directory, &tracker, &counts, &webhooks).await {
+                            Some(Ok(result)) => info!("Scan of changed folder {} completed: {} files read, {} tracks processed, {} errors recorded",
+                                                      directory, result.files_scanned, result.tracks_processed, result.errors_recorded),
+                            Some(Err(e)) => error!("Error during scan of changed folder {}: {}", directory, e),
+                            None => {
+                                tokio::time::sleep(BUSY_RETRY_INTERVAL).await;
+                                continue;
+                            }
+                        }
+                        break;
+                    }
+                }
+                times = current;
+            }
+        });
+    }
+}
@@ -0,0 +1,279 @@
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, StatusCode},
+    response::{Json, Response},
+};
+use log::{error, info};
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::{IntoParams, ToSchema};
+
+use entity::prelude::Track;
+use entity::track;
+
+use crate::api::{self, AppState};
+use crate::error::{ApiError, ApiPath, ApiQuery, ApiResult};
+use crate::hidden::{Visibility, VisibilityQuery};
+use crate::scheduler::ScanTracker;
+use crate::storage::Storage;
+use crate::stream_metrics::METRICS;
+use crate::transcoder::{self, TranscodeOptions};
+
+const DEFAULT_OFFSET_SECONDS: u32 = 30;
+const DEFAULT_LENGTH_SECONDS: u32 = 30;
+const BITRATE_KBPS: u32 = 128;
+const CONTENT_TYPE: &str = "audio/mpeg";
+// How often pregeneration checks whether the startup scan has finished
+const SCAN_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Short, loudness normalized MP3 clips of tracks for sharing pages and demo
+/// mode, kept on disk. A clip is keyed by the track's path and modification
+/// time and the clip settings, so a changed file or new settings make a new one.
+pub struct PreviewClips {
+    dir: PathBuf,
+    offset_seconds: u32,
+    length_seconds: u32,
+    pregenerate: bool,
+    // One ffmpeg at a time, so requests for the same clip don't both write it
+    generating: Mutex<()>,
+    batch_running: AtomicBool,
+}
+
+impl PreviewClips {
+    /// Read PREVIEW_CACHE_DIR, PREVIEW_OFFSET_SECONDS, PREVIEW_LENGTH_SECONDS
+    /// (defaulting to DEMO_PREVIEW_SECONDS) and PREVIEW_PREGENERATE
+    pub fn from_env() -> Self {
+        let seconds = |var: &str| env::var(var).ok().and_then(|s| s.trim().parse::<u32>().ok());
+        let dir = env::var("PREVIEW_CACHE_DIR")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| dirs::cache_dir().map(|dir| dir.join("ongaku-server").join("previews")))
+            .unwrap_or_else(|| env::temp_dir().join("ongaku-server-previews"));
+
+        Self {
+            dir,
+            offset_seconds: seconds("PREVIEW_OFFSET_SECONDS").unwrap_or(DEFAULT_OFFSET_SECONDS),
+            length_seconds: seconds("PREVIEW_LENGTH_SECONDS")
+                .or_else(|| seconds("DEMO_PREVIEW_SECONDS"))
+                .filter(|length| *length > 0)
+                .unwrap_or(DEFAULT_LENGTH_SECONDS),
+            pregenerate: env::var("PREVIEW_PREGENERATE").map(|s| s == "true" || s == "1").unwrap_or(false),
+            generating: Mutex::new(()),
+            batch_running: AtomicBool::new(false),
+        }
+    }
+
+    pub fn length_seconds(&self) -> u32 {
+        self.length_seconds
+    }
+
+    // Where in the track the clip starts. Tracks too short for the configured
+    // offset start earlier, so the clip still runs its full length if it can.
+    fn start_seconds(&self, track: &track::Model) -> u32 {
+        let duration = track.duration_seconds.max(0) as u32;
+        self.offset_seconds.min(duration.saturating_sub(self.length_seconds))
+    }
+
+    fn clip_path(&self, track: &track::Model) -> PathBuf {
+        let key = format!(
+            "{}:{}:{}:{}",
+            track.path,
+            track.modified.timestamp(),
+            self.start_seconds(track),
+            self.length_seconds,
+        );
+        self.dir.join(format!("{}-{:x}.mp3", track.id, md5::compute(key.as_bytes())))
+    }
+
+    fn options(&self, track: &track::Model) -> TranscodeOptions {
+        let mut options = TranscodeOptions::mp3(BITRATE_KBPS);
+        options.start_seconds = Some(self.start_seconds(track));
+        options.max_seconds = Some(self.length_seconds);
+        options.normalize_loudness = true;
+        options
+    }
+
+    /// Write a track's clip unless it is already cached. Returns whether it was generated.
+    async fn generate(&self, storage: &dyn Storage, track: &track::Model) -> io::Result<bool> {
+        let path = self.clip_path(track);
+        let _guard = self.generating.lock().await;
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(false);
+        }
+        tokio::fs::create_dir_all(&self.dir).await?;
+        transcoder::convert_to_file(storage, &track.path, &self.options(track), &path).await?;
+        Ok(true)
+    }
+
+    /// A track's clip, generated now if it isn't cached yet
+    pub async fn clip(&self, storage: &dyn Storage, track: &track::Model) -> io::Result<Vec<u8>> {
+        let path = self.clip_path(track);
+        match tokio::fs::read(&path).await {
+            Ok(clip) => {
+                METRICS.preview_cache_lookup(true);
+                Ok(clip)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                METRICS.preview_cache_lookup(false);
+                self.generate(storage, track).await?;
+                tokio::fs::read(&path).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // Claim the batch job, false when one is already running
+    fn start_batch(&self) -> bool {
+        !self.batch_running.swap(true, Ordering::SeqCst)
+    }
+
+    // Generate the clips tracks don't have yet, then release the batch job
+    async fn run_batch(&self, storage: &dyn Storage, tracks: Vec<track::Model>) {
+        let mut generated = 0;
+        for track in &tracks {
+            match self.generate(storage, track).await {
+                Ok(true) => generated += 1,
+                Ok(false) => {}
+                Err(e) => error!("Failed to generate a preview of {}: {}", track.path, e),
+            }
+        }
+        info!("Preview generation finished, generated {} clips for {} tracks", generated, tracks.len());
+        self.batch_running.store(false, Ordering::SeqCst);
+    }
+
+    /// With PREVIEW_PREGENERATE set, generate clips for the whole library in
+    /// the background once the startup scan has finished
+    pub fn spawn_pregeneration(self: Arc<Self>, db: DatabaseConnection, storage: Arc<dyn Storage>, scans: Arc<ScanTracker>) {
+        if !self.pregenerate {
+            return;
+        }
+        tokio::spawn(async move {
+            while scans.is_running() {
+                tokio::time::sleep(SCAN_POLL_INTERVAL).await;
+            }
+            if !self.start_batch() {
+                return;
+            }
+            match present_tracks(&db).await {
+                Ok(tracks) => {
+                    info!("Generating preview clips for {} tracks in {}", tracks.len(), self.dir.display());
+                    self.run_batch(storage.as_ref(), tracks).await;
+                }
+                Err(e) => {
+                    error!("Failed to list tracks for preview generation: {}", e);
+                    self.batch_running.store(false, Ordering::SeqCst);
+                }
+            }
+        });
+    }
+}
+
+// Tracks whose files can be read
+async fn present_tracks(db: &DatabaseConnection) -> Result<Vec<track::Model>, DbErr> {
+    Track::find()
+        .filter(track::Column::MissingSince.is_null())
+        .order_by_asc(track::Column::Path)
+        .all(db)
+        .await
+}
+
+/// A track's preview clip, generated now if it isn't cached yet
+pub async fn clip_body(state: &AppState, track: &track::Model) -> ApiResult<Bytes> {
+    let clip = state.previews.clip(state.storage.as_ref(), track)
+        .await
+        .map_err(|e| {
+            error!("Failed to generate a preview of {}: {}", track.path, e);
+            ApiError::internal("Failed to generate a preview of the track")
+        })?;
+    Ok(Bytes::from(clip))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PreviewQuery {
+    /// Signed token from /tracks/{id}/stream-url, as for /tracks/{id}/play
+    pub token: Option<String>,
+}
+
+// GET /tracks/:id/preview - A short, loudness normalized MP3 clip of the track
+#[utoipa::path(
+    get,
+    path = "/tracks/{id}/preview",
+    tag = "tracks",
+    params(("id" = i32, Path, description = "Track ID"), PreviewQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Preview clip", content_type = "audio/mpeg"),
+        (status = 401, description = "Signed stream token required", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Invalid or expired stream token", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Track not found", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_preview(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    ApiQuery(params): ApiQuery<PreviewQuery>,
+    visibility: Visibility,
+) -> ApiResult<Response> {
+    api::check_stream_token(&state, id, params.token.as_deref())?;
+    let track = Track::find_by_id(id)
+        .filter(visibility.condition())
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Track {} not found", id)))?;
+    let clip = clip_body(&state, &track).await?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, CONTENT_TYPE)
+        .header(header::CONTENT_LENGTH, clip.len().to_string())
+        .header(header::CACHE_CONTROL, "public, max-age=86400")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(Body::from(clip))?)
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PreviewBatchResponse {
+    /// Present tracks that will get a clip if they don't have one
+    pub tracks: usize,
+}
+
+// POST /admin/previews/generate - Generate preview clips for every track without one, in the background
+#[utoipa::path(
+    post,
+    path = "/admin/previews/generate",
+    tag = "library",
+    responses(
+        (status = 202, description = "Generation started", body = PreviewBatchResponse),
+        (status = 409, description = "Preview generation is already running", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn generate_previews(State(state): State<AppState>) -> ApiResult<(StatusCode, Json<PreviewBatchResponse>)> {
+    if !state.previews.start_batch() {
+        return Err(ApiError::conflict("Preview generation is already running"));
+    }
+    let pending = match present_tracks(&state.db).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            state.previews.batch_running.store(false, Ordering::SeqCst);
+            return Err(e.into());
+        }
+    };
+
+    let tracks = pending.len();
+    info!("Generating preview clips for up to {} tracks", tracks);
+    tokio::spawn(async move {
+        state.previews.run_batch(state.storage.as_ref(), pending).await;
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(PreviewBatchResponse { tracks })))
+}
@@ -0,0 +1,30 @@
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use entity::track;
+
+/// Hook for adjusting track metadata after tags are read and before the track
+/// is written to the database, e.g. custom tag normalization, inferring
+/// metadata from the path, or labeling tracks.
+///
+/// Processors run in registration order on every scanned file.
+pub trait TrackProcessor: Send + Sync {
+    fn process(&self, track: &mut track::ActiveModel, path: &Path);
+}
+
+static PROCESSORS: RwLock<Vec<Arc<dyn TrackProcessor>>> = RwLock::new(Vec::new());
+
+/// Register a processor to run on every scanned track. Call this at startup,
+/// before the first scan is started.
+pub fn register(processor: impl TrackProcessor + 'static) {
+    PROCESSORS.write().unwrap().push(Arc::new(processor));
+}
+
+/// Run all registered processors against a freshly read track
+pub fn apply(track: &mut track::ActiveModel, path: &Path) {
+    // Clone the list so processors never run while the registry lock is held
+    let processors = PROCESSORS.read().unwrap().clone();
+    for processor in processors {
+        processor.process(track, path);
+    }
+}
@@ -0,0 +1,136 @@
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{request::Parts, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use log::debug;
+
+use crate::api::AppState;
+use crate::error::ApiError;
+
+/// How the server is reached when it runs behind a reverse proxy
+#[derive(Clone, Debug, Default)]
+pub struct ProxyConfig {
+    /// Path the server is served under, e.g. "/music", or empty at the root
+    pub base_path: String,
+    /// Believe the X-Forwarded-* headers. Only safe when a proxy sets them,
+    /// since clients could otherwise claim any address.
+    pub trust_forwarded: bool,
+    /// Public URL of the server including the base path, used for absolute
+    /// URLs instead of the Host and X-Forwarded-* headers
+    pub public_url: Option<String>,
+}
+
+// "/music/" and "music" -> "/music"; "/" and "" -> ""
+fn normalize_base_path(path: &str) -> String {
+    let path = path.trim().trim_matches('/');
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", path)
+    }
+}
+
+impl ProxyConfig {
+    /// Read BASE_PATH, TRUST_PROXY_HEADERS and PUBLIC_URL
+    pub fn from_env() -> Self {
+        Self {
+            base_path: normalize_base_path(&env::var("BASE_PATH").unwrap_or_default()),
+            trust_forwarded: env::var("TRUST_PROXY_HEADERS").map(|s| s == "true" || s == "1").unwrap_or(false),
+            public_url: env::var("PUBLIC_URL")
+                .ok()
+                .map(|url| url.trim().trim_end_matches('/').to_string())
+                .filter(|url| !url.is_empty()),
+        }
+    }
+
+    /// Path the API is routed under, e.g. "/music/api/v1"
+    pub fn api_path(&self) -> String {
+        format!("{}/api/v1", self.base_path)
+    }
+}
+
+/// Where a request came from and the URL the client used to reach the
+/// server, taking a trusted reverse proxy's X-Forwarded-* headers into account
+pub struct RequestOrigin {
+    /// The client's address, or the proxy's when its headers aren't trusted
+    pub client_ip: Option<IpAddr>,
+    // "https://example.com/music", or None when the request had no Host
+    base_url: Option<String>,
+    // Path prefix in front of /api/v1 as the client sees it
+    base_path: String,
+}
+
+// First value of a header that proxies may send as a comma separated list
+fn first_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+impl RequestOrigin {
+    fn from_parts(parts: &Parts, config: &ProxyConfig) -> Self {
+        let headers = &parts.headers;
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip());
+        let forwarded = |name| first_value(headers, name).filter(|_| config.trust_forwarded);
+
+        // The leftmost X-Forwarded-For entry is the client; later ones are proxies
+        let client_ip = forwarded("x-forwarded-for")
+            .and_then(|ip| ip.parse().ok())
+            .or(peer);
+        // A proxy that strips its location before passing requests on says so in X-Forwarded-Prefix
+        let base_path = forwarded("x-forwarded-prefix")
+            .map(normalize_base_path)
+            .unwrap_or_else(|| config.base_path.clone());
+        let base_url = match &config.public_url {
+            Some(public_url) => Some(public_url.clone()),
+            None => {
+                let scheme = forwarded("x-forwarded-proto").unwrap_or("http");
+                forwarded("x-forwarded-host")
+                    .or_else(|| first_value(headers, "host"))
+                    .map(|host| format!("{}://{}{}", scheme, host, base_path))
+            }
+        };
+
+        Self { client_ip, base_url, base_path }
+    }
+
+    /// URL of an API route for handing to clients, e.g. "/tracks/1/play" ->
+    /// "https://example.com/music/api/v1/tracks/1/play". Only the path when the
+    /// request didn't say which host it was for.
+    pub fn api_url(&self, path: &str) -> String {
+        match &self.base_url {
+            Some(base_url) => format!("{}/api/v1{}", base_url, path),
+            None => format!("{}/api/v1{}", self.base_path, path),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for RequestOrigin {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_parts(parts, &state.proxy))
+    }
+}
+
+// Log each request with the client's address, as the proxy reports it when trusted
+pub async fn log_requests(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let (parts, body) = request.into_parts();
+    let origin = RequestOrigin::from_parts(&parts, &state.proxy);
+    let method = parts.method.clone();
+    let path = parts.uri.path().to_string();
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+    let client = origin.client_ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string());
+    debug!("{} {} from {}: {}", method, path, client, response.status());
+    response
+}
@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{Json, Response},
+};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use sea_orm::metric;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::api::AppState;
+use crate::error::ApiJson;
+
+const DEFAULT_SLOW_QUERY_MS: u64 = 500;
+// Slow queries kept for /admin/diagnostics
+const RECENT_SLOW_QUERIES: usize = 50;
+/// Header asking for the statements of a single request to be logged
+pub const LOG_QUERIES_HEADER: &str = "x-log-queries";
+
+tokio::task_local! {
+    // Set while handling a request that asked for its statements to be logged
+    static REQUEST_LOGGING: bool;
+}
+
+/// A statement that took longer than SLOW_QUERY_MS. Parameter values are
+/// left out, since they carry user names, paths and search terms.
+#[derive(Clone, Serialize, ToSchema)]
+pub struct RecordedQuery {
+    /// Statement with $1, $2... where its parameters go
+    pub query: String,
+    pub parameters: usize,
+    pub elapsed_ms: u64,
+    pub failed: bool,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct QueryLogSettings {
+    /// Whether every statement is logged
+    pub log_queries: bool,
+    /// Statements slower than this are logged and kept for diagnostics,
+    /// None when the slow query log is off
+    pub slow_query_ms: Option<u64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct QueryLogUpdate {
+    pub log_queries: bool,
+}
+
+/// Logging of the SQL statements the server runs: all of them while
+/// switched on, and those slower than a threshold always
+pub struct QueryLog {
+    enabled: AtomicBool,
+    slow_threshold: Option<Duration>,
+    recent: Mutex<VecDeque<RecordedQuery>>,
+}
+
+impl QueryLog {
+    /// Statements are logged from startup with SQL_LOG=true; slow ones above
+    /// SLOW_QUERY_MS (default 500, 0 turns the slow query log off)
+    pub fn from_env() -> Self {
+        let slow_query_ms = env::var("SLOW_QUERY_MS")
+            .ok()
+            .and_then(|ms| ms.trim().parse().ok())
+            .unwrap_or(DEFAULT_SLOW_QUERY_MS);
+        Self {
+            enabled: AtomicBool::new(env::var("SQL_LOG").is_ok_and(|value| value.eq_ignore_ascii_case("true") || value == "1")),
+            slow_threshold: (slow_query_ms > 0).then(|| Duration::from_millis(slow_query_ms)),
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn settings(&self) -> QueryLogSettings {
+        QueryLogSettings {
+            log_queries: self.is_enabled(),
+            slow_query_ms: self.slow_threshold.map(|threshold| threshold.as_millis() as u64),
+        }
+    }
+
+    /// Slow queries since the server started, newest first
+    pub fn recent_slow_queries(&self) -> Vec<RecordedQuery> {
+        self.recent.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    /// Called by the database connection after every statement
+    pub fn record(&self, query: &metric::Info<'_>) {
+        let elapsed_ms = query.elapsed.as_millis() as u64;
+        let sql = query.statement.sql.as_str();
+        let parameters = query.statement.values.as_ref().map_or(0, |values| values.0.len());
+
+        if self.slow_threshold.is_some_and(|threshold| query.elapsed >= threshold) {
+            warn!("Slow query ({} ms, {} parameters): {}", elapsed_ms, parameters, sql);
+            let mut recent = self.recent.lock().unwrap();
+            if recent.len() == RECENT_SLOW_QUERIES {
+                recent.pop_front();
+            }
+            recent.push_back(RecordedQuery {
+                query: sql.to_string(),
+                parameters,
+                elapsed_ms,
+                failed: query.failed,
+                at: Utc::now(),
+            });
+        } else if self.is_enabled() || REQUEST_LOGGING.try_with(|enabled| *enabled).unwrap_or(false) {
+            info!("Query ({} ms, {} parameters): {}", elapsed_ms, parameters, sql);
+        }
+    }
+}
+
+// Log the statements of requests sent with X-Log-Queries: true. Statements
+// run by tasks a handler spawns aren't covered.
+pub async fn scope_requests(request: Request, next: Next) -> Response {
+    let requested = request.headers()
+        .get(LOG_QUERIES_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("true") || value == "1");
+    if requested {
+        REQUEST_LOGGING.scope(true, next.run(request)).await
+    } else {
+        next.run(request).await
+    }
+}
+
+// GET /admin/query-log - Whether SQL statements are logged, and the slow query threshold
+#[utoipa::path(
+    get,
+    path = "/admin/query-log",
+    tag = "library",
+    responses(
+        (status = 200, description = "Query log settings", body = QueryLogSettings),
+    )
+)]
+pub async fn get_query_log(State(state): State<AppState>) -> Json<QueryLogSettings> {
+    Json(state.query_log.settings())
+}
+
+// PUT /admin/query-log - Switch logging of every SQL statement on or off
+#[utoipa::path(
+    put,
+    path = "/admin/query-log",
+    tag = "library",
+    request_body = QueryLogUpdate,
+    responses(
+        (status = 200, description = "Query log settings", body = QueryLogSettings),
+        (status = 400, description = "Invalid body", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn update_query_log(
+    State(state): State<AppState>,
+    ApiJson(update): ApiJson<QueryLogUpdate>,
+) -> Json<QueryLogSettings> {
+    state.query_log.set_enabled(update.log_queries);
+    info!("SQL statement logging switched {}", if update.log_queries { "on" } else { "off" });
+    Json(state.query_log.settings())
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use log::{debug, error, info};
+use sha2::{Digest, Sha256};
+
+use crate::storage::{FileEntry, Storage};
+use crate::stream_metrics::METRICS;
+
+// Files are cached in fixed-size chunks so seeks and range requests only
+// fetch the parts of a file that are actually played
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+#[derive(Default)]
+struct CacheIndex {
+    // Chunk file name -> (size, last use)
+    chunks: HashMap<String, (u64, u64)>,
+    total_bytes: u64,
+    clock: u64,
+}
+
+impl CacheIndex {
+    fn touch(&mut self, name: &str) -> bool {
+        self.clock += 1;
+        match self.chunks.get_mut(name) {
+            Some((_, last_used)) => {
+                *last_used = self.clock;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, name: String, size: u64) {
+        self.clock += 1;
+        if let Some((old_size, _)) = self.chunks.insert(name, (size, self.clock)) {
+            self.total_bytes -= old_size;
+        }
+        self.total_bytes += size;
+    }
+
+    /// Drop least recently used chunks until the cache fits, returning their names
+    fn evict(&mut self, max_bytes: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.total_bytes > max_bytes {
+            let Some(oldest) = self.chunks.iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(name, _)| name.clone())
+            else {
+                break;
+            };
+            if let Some((size, _)) = self.chunks.remove(&oldest) {
+                self.total_bytes -= size;
+            }
+            evicted.push(oldest);
+        }
+        evicted
+    }
+}
+
+/// Storage wrapper that keeps recently streamed file chunks on local disk,
+/// bounded to `max_bytes` with least-recently-used eviction. Chunks are keyed
+/// by path, size and modification time, so a changed file is fetched again.
+pub struct ReadCache {
+    inner: Arc<dyn Storage>,
+    dir: PathBuf,
+    max_bytes: u64,
+    index: Mutex<CacheIndex>,
+}
+
+impl ReadCache {
+    /// Create the cache, picking up chunks left in `dir` by a previous run
+    pub fn new(inner: Arc<dyn Storage>, dir: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut existing = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.ends_with(".tmp") {
+                let _ = std::fs::remove_file(entry.path());
+                continue;
+            }
+            existing.push((name, metadata.len(), metadata.modified().ok()));
+        }
+
+        // Oldest first, so the most recently written chunks count as most recently used
+        existing.sort_by_key(|(_, _, modified)| *modified);
+        let mut index = CacheIndex::default();
+        for (name, size, _) in existing {
+            index.insert(name, size);
+        }
+
+        let cache = Self {
+            inner,
+            dir,
+            max_bytes,
+            index: Mutex::new(index),
+        };
+        cache.evict();
+
+        let used = cache.index.lock().unwrap().total_bytes;
+        info!("Stream read cache at {} ({} of {} MB used)", cache.dir.display(), used / 1024 / 1024, max_bytes / 1024 / 1024);
+        Ok(cache)
+    }
+
+    fn chunk_name(entry: &FileEntry, chunk: u64) -> String {
+        let identity = format!("{}\0{}\0{}", entry.path, entry.size, entry.modified.timestamp_millis());
+        let digest = hex::encode(Sha256::digest(identity.as_bytes()));
+        format!("{}-{}", &digest[..32], chunk)
+    }
+
+    fn evict(&self) {
+        let evicted = self.index.lock().unwrap().evict(self.max_bytes);
+        for name in evicted {
+            if let Err(e) = std::fs::remove_file(self.dir.join(&name)) {
+                debug!("Failed to remove evicted cache chunk {}: {:?}", name, e);
+            }
+        }
+    }
+
+    async fn read_chunk(&self, entry: &FileEntry, chunk: u64) -> io::Result<Vec<u8>> {
+        let name = Self::chunk_name(entry, chunk);
+        let path = self.dir.join(&name);
+
+        if self.index.lock().unwrap().touch(&name) {
+            match tokio::fs::read(&path).await {
+                Ok(data) => {
+                    METRICS.stream_cache_lookup(true);
+                    return Ok(data);
+                }
+                Err(e) => debug!("Cache chunk {} unreadable, fetching again: {:?}", name, e),
+            }
+        }
+        METRICS.stream_cache_lookup(false);
+
+        let start = chunk * CHUNK_SIZE;
+        let end = (start + CHUNK_SIZE).min(entry.size) - 1;
+        let data = self.inner.read_range(&entry.path, start, end).await?;
+
+        // A failed cache write only costs a future fetch, so it never fails the read
+        if let Err(e) = write_chunk(&path, &data).await {
+            error!("Failed to write stream cache chunk {}: {:?}", path.display(), e);
+        } else {
+            self.index.lock().unwrap().insert(name, data.len() as u64);
+            self.evict();
+        }
+        Ok(data)
+    }
+}
+
+async fn write_chunk(path: &Path, data: &[u8]) -> io::Result<()> {
+    // Write then rename so a concurrent reader never sees a partial chunk
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, data).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+#[async_trait]
+impl Storage for ReadCache {
+    async fn list(&self) -> io::Result<Vec<FileEntry>> {
+        self.inner.list().await
+    }
+
+    async fn stat(&self, path: &str) -> io::Result<FileEntry> {
+        self.inner.stat(path).await
+    }
+
+    async fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let entry = self.inner.stat(path).await?;
+        if entry.size == 0 {
+            return Ok(Vec::new());
+        }
+        self.read_range(path, 0, entry.size - 1).await
+    }
+
+    async fn read_range(&self, path: &str, start: u64, end: u64) -> io::Result<Vec<u8>> {
+        let entry = self.inner.stat(path).await?;
+        if end >= entry.size || start > end {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "range is outside the file"));
+        }
+
+        let mut buffer = Vec::with_capacity((end - start + 1) as usize);
+        for chunk in start / CHUNK_SIZE..=end / CHUNK_SIZE {
+            let data = self.read_chunk(&entry, chunk).await?;
+            let chunk_start = chunk * CHUNK_SIZE;
+            let from = start.saturating_sub(chunk_start) as usize;
+            let to = ((end - chunk_start + 1) as usize).min(data.len());
+            buffer.extend_from_slice(&data[from..to]);
+        }
+        Ok(buffer)
+    }
+
+    fn local_path(&self, path: &str) -> Option<PathBuf> {
+        self.inner.local_path(path)
+    }
+
+    fn caches_reads(&self) -> bool {
+        true
+    }
+
+    fn album_art_dir(&self, path: &str) -> Option<PathBuf> {
+        self.inner.album_art_dir(path)
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::Json,
+};
+use log::{info, warn};
+use sea_orm::sea_query::{Expr, Order};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, EntityTrait, NotSet, QueryFilter, QueryOrder};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use entity::prelude::{Playlist, Track};
+use entity::{playlist, track};
+
+use crate::api::AppState;
+use crate::media_types;
+use crate::error::{ApiError, ApiJson, ApiPath, ApiResult};
+use crate::hidden::{Visibility, VisibilityQuery};
+use crate::lastfm::{self, LastfmClient};
+use crate::playlists::{self, PlaylistResponse};
+
+const DEFAULT_SIZE: usize = 50;
+const MAX_SIZE: usize = 500;
+// Top artists the mix is seeded from, and how many similar artists each brings
+const SEED_ARTISTS: usize = 10;
+const SIMILAR_PER_SEED: usize = 30;
+// Recent favourites rather than all-time ones, so the mix follows the user's taste
+const TOP_ARTISTS_PERIOD: &str = "3month";
+const TRACKS_PER_ARTIST: usize = 3;
+
+#[derive(Deserialize, ToSchema)]
+pub struct RecommendedPlaylistRequest {
+    /// Last.fm user whose listening the playlist is built from
+    pub lastfm_user: String,
+    /// Defaults to "Recommended for <lastfm_user>"
+    pub name: Option<String>,
+    /// Number of tracks (default: 50, max: 500)
+    pub size: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RecommendedPlaylistResponse {
+    pub playlist: PlaylistResponse,
+    /// Library artists the tracks came from, most recommended first
+    pub artists: Vec<String>,
+}
+
+/// Artists for a Last.fm user, most recommended first: artists similar to the
+/// ones they listen to most, ranked by how many of those they resemble and how
+/// closely, followed by their top artists themselves
+async fn recommended_artists(client: &LastfmClient, user: &str) -> Result<Vec<String>, String> {
+    let top = client.get_top_artists(user, TOP_ARTISTS_PERIOD, SEED_ARTISTS * 2).await?;
+
+    let mut scores: HashMap<String, (String, f64)> = HashMap::new();
+    for seed in top.iter().take(SEED_ARTISTS) {
+        // One artist Last.fm can't find shouldn't spoil the mix
+        let similar = match client.get_similar_artists(seed, SIMILAR_PER_SEED).await {
+            Ok(similar) => similar,
+            Err(e) => {
+                warn!("Failed to get artists similar to {} from Last.fm: {}", seed, e);
+                continue;
+            }
+        };
+        for (name, similarity) in similar {
+            scores.entry(name.to_lowercase()).or_insert_with(|| (name, 0.0)).1 += similarity;
+        }
+    }
+
+    let known: HashSet<String> = top.iter().map(|name| name.to_lowercase()).collect();
+    let mut similar: Vec<(String, f64)> = scores.into_iter()
+        .filter(|(key, _)| !known.contains(key))
+        .map(|(_, score)| score)
+        .collect();
+    similar.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    Ok(similar.into_iter().map(|(name, _)| name).chain(top).collect())
+}
+
+/// Pick up to `size` library tracks by the recommended artists, a few per
+/// artist in recommendation order, interleaved so no artist plays twice in a
+/// row while others are left. `seed` varies which of an artist's tracks are picked.
+async fn build_mix(state: &AppState, visibility: &Visibility, artists: &[String], size: usize, seed: &str) -> ApiResult<(Vec<i32>, Vec<String>)> {
+    let normalization = state.text_normalization;
+    let keys: Vec<String> = artists.iter().map(|artist| normalization.search_key(artist)).collect();
+
+    // Compared against the lowercased search column, and against the tag as
+    // written for tracks the text index hasn't reached yet
+    let tracks = Track::find()
+        .filter(
+            Condition::any()
+                .add(track::Column::SearchArtist.is_in(keys.clone()))
+                .add(Condition::all().add(track::Column::SearchArtist.is_null()).add(track::Column::Artist.is_in(artists.to_vec()))),
+        )
+        .filter(track::Column::MissingSince.is_null())
+        .filter(track::Column::MediaType.eq(media_types::MUSIC))
+        .filter(visibility.condition())
+        .order_by(Expr::cust_with_values("md5($1 || ':' || \"track\".\"id\"::text)", [seed.to_string()]), Order::Asc)
+        .all(&state.db)
+        .await?;
+
+    let mut by_artist: HashMap<String, Vec<track::Model>> = HashMap::new();
+    for track in tracks {
+        let key = track.search_artist.clone().unwrap_or_else(|| normalization.search_key(&track.artist));
+        let picks = by_artist.entry(key).or_default();
+        if picks.len() < TRACKS_PER_ARTIST {
+            picks.push(track);
+        }
+    }
+
+    // Only as many artists as the mix needs, so the most recommended come first
+    let mut seen = HashSet::new();
+    let mut groups: Vec<Vec<track::Model>> = Vec::new();
+    let mut picked = 0;
+    for key in &keys {
+        if picked >= size || !seen.insert(key) {
+            continue;
+        }
+        if let Some(picks) = by_artist.remove(key) {
+            picked += picks.len();
+            groups.push(picks);
+        }
+    }
+
+    let matched = groups.iter().map(|picks| picks[0].artist.clone()).collect();
+    let mut track_ids = Vec::with_capacity(size);
+    for round in 0..TRACKS_PER_ARTIST {
+        track_ids.extend(groups.iter().filter_map(|picks| picks.get(round)).map(|track| track.id));
+    }
+    track_ids.truncate(size);
+
+    Ok((track_ids, matched))
+}
+
+async fn recommend(state: &AppState, visibility: &Visibility, lastfm_user: &str, size: usize, seed: &str) -> ApiResult<(Vec<i32>, Vec<String>)> {
+    let client = LastfmClient::new().map_err(|e| {
+        warn!("Failed to create Last.fm client: {}", e);
+        lastfm::lastfm_unavailable()
+    })?;
+    let artists = recommended_artists(&client, lastfm_user).await.map_err(|e| {
+        warn!("Failed to get recommendations for {} from Last.fm: {}", lastfm_user, e);
+        lastfm::lastfm_error(&format!("Failed to get recommendations from Last.fm: {}", e))
+    })?;
+    build_mix(state, visibility, &artists, size, seed).await
+}
+
+// POST /playlists/recommended - Create a playlist of library tracks by artists Last.fm recommends for a user
+#[utoipa::path(
+    post,
+    path = "/playlists/recommended",
+    tag = "playlists",
+    params(VisibilityQuery),
+    request_body = RecommendedPlaylistRequest,
+    responses(
+        (status = 201, description = "Playlist created", body = RecommendedPlaylistResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 500, description = "Last.fm is unavailable", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn create_recommended_playlist(
+    State(state): State<AppState>,
+    visibility: Visibility,
+    ApiJson(request): ApiJson<RecommendedPlaylistRequest>,
+) -> ApiResult<(StatusCode, Json<RecommendedPlaylistResponse>)> {
+    let lastfm_user = request.lastfm_user.trim().to_string();
+    if lastfm_user.is_empty() {
+        return Err(ApiError::invalid_field("lastfm_user", "must not be empty"));
+    }
+    let size = request.size.unwrap_or(DEFAULT_SIZE);
+    if size == 0 || size > MAX_SIZE {
+        return Err(ApiError::invalid_field("size", format!("must be between 1 and {}", MAX_SIZE)));
+    }
+    let name = request.name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| format!("Recommended for {}", lastfm_user));
+
+    let now = chrono::Utc::now();
+    let (track_ids, artists) = recommend(&state, &visibility, &lastfm_user, size, &now.to_rfc3339()).await?;
+
+    let model = Playlist::insert(playlist::ActiveModel {
+        id: NotSet,
+        name: Set(name),
+        comment: Set(format!("Artists Last.fm recommends for {}, from the library", lastfm_user)),
+        track_ids: Set(serde_json::json!(track_ids)),
+        public: Set(false),
+        created: Set(now),
+        changed: Set(now),
+        lastfm_user: Set(Some(lastfm_user.clone())),
+        mix_size: Set(Some(size as i32)),
+        refreshed: Set(Some(now)),
+    })
+    .exec_with_returning(&state.db)
+    .await?;
+    info!("Created recommended playlist {} for {} with {} tracks", model.id, lastfm_user, track_ids.len());
+
+    let tracks = playlists::playlist_tracks(&state, &visibility, &track_ids).await?;
+    Ok((StatusCode::CREATED, Json(RecommendedPlaylistResponse {
+        playlist: playlists::playlist_response(model, tracks),
+        artists,
+    })))
+}
+
+// POST /playlists/:id/refresh - Rebuild a recommended playlist from the user's current Last.fm listening
+#[utoipa::path(
+    post,
+    path = "/playlists/{id}/refresh",
+    tag = "playlists",
+    params(("id" = i32, Path, description = "Playlist ID"), VisibilityQuery),
+    responses(
+        (status = 200, description = "Refreshed playlist", body = RecommendedPlaylistResponse),
+        (status = 404, description = "Playlist not found", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Not a recommended playlist", body = Problem, content_type = "application/problem+json"),
+        (status = 500, description = "Last.fm is unavailable", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn refresh_playlist(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+    visibility: Visibility,
+) -> ApiResult<Json<RecommendedPlaylistResponse>> {
+    let model = Playlist::find_by_id(id)
+        .one(&state.db)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Playlist {} not found", id)))?;
+    let Some(lastfm_user) = model.lastfm_user.clone() else {
+        return Err(ApiError::new(StatusCode::CONFLICT, "not_recommended", format!("Playlist {} is not a recommended playlist", id)));
+    };
+    let size = model.mix_size.map(|size| size as usize).unwrap_or(DEFAULT_SIZE);
+
+    let now = chrono::Utc::now();
+    let (track_ids, artists) = recommend(&state, &visibility, &lastfm_user, size, &now.to_rfc3339()).await?;
+
+    let mut active: playlist::ActiveModel = model.into();
+    active.track_ids = Set(serde_json::json!(track_ids));
+    active.changed = Set(now);
+    active.refreshed = Set(Some(now));
+    let model = active.update(&state.db).await?;
+    info!("Refreshed recommended playlist {} for {} with {} tracks", id, lastfm_user, track_ids.len());
+
+    let tracks = playlists::playlist_tracks(&state, &visibility, &track_ids).await?;
+    Ok(Json(RecommendedPlaylistResponse {
+        playlist: playlists::playlist_response(model, tracks),
+        artists,
+    }))
+}
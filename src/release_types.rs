@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::request::Parts,
+};
+use sea_orm::sea_query::Expr;
+use sea_orm::Condition;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::api::AppState;
+use crate::error::{ApiError, ApiQuery};
+
+pub const ALBUM: &str = "album";
+pub const EP: &str = "ep";
+pub const SINGLE: &str = "single";
+pub const LIVE: &str = "live";
+pub const COMPILATION: &str = "compilation";
+/// In order of precedence when a release is tagged with several types, e.g.
+/// MusicBrainz's "album; live": secondary types say more than primary ones
+pub const RELEASE_TYPES: &[&str] = &[COMPILATION, LIVE, SINGLE, EP, ALBUM];
+
+// Tags holding the MusicBrainz release group type, as Picard writes them to
+// Vorbis comments, APE, ID3 (TXXX) and MP4 (freeform atoms)
+const RELEASE_TYPE_TAGS: &[&str] = &[
+    "RELEASETYPE",
+    "MUSICBRAINZ_ALBUMTYPE",
+    "MusicBrainz Album Type",
+    "----:com.apple.iTunes:MusicBrainz Album Type",
+];
+// The compilation flag (TCMP, cpil, COMPILATION)
+const COMPILATION_TAG: &str = "FlagCompilation";
+
+// Releases without a type tag are singles up to 3 tracks and EPs up to 6,
+// when they run under half an hour, as the stores classify them
+const MAX_SINGLE_TRACKS: usize = 3;
+const MAX_EP_TRACKS: usize = 6;
+const MAX_SHORT_RELEASE_SECONDS: i64 = 30 * 60;
+
+/// SQL for a track's release type tags, joined with semicolons, with
+/// "compilation" for tracks flagged as part of one
+pub fn tag_values_sql() -> String {
+    let tags: Vec<String> = RELEASE_TYPE_TAGS.iter()
+        .map(|tag| format!("\"track\".\"tags\"->>'{}'", tag))
+        .collect();
+    format!(
+        "concat_ws(';', {}, CASE WHEN \"track\".\"tags\"->>'{}' = '1' THEN '{}' END)",
+        tags.join(", "),
+        COMPILATION_TAG,
+        COMPILATION,
+    )
+}
+
+/// The same as tag_values_sql, from a track's stored tags
+pub fn tag_values(tags: &serde_json::Value) -> String {
+    let mut values: Vec<&str> = RELEASE_TYPE_TAGS.iter()
+        .filter_map(|tag| tags.get(tag).and_then(|value| value.as_str()))
+        .collect();
+    if tags.get(COMPILATION_TAG).and_then(|value| value.as_str()) == Some("1") {
+        values.push(COMPILATION);
+    }
+    values.join(";")
+}
+
+/// The release type named in tag values such as "Album; Live" or
+/// "single/ep", None when they name none of the known types
+pub fn parse(values: &str) -> Option<&'static str> {
+    let named: Vec<String> = values.split([';', '/', ','])
+        .map(|value| value.trim().to_lowercase())
+        .collect();
+    RELEASE_TYPES.iter()
+        .find(|release_type| named.iter().any(|value| value == *release_type))
+        .copied()
+}
+
+/// A release's type: the one most of its tracks are tagged with, otherwise
+/// inferred from its track count and length
+pub fn classify<'a>(tag_values: impl Iterator<Item = &'a str>, track_count: usize, duration_seconds: i64) -> &'static str {
+    let mut tagged: HashMap<&'static str, usize> = HashMap::new();
+    for release_type in tag_values.filter_map(parse) {
+        *tagged.entry(release_type).or_default() += 1;
+    }
+    // Ties go to the type with precedence
+    let most_tagged = RELEASE_TYPES.iter()
+        .rev()
+        .copied()
+        .filter(|release_type| tagged.contains_key(release_type))
+        .max_by_key(|release_type| tagged[release_type]);
+    if let Some(release_type) = most_tagged {
+        return release_type;
+    }
+
+    if track_count == 0 || duration_seconds >= MAX_SHORT_RELEASE_SECONDS {
+        ALBUM
+    } else if track_count <= MAX_SINGLE_TRACKS {
+        SINGLE
+    } else if track_count <= MAX_EP_TRACKS {
+        EP
+    } else {
+        ALBUM
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReleaseTypeQuery {
+    /// Comma separated release types to include (album, ep, single, live,
+    /// compilation), e.g. "single,ep" for a singles and EPs section (default: all)
+    pub release_type: Option<String>,
+}
+
+/// Which release types an album listing includes: all of them unless the
+/// request narrows them down with release_type
+pub struct ReleaseTypes(Option<Vec<&'static str>>);
+
+impl ReleaseTypes {
+    pub fn parse(value: Option<&str>) -> Result<Self, ApiError> {
+        let Some(value) = value.map(str::trim).filter(|value| !value.is_empty() && !value.eq_ignore_ascii_case("all")) else {
+            return Ok(Self(None));
+        };
+        let types = value.split(',')
+            .map(|name| {
+                RELEASE_TYPES.iter()
+                    .find(|known| known.eq_ignore_ascii_case(name.trim()))
+                    .copied()
+                    .ok_or_else(|| ApiError::invalid_field("release_type", format!("must be \"all\" or any of {}", RELEASE_TYPES.join(", "))))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self(Some(types)))
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub fn includes(&self, release_type: &str) -> bool {
+        self.0.as_ref().is_none_or(|types| types.contains(&release_type))
+    }
+
+    /// Condition on track columns selecting tracks of tag-based albums of the
+    /// listed types, as stored in the album table
+    pub fn condition(&self) -> Condition {
+        let Some(types) = &self.0 else {
+            return Condition::all();
+        };
+        let placeholders: Vec<String> = (1..=types.len()).map(|n| format!("${}", n)).collect();
+        Condition::all().add(Expr::cust_with_values(
+            format!(
+                r#"("track"."album_artist", "track"."album") IN (SELECT "album_artist", "name" FROM "album" WHERE "release_type" IN ({}))"#,
+                placeholders.join(", "),
+            ),
+            types.iter().map(|release_type| release_type.to_string()),
+        ))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ReleaseTypes {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let ApiQuery(params) = ApiQuery::<ReleaseTypeQuery>::from_request_parts(parts, state).await?;
+        Self::parse(params.release_type.as_deref())
+    }
+}
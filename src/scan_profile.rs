@@ -0,0 +1,178 @@
+use std::env;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::info;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+// How many of the slowest directories and files to keep
+const SLOWEST_LIMIT: usize = 10;
+
+#[derive(Clone, Copy)]
+pub enum Phase {
+    /// Reading directory entries (or object listings)
+    Traversal,
+    /// Reading file size and timestamps
+    Stat,
+    /// Opening files and parsing tags and audio properties
+    TagRead,
+    /// Writing tracks to the database
+    DbUpsert,
+}
+
+impl Phase {
+    const ALL: [Phase; 4] = [Phase::Traversal, Phase::Stat, Phase::TagRead, Phase::DbUpsert];
+
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Traversal => "traversal",
+            Phase::Stat => "stat",
+            Phase::TagRead => "tag_read",
+            Phase::DbUpsert => "db_upsert",
+        }
+    }
+}
+
+#[derive(Default)]
+struct PhaseTotal {
+    elapsed: Duration,
+    items: u64,
+}
+
+#[derive(Default)]
+struct ProfileData {
+    phases: [PhaseTotal; 4],
+    slowest_directories: Vec<(String, Duration)>,
+    slowest_files: Vec<(String, Duration)>,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct PhaseSummary {
+    pub phase: &'static str,
+    pub total_ms: u64,
+    pub items: u64,
+    pub items_per_second: f64,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct SlowPath {
+    pub path: String,
+    pub ms: u64,
+}
+
+/// Per-phase timings for one scan. Stat and tag reads run concurrently, so
+/// their totals are summed across workers and items_per_second is the rate a
+/// single worker achieves.
+#[derive(Clone, Serialize, ToSchema)]
+pub struct ScanProfileSummary {
+    pub phases: Vec<PhaseSummary>,
+    pub slowest_directories: Vec<SlowPath>,
+    pub slowest_files: Vec<SlowPath>,
+}
+
+/// Records where scan time goes when SCAN_PROFILE is enabled; every method is
+/// a no-op otherwise
+#[derive(Default)]
+pub struct ScanProfiler {
+    enabled: bool,
+    data: Mutex<ProfileData>,
+}
+
+fn keep_slowest(slowest: &mut Vec<(String, Duration)>, path: &str, elapsed: Duration) {
+    if slowest.len() >= SLOWEST_LIMIT && slowest.last().is_some_and(|(_, fastest)| elapsed <= *fastest) {
+        return;
+    }
+    slowest.push((path.to_string(), elapsed));
+    slowest.sort_by_key(|(_, elapsed)| std::cmp::Reverse(*elapsed));
+    slowest.truncate(SLOWEST_LIMIT);
+}
+
+fn slow_paths(slowest: &[(String, Duration)]) -> Vec<SlowPath> {
+    slowest.iter()
+        .map(|(path, elapsed)| SlowPath { path: path.clone(), ms: elapsed.as_millis() as u64 })
+        .collect()
+}
+
+impl ScanProfiler {
+    /// Enabled with SCAN_PROFILE=true
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env::var("SCAN_PROFILE").map(|s| s == "true" || s == "1").unwrap_or(false),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn record(&self, phase: Phase, elapsed: Duration, items: u64) {
+        if !self.enabled {
+            return;
+        }
+        let mut data = self.data.lock().unwrap();
+        let total = &mut data.phases[phase as usize];
+        total.elapsed += elapsed;
+        total.items += items;
+    }
+
+    /// Record one directory listing, split into traversal and stat time
+    pub fn record_dir(&self, path: &str, elapsed: Duration, stat_time: Duration, files: u64) {
+        if !self.enabled {
+            return;
+        }
+        self.record(Phase::Traversal, elapsed.saturating_sub(stat_time), 1);
+        self.record(Phase::Stat, stat_time, files);
+        keep_slowest(&mut self.data.lock().unwrap().slowest_directories, path, elapsed);
+    }
+
+    /// Record reading one file's tags
+    pub fn record_file(&self, path: &str, elapsed: Duration) {
+        if !self.enabled {
+            return;
+        }
+        self.record(Phase::TagRead, elapsed, 1);
+        keep_slowest(&mut self.data.lock().unwrap().slowest_files, path, elapsed);
+    }
+
+    pub fn summary(&self) -> Option<ScanProfileSummary> {
+        if !self.enabled {
+            return None;
+        }
+        let data = self.data.lock().unwrap();
+        let phases = Phase::ALL.iter()
+            .map(|&phase| {
+                let total = &data.phases[phase as usize];
+                let seconds = total.elapsed.as_secs_f64();
+                PhaseSummary {
+                    phase: phase.name(),
+                    total_ms: total.elapsed.as_millis() as u64,
+                    items: total.items,
+                    items_per_second: if seconds > 0.0 { total.items as f64 / seconds } else { 0.0 },
+                }
+            })
+            .collect();
+
+        Some(ScanProfileSummary {
+            phases,
+            slowest_directories: slow_paths(&data.slowest_directories),
+            slowest_files: slow_paths(&data.slowest_files),
+        })
+    }
+}
+
+impl ScanProfileSummary {
+    pub fn log(&self) {
+        info!("Scan profile:");
+        for phase in &self.phases {
+            info!("  {:<10} {:>9} ms {:>8} items {:>10.1}/s", phase.phase, phase.total_ms, phase.items, phase.items_per_second);
+        }
+        for dir in &self.slowest_directories {
+            info!("  slow directory {:>7} ms {}", dir.ms, dir.path);
+        }
+        for file in &self.slowest_files {
+            info!("  slow file {:>7} ms {}", file.ms, file.path);
+        }
+    }
+}
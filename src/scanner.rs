@@ -1,57 +1,133 @@
 use std::path::PathBuf;
-use std::collections::HashMap;
-use std::fs::Metadata;
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read, Seek};
 use std::path::Path;
-use tokio::sync::{mpsc, Semaphore};
+use futures::stream::{self, StreamExt};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use indicatif::{ProgressBar, ProgressStyle, MultiProgress};
-use log::{info, error};
-use async_recursion::async_recursion;
+use log::{info, error, warn};
 use regex::Regex;
 use lofty::prelude::*;
+use lofty::config::ParseOptions;
+use lofty::file::{FileType, TaggedFile};
+use lofty::ape::ApeFile;
+use lofty::flac::FlacFile;
+use lofty::iff::aiff::AiffFile;
+use lofty::iff::wav::WavFile;
+use lofty::mp4::{AdvisoryRating, Atom, AtomData, AtomIdent, Mp4File};
+use lofty::mpeg::MpegFile;
+use lofty::ogg::{OpusFile, VorbisFile};
+use lofty::wavpack::WavPackFile;
 use lofty::probe::Probe;
 use lofty::error::LoftyError;
 use sea_orm::ActiveValue::Set;
-use sea_orm::{NotSet, DatabaseConnection};
-
-use entity::track;
+use sea_orm::{ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, NotSet, RuntimeErr, TransactionTrait};
+
+use entity::{scan_error, track};
+
+use crate::album_artists::{self, AlbumArtistFallback};
+use crate::albums;
+use crate::artists::{self, ArtistSplitter};
+use crate::audio_info::{self, AudioInfo, Describe};
+use crate::browse;
+use crate::chapters;
+use crate::content_type;
+use crate::file_sizes;
+use crate::history::{self, ChangeSource};
+use crate::images;
+use crate::in_progress::InProgressFilter;
+use crate::integrity;
+use crate::media_types;
+use crate::missing;
+use crate::musical_keys;
+use crate::paths;
+use crate::processor;
+use crate::scan_profile::{Phase, ScanProfileSummary, ScanProfiler};
+use crate::slugs;
+use crate::storage::{self, FileEntry, Storage};
+use crate::tag_region;
+use crate::text_index::{self, IgnoredArticles, TextNormalization};
 
 pub struct ScanConfig {
     pub music_path: String,
     pub show_progress: bool,
     pub batch_size: usize,
-    pub path_batch_size: usize,  // Number of paths to check in each DB query
-    pub use_optimized_scanning: bool,  // Use new optimized scanning approach
+    /// Deep scan: also decode FLAC files to verify their audio checksums
+    pub verify_audio: bool,
 }
 
 impl Default for ScanConfig {
     fn default() -> Self {
         Self {
-            music_path: "/mnt/shucked/Music".to_string(),
+            music_path: paths::default_music_path(),
             show_progress: true,
             batch_size: 100,        // Smaller batches for more consistent performance
-            path_batch_size: 2500,  // Balanced for good query efficiency
-            use_optimized_scanning: true,
+            verify_audio: false,
         }
     }
 }
 
 pub struct ScanResult {
+    pub scan_id: String,
     pub files_scanned: u64,
     pub tracks_processed: usize,
+    pub errors_recorded: usize,
+    /// Per-phase timings, when SCAN_PROFILE is enabled
+    pub profile: Option<ScanProfileSummary>,
+    /// Albums that first appeared in this scan, as (album artist, name)
+    pub new_albums: Vec<(String, String)>,
+}
+
+/// Work item sent from the library walk to the database writer
+pub enum ScanItem {
+    Track(Box<track::ActiveModel>),
+    Failed(ScanFailure),
+    /// A file whose modification time changed but whose tags didn't
+    Unchanged { path: String, modified: chrono::DateTime<chrono::Utc> },
+}
+
+/// What the database knows about a file from its last scan
+pub struct KnownFile {
+    pub modified: chrono::DateTime<chrono::Utc>,
+    pub tag_hash: Option<String>,
+}
+
+/// A file that could not be scanned, recorded in the scan_error table
+pub struct ScanFailure {
+    pub path: String,
+    pub kind: &'static str,
+    pub message: String,
 }
 
 pub async fn scan_music_library(
     db: &DatabaseConnection,
     config: ScanConfig,
 ) -> Result<ScanResult, Box<dyn std::error::Error + Send + Sync>> {
-    let path_buf = PathBuf::from(&config.music_path);
-    let path = path_buf.as_path();
+    let scan_id = uuid::Uuid::new_v4().to_string();
+    info!("Starting music library scan {} at: {}", scan_id, config.music_path);
+
+    let library_roots = storage::library_roots(&config.music_path);
+    let storage = storage::for_library(&config.music_path);
+    let last_track_id = missing::last_track_id(db).await?;
 
-    info!("Starting music library scan at: {}", config.music_path);
+    // List files up front, which also gives the total for progress estimation.
+    // Unfinished downloads are left for a later scan rather than recorded as junk rows or errors
+    let in_progress = InProgressFilter::from_env();
+    let profiler = Arc::new(ScanProfiler::from_env());
 
-    // Count total files for progress estimation
-    let total_files = count_files(path);
+    let mut entries = list_entries(&storage, &config.music_path, &profiler).await?;
+    if paths::CASE_INSENSITIVE {
+        match_stored_case(db, &mut entries).await?;
+    }
+    let listed_paths: HashSet<String> = entries.iter().map(|entry| entry.path.clone()).collect();
+    let listed = entries.len();
+    entries.retain(|entry| !in_progress.is_in_progress(entry));
+    if entries.len() < listed {
+        info!("Skipping {} in-progress files", listed - entries.len());
+    }
+    let total_files = entries.len() as u64;
 
     // Create MultiProgress container for better log handling
     let multi = MultiProgress::new();
@@ -79,47 +155,92 @@ pub async fn scan_music_library(
 
     let (tx, mut rx) = mpsc::channel(2000);  // Balanced channel buffer for improved performance
     let tx_clone = tx.clone();
+    let profiler_clone = profiler.clone();
+    let scan_storage = storage.clone();
+    let readers = TagReaders::from_env();
 
-    // Use optimized scanning approach
-    let scan_handle = if config.use_optimized_scanning {
-        let db_clone = db.clone();
-        tokio::spawn(async move {
-            scan_dir_optimized(&path_buf, &tx_clone, &db_clone, config.path_batch_size).await;
-        })
-    } else {
-        // Fallback to original approach
-        let modified_by_path = get_all_modified_by_path(db).await?;
-        tokio::spawn(async move {
-            scan_dir(&path_buf, &tx_clone, &modified_by_path).await;
-        })
-    };
+    let known_files = get_all_known_files(db).await?;
+    let failed_reads = get_failed_reads(db).await?;
+    let scan_handle = tokio::spawn(async move {
+        scan_entries(scan_storage, entries, &tx_clone, &known_files, &failed_reads, &readers, &profiler_clone).await;
+    });
 
     drop(tx);
 
     let mut stack: Vec<track::ActiveModel> = Vec::with_capacity(config.batch_size);
+    let mut failures: Vec<ScanFailure> = Vec::new();
+    let mut unchanged: Vec<(String, chrono::DateTime<chrono::Utc>)> = Vec::new();
     let mut tracks_processed = 0;
+    let mut tracks_unchanged = 0;
+    let mut errors_recorded = 0;
+    let read_retries = ReadRetries::from_env();
+
+    while let Some(item) = rx.recv().await {
+        match item {
+            ScanItem::Track(track) => {
+                stack.push(*track);
+                tracks_processed += 1;
+
+                if stack.len() >= config.batch_size {
+                    let upsert_started = Instant::now();
+                    let unwritten = store_tracks(&stack, db).await;
+                    profiler.record(Phase::DbUpsert, upsert_started.elapsed(), stack.len() as u64);
+                    errors_recorded += unwritten.len();
+                    failures.extend(unwritten);
+                    // Update progress after successful database operation
+                    progress.inc(stack.len() as u64);
+                    stack.clear();
+                }
+            }
+            ScanItem::Failed(failure) => {
+                failures.push(failure);
+                errors_recorded += 1;
 
-    while let Some(track) = rx.recv().await {
-        stack.push(track);
-        tracks_processed += 1;
-
-        if stack.len() >= config.batch_size {
-            upsert_tracks(&stack, db).await?;
-            // Update progress after successful database operation
-            progress.inc(stack.len() as u64);
-            stack.clear();
+                if failures.len() >= config.batch_size {
+                    record_scan_errors(&failures, &scan_id, &read_retries, db).await?;
+                    failures.clear();
+                }
+            }
+            ScanItem::Unchanged { path, modified } => {
+                unchanged.push((path, modified));
+                tracks_unchanged += 1;
+
+                if unchanged.len() >= config.batch_size {
+                    touch_tracks(&unchanged, db).await?;
+                    progress.inc(unchanged.len() as u64);
+                    unchanged.clear();
+                }
+            }
         }
     }
 
+    if !unchanged.is_empty() {
+        touch_tracks(&unchanged, db).await?;
+        progress.inc(unchanged.len() as u64);
+        unchanged.clear();
+    }
+    if tracks_unchanged > 0 {
+        info!("Skipped {} files whose tags are unchanged", tracks_unchanged);
+    }
+
     if !stack.is_empty() {
-        upsert_tracks(&stack, db).await?;
+        let upsert_started = Instant::now();
+        let unwritten = store_tracks(&stack, db).await;
+        profiler.record(Phase::DbUpsert, upsert_started.elapsed(), stack.len() as u64);
+        errors_recorded += unwritten.len();
+        failures.extend(unwritten);
         // Update progress after final database operation
         progress.inc(stack.len() as u64);
         stack.clear();
     }
 
+    if !failures.is_empty() {
+        record_scan_errors(&failures, &scan_id, &read_retries, db).await?;
+        failures.clear();
+    }
+
     // Update progress for any remaining files that didn't need processing
-    let remaining_files = total_files.saturating_sub(tracks_processed as u64);
+    let remaining_files = total_files.saturating_sub((tracks_processed + tracks_unchanged) as u64);
     if remaining_files > 0 {
         progress.inc(remaining_files);
     }
@@ -128,310 +249,760 @@ pub async fn scan_music_library(
 
     if let Err(e) = scan_handle.await {
         error!("Scan task failed: {:?}", e);
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("Scan task failed: {:?}", e))));
+        return Err(Box::new(std::io::Error::other(format!("Scan task failed: {:?}", e))));
     }
 
-    let scan_result = ScanResult {
+    let mut scan_result = ScanResult {
+        scan_id,
         files_scanned: total_files,
         tracks_processed,
+        errors_recorded,
+        profile: profiler.summary(),
+        new_albums: Vec::new(),
     };
 
+    // Record which library folder each track is in, also for unchanged files
+    // when folders are added to EXTRA_MUSIC_PATHS
+    if let Err(e) = assign_library_roots(db, &library_roots).await {
+        error!("Failed to assign library folders: {}", e);
+    }
+    if let Err(e) = media_types::assign_media_types(db, &library_roots).await {
+        error!("Failed to assign media types: {}", e);
+    }
+    if let Err(e) = album_artists::assign_album_artists(db, &AlbumArtistFallback::from_env()).await {
+        error!("Failed to assign album artists: {}", e);
+    }
+
+    // Soft-delete tracks whose files are gone and restore ones that came back,
+    // before the passes below derive anything from the track table
+    if let Err(e) = missing::reconcile_missing(db, &library_roots, &listed_paths, last_track_id).await {
+        error!("Failed to update missing tracks: {}", e);
+    }
+
+    scan_result.new_albums = refresh_derived(db).await;
+
+    // Tracks stored before file sizes were recorded get theirs without a rescan
+    if let Err(e) = file_sizes::backfill_file_sizes(db, storage.as_ref()).await {
+        error!("Failed to record file sizes: {}", e);
+    }
+
+    // Deep scans decode FLAC files that changed since they were last verified
+    if config.verify_audio || integrity::verify_from_env() {
+        if let Err(e) = integrity::verify_flac_checksums(db, storage.as_ref()).await {
+            error!("Failed to verify audio checksums: {}", e);
+        }
+    }
+
     // Log completion with database count
     use entity::prelude::Track;
     use sea_orm::{EntityTrait, PaginatorTrait};
     let total_tracks_in_db = Track::find().count(db).await.unwrap_or(0);
 
     // Final logging
-    info!("Scan completed: {} files scanned, {} tracks processed, {} tracks in database",
-          scan_result.files_scanned, scan_result.tracks_processed, total_tracks_in_db);
+    info!("Scan completed: {} files scanned, {} tracks processed, {} errors recorded, {} tracks in database",
+          scan_result.files_scanned, scan_result.tracks_processed, scan_result.errors_recorded, total_tracks_in_db);
+    if let Some(profile) = &scan_result.profile {
+        profile.log();
+    }
 
     Ok(scan_result)
 }
 
-pub async fn get_all_modified_by_path(db: &DatabaseConnection) -> Result<HashMap<String, chrono::DateTime<chrono::Utc>>, sea_orm::DbErr> {
+async fn assign_library_roots(db: &DatabaseConnection, roots: &[String]) -> Result<(), sea_orm::DbErr> {
     use entity::prelude::Track;
-    use sea_orm::EntityTrait;
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+    // Shortest first so folders nested inside another root win
+    let mut roots = roots.to_vec();
+    roots.sort_by_key(|root| root.len());
+    for root in roots {
+        Track::update_many()
+            .col_expr(track::Column::LibraryRoot, sea_query::Expr::value(root.as_str()))
+            .filter(track::Column::Path.starts_with(format!("{}/", root)))
+            .filter(
+                track::Column::LibraryRoot.is_null()
+                    .or(track::Column::LibraryRoot.ne(root.as_str())),
+            )
+            .exec(db)
+            .await?;
+    }
+    Ok(())
+}
 
-    info!("Loading existing track metadata from database...");
-    let tracks = Track::find().all(db).await?;
+/// Spell listed paths the way they are stored when they differ only in case,
+/// so renaming "music" to "Music" on a case-insensitive filesystem, or
+/// setting MUSIC_PATH with a different case, doesn't add every track again.
+/// Stored paths are read a page at a time rather than all at once.
+async fn match_stored_case(db: &DatabaseConnection, entries: &mut [FileEntry]) -> Result<(), DbErr> {
+    use entity::prelude::Track;
+    use sea_orm::{EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+
+    let listed: HashMap<String, usize> = entries.iter()
+        .enumerate()
+        .map(|(index, entry)| (paths::key(&entry.path).into_owned(), index))
+        .collect();
+
+    let mut after = 0;
+    loop {
+        let page: Vec<(i32, String)> = Track::find()
+            .select_only()
+            .column(track::Column::Id)
+            .column(track::Column::Path)
+            .filter(track::Column::Id.gt(after))
+            .order_by_asc(track::Column::Id)
+            .limit(KNOWN_FILES_PAGE)
+            .into_tuple()
+            .all(db)
+            .await?;
+        let Some(&(last, _)) = page.last() else {
+            break;
+        };
+        after = last;
 
-    let mut result = HashMap::new();
-    for track in tracks {
-        result.insert(track.path, track.modified);
+        for (_, path) in page {
+            if let Some(&index) = listed.get(paths::key(&path).as_ref()) {
+                if entries[index].path != path {
+                    entries[index].path = path;
+                }
+            }
+        }
     }
+    Ok(())
+}
 
-    info!("Loaded {} existing tracks", result.len());
-    Ok(result)
+/// List every file in the library. When profiling a local library the walk
+/// is done here so each directory's timings can be recorded.
+async fn list_entries(storage: &Arc<dyn Storage>, music_path: &str, profiler: &Arc<ScanProfiler>) -> std::io::Result<Vec<FileEntry>> {
+    let local_root = storage.local_path(music_path).filter(|_| profiler.is_enabled());
+    let Some(root) = local_root else {
+        let started = Instant::now();
+        let entries = storage.list().await?;
+        profiler.record(Phase::Traversal, started.elapsed(), entries.len() as u64);
+        return Ok(entries);
+    };
+
+    let profiler = profiler.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut entries = Vec::new();
+        storage::collect_local_entries(&root, &mut entries, &mut |dir, listing, elapsed| {
+            profiler.record_dir(&paths::to_stored_lossy(dir), elapsed, listing.stat_time, listing.files.len() as u64);
+        });
+        entries
+    })
+    .await
+    .map_err(std::io::Error::other)
 }
 
-/// Optimized version that queries database in batches instead of loading everything
-pub async fn get_modified_times_for_paths(
-    db: &DatabaseConnection,
-    paths: &[String]
-) -> Result<HashMap<String, chrono::DateTime<chrono::Utc>>, sea_orm::DbErr> {
-    use entity::prelude::Track;
-    use sea_orm::{EntityTrait, ColumnTrait, QueryFilter};
+// Rows of the paths and modification times the last scans recorded, read
+// without the rest of each track
+type KnownFileRow = (i32, String, chrono::DateTime<chrono::Utc>, Option<String>);
 
-    if paths.is_empty() {
-        return Ok(HashMap::new());
-    }
+// Tracks read per query when loading every known file
+const KNOWN_FILES_PAGE: u64 = 10_000;
 
-    let tracks = Track::find()
-        .filter(track::Column::Path.is_in(paths.iter().cloned()))
-        .all(db)
-        .await?;
+fn known_files_query() -> sea_orm::Select<entity::prelude::Track> {
+    use entity::prelude::Track;
+    use sea_orm::{EntityTrait, QuerySelect};
+
+    Track::find()
+        .select_only()
+        .column(track::Column::Id)
+        .column(track::Column::Path)
+        .column(track::Column::Modified)
+        .column(track::Column::TagHash)
+}
+
+/// Every file the database knows about, read a page at a time so only the
+/// path, modification time and tag hash of each track are ever held
+pub async fn get_all_known_files(db: &DatabaseConnection) -> Result<HashMap<String, KnownFile>, sea_orm::DbErr> {
+    use sea_orm::{ColumnTrait, QueryFilter, QueryOrder, QuerySelect};
 
+    info!("Loading existing track paths from database...");
     let mut result = HashMap::new();
-    for track in tracks {
-        result.insert(track.path, track.modified);
+    let mut after_id = 0;
+    loop {
+        let rows: Vec<KnownFileRow> = known_files_query()
+            .filter(track::Column::Id.gt(after_id))
+            .order_by_asc(track::Column::Id)
+            .limit(KNOWN_FILES_PAGE)
+            .into_tuple()
+            .all(db)
+            .await?;
+        let Some((last_id, ..)) = rows.last() else {
+            break;
+        };
+        after_id = *last_id;
+        let full_page = rows.len() as u64 == KNOWN_FILES_PAGE;
+        for (_, path, modified, tag_hash) in rows {
+            result.insert(path, KnownFile { modified, tag_hash });
+        }
+        if !full_page {
+            break;
+        }
     }
 
+    info!("Loaded {} existing tracks", result.len());
     Ok(result)
 }
 
-pub fn count_files(path: &Path) -> u64 {
-    let mut count = 0;
-    let entries = match path.read_dir() {
-        Ok(entries) => entries,
-        Err(e) => {
-            error!("Failed to read directory {}: {:?}", path.display(), e);
-            return 0;
-        }
-    };
+/// Read the listed files that changed since the last scan, and those whose
+/// retry after a failed read is due, reusing the stat information captured
+/// while listing
+pub async fn scan_entries(
+    storage: Arc<dyn Storage>,
+    entries: Vec<FileEntry>,
+    tx: &tokio::sync::mpsc::Sender<ScanItem>,
+    known_files: &HashMap<String, KnownFile>,
+    failed_reads: &HashMap<String, FailedRead>,
+    readers: &TagReaders,
+    profiler: &Arc<ScanProfiler>,
+) {
     for entry in entries {
-        let entry = match entry {
-            Ok(entry) => entry,
-            Err(e) => {
-                error!("Failed to read directory entry: {:?}", e);
-                continue;
-            }
+        let known = known_files.get(&entry.path);
+        let modified_last_scan = known
+            .map(|known| known.modified)
+            .unwrap_or_else(|| chrono::DateTime::from(std::time::SystemTime::UNIX_EPOCH));
+
+        // Only process if file has been modified since last scan
+        // Use duration comparison to handle potential timestamp precision differences
+        let time_diff = entry.modified.signed_duration_since(modified_last_scan);
+        let failed = failed_reads.get(&entry.path);
+        let should_read = match failed {
+            Some(failed) if failed.due => true,
+            // Waiting for its retry, or given up on, until the file changes
+            Some(failed) => entry.modified > failed.occurred,
+            None => time_diff > chrono::Duration::seconds(1),
         };
-        let path = entry.path();
-
-        if path.is_dir() {
-            count += count_files(&path);
+        if should_read {
+            // File has been modified since last scan - spawn async task for processing
+            let tx = tx.clone();
+            let storage = storage.clone();
+            let profiler = profiler.clone();
+            // A retry reads the whole file, whatever its tag region hashes to
+            let known_tag_hash = known.filter(|_| failed.is_none()).and_then(|known| known.tag_hash.clone());
+
+            // Wait for a reader before spawning, so a large library doesn't
+            // queue a task per file while the readers and the writer catch up
+            let permit = readers.acquire().await;
+            let quick_check = readers.quick_check;
+            tokio::spawn(async move {
+                process_file(storage.as_ref(), &entry, known_tag_hash, quick_check, &tx, &profiler).await;
+                drop(permit);
+            });
         } else {
-            count += 1;
+            // File hasn't been modified since last scan, skip processing
+            log::debug!("Skipping unchanged file: {}", entry.path);
         }
     }
-    count
 }
 
-#[async_recursion]
-pub async fn scan_dir(path: &Path, tx: &tokio::sync::mpsc::Sender<track::ActiveModel>, modified_by_path: &HashMap<String, chrono::DateTime<chrono::Utc>>) {
-    let entries = match path.read_dir() {
-        Ok(entries) => entries,
+/// Read a single file's tags and hand the result to the database writer. With
+/// the quick check, files whose tag region hashes the same as at the last scan
+/// are only reported as unchanged.
+async fn process_file(
+    storage: &dyn Storage,
+    entry: &FileEntry,
+    known_tag_hash: Option<String>,
+    quick_check: bool,
+    tx: &tokio::sync::mpsc::Sender<ScanItem>,
+    profiler: &ScanProfiler,
+) {
+    let path = Path::new(&entry.path);
+    let started = Instant::now();
+    let tag_hash = if quick_check {
+        tag_region::tag_hash(storage, &entry.path, entry.size).await.unwrap_or_else(|e| {
+            log::debug!("Failed to hash the tags of {}: {:?}", entry.path, e);
+            None
+        })
+    } else {
+        None
+    };
+    if tag_hash.is_some() && tag_hash == known_tag_hash {
+        profiler.record_file(&entry.path, started.elapsed());
+        let unchanged = ScanItem::Unchanged { path: entry.path.clone(), modified: entry.modified };
+        if let Err(e) = tx.send(unchanged).await {
+            error!("Failed to send unchanged file through channel: {:?}", e);
+        }
+        return;
+    }
+    let tags = read_tags(storage, entry, tag_hash).await;
+    profiler.record_file(&entry.path, started.elapsed());
+    match tags {
+        Ok(mut track) => {
+            processor::apply(&mut track, path);
+
+            if let Err(e) = tx.send(ScanItem::Track(Box::new(track))).await {
+                error!("Failed to send track data through channel: {:?}", e);
+            }
+        },
         Err(e) => {
-            error!("Failed to read directory {}: {:?}", path.display(), e);
-            return;
+            // Only care about supported files
+            if lofty::file::FileType::from_path(path).is_some() {
+                error!("Error reading tags: {:?}", e);
+                error!("In path: {}", entry.path);
+                send_failure(tx, path, e.kind(), e.message()).await;
+            }
         }
-    };
-    for entry in entries {
-        let entry = match entry {
-            Ok(entry) => entry,
-            Err(e) => {
-                error!("Failed to read directory entry: {:?}", e);
-                continue;
+    }
+}
+
+// Files read at once when re-reading a directory or album
+const FILE_SCAN_CONCURRENCY: usize = 8;
+
+/// How a scan reads the files it walks: how many at once, and whether with
+/// the tag region quick check
+pub struct TagReaders {
+    permits: Arc<Semaphore>,
+    quick_check: bool,
+}
+
+impl TagReaders {
+    /// Read SCAN_TAG_READERS, defaulting to twice the CPU count since reads
+    /// also wait on the disk or network, and SCAN_QUICK_CHECK
+    pub fn from_env() -> Self {
+        let readers = std::env::var("SCAN_TAG_READERS")
+            .ok()
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .filter(|readers| *readers > 0)
+            .unwrap_or_else(|| std::thread::available_parallelism().map(|cpus| cpus.get() * 2).unwrap_or(8));
+        Self {
+            permits: Arc::new(Semaphore::new(readers)),
+            quick_check: tag_region::quick_check_from_env(),
+        }
+    }
+
+    // A free reader, held until the file has been read and handed to the writer
+    async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.permits.clone().acquire_owned().await.expect("tag reader semaphore is never closed")
+    }
+}
+
+// Bring everything derived from the track table up to date after tracks were
+// written. Returns the albums that first appeared, as (album artist, name).
+async fn refresh_derived(db: &DatabaseConnection) -> Vec<(String, String)> {
+    // Credit collaborations to each participating artist before slugs are assigned
+    if let Err(e) = artists::refresh_track_artists(db, &ArtistSplitter::from_env()).await {
+        error!("Failed to update artist credits: {}", e);
+    }
+
+    // Give new artists and albums their stable IDs
+    if let Err(e) = slugs::assign_slugs(db).await {
+        error!("Failed to assign artist/album slugs: {}", e);
+    }
+
+    // Precompute per-album art references for album listings
+    let new_albums = albums::refresh_albums(db).await.unwrap_or_else(|e| {
+        error!("Failed to update albums: {}", e);
+        Vec::new()
+    });
+
+    // Artist, genre and album listings of the default view
+    if let Err(e) = browse::refresh_browse_tables(db).await {
+        error!("Failed to update browse tables: {}", e);
+    }
+
+    // Keep search text and sort keys in step with tag changes and TEXT_NORMALIZATION
+    if let Err(e) = text_index::refresh_text_index(db, TextNormalization::from_env(), &IgnoredArticles::from_env()).await {
+        error!("Failed to update search text and sort keys: {}", e);
+    }
+
+    new_albums
+}
+
+/// Every file below a directory of the library
+pub async fn list_directory(storage: &Arc<dyn Storage>, directory: &str) -> std::io::Result<Vec<FileEntry>> {
+    match storage.local_path(directory) {
+        Some(local) => {
+            if !tokio::fs::metadata(&local).await?.is_dir() {
+                return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} is not a directory", directory)));
             }
-        };
-        let path = entry.path();
-
-        if path.is_dir() {
-            scan_dir(&path, &tx, &modified_by_path).await;
-        } else if path.is_file() {
-            let metadata = match tokio::fs::metadata(&path).await {
-                Ok(metadata) => metadata,
-                Err(e) => {
-                    error!("Failed to read metadata for {}: {:?}", path.display(), e);
-                    continue;
-                }
-            };
-            let modified: chrono::DateTime<chrono::Utc> = match metadata.modified() {
-                Ok(modified) => chrono::DateTime::from(modified),
-                Err(e) => {
-                    error!("Failed to get modified time for {}: {:?}", path.display(), e);
-                    continue;
-                }
-            };
-            let path_str = match path.to_str() {
-                Some(path_str) => path_str,
-                None => {
-                    error!("Failed to convert path to string: {}", path.display());
-                    continue;
-                }
-            };
-            let modified_last_scan = match modified_by_path.get(path_str) {
-                Some(modified) => modified.clone(),
-                None => chrono::DateTime::from(std::time::SystemTime::UNIX_EPOCH)
-            };
+            tokio::task::spawn_blocking(move || {
+                let mut entries = Vec::new();
+                storage::collect_local_entries(&local, &mut entries, &mut |_, _, _| {});
+                entries
+            })
+            .await
+            .map_err(std::io::Error::other)
+        }
+        None => {
+            let prefix = format!("{}/", directory);
+            let mut entries = storage.list().await?;
+            entries.retain(|entry| entry.path.starts_with(&prefix));
+            Ok(entries)
+        }
+    }
+}
 
-            // Only process if file has been modified since last scan
-            // Use duration comparison to handle potential timestamp precision differences
-            let time_diff = modified.signed_duration_since(modified_last_scan);
-            if time_diff > chrono::Duration::seconds(1) {
-                // File has been modified since last scan
-                let tx = tx.clone();
-                tokio::spawn(async move {
-                    let track = read_tags(&path, &metadata).await;
-                    match track {
-                        Ok(track) => {
-                            if let Err(e) = tx.send(track).await {
-                                error!("Failed to send track data through channel: {:?}", e);
-                            }
-                        },
-                        Err(e) => {
-                            // Only care about supported files
-                            if lofty::file::FileType::from_path(&path).is_some() {
-                                error!("Error reading tags: {:?}", e);
-                                if let Some(path_str) = path.to_str() {
-                                    error!("In path: {}", path_str);
-                                } else {
-                                    error!("In path: {:?}", path);
-                                }
-                            }
-                        }
-                    }
-                });
-            } else {
-                // File hasn't been modified since last scan, skip processing
-                log::debug!("Skipping unchanged file: {}", path_str);
+/// Re-read the given files whatever their modification time, the way a scan
+/// does, and refresh everything derived from them, to pick up tag edits to a
+/// directory or album without a full scan. Tracks in `known` whose files
+/// aren't among `files` are marked missing.
+pub async fn scan_files(
+    db: &DatabaseConnection,
+    music_path: &str,
+    files: Vec<FileEntry>,
+    known: &[track::Model],
+) -> Result<ScanResult, Box<dyn std::error::Error + Send + Sync>> {
+    let scan_id = uuid::Uuid::new_v4().to_string();
+    info!("Re-reading {} files in scan {}", files.len(), scan_id);
+
+    let storage = storage::for_library(music_path);
+    let last_track_id = missing::last_track_id(db).await?;
+
+    let reads: Vec<_> = files.iter()
+        .map(|entry| {
+            let storage = storage.clone();
+            async move { (entry, read_tags(storage.as_ref(), entry, None).await) }
+        })
+        .collect();
+    let mut results = stream::iter(reads).buffer_unordered(FILE_SCAN_CONCURRENCY);
+
+    let mut tracks = Vec::new();
+    let mut failures = Vec::new();
+    while let Some((entry, tags)) = results.next().await {
+        let path = Path::new(&entry.path);
+        match tags {
+            Ok(mut track) => {
+                processor::apply(&mut track, path);
+                tracks.push(track);
+            }
+            // Only care about supported files
+            Err(e) if FileType::from_path(path).is_some() => {
+                failures.push(ScanFailure { path: entry.path.clone(), kind: e.kind(), message: e.message() });
             }
-            // Progress will be updated after database upsert, not here
+            Err(_) => {}
         }
     }
+    drop(results);
+
+    let tracks_processed = tracks.len();
+    for batch in tracks.chunks(100) {
+        failures.extend(store_tracks(batch, db).await);
+    }
+    record_scan_errors(&failures, &scan_id, &ReadRetries::from_env(), db).await?;
+    let errors_recorded = failures.len();
+
+    let present: HashSet<String> = files.iter().map(|entry| entry.path.clone()).collect();
+    missing::reconcile_tracks(db, known, &present, last_track_id).await?;
+    let library_roots = storage::library_roots(music_path);
+    if let Err(e) = assign_library_roots(db, &library_roots).await {
+        error!("Failed to assign library folders: {}", e);
+    }
+    if let Err(e) = media_types::assign_media_types(db, &library_roots).await {
+        error!("Failed to assign media types: {}", e);
+    }
+    if let Err(e) = album_artists::assign_album_artists(db, &AlbumArtistFallback::from_env()).await {
+        error!("Failed to assign album artists: {}", e);
+    }
+    let new_albums = refresh_derived(db).await;
+
+    info!("Re-read {} files: {} tracks processed, {} errors recorded", files.len(), tracks_processed, errors_recorded);
+    Ok(ScanResult {
+        scan_id,
+        files_scanned: files.len() as u64,
+        tracks_processed,
+        errors_recorded,
+        profile: None,
+        new_albums,
+    })
 }
 
-/// Optimized scanning that processes files in batches to avoid loading entire DB into memory
-#[async_recursion]
-pub async fn scan_dir_optimized(
-    path: &Path,
-    tx: &tokio::sync::mpsc::Sender<track::ActiveModel>,
+/// Scan one folder of the library the way a scan does: files that are new or
+/// changed since they were last read are read, and tracks whose files are
+/// gone are marked missing, leaving the rest of the library alone
+pub async fn scan_folder(
     db: &DatabaseConnection,
-    batch_size: usize,
-) {
-    // Collect all file paths first
-    let mut file_paths = Vec::new();
-    collect_file_paths(path, &mut file_paths);
+    music_path: &str,
+    folder: &str,
+) -> Result<ScanResult, Box<dyn std::error::Error + Send + Sync>> {
+    use sea_orm::{EntityTrait, QueryFilter};
 
-    // Create a semaphore to limit concurrent file processing
-    let semaphore = Arc::new(Semaphore::new(50)); // Limit to 50 concurrent file operations
+    let storage = storage::for_library(music_path);
+    let mut files = list_directory(&storage, folder).await?;
+    let listed: HashSet<String> = files.iter().map(|entry| entry.path.clone()).collect();
+    let in_progress = InProgressFilter::from_env();
+    files.retain(|entry| !in_progress.is_in_progress(entry));
 
-    // Process files in batches
-    for chunk in file_paths.chunks(batch_size) {
-        let paths: Vec<String> = chunk.iter()
-            .filter_map(|p| p.to_str().map(|s| s.to_string()))
-            .collect();
+    let known = entity::prelude::Track::find()
+        .filter(track::Column::Path.starts_with(format!("{}/", folder)))
+        .all(db)
+        .await?;
+    let by_path: HashMap<&str, &track::Model> = known.iter().map(|track| (track.path.as_str(), track)).collect();
+    files.retain(|entry| match by_path.get(entry.path.as_str()) {
+        Some(track) => track.missing_since.is_some() || entry.modified.signed_duration_since(track.modified) > chrono::Duration::seconds(1),
+        None => true,
+    });
+
+    // Only the tracks being re-read and those whose files are gone take part,
+    // so unchanged tracks aren't taken for missing
+    let changed: HashSet<&str> = files.iter().map(|entry| entry.path.as_str()).collect();
+    let affected: Vec<track::Model> = known.iter()
+        .filter(|track| !listed.contains(&track.path) || changed.contains(track.path.as_str()))
+        .cloned()
+        .collect();
+    scan_files(db, music_path, files, &affected).await
+}
 
-        // Query database for this batch of paths
-        let modified_by_path = match get_modified_times_for_paths(db, &paths).await {
-            Ok(map) => map,
-            Err(e) => {
-                error!("Failed to query modified times from database: {:?}", e);
-                continue;
-            }
-        };
+/// Read one file's tags and audio properties into a track the same way a scan
+/// does, for files added or replaced outside a scan
+pub async fn read_track(storage: &dyn Storage, entry: &FileEntry) -> Result<track::ActiveModel, String> {
+    let mut track = read_tags(storage, entry, None).await.map_err(|e| e.message())?;
+    processor::apply(&mut track, Path::new(&entry.path));
+    Ok(track)
+}
 
-        // Process each file in this batch
-        for file_path in chunk {
-            let metadata = match tokio::fs::metadata(&file_path).await {
-                Ok(metadata) => metadata,
-                Err(e) => {
-                    error!("Failed to read metadata for {}: {:?}", file_path.display(), e);
-                    continue;
-                }
-            };
+async fn send_failure(tx: &tokio::sync::mpsc::Sender<ScanItem>, path: &Path, kind: &'static str, message: String) {
+    let failure = ScanFailure {
+        path: paths::to_stored_lossy(path),
+        kind,
+        message,
+    };
+    if let Err(e) = tx.send(ScanItem::Failed(failure)).await {
+        error!("Failed to send scan error through channel: {:?}", e);
+    }
+}
 
-            let modified: chrono::DateTime<chrono::Utc> = match metadata.modified() {
-                Ok(modified) => chrono::DateTime::from(modified),
-                Err(e) => {
-                    error!("Failed to get modified time for {}: {:?}", file_path.display(), e);
-                    continue;
-                }
-            };
+/// Persist scan failures so they can be inspected through the API after the scan.
+/// Each path keeps only its latest error, so files that fail on every scan
+/// don't add a row each time, with the number of scans in a row it failed in
+/// and when a later scan should read it again.
+pub async fn record_scan_errors(failures: &[ScanFailure], scan_id: &str, retries: &ReadRetries, db: &DatabaseConnection) -> Result<(), sea_orm::DbErr> {
+    use sea_orm::{EntityTrait, QueryFilter, QuerySelect};
 
-            let path_str = match file_path.to_str() {
-                Some(path_str) => path_str,
-                None => {
-                    error!("Failed to convert path to string: {}", file_path.display());
-                    continue;
-                }
-            };
+    if failures.is_empty() {
+        return Ok(());
+    }
 
-            let modified_last_scan = modified_by_path.get(path_str)
-                .cloned()
-                .unwrap_or_else(|| chrono::DateTime::from(std::time::SystemTime::UNIX_EPOCH));
-
-            // Only process if file has been modified since last scan
-            // Use duration comparison to handle potential timestamp precision differences
-            let time_diff = modified.signed_duration_since(modified_last_scan);
-            if time_diff > chrono::Duration::seconds(1) {
-                // File has been modified since last scan - spawn async task for processing
-                let tx = tx.clone();
-                let file_path = file_path.clone();
-                let semaphore_permit = semaphore.clone();
-
-                tokio::spawn(async move {
-                    // Acquire a permit to limit concurrent operations
-                    let _permit = semaphore_permit.acquire().await.unwrap();
-
-                    let track = read_tags(&file_path, &metadata).await;
-                    match track {
-                        Ok(track) => {
-                            if let Err(e) = tx.send(track).await {
-                                error!("Failed to send track data through channel: {:?}", e);
-                            }
-                        },
-                        Err(e) => {
-                            // Only care about supported files
-                            if lofty::file::FileType::from_path(&file_path).is_some() {
-                                error!("Error reading tags: {:?}", e);
-                                if let Some(path_str) = file_path.to_str() {
-                                    error!("In path: {}", path_str);
-                                } else {
-                                    error!("In path: {:?}", file_path);
-                                }
-                            }
-                        }
-                    }
-                    // Permit is automatically released when _permit is dropped
-                });
-            } else {
-                // File hasn't been modified since last scan, skip processing
-                log::debug!("Skipping unchanged file: {}", path_str);
+    let occurred = chrono::Utc::now();
+    let txn = db.begin().await?;
+    for batch in failures.chunks(500) {
+        let paths: Vec<String> = batch.iter().map(|failure| failure.path.clone()).collect();
+        let previous: HashMap<String, i32> = scan_error::Entity::find()
+            .select_only()
+            .column(scan_error::Column::Path)
+            .column(scan_error::Column::Attempts)
+            .filter(scan_error::Column::Path.is_in(paths.clone()))
+            .into_tuple::<(String, i32)>()
+            .all(&txn)
+            .await?
+            .into_iter()
+            .collect();
+        scan_error::Entity::delete_many()
+            .filter(scan_error::Column::Path.is_in(paths))
+            .exec(&txn)
+            .await?;
+
+        let models = batch.iter().map(|failure| {
+            let attempts = previous.get(&failure.path).map_or(1, |attempts| attempts + 1);
+            scan_error::ActiveModel {
+                id: NotSet,
+                path: Set(failure.path.clone()),
+                kind: Set(failure.kind.to_string()),
+                message: Set(failure.message.clone()),
+                scan_id: Set(scan_id.to_string()),
+                occurred: Set(occurred),
+                attempts: Set(attempts),
+                next_retry_at: Set(retries.next_retry(failure.kind, attempts, occurred)),
             }
-        }
+        });
+        scan_error::Entity::insert_many(models).exec(&txn).await?;
     }
+    txn.commit().await
 }
 
-/// Recursively collect all file paths
-fn collect_file_paths(path: &Path, file_paths: &mut Vec<PathBuf>) {
-    let entries = match path.read_dir() {
-        Ok(entries) => entries,
-        Err(e) => {
-            error!("Failed to read directory {}: {:?}", path.display(), e);
-            return;
+/// A file whose last read failed with an I/O error
+pub struct FailedRead {
+    /// When the read failed
+    pub occurred: chrono::DateTime<chrono::Utc>,
+    /// Whether its retry is due
+    pub due: bool,
+}
+
+/// Files whose last read failed with an I/O error, which scans only read
+/// again once their retry is due or the file changed
+pub async fn get_failed_reads(db: &DatabaseConnection) -> Result<HashMap<String, FailedRead>, sea_orm::DbErr> {
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QuerySelect};
+
+    let now = chrono::Utc::now();
+    let rows: Vec<(String, chrono::DateTime<chrono::Utc>, Option<chrono::DateTime<chrono::Utc>>)> = scan_error::Entity::find()
+        .select_only()
+        .column(scan_error::Column::Path)
+        .column(scan_error::Column::Occurred)
+        .column(scan_error::Column::NextRetryAt)
+        .filter(scan_error::Column::Kind.eq(READ_FILE_ERROR))
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let failed: HashMap<String, FailedRead> = rows.into_iter()
+        .map(|(path, occurred, next_retry_at)| (path, FailedRead { occurred, due: next_retry_at.is_some_and(|at| at <= now) }))
+        .collect();
+    let due = failed.values().filter(|failed| failed.due).count();
+    if due > 0 {
+        info!("Retrying {} files that failed to read", due);
+    }
+    Ok(failed)
+}
+
+// The failures that might not happen again: files that can't be parsed or
+// have no tags fail the same way until they change
+const READ_FILE_ERROR: &str = "read_file";
+
+/// How later scans read again files that failed with an I/O error, such as
+/// files locked by another program or on a mount that briefly dropped out
+pub struct ReadRetries {
+    attempts: u32,
+    delay: Duration,
+}
+
+impl ReadRetries {
+    /// SCAN_RETRY_ATTEMPTS, the retries before giving up on a file (default 5,
+    /// 0 to not retry), and SCAN_RETRY_DELAY_MINUTES, the wait before the
+    /// first retry (default 30), doubled after each further failure
+    pub fn from_env() -> Self {
+        let attempts = std::env::var("SCAN_RETRY_ATTEMPTS")
+            .ok()
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            .unwrap_or(5);
+        let delay = std::env::var("SCAN_RETRY_DELAY_MINUTES")
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(30);
+        Self { attempts, delay: Duration::from_secs(delay * 60) }
+    }
+
+    // When a scan should read a file again that has now failed `attempts`
+    // times in a row; None for failures that aren't retried and once the
+    // retries are used up
+    fn next_retry(&self, kind: &str, attempts: i32, occurred: chrono::DateTime<chrono::Utc>) -> Option<chrono::DateTime<chrono::Utc>> {
+        if kind != READ_FILE_ERROR || attempts < 1 || attempts as u32 > self.attempts {
+            return None;
         }
-    };
+        let backoff = self.delay.saturating_mul(1 << (attempts - 1).min(20));
+        occurred.checked_add_signed(chrono::Duration::from_std(backoff).ok()?)
+    }
+}
 
-    for entry in entries {
-        let entry = match entry {
-            Ok(entry) => entry,
-            Err(e) => {
-                error!("Failed to read directory entry: {:?}", e);
-                continue;
+// Postgres errors from concurrent writers that succeed when tried again:
+// serialization failures, deadlocks and lock timeouts
+const TRANSIENT_SQLSTATES: [&str; 3] = ["40001", "40P01", "55P03"];
+// Tries for each write before giving up on it, doubling the wait in between
+const WRITE_ATTEMPTS: u32 = 4;
+const WRITE_BACKOFF: Duration = Duration::from_millis(200);
+
+fn is_transient(error: &DbErr) -> bool {
+    match error {
+        DbErr::ConnectionAcquire(_) | DbErr::Conn(_) => true,
+        DbErr::Exec(RuntimeErr::SqlxError(e)) | DbErr::Query(RuntimeErr::SqlxError(e)) => e.as_database_error()
+            .and_then(|e| e.code())
+            .is_some_and(|code| TRANSIENT_SQLSTATES.contains(&code.as_ref())),
+        _ => false,
+    }
+}
+
+/// Upsert tracks and clear their resolved scan errors in one transaction,
+/// retrying with backoff when the database is busy
+pub async fn write_tracks(tracks: &[track::ActiveModel], db: &DatabaseConnection) -> Result<(), DbErr> {
+    let mut attempt = 1;
+    loop {
+        let result = async {
+            let txn = db.begin().await?;
+            let before = history::snapshot(&txn, track::Column::Path.is_in(track_paths(tracks))).await?;
+            upsert_tracks(tracks, &txn).await?;
+            history::record(&txn, ChangeSource::Scan, before).await?;
+            clear_resolved_scan_errors(tracks, &txn).await?;
+            txn.commit().await
+        }.await;
+        match result {
+            Err(e) if attempt < WRITE_ATTEMPTS && is_transient(&e) => {
+                let delay = WRITE_BACKOFF * 2u32.pow(attempt - 1);
+                warn!("Writing {} tracks failed ({}), retrying in {:?}", tracks.len(), e, delay);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
             }
-        };
-        let path = entry.path();
+            result => return result,
+        }
+    }
+}
 
-        if path.is_dir() {
-            collect_file_paths(&path, file_paths);
-        } else if path.is_file() {
-            file_paths.push(path);
+/// Write a batch of scanned tracks, returning the ones that couldn't be
+/// written as `database` scan failures. A batch that still fails after
+/// retrying is written one track at a time so a single bad row doesn't lose
+/// the rest, unless the database stayed busy or unreachable.
+async fn store_tracks(tracks: &[track::ActiveModel], db: &DatabaseConnection) -> Vec<ScanFailure> {
+    let Err(e) = write_tracks(tracks, db).await else {
+        return Vec::new();
+    };
+    if tracks.len() == 1 || is_transient(&e) {
+        error!("Failed to store {} tracks: {}", tracks.len(), e);
+        return tracks.iter().map(|track| unwritten(track, &e)).collect();
+    }
+    warn!("Writing a batch of {} tracks failed ({}), writing them one at a time", tracks.len(), e);
+
+    let mut failures = Vec::new();
+    for track in tracks {
+        if let Err(e) = write_tracks(std::slice::from_ref(track), db).await {
+            let failure = unwritten(track, &e);
+            error!("Failed to store {}: {}", failure.path, e);
+            failures.push(failure);
         }
     }
+    failures
+}
+
+fn unwritten(track: &track::ActiveModel, error: &DbErr) -> ScanFailure {
+    ScanFailure {
+        path: match &track.path {
+            Set(path) => path.clone(),
+            _ => String::new(),
+        },
+        kind: "database",
+        message: error.to_string(),
+    }
+}
+
+/// Record the new modification time of files skipped because their tags are
+/// unchanged, so later scans skip them without hashing
+pub async fn touch_tracks(files: &[(String, chrono::DateTime<chrono::Utc>)], db: &DatabaseConnection) -> Result<(), DbErr> {
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+    let txn = db.begin().await?;
+    for (path, modified) in files {
+        track::Entity::update_many()
+            .col_expr(track::Column::Modified, sea_query::Expr::value(*modified))
+            .filter(track::Column::Path.eq(path.as_str()))
+            .exec(&txn)
+            .await?;
+    }
+    txn.commit().await
+}
+
+fn track_paths(tracks: &[track::ActiveModel]) -> Vec<String> {
+    tracks.iter()
+        .filter_map(|track| match &track.path {
+            Set(path) => Some(path.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Remove recorded scan errors for files that have now been scanned successfully
+pub async fn clear_resolved_scan_errors<C: ConnectionTrait>(tracks: &[track::ActiveModel], db: &C) -> Result<(), sea_orm::DbErr> {
+    use sea_orm::{EntityTrait, ColumnTrait, QueryFilter};
+
+    let paths = track_paths(tracks);
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    scan_error::Entity::delete_many()
+        .filter(scan_error::Column::Path.is_in(paths))
+        .exec(db)
+        .await?;
+    Ok(())
 }
 
-pub async fn upsert_tracks(tracks: &Vec<track::ActiveModel>, db: &DatabaseConnection) -> Result<sea_orm::InsertResult<track::ActiveModel>, sea_orm::DbErr> {
+pub async fn upsert_tracks<C: ConnectionTrait>(tracks: &[track::ActiveModel], db: &C) -> Result<sea_orm::InsertResult<track::ActiveModel>, sea_orm::DbErr> {
     use sea_orm::EntityTrait;
 
     if tracks.is_empty() {
@@ -449,9 +1020,15 @@ pub async fn upsert_tracks(tracks: &Vec<track::ActiveModel>, db: &DatabaseConnec
             track::Column::TrackNumber,
             track::Column::Year,
             track::Column::Genre,
-            track::Column::AlbumArtist,
+            track::Column::AlbumArtistTag,
             track::Column::Publisher,
             track::Column::CatalogNumber,
+            track::Column::Isrc,
+            track::Column::Barcode,
+            track::Column::Language,
+            track::Column::ReleaseCountry,
+            track::Column::Explicit,
+            track::Column::Comment,
             track::Column::DurationSeconds,
             track::Column::AudioBitrate,
             track::Column::OverallBitrate,
@@ -459,87 +1036,267 @@ pub async fn upsert_tracks(tracks: &Vec<track::ActiveModel>, db: &DatabaseConnec
             track::Column::BitDepth,
             track::Column::Channels,
             track::Column::Tags,
+            track::Column::AlbumArtPath,
+            track::Column::AlbumArtMimeType,
+            track::Column::AlbumArtSize,
+            track::Column::Images,
+            track::Column::ContentType,
+            track::Column::ContentHash,
+            track::Column::TagHash,
+            track::Column::AudioMd5,
+            track::Column::AudioMd5Status,
+            track::Column::AudioMd5Checked,
+            track::Column::Chapters,
+            track::Column::AudioInfo,
+            track::Column::Codec,
+            track::Column::CodecProfile,
+            track::Column::Encoder,
+            track::Column::Bpm,
+            track::Column::InitialKey,
+            track::Column::FileSize,
             track::Column::Modified,
         ])
+        // Files still without an album artist tag keep the album artist they
+        // were given, rather than losing it until the end of the scan
+        .value(
+            track::Column::AlbumArtist,
+            sea_query::Expr::cust(r#"CASE WHEN "excluded"."album_artist_tag" = '' AND "track"."album_artist_tag" = '' THEN "track"."album_artist" ELSE "excluded"."album_artist" END"#),
+        )
         .to_owned();
 
     // Log only every 5th batch to reduce noise
-    if tracks.len() >= 500 || tracks.len() % 500 == 0 {
+    if tracks.len() >= 500 || tracks.len().is_multiple_of(500) {
         info!("Upserting batch of {} tracks", tracks.len());
     }
 
-    track::Entity::insert_many(tracks.clone())
+    track::Entity::insert_many(tracks.to_vec())
         .on_conflict(on_conflict)
         .exec(db)
         .await
 }
 
-// Extract and save album art from audio file
-async fn extract_album_art(
-    path: &Path,
+// File extension and MIME type for an embedded picture
+fn picture_format(picture: &lofty::picture::Picture) -> (&'static str, Option<String>) {
+    match picture.mime_type() {
+        Some(mime) => {
+            let mime_str = mime.as_str();
+            let ext = if mime_str.contains("jpeg") || mime_str.contains("jpg") {
+                "jpg"
+            } else if mime_str.contains("png") {
+                "png"
+            } else if mime_str.contains("webp") {
+                "webp"
+            } else {
+                "jpg" // Default to jpg
+            };
+            (ext, Some(mime_str.to_string()))
+        }
+        None => ("jpg", None),
+    }
+}
+
+// Extract and save the embedded images from an audio file, the first of each type
+async fn extract_images(
+    album_art_dir: Option<PathBuf>,
     tag: &lofty::tag::Tag,
-) -> (Option<String>, Option<String>, Option<i32>) {
-    // Look for album art in the tag
+) -> Vec<images::StoredImage> {
+    let mut stored: Vec<images::StoredImage> = Vec::new();
+
     for picture in tag.pictures() {
         let picture_data = picture.data();
-        if !picture_data.is_empty() {
-            // Create album art directory if it doesn't exist
-            let audio_dir = match path.parent() {
-                Some(dir) => dir,
-                None => continue,
-            };
+        let image_type = images::image_type(picture.pic_type());
+        if picture_data.is_empty() || stored.iter().any(|image| image.image_type == image_type) {
+            continue;
+        }
+
+        // Create album art directory if it doesn't exist
+        let album_art_dir = match &album_art_dir {
+            Some(dir) => dir,
+            None => continue,
+        };
 
-            let album_art_dir = audio_dir.join(".album_art");
-            if let Err(e) = tokio::fs::create_dir_all(&album_art_dir).await {
-                error!("Failed to create album art directory: {:?}", e);
-                continue;
+        if let Err(e) = tokio::fs::create_dir_all(&album_art_dir).await {
+            error!("Failed to create album art directory: {:?}", e);
+            continue;
+        }
+
+        // Front covers keep the cover.* name album art has always had
+        let (extension, mime_type) = picture_format(picture);
+        let name = if image_type == images::FRONT { "cover" } else { image_type };
+        let image_path = album_art_dir.join(format!("{}.{}", name, extension));
+
+        match tokio::fs::write(&image_path, picture_data).await {
+            Ok(_) => stored.push(images::StoredImage {
+                image_type: image_type.to_string(),
+                path: paths::to_stored_lossy(&image_path),
+                mime_type,
+                size: picture_data.len() as i32,
+            }),
+            Err(e) => {
+                error!("Failed to save {} image to {:?}: {:?}", image_type, image_path, e);
             }
+        }
+    }
 
-            // Generate filename based on picture type and format
-            let (extension, mime_type) = match picture.mime_type() {
-                Some(mime) => {
-                    let mime_str = mime.as_str();
-                    let ext = if mime_str.contains("jpeg") || mime_str.contains("jpg") {
-                        "jpg"
-                    } else if mime_str.contains("png") {
-                        "png"
-                    } else if mime_str.contains("webp") {
-                        "webp"
-                    } else {
-                        "jpg" // Default to jpg
-                    };
-                    (ext, Some(mime_str.to_string()))
-                }
-                None => ("jpg", None),
-            };
+    stored
+}
 
-            let filename = format!("cover.{}", extension);
-            let album_art_path = album_art_dir.join(&filename);
+/// Integer MP4 atoms the generic tag leaves out
+#[derive(Default)]
+struct Mp4Atoms {
+    /// Parental advisory from the rtng atom
+    advisory: Option<bool>,
+    /// iTunes media kind from the stik atom, e.g. 2 for audiobooks
+    media_kind: Option<u8>,
+    /// Tempo from the tmpo atom
+    bpm: Option<u16>,
+}
 
-            // Save the album art file
-            match tokio::fs::write(&album_art_path, picture_data).await {
-                Ok(_) => {
-                    let path_str = album_art_path.to_string_lossy().to_string();
-                    let size = picture_data.len() as i32;
+// Name Mp3tag and other taggers give an iTunes media kind as ITUNESMEDIATYPE
+fn media_kind_name(kind: u8) -> String {
+    match kind {
+        1 => "Normal".to_string(),
+        2 => "Audiobook".to_string(),
+        6 => "Music Video".to_string(),
+        9 => "Movie".to_string(),
+        10 => "TV Show".to_string(),
+        11 => "Booklet".to_string(),
+        14 => "Ringtone".to_string(),
+        21 => "Podcast".to_string(),
+        23 => "iTunes U".to_string(),
+        kind => kind.to_string(),
+    }
+}
 
-                    return (Some(path_str), mime_type, Some(size));
-                }
-                Err(e) => {
-                    error!("Failed to save album art to {:?}: {:?}", album_art_path, e);
-                }
-            }
+// Read a file's tags and the details of its audio stream. Files of the
+// formats AudioInfo knows are read as such, since the generic properties
+// leave their codec details out; MP4 files also for their advisory rating
+// and media kind, integer atoms the generic tag leaves out.
+fn read_tagged_file<R: Read + Seek>(probe: Probe<R>) -> Result<(TaggedFile, Mp4Atoms, AudioInfo), LoftyError> {
+    let (tagged_file, audio_info) = match probe.file_type() {
+        Some(FileType::Mp4) => return read_mp4(&mut probe.into_inner()),
+        Some(FileType::Mpeg) => {
+            let mut reader = probe.into_inner();
+            let lame = audio_info::read_lame_header(&mut reader)?;
+            let (tagged_file, mut audio_info) = audio_info::read_described::<MpegFile, _>(&mut reader)?;
+            audio_info.lame = lame;
+            (tagged_file, audio_info)
         }
+        Some(FileType::Flac) => audio_info::read_described::<FlacFile, _>(&mut probe.into_inner())?,
+        Some(FileType::Opus) => audio_info::read_described::<OpusFile, _>(&mut probe.into_inner())?,
+        Some(FileType::Vorbis) => audio_info::read_described::<VorbisFile, _>(&mut probe.into_inner())?,
+        Some(FileType::Wav) => audio_info::read_described::<WavFile, _>(&mut probe.into_inner())?,
+        Some(FileType::Aiff) => audio_info::read_described::<AiffFile, _>(&mut probe.into_inner())?,
+        Some(FileType::Ape) => audio_info::read_described::<ApeFile, _>(&mut probe.into_inner())?,
+        Some(FileType::WavPack) => audio_info::read_described::<WavPackFile, _>(&mut probe.into_inner())?,
+        _ => (probe.read()?, AudioInfo::default()),
+    };
+    Ok((tagged_file, Mp4Atoms::default(), audio_info))
+}
+
+fn read_mp4<R: Read + Seek>(reader: &mut R) -> Result<(TaggedFile, Mp4Atoms, AudioInfo), LoftyError> {
+    let mp4 = Mp4File::read_from(reader, ParseOptions::new())?;
+    let atoms = Mp4Atoms {
+        advisory: mp4.ilst()
+            .and_then(|ilst| ilst.advisory_rating())
+            .map(|rating| rating == AdvisoryRating::Explicit),
+        media_kind: mp4.ilst()
+            .and_then(|ilst| ilst.get(&AtomIdent::Fourcc(*b"stik")))
+            .into_iter()
+            .flat_map(Atom::data)
+            .find_map(|data| match data {
+                AtomData::SignedInteger(kind) => u8::try_from(*kind).ok(),
+                AtomData::UnsignedInteger(kind) => u8::try_from(*kind).ok(),
+                AtomData::Unknown { data, .. } => data.last().copied(),
+                _ => None,
+            }),
+        bpm: mp4.ilst()
+            .and_then(|ilst| ilst.get(&AtomIdent::Fourcc(*b"tmpo")))
+            .into_iter()
+            .flat_map(Atom::data)
+            .find_map(|data| match data {
+                AtomData::SignedInteger(bpm) => u16::try_from(*bpm).ok(),
+                AtomData::UnsignedInteger(bpm) => u16::try_from(*bpm).ok(),
+                AtomData::Unknown { data, .. } => match data.as_slice() {
+                    [high, low] => Some(u16::from_be_bytes([*high, *low])),
+                    _ => None,
+                },
+                _ => None,
+            }),
+    };
+    let audio_info = mp4.properties().describe();
+    Ok((mp4.into(), atoms, audio_info))
+}
+
+// Chapter starts of an MP4 file. A damaged chapter list only loses the chapters.
+fn mp4_chapter_starts<R: Read + Seek>(tagged_file: &TaggedFile, reader: &mut R) -> Vec<(i64, String)> {
+    if tagged_file.file_type() != FileType::Mp4 {
+        return Vec::new();
     }
+    chapters::read_mp4_starts(reader).unwrap_or_else(|e| {
+        warn!("Failed to read chapters: {}", e);
+        Vec::new()
+    })
+}
 
-    (None, None, None)
+/// Run a tag parse on the blocking thread pool. Lofty reads and parses
+/// synchronously, which on the runtime's workers would stall every other
+/// task, API requests included, for the length of a large scan.
+async fn parse_blocking<T: Send + 'static>(parse: impl FnOnce() -> Result<T, LoftyError> + Send + 'static) -> Result<T, TagError> {
+    tokio::task::spawn_blocking(parse)
+        .await
+        .map_err(|e| TagError::ReadFile(std::io::Error::other(e)))?
+        .map_err(TagError::from)
 }
 
-async fn read_tags(path: &Path, metadata: &Metadata) -> Result<track::ActiveModel, TagError> {
-    let created = chrono::DateTime::from(metadata.created().unwrap());
-    let modified = chrono::DateTime::from(metadata.modified().unwrap());
+/// Whether a track is explicit according to its ITUNESADVISORY (1 or 4 for
+/// explicit, 0 or 2 for clean) or EXPLICIT tag. None when neither is set.
+fn explicit_from_tags(all_tags: &HashMap<String, String>) -> Option<bool> {
+    ["ITUNESADVISORY", "EXPLICIT"].iter()
+        .filter_map(|key| all_tags.iter().find(|(tag, _)| tag.eq_ignore_ascii_case(key)))
+        .find_map(|(_, value)| match value.trim().to_lowercase().as_str() {
+            "1" | "4" | "explicit" | "true" | "yes" => Some(true),
+            "0" | "2" | "clean" | "false" | "no" => Some(false),
+            _ => None,
+        })
+}
 
-    let probe = Probe::open(path)?;
-    let tagged_file = probe.read()?;
+async fn read_tags(storage: &dyn Storage, entry: &FileEntry, tag_hash: Option<String>) -> Result<track::ActiveModel, TagError> {
+    let path = Path::new(&entry.path);
+
+    // Not every filesystem records a creation time (e.g. many network mounts and
+    // object stores), so fall back to the modification time
+    let modified = entry.modified;
+    let created = entry.created.unwrap_or(modified);
+
+    let (tagged_file, mp4_atoms, mut audio_info, chapter_starts, content_hash, audio_md5) = match storage.local_path(&entry.path) {
+        Some(local_path) => {
+            let content_hash = content_hash(storage, &entry.path, entry.size).await?;
+            let (tagged_file, mp4_atoms, audio_info, chapter_starts) = parse_blocking(move || {
+                let (tagged_file, mp4_atoms, audio_info) = read_tagged_file(Probe::open(&local_path)?.guess_file_type()?)?;
+                let chapter_starts = mp4_chapter_starts(&tagged_file, &mut std::fs::File::open(&local_path)?);
+                Ok((tagged_file, mp4_atoms, audio_info, chapter_starts))
+            }).await?;
+            let audio_md5 = if tagged_file.file_type() == FileType::Flac && entry.size >= 42 {
+                integrity::flac_audio_md5(&storage.read_range(&entry.path, 0, 41).await?)
+            } else {
+                None
+            };
+            (tagged_file, mp4_atoms, audio_info, chapter_starts, content_hash, audio_md5)
+        }
+        None => {
+            // Remote files are fetched once and parsed from memory
+            let data = storage.read(&entry.path).await?;
+            let content_hash = content_hash_of(&data);
+            let audio_md5 = integrity::flac_audio_md5(&data);
+            let (tagged_file, mp4_atoms, audio_info, chapter_starts) = parse_blocking(move || {
+                let (tagged_file, mp4_atoms, audio_info) = read_tagged_file(Probe::new(Cursor::new(&data)).guess_file_type()?)?;
+                let chapter_starts = mp4_chapter_starts(&tagged_file, &mut Cursor::new(&data));
+                Ok((tagged_file, mp4_atoms, audio_info, chapter_starts))
+            }).await?;
+            (tagged_file, mp4_atoms, audio_info, chapter_starts, content_hash, audio_md5)
+        }
+    };
 
     let tag_option = match tagged_file.primary_tag() {
         Some(primary_tag) => Option::from(primary_tag),
@@ -550,13 +1307,19 @@ async fn read_tags(path: &Path, metadata: &Metadata) -> Result<track::ActiveMode
     }
     let tag = tag_option.unwrap();
 
+    // Prefer the format lofty detected from the file contents over the extension
+    let content_type = content_type::from_file_type(&tagged_file.file_type())
+        .map(str::to_string)
+        .unwrap_or_else(|| content_type::from_path(&entry.path));
+
     let properties = tagged_file.properties();
     let duration = properties.duration();
+    let chapters = chapters::from_starts(chapter_starts, duration.as_millis() as i64);
 
     let mut all_tags = HashMap::new();
+    let re = Regex::new(r#"Unknown\("(.+)"\)"#).unwrap();
     for item in tag.items() {
         let key = format!("{:?}", item.key());
-        let re = Regex::new(r#"Unknown\("(.+)"\)"#).unwrap();
         let key = re.replace_all(&key, "$1").to_string();
         let value = item.value().clone().into_string().unwrap_or("".to_string());
         all_tags.insert(key, value);
@@ -613,12 +1376,36 @@ async fn read_tags(path: &Path, metadata: &Metadata) -> Result<track::ActiveMode
                 })
         });
 
+    if let Some(kind) = mp4_atoms.media_kind {
+        all_tags.entry("ITUNESMEDIATYPE".to_string()).or_insert_with(|| media_kind_name(kind));
+    }
+    let explicit = mp4_atoms.advisory.or_else(|| explicit_from_tags(&all_tags));
+    let release_country = release_country_from_tags(&all_tags);
+    let bpm = [ItemKey::Bpm, ItemKey::IntegerBpm].iter()
+        .filter_map(|key| tag.get_string(key))
+        .find_map(normalize_bpm)
+        .or_else(|| mp4_atoms.bpm.and_then(|bpm| normalize_bpm(&bpm.to_string())));
+    let initial_key = tag.get_string(&ItemKey::InitialKey).and_then(musical_keys::normalize_key);
+
+    audio_info.encoder = tag.get_string(&ItemKey::EncoderSoftware)
+        .map(str::to_string)
+        .or_else(|| audio_info.lame.as_ref().and_then(|lame| lame.encoder.clone()));
+    audio_info.encoder_settings = tag.get_string(&ItemKey::EncoderSettings).map(str::to_string);
+
     // Extract album art
-    let (album_art_path, album_art_mime_type, album_art_size) = extract_album_art(path, tag).await;
+    let images = extract_images(storage.album_art_dir(&entry.path), tag).await;
+    // The album art is the front cover, or whatever picture there is for files that don't type theirs
+    let album_art = images.iter()
+        .find(|image| image.image_type == images::FRONT)
+        .or(images.first());
+    let (album_art_path, album_art_mime_type, album_art_size) = match album_art {
+        Some(image) => (Some(image.path.clone()), image.mime_type.clone(), Some(image.size)),
+        None => (None, None, None),
+    };
 
     Ok(track::ActiveModel {
         id: NotSet,
-        path: Set(path.to_str().unwrap_or("").to_string()),
+        path: Set(entry.path.clone()),
         extension: Set(path.extension().unwrap_or_default().to_str().unwrap_or("").to_string()),
         title: Set(tag.title().as_deref().unwrap_or("").to_string()),
         artist: Set(tag.artist().as_deref().unwrap_or("").to_string()),
@@ -627,7 +1414,10 @@ async fn read_tags(path: &Path, metadata: &Metadata) -> Result<track::ActiveMode
         track_number: Set(track_number),
         year: Set(year),
         genre: Set(tag.genre().as_deref().unwrap_or("").to_string()),
+        // The effective album artist is filled in for untagged files by
+        // album_artists::assign_album_artists after the scan
         album_artist: Set(tag.get_string(&ItemKey::AlbumArtist).unwrap_or("").to_string()),
+        album_artist_tag: Set(tag.get_string(&ItemKey::AlbumArtist).unwrap_or("").to_string()),
         publisher: Set(tag.get_string(&ItemKey::Publisher).unwrap_or("").to_string()),
         catalog_number: Set(tag.get_string(&ItemKey::CatalogNumber).unwrap_or("").to_string()),
         duration_seconds: Set(duration.as_secs() as i32),
@@ -643,20 +1433,241 @@ async fn read_tags(path: &Path, metadata: &Metadata) -> Result<track::ActiveMode
         album_art_path: Set(album_art_path),
         album_art_mime_type: Set(album_art_mime_type),
         album_art_size: Set(album_art_size),
+        images: Set(serde_json::to_value(&images).unwrap_or_else(|_| serde_json::Value::Array(Vec::new()))),
+        content_type: Set(Some(content_type)),
+        isrc: Set(tag.get_string(&ItemKey::Isrc).and_then(normalize_isrc)),
+        barcode: Set(tag.get_string(&ItemKey::Barcode).and_then(normalize_barcode)),
+        language: Set(tag.get_string(&ItemKey::Language).and_then(normalize_language)),
+        release_country: Set(release_country),
+        explicit: Set(explicit),
+        comment: Set(comment_from_tag(tag)),
+        // Derived from the tags above by text_index::refresh_text_index after the scan
+        search_text: NotSet,
+        search_title: NotSet,
+        search_artist: NotSet,
+        search_album: NotSet,
+        search_album_artist: NotSet,
+        search_genre: NotSet,
+        transliteration: NotSet,
+        sort_artist: NotSet,
+        sort_album: NotSet,
+        sort_title: NotSet,
+        is_hidden: NotSet,
+        // Filled in by assign_library_roots after the scan
+        library_root: NotSet,
+        // Set and cleared by missing::reconcile_missing after the scan
+        missing_since: NotSet,
+        content_hash: Set(Some(content_hash)),
+        // Only hashed by scans with the quick check, so a stale hash is never trusted
+        tag_hash: Set(tag_hash),
+        // A changed file has to be verified again
+        audio_md5: Set(audio_md5),
+        audio_md5_status: Set(None),
+        audio_md5_checked: Set(None),
+        // Set by media_types::assign_media_types after the scan
+        media_type: NotSet,
+        chapters: Set(serde_json::to_value(&chapters).unwrap_or_else(|_| serde_json::Value::Array(Vec::new()))),
+        codec: Set(audio_info.codec.clone()),
+        codec_profile: Set(audio_info.codec_profile.clone()),
+        encoder: Set(audio_info.encoder.clone()),
+        bpm: Set(bpm),
+        initial_key: Set(initial_key),
+        file_size: Set(Some(entry.size as i64)),
+        audio_info: Set(serde_json::to_value(&audio_info).unwrap_or_else(|_| serde_json::Value::Object(serde_json::Map::new()))),
+        // Defaults to now on insert, and isn't in the upsert's update columns
+        added: NotSet,
         created: Set(created),
         modified: Set(modified),
     })
 }
 
+// Files are recognized after a move by their size and first and last 64 KB,
+// which tells audio files apart without reading them whole
+const HASH_SAMPLE_SIZE: u64 = 64 * 1024;
+
+fn fingerprint(size: u64, head: &[u8], tail: &[u8]) -> String {
+    let mut context = md5::Context::new();
+    context.consume(size.to_le_bytes());
+    context.consume(head);
+    context.consume(tail);
+    format!("{:x}", context.compute())
+}
+
+async fn content_hash(storage: &dyn Storage, path: &str, size: u64) -> std::io::Result<String> {
+    if size == 0 {
+        return Ok(fingerprint(0, &[], &[]));
+    }
+    let head = storage.read_range(path, 0, size.min(HASH_SAMPLE_SIZE) - 1).await?;
+    let tail = storage.read_range(path, size.saturating_sub(HASH_SAMPLE_SIZE), size - 1).await?;
+    Ok(fingerprint(size, &head, &tail))
+}
+
+fn content_hash_of(data: &[u8]) -> String {
+    let sample = HASH_SAMPLE_SIZE as usize;
+    fingerprint(data.len() as u64, &data[..data.len().min(sample)], &data[data.len().saturating_sub(sample)..])
+}
+
+/// Normalize an ISRC to its 12 character form (e.g. "US-RC1-76-07839" -> "USRC17607839"),
+/// returning None for values that aren't a valid ISRC
+pub fn normalize_isrc(value: &str) -> Option<String> {
+    let isrc: String = value.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_ascii_uppercase();
+
+    // Checked before slicing, since the lengths are in bytes
+    let valid = isrc.is_ascii()
+        && isrc.len() == 12
+        && isrc[..2].chars().all(|c| c.is_ascii_alphabetic())
+        && isrc[2..5].chars().all(|c| c.is_ascii_alphanumeric())
+        && isrc[5..].chars().all(|c| c.is_ascii_digit());
+    valid.then_some(isrc)
+}
+
+/// The file's comment, or its description (MP4 `desc`, Vorbis DESCRIPTION)
+/// without one. iTunes writes its normalization data and gapless info as
+/// comments, which aren't anyone's notes.
+fn comment_from_tag(tag: &lofty::tag::Tag) -> Option<String> {
+    let description = ItemKey::Unknown("DESCRIPTION".to_string());
+    let comment = tag.get_strings(&ItemKey::Comment)
+        .chain(tag.get_strings(&ItemKey::Description))
+        .chain(tag.get_strings(&description))
+        .map(str::trim)
+        .find(|comment| !comment.is_empty() && !is_itunes_data(comment))
+        .map(str::to_string);
+    comment
+}
+
+// iTunNORM and iTunSMPB values: runs of 8 or 16 digit hex words
+fn is_itunes_data(comment: &str) -> bool {
+    comment.split_whitespace().count() >= 4
+        && comment.split_whitespace().all(|word| matches!(word.len(), 8 | 16) && word.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Normalize a UPC/EAN barcode to its digits, returning None unless it has a
+/// valid EAN-8, UPC-A, EAN-13 or GTIN-14 length
+pub fn normalize_barcode(value: &str) -> Option<String> {
+    let barcode: String = value.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect();
+
+    let valid = matches!(barcode.len(), 8 | 12 | 13 | 14)
+        && barcode.chars().all(|c| c.is_ascii_digit());
+    valid.then_some(barcode)
+}
+
+/// Normalize a language tag to a lowercase ISO 639 code (e.g. "JPN" -> "jpn"),
+/// keeping the first of several. None for anything but a 2 or 3 letter code.
+pub fn normalize_language(value: &str) -> Option<String> {
+    let language = value.split([';', '/', ',']).next()?.trim();
+    let valid = matches!(language.len(), 2 | 3) && language.chars().all(|c| c.is_ascii_alphabetic());
+    valid.then(|| language.to_ascii_lowercase())
+}
+
+/// Normalize a release country to an uppercase ISO 3166-1 code (e.g. "jp" ->
+/// "JP"), including MusicBrainz's XW for worldwide and XE for Europe
+pub fn normalize_country(value: &str) -> Option<String> {
+    let country = value.trim();
+    let valid = country.len() == 2 && country.chars().all(|c| c.is_ascii_alphabetic());
+    valid.then(|| country.to_ascii_uppercase())
+}
+
+/// Parse a BPM tag, e.g. "128" or "127.98" (or "127,98"), rounded to
+/// hundredths. Zero, which some taggers write for unknown, gives None.
+pub fn normalize_bpm(value: &str) -> Option<f64> {
+    let bpm: f64 = value.trim().replace(',', ".").parse().ok()?;
+    (bpm > 0.0 && bpm < 1000.0).then(|| (bpm * 100.0).round() / 100.0)
+}
+
+// MusicBrainz Picard's release country: RELEASECOUNTRY in Vorbis comments and
+// APE, a TXXX frame in ID3 and a freeform atom in MP4
+fn release_country_from_tags(all_tags: &HashMap<String, String>) -> Option<String> {
+    ["RELEASECOUNTRY", "MusicBrainz Album Release Country", "----:com.apple.iTunes:MusicBrainz Album Release Country"].iter()
+        .filter_map(|key| all_tags.iter().find(|(tag, _)| tag.eq_ignore_ascii_case(key)))
+        .find_map(|(_, value)| normalize_country(value))
+}
+
 #[derive(Debug)]
-#[allow(dead_code)]
 enum TagError {
+    ReadFile(std::io::Error),
     ReadTag(LoftyError),
     NoTags,
 }
 
+impl TagError {
+    fn kind(&self) -> &'static str {
+        match self {
+            TagError::ReadFile(_) => "read_file",
+            TagError::ReadTag(_) => "read_tag",
+            TagError::NoTags => "no_tags",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            TagError::ReadFile(e) => e.to_string(),
+            TagError::ReadTag(e) => e.to_string(),
+            TagError::NoTags => "File has no readable tags".to_string(),
+        }
+    }
+}
+
+impl From<std::io::Error> for TagError {
+    fn from(e: std::io::Error) -> Self {
+        TagError::ReadFile(e)
+    }
+}
+
 impl From<LoftyError> for TagError {
     fn from(e: LoftyError) -> Self {
         TagError::ReadTag(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{normalize_isrc, ReadRetries};
+
+    #[test]
+    fn normalizes_isrc() {
+        assert_eq!(normalize_isrc("us-rc1-76-07839").as_deref(), Some("USRC17607839"));
+        assert_eq!(normalize_isrc(" USRC1 7607839 ").as_deref(), Some("USRC17607839"));
+        assert_eq!(normalize_isrc("USRC1760783"), None);
+        assert_eq!(normalize_isrc("1SRC17607839"), None);
+    }
+
+    #[test]
+    fn rejects_non_ascii_isrc() {
+        // 12 bytes, but multi-byte characters would split inside the slices
+        assert_eq!(normalize_isrc("ÜSRC1760783"), None);
+        assert_eq!(normalize_isrc("USRC1760ü39"), None);
+        assert_eq!(normalize_isrc("日本RC1760"), None);
+        assert_eq!(normalize_isrc("ｕｓｒｃ１７６０７８３９"), None);
+    }
+
+    #[test]
+    fn backs_off_read_retries_until_the_cap() {
+        let retries = ReadRetries { attempts: 3, delay: Duration::from_secs(30 * 60) };
+        let occurred = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let after = |minutes| Some(occurred + chrono::Duration::minutes(minutes));
+        assert_eq!(retries.next_retry("read_file", 1, occurred), after(30));
+        assert_eq!(retries.next_retry("read_file", 2, occurred), after(60));
+        assert_eq!(retries.next_retry("read_file", 3, occurred), after(120));
+        assert_eq!(retries.next_retry("read_file", 4, occurred), None);
+    }
+
+    #[test]
+    fn only_retries_read_failures() {
+        let retries = ReadRetries { attempts: 3, delay: Duration::from_secs(60) };
+        let occurred = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        assert_eq!(retries.next_retry("read_tag", 1, occurred), None);
+        assert_eq!(retries.next_retry("no_tags", 1, occurred), None);
+        let disabled = ReadRetries { attempts: 0, delay: Duration::from_secs(60) };
+        assert_eq!(disabled.next_retry("read_file", 1, occurred), None);
+        // Long streaks don't overflow the backoff
+        let unlimited = ReadRetries { attempts: u32::MAX, delay: Duration::from_secs(60) };
+        assert!(unlimited.next_retry("read_file", i32::MAX, occurred).is_some());
+    }
+}
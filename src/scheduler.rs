@@ -0,0 +1,393 @@
+use std::env;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Local, Utc};
+use cron::Schedule;
+use log::{error, info, warn};
+use sea_orm::DatabaseConnection;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use entity::track;
+
+use crate::browse::CountCache;
+use crate::hidden;
+use crate::scan_profile::ScanProfileSummary;
+use crate::scanner::{self, ScanConfig, ScanResult};
+use crate::storage::FileEntry;
+use crate::webhooks::Webhooks;
+
+type ScanOutcome = Result<ScanResult, Box<dyn std::error::Error + Send + Sync>>;
+
+// How often a folder scan that is due checks whether another scan has finished
+pub const BUSY_RETRY_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Clone, Default, Serialize, ToSchema)]
+pub struct LastScan {
+    pub scan_id: Option<String>,
+    pub trigger: String,
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    pub files_scanned: u64,
+    pub tracks_processed: usize,
+    pub errors_recorded: usize,
+    pub error: Option<String>,
+    /// Per-phase timings, when SCAN_PROFILE is enabled
+    pub profile: Option<ScanProfileSummary>,
+}
+
+#[derive(Clone, Default, Serialize, ToSchema)]
+pub struct ScanStatus {
+    pub running: bool,
+    pub current_trigger: Option<String>,
+    pub current_started: Option<DateTime<Utc>>,
+    pub schedule: Option<String>,
+    pub next_scheduled_run: Option<DateTime<Utc>>,
+    pub last_scan: Option<LastScan>,
+    /// Folders scanned on their own schedules (FOLDER_SCAN_SCHEDULES)
+    pub folders: Vec<FolderScanStatus>,
+}
+
+/// Which of several due folder scans runs first
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl FromStr for ScanPriority {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "low" => Ok(Self::Low),
+            "normal" => Ok(Self::Normal),
+            "high" => Ok(Self::High),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct FolderScanStatus {
+    pub folder: String,
+    pub schedule: String,
+    pub priority: ScanPriority,
+    pub next_run: Option<DateTime<Utc>>,
+    /// Due, and waiting for another scan to finish
+    pub waiting: bool,
+    pub last_scan: Option<LastScan>,
+}
+
+/// A library folder scanned on its own schedule
+pub struct FolderSchedule {
+    pub folder: String,
+    pub expression: String,
+    pub schedule: Schedule,
+    pub priority: ScanPriority,
+}
+
+/// Folder schedules from the semicolon separated `folder=schedule` or
+/// `folder=schedule=priority` rules of FOLDER_SCAN_SCHEDULES. Relative
+/// folders are under MUSIC_PATH. Invalid rules are logged and skipped.
+pub fn folder_schedules_from_env(music_path: &str) -> Vec<FolderSchedule> {
+    let rules = env::var("FOLDER_SCAN_SCHEDULES").unwrap_or_default();
+    rules.split(';')
+        .filter(|rule| !rule.trim().is_empty())
+        .filter_map(|rule| {
+            let Some((rest, last)) = rule.rsplit_once('=') else {
+                warn!("Ignoring FOLDER_SCAN_SCHEDULES rule {:?}: expected folder=schedule", rule);
+                return None;
+            };
+            let (folder, expression, priority) = match last.parse::<ScanPriority>() {
+                Ok(priority) => match rest.rsplit_once('=') {
+                    Some((folder, expression)) => (folder, expression, priority),
+                    None => (rest, "", priority),
+                },
+                Err(()) => (rest, last, ScanPriority::default()),
+            };
+            let folder = hidden::resolve_directory(music_path, folder)?;
+            let expression = expression.trim().to_string();
+            match parse_schedule(&expression) {
+                Ok(schedule) => Some(FolderSchedule { folder, expression, schedule, priority }),
+                Err(e) => {
+                    warn!("Ignoring FOLDER_SCAN_SCHEDULES rule {:?}: invalid schedule: {}", rule, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Shared scan state so the initial scan, manual rescans and scheduled scans
+/// never run on top of each other
+#[derive(Default)]
+pub struct ScanTracker {
+    running: AtomicBool,
+    status: RwLock<ScanStatus>,
+    // When a scan last wrote tracks, for clients polling for library changes
+    last_library_change: RwLock<Option<DateTime<Utc>>>,
+}
+
+impl ScanTracker {
+    pub fn status(&self) -> ScanStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    /// When the most recent scan that added or updated tracks finished
+    pub fn last_library_change(&self) -> Option<DateTime<Utc>> {
+        *self.last_library_change.read().unwrap()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    fn set_schedule(&self, schedule: &str, next_run: Option<DateTime<Utc>>) {
+        let mut status = self.status.write().unwrap();
+        status.schedule = Some(schedule.to_string());
+        status.next_scheduled_run = next_run;
+    }
+
+    fn set_folders(&self, folders: Vec<FolderScanStatus>) {
+        self.status.write().unwrap().folders = folders;
+    }
+
+    fn try_start(&self, trigger: &str) -> bool {
+        if self.running.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            return false;
+        }
+
+        let mut status = self.status.write().unwrap();
+        status.running = true;
+        status.current_trigger = Some(trigger.to_string());
+        status.current_started = Some(Utc::now());
+        true
+    }
+
+    fn finish(&self, outcome: &ScanOutcome) {
+        {
+            let mut status = self.status.write().unwrap();
+            let mut last_scan = LastScan {
+                trigger: status.current_trigger.take().unwrap_or_default(),
+                started: status.current_started.take().unwrap_or_else(Utc::now),
+                finished: Utc::now(),
+                ..Default::default()
+            };
+            match outcome {
+                Ok(result) => {
+                    if result.tracks_processed > 0 {
+                        *self.last_library_change.write().unwrap() = Some(last_scan.finished);
+                    }
+                    last_scan.scan_id = Some(result.scan_id.clone());
+                    last_scan.files_scanned = result.files_scanned;
+                    last_scan.tracks_processed = result.tracks_processed;
+                    last_scan.errors_recorded = result.errors_recorded;
+                    last_scan.profile = result.profile.clone();
+                }
+                Err(e) => last_scan.error = Some(e.to_string()),
+            }
+            status.running = false;
+            status.last_scan = Some(last_scan);
+        }
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Run a scan unless one is already in progress. Returns None when skipped.
+pub async fn run_scan(
+    db: &DatabaseConnection,
+    config: ScanConfig,
+    trigger: &str,
+    tracker: &ScanTracker,
+    counts: &CountCache,
+    webhooks: &Webhooks,
+) -> Option<ScanOutcome> {
+    if !tracker.try_start(trigger) {
+        return None;
+    }
+
+    let outcome = scanner::scan_music_library(db, config).await;
+    if let Ok(result) = &outcome {
+        counts.invalidate();
+        webhooks.scan_completed(result);
+    }
+    tracker.finish(&outcome);
+    Some(outcome)
+}
+
+/// Re-read some files now (see scanner::scan_files) unless a scan is already
+/// in progress. Returns None when skipped.
+pub async fn run_file_scan(
+    db: &DatabaseConnection,
+    music_path: &str,
+    files: Vec<FileEntry>,
+    known: &[track::Model],
+    tracker: &ScanTracker,
+    counts: &CountCache,
+    webhooks: &Webhooks,
+) -> Option<ScanOutcome> {
+    if !tracker.try_start("path") {
+        return None;
+    }
+
+    let outcome = scanner::scan_files(db, music_path, files, known).await;
+    if let Ok(result) = &outcome {
+        counts.invalidate();
+        webhooks.scan_completed(result);
+    }
+    tracker.finish(&outcome);
+    Some(outcome)
+}
+
+/// Scan the new and changed files of one folder (see scanner::scan_folder)
+/// unless a scan is already in progress. Returns None when skipped.
+pub async fn run_folder_scan(
+    db: &DatabaseConnection,
+    music_path: &str,
+    folder: &str,
+    tracker: &ScanTracker,
+    counts: &CountCache,
+    webhooks: &Webhooks,
+) -> Option<ScanOutcome> {
+    if !tracker.try_start(&format!("folder:{}", folder)) {
+        return None;
+    }
+
+    let outcome = scanner::scan_folder(db, music_path, folder).await;
+    if let Ok(result) = &outcome {
+        counts.invalidate();
+        webhooks.scan_completed(result);
+    }
+    tracker.finish(&outcome);
+    Some(outcome)
+}
+
+/// Parse a cron expression (seconds first, e.g. "0 0 4 * * *" for 4am daily)
+pub fn parse_schedule(expression: &str) -> Result<Schedule, cron::error::Error> {
+    Schedule::from_str(expression)
+}
+
+fn next_run(schedule: &Schedule) -> Option<DateTime<Utc>> {
+    schedule.upcoming(Local).next().map(|time| time.with_timezone(&Utc))
+}
+
+/// Start a background task that runs incremental scans on the given schedule,
+/// evaluated in the server's local time zone
+pub fn spawn_scheduler(
+    db: DatabaseConnection,
+    music_path: String,
+    expression: String,
+    schedule: Schedule,
+    tracker: Arc<ScanTracker>,
+    counts: Arc<CountCache>,
+    webhooks: Arc<Webhooks>,
+) {
+    tokio::spawn(async move {
+        info!("Scheduled scans enabled: {}", expression);
+
+        loop {
+            let Some(next) = next_run(&schedule) else {
+                warn!("Scan schedule '{}' has no upcoming runs, stopping scheduler", expression);
+                tracker.set_schedule(&expression, None);
+                return;
+            };
+            tracker.set_schedule(&expression, Some(next));
+
+            let wait = (next - Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+
+            let scan_config = ScanConfig {
+                music_path: music_path.clone(),
+                show_progress: false,
+                ..Default::default()
+            };
+
+            // Record the following run before scanning so status stays accurate mid-scan
+            tracker.set_schedule(&expression, next_run(&schedule));
+
+            match run_scan(&db, scan_config, "scheduled", &tracker, &counts, &webhooks).await {
+                Some(Ok(result)) => {
+                    info!("Scheduled scan {} completed: {} files scanned, {} tracks processed, {} errors recorded",
+                          result.scan_id, result.files_scanned, result.tracks_processed, result.errors_recorded);
+                }
+                Some(Err(e)) => error!("Error during scheduled scan: {}", e),
+                None => info!("Skipping scheduled scan, a scan is already running"),
+            }
+        }
+    });
+}
+
+/// Start a background task that scans folders on their own schedules. Folder
+/// scans that come due while another scan runs wait for it, and those waiting
+/// together run highest priority first.
+pub fn spawn_folder_scheduler(
+    db: DatabaseConnection,
+    music_path: String,
+    folders: Vec<FolderSchedule>,
+    tracker: Arc<ScanTracker>,
+    counts: Arc<CountCache>,
+    webhooks: Arc<Webhooks>,
+) {
+    tokio::spawn(async move {
+        for folder in &folders {
+            info!("Scheduled scans of {} enabled: {} ({:?} priority)", folder.folder, folder.expression, folder.priority);
+        }
+        let mut status: Vec<FolderScanStatus> = folders.iter()
+            .map(|folder| FolderScanStatus {
+                folder: folder.folder.clone(),
+                schedule: folder.expression.clone(),
+                priority: folder.priority,
+                next_run: next_run(&folder.schedule),
+                waiting: false,
+                last_scan: None,
+            })
+            .collect();
+        // Due folders in the order they came due
+        let mut waiting: Vec<usize> = Vec::new();
+
+        loop {
+            let now = Utc::now();
+            for (index, folder) in folders.iter().enumerate() {
+                if status[index].next_run.is_some_and(|next| next <= now) {
+                    if !waiting.contains(&index) {
+                        waiting.push(index);
+                    }
+                    status[index].next_run = next_run(&folder.schedule);
+                }
+                status[index].waiting = waiting.contains(&index);
+            }
+            tracker.set_folders(status.clone());
+
+            // The first of the highest priority
+            let Some(position) = (0..waiting.len()).rev().max_by_key(|&position| folders[waiting[position]].priority) else {
+                let Some(next) = status.iter().filter_map(|folder| folder.next_run).min() else {
+                    warn!("No folder scan schedule has upcoming runs, stopping folder scheduler");
+                    return;
+                };
+                tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+                continue;
+            };
+            let index = waiting[position];
+            let folder = &folders[index].folder;
+            match run_folder_scan(&db, &music_path, folder, &tracker, &counts, &webhooks).await {
+                Some(outcome) => {
+                    waiting.remove(position);
+                    match outcome {
+                        Ok(result) => info!("Scheduled scan of {} completed: {} files read, {} tracks processed, {} errors recorded",
+                                            folder, result.files_scanned, result.tracks_processed, result.errors_recorded),
+                        Err(e) => error!("Error during scheduled scan of {}: {}", folder, e),
+                    }
+                    status[index].last_scan = tracker.status().last_scan;
+                }
+                None => tokio::time::sleep(BUSY_RETRY_INTERVAL).await,
+            }
+        }
+    });
+}
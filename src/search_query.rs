@@ -0,0 +1,377 @@
+// The query syntax of /tracks/search. Plain queries match their whole text
+// against every text field, as they always have. Queries that name a field
+// (`artist:"Miles Davis"`) or use an operator (AND, OR, NOT, a leading `-`)
+// are parsed into a condition instead:
+//
+//     artist:"Miles Davis" AND year:1959 NOT album:live
+//     (genre:jazz OR genre:blues) year:1950..1969 -explicit:true
+//
+// Terms next to each other without an operator must all match. Words that
+// aren't a field filter run together into one phrase matched like a plain
+// query, so `kind of blue year:1959` looks for "kind of blue".
+
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, Condition};
+
+use entity::track;
+
+use crate::error::ApiError;
+use crate::musical_keys;
+use crate::text_index::{self, TextNormalization};
+
+// Nesting deeper than this is refused rather than risk the stack
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Copy)]
+enum FieldKind {
+    // Compared with the normalized search column, falling back to the raw one
+    // for tracks the text index hasn't reached yet
+    Normalized(&'static str, &'static str),
+    Text(&'static str),
+    Number(&'static str),
+    Flag(&'static str),
+    // A musical key in any notation, compared in the notation it is stored in
+    Key(&'static str),
+}
+
+// Field names and their aliases, with the track column each one filters
+const FIELDS: &[(&[&str], FieldKind)] = &[
+    (&["title"], FieldKind::Normalized("search_title", "title")),
+    (&["artist"], FieldKind::Normalized("search_artist", "artist")),
+    (&["album"], FieldKind::Normalized("search_album", "album")),
+    (&["albumartist", "album_artist"], FieldKind::Normalized("search_album_artist", "album_artist")),
+    (&["genre"], FieldKind::Normalized("search_genre", "genre")),
+    (&["comment"], FieldKind::Text("comment")),
+    (&["path"], FieldKind::Text("path")),
+    (&["ext", "extension"], FieldKind::Text("extension")),
+    (&["label", "publisher"], FieldKind::Text("publisher")),
+    (&["catalog", "catalog_number"], FieldKind::Text("catalog_number")),
+    (&["isrc"], FieldKind::Text("isrc")),
+    (&["language", "lang"], FieldKind::Text("language")),
+    (&["country", "release_country"], FieldKind::Text("release_country")),
+    (&["codec"], FieldKind::Text("codec")),
+    (&["profile", "codec_profile"], FieldKind::Text("codec_profile")),
+    (&["encoder"], FieldKind::Text("encoder")),
+    (&["key", "initialkey", "initial_key"], FieldKind::Key("initial_key")),
+    (&["year"], FieldKind::Number("year")),
+    (&["track", "track_number"], FieldKind::Number("track_number")),
+    (&["disc", "disc_number"], FieldKind::Number("disc_number")),
+    (&["duration"], FieldKind::Number("duration_seconds")),
+    (&["bitrate"], FieldKind::Number("audio_bitrate")),
+    (&["bpm"], FieldKind::Number("bpm")),
+    (&["samplerate", "sample_rate"], FieldKind::Number("sample_rate")),
+    (&["bitdepth", "bit_depth"], FieldKind::Number("bit_depth")),
+    (&["channels"], FieldKind::Number("channels")),
+    (&["explicit"], FieldKind::Flag("explicit")),
+];
+
+fn field(name: &str) -> Option<FieldKind> {
+    FIELDS.iter()
+        .find(|(names, _)| names.iter().any(|known| known.eq_ignore_ascii_case(name)))
+        .map(|(_, kind)| *kind)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    // A word or quoted phrase, with the position of the colon ending its
+    // field name when it was written outside quotes
+    Word(String, Option<usize>),
+}
+
+fn tokenize(query: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let mut word = String::new();
+                let mut colon = None;
+                let mut quoted = false;
+                if c == '-' {
+                    chars.next();
+                    match chars.peek() {
+                        Some(next) if !next.is_whitespace() && *next != ')' => tokens.push(Token::Not),
+                        _ => word.push('-'),
+                    }
+                }
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    chars.next();
+                    match c {
+                        '"' => {
+                            quoted = true;
+                            for c in chars.by_ref() {
+                                if c == '"' {
+                                    break;
+                                }
+                                word.push(c);
+                            }
+                        }
+                        ':' if colon.is_none() && !quoted => {
+                            colon = Some(word.len());
+                            word.push(c);
+                        }
+                        c => word.push(c),
+                    }
+                }
+                tokens.push(match word.as_str() {
+                    "AND" if !quoted => Token::And,
+                    "OR" if !quoted => Token::Or,
+                    "NOT" if !quoted => Token::Not,
+                    _ => Token::Word(word, colon),
+                });
+            }
+        }
+    }
+    tokens
+}
+
+enum Comparison {
+    Contains(String),
+    Equals(String),
+    Number(&'static str, i64),
+    Between(i64, i64),
+    Flag(bool),
+}
+
+enum Node {
+    Text(String),
+    Field(FieldKind, Comparison),
+    Not(Box<Node>),
+    And(Vec<Node>),
+    Or(Vec<Node>),
+}
+
+fn invalid(message: impl Into<String>) -> ApiError {
+    ApiError::invalid_field("q", message)
+}
+
+fn parse_number(name: &str, value: &str) -> Result<i64, ApiError> {
+    value.trim().parse().map_err(|_| invalid(format!("{} must be compared with a whole number, not \"{}\"", name, value)))
+}
+
+// A field filter's value: text with an optional leading = for an exact
+// match, a number with an optional comparison or a from..to range, or a flag
+fn comparison(name: &str, kind: FieldKind, value: &str) -> Result<Comparison, ApiError> {
+    match kind {
+        FieldKind::Normalized(..) | FieldKind::Text(_) => Ok(match value.strip_prefix('=') {
+            Some(exact) => Comparison::Equals(exact.to_string()),
+            None => Comparison::Contains(value.to_string()),
+        }),
+        FieldKind::Number(_) => {
+            if let Some((from, to)) = value.split_once("..") {
+                return Ok(Comparison::Between(parse_number(name, from)?, parse_number(name, to)?));
+            }
+            for operator in [">=", "<=", ">", "<", "="] {
+                if let Some(number) = value.strip_prefix(operator) {
+                    return Ok(Comparison::Number(operator, parse_number(name, number)?));
+                }
+            }
+            Ok(Comparison::Number("=", parse_number(name, value)?))
+        }
+        FieldKind::Key(_) => musical_keys::normalize_key(value.strip_prefix('=').unwrap_or(value))
+            .map(Comparison::Equals)
+            .ok_or_else(|| invalid(format!("{} must be a key such as Am, 8A or 1m, not \"{}\"", name, value))),
+        FieldKind::Flag(_) => match value.to_lowercase().as_str() {
+            "true" | "yes" | "1" => Ok(Comparison::Flag(true)),
+            "false" | "no" | "0" => Ok(Comparison::Flag(false)),
+            _ => Err(invalid(format!("{} must be true or false, not \"{}\"", name, value))),
+        },
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn or(&mut self, depth: usize) -> Result<Node, ApiError> {
+        let mut terms = vec![self.and(depth)?];
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            terms.push(self.and(depth)?);
+        }
+        Ok(if terms.len() == 1 { terms.remove(0) } else { Node::Or(terms) })
+    }
+
+    fn and(&mut self, depth: usize) -> Result<Node, ApiError> {
+        let mut terms = Vec::new();
+        let mut explicit_and = false;
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) | Some(Token::Or) => break,
+                Some(Token::And) => {
+                    self.position += 1;
+                    if terms.is_empty() || explicit_and {
+                        return Err(invalid("AND needs a term on both sides"));
+                    }
+                    explicit_and = true;
+                }
+                _ => {
+                    let term = self.unary(depth)?;
+                    // Words next to each other run together into one phrase
+                    match (terms.last_mut(), term) {
+                        (Some(Node::Text(phrase)), Node::Text(word)) if !explicit_and => {
+                            phrase.push(' ');
+                            phrase.push_str(&word);
+                        }
+                        (_, term) => terms.push(term),
+                    }
+                    explicit_and = false;
+                }
+            }
+        }
+        if explicit_and {
+            return Err(invalid("AND needs a term on both sides"));
+        }
+        match terms.len() {
+            0 => Err(invalid("expected a search term")),
+            1 => Ok(terms.remove(0)),
+            _ => Ok(Node::And(terms)),
+        }
+    }
+
+    fn unary(&mut self, depth: usize) -> Result<Node, ApiError> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("query is nested too deeply"));
+        }
+        match self.tokens.get(self.position) {
+            Some(Token::Not) => {
+                self.position += 1;
+                Ok(Node::Not(Box::new(self.unary(depth + 1)?)))
+            }
+            Some(Token::Open) => {
+                self.position += 1;
+                let node = self.or(depth + 1)?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(invalid("unbalanced parentheses"));
+                }
+                self.position += 1;
+                Ok(node)
+            }
+            Some(Token::Word(word, colon)) => {
+                let node = match colon.and_then(|colon| Some((&word[..colon], field(&word[..colon])?, &word[colon + 1..]))) {
+                    Some((name, kind, value)) => Node::Field(kind, comparison(name, kind, value)?),
+                    None => Node::Text(word.clone()),
+                };
+                self.position += 1;
+                Ok(node)
+            }
+            _ => Err(invalid("expected a search term")),
+        }
+    }
+}
+
+/// A search query, parsed when it uses the field and operator syntax
+pub struct SearchQuery(Node);
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Result<Self, ApiError> {
+        let tokens = tokenize(query);
+        let structured = tokens.iter().any(|token| match token {
+            Token::Word(word, Some(colon)) => field(&word[..*colon]).is_some(),
+            Token::And | Token::Or | Token::Not => true,
+            _ => false,
+        });
+        if !structured {
+            return Ok(Self(Node::Text(query.to_string())));
+        }
+
+        let mut parser = Parser { tokens, position: 0 };
+        let node = parser.or(0)?;
+        if parser.position < parser.tokens.len() {
+            return Err(invalid("unbalanced parentheses"));
+        }
+        Ok(Self(node))
+    }
+
+    /// Condition on track columns selecting the tracks the query matches
+    pub fn condition(&self, normalization: TextNormalization) -> Condition {
+        node_condition(&self.0, normalization)
+    }
+}
+
+/// Tracks with `term` in any of their text fields, in the spelling written
+/// or, for kana, in the other script
+pub fn text_condition(normalization: TextNormalization, term: &str) -> Condition {
+    let condition = Condition::any()
+        .add(track::Column::Title.contains(term))
+        .add(track::Column::Artist.contains(term))
+        .add(track::Column::Album.contains(term))
+        .add(track::Column::Genre.contains(term))
+        .add(track::Column::AlbumArtist.contains(term))
+        .add(track::Column::Comment.contains(term))
+        .add(track::Column::SearchText.contains(normalization.search_key(term)))
+        .add(track::Column::Transliteration.contains(term.to_lowercase()));
+    // A kana query also matches the other script's spelling (e.g. さくら and サクラ)
+    match text_index::romanize(term) {
+        Some(romaji) => condition.add(track::Column::Transliteration.contains(romaji)),
+        None => condition,
+    }
+}
+
+/// Escape LIKE's wildcards (and its escape character) so they match literally
+pub fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+fn like_pattern(value: &str) -> String {
+    format!("%{}%", escape_like(value))
+}
+
+fn field_condition(kind: FieldKind, comparison: &Comparison, normalization: TextNormalization) -> Condition {
+    let column = match kind {
+        FieldKind::Normalized(search_column, column) => format!(r#"COALESCE("track"."{}", LOWER("track"."{}"))"#, search_column, column),
+        FieldKind::Text(column) => format!(r#"LOWER(COALESCE("track"."{}", ''))"#, column),
+        FieldKind::Number(column) | FieldKind::Key(column) => format!(r#""track"."{}""#, column),
+        // Tracks without the flag count as false, so NOT explicit:true keeps them
+        FieldKind::Flag(column) => format!(r#"COALESCE("track"."{}", false)"#, column),
+    };
+    // Text is compared the way the column stores it
+    let text = |text: &str| match kind {
+        FieldKind::Normalized(..) => normalization.search_key(text),
+        FieldKind::Key(_) => text.to_string(),
+        _ => text.to_lowercase(),
+    };
+    let expr = match comparison {
+        Comparison::Contains(value) => Expr::cust_with_values(format!("{} LIKE $1", column), [like_pattern(&text(value))]),
+        Comparison::Equals(value) => Expr::cust_with_values(format!("{} = $1", column), [text(value)]),
+        Comparison::Number(operator, number) => Expr::cust_with_values(format!("{} {} $1", column, operator), [*number]),
+        Comparison::Between(from, to) => Expr::cust_with_values(format!("{} BETWEEN $1 AND $2", column), [*from, *to]),
+        Comparison::Flag(flag) => Expr::cust_with_values(format!("{} = $1", column), [*flag]),
+    };
+    Condition::all().add(expr)
+}
+
+fn node_condition(node: &Node, normalization: TextNormalization) -> Condition {
+    match node {
+        Node::Text(term) => text_condition(normalization, term),
+        Node::Field(kind, comparison) => field_condition(*kind, comparison, normalization),
+        Node::Not(node) => node_condition(node, normalization).not(),
+        Node::And(nodes) => nodes.iter().fold(Condition::all(), |condition, node| condition.add(node_condition(node, normalization))),
+        Node::Or(nodes) => nodes.iter().fold(Condition::any(), |condition, node| condition.add(node_condition(node, normalization))),
+    }
+}
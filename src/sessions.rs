@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use axum::body::Body;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+// Bodies are sent in chunks so bytes served can be tracked and a terminated
+// session stops mid-transfer
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// An in-flight response from the stream handlers
+pub struct StreamSession {
+    pub id: String,
+    pub user: Option<String>,
+    pub client: Option<String>,
+    pub address: Option<String>,
+    pub track_id: i32,
+    pub title: String,
+    pub artist: String,
+    pub bitrate: i32,
+    pub total_bytes: u64,
+    pub started: DateTime<Utc>,
+    bytes_served: AtomicU64,
+    terminated: AtomicBool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SessionInfo {
+    pub id: String,
+    pub user: Option<String>,
+    pub client: Option<String>,
+    /// Client's IP address, as a trusted reverse proxy reports it
+    pub address: Option<String>,
+    pub track_id: i32,
+    pub title: String,
+    pub artist: String,
+    pub bitrate: i32,
+    pub bytes_served: u64,
+    pub total_bytes: u64,
+    pub started: DateTime<Utc>,
+    pub duration_seconds: i64,
+}
+
+/// Details recorded when a stream starts
+pub struct NewSession {
+    pub user: Option<String>,
+    pub client: Option<String>,
+    pub address: Option<String>,
+    pub track_id: i32,
+    pub title: String,
+    pub artist: String,
+    pub bitrate: i32,
+    pub total_bytes: u64,
+}
+
+/// Registry of active streams, updated by the stream handlers
+#[derive(Default)]
+pub struct SessionRegistry {
+    sessions: RwLock<HashMap<String, Arc<StreamSession>>>,
+}
+
+/// Removes its session from the registry when the response body is dropped,
+/// whether it finished, the client disconnected or it was terminated
+pub struct SessionGuard {
+    registry: Arc<SessionRegistry>,
+    session: Arc<StreamSession>,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.registry.sessions.write().unwrap().remove(&self.session.id);
+    }
+}
+
+impl SessionRegistry {
+    pub fn start(self: &Arc<Self>, new: NewSession) -> SessionGuard {
+        let session = Arc::new(StreamSession {
+            id: uuid::Uuid::new_v4().to_string(),
+            user: new.user,
+            client: new.client,
+            address: new.address,
+            track_id: new.track_id,
+            title: new.title,
+            artist: new.artist,
+            bitrate: new.bitrate,
+            total_bytes: new.total_bytes,
+            started: Utc::now(),
+            bytes_served: AtomicU64::new(0),
+            terminated: AtomicBool::new(false),
+        });
+        self.sessions.write().unwrap().insert(session.id.clone(), session.clone());
+
+        SessionGuard {
+            registry: self.clone(),
+            session,
+        }
+    }
+
+    /// Active sessions, oldest first
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let now = Utc::now();
+        let mut sessions: Vec<SessionInfo> = self.sessions.read().unwrap()
+            .values()
+            .map(|session| SessionInfo {
+                id: session.id.clone(),
+                user: session.user.clone(),
+                client: session.client.clone(),
+                address: session.address.clone(),
+                track_id: session.track_id,
+                title: session.title.clone(),
+                artist: session.artist.clone(),
+                bitrate: session.bitrate,
+                bytes_served: session.bytes_served.load(Ordering::Relaxed),
+                total_bytes: session.total_bytes,
+                started: session.started,
+                duration_seconds: now.signed_duration_since(session.started).num_seconds(),
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.started);
+        sessions
+    }
+
+    /// Stop a session's transfer. Returns false if no such session is active.
+    pub fn terminate(&self, id: &str) -> bool {
+        match self.sessions.read().unwrap().get(id) {
+            Some(session) => {
+                session.terminated.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Wrap response data in a body that reports progress to its session
+pub fn tracked_body(data: Bytes, guard: SessionGuard) -> Body {
+    let chunks = (0..data.len())
+        .step_by(CHUNK_SIZE)
+        .map(move |start| Ok(data.slice(start..data.len().min(start + CHUNK_SIZE))));
+    tracked_stream(futures::stream::iter(chunks), guard)
+}
+
+/// Wrap a stream of response data in a body that reports progress to its
+/// session, passing chunks on as they arrive
+pub fn tracked_stream<S>(stream: S, guard: SessionGuard) -> Body
+where
+    S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+{
+    let stream = futures::stream::unfold((stream.boxed(), guard), |(mut stream, guard)| async move {
+        let chunk = stream.next().await?;
+        if guard.session.terminated.load(Ordering::SeqCst) {
+            let error = io::Error::other("stream session terminated");
+            return Some((Err(error), (futures::stream::empty().boxed(), guard)));
+        }
+
+        if let Ok(chunk) = &chunk {
+            guard.session.bytes_served.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+        Some((chunk, (stream, guard)))
+    });
+
+    Body::from_stream(stream)
+}
@@ -0,0 +1,126 @@
+use axum::{
+    extract::State,
+    response::Json,
+};
+use sea_orm::{ColumnTrait, EntityTrait, Order, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect};
+use sea_query::Expr;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use entity::prelude::Track;
+use entity::track;
+
+use crate::api::{AppState, TrackResponse};
+use crate::media_types::{MediaTypeQuery, MediaTypes};
+use crate::error::{ApiError, ApiQuery, ApiResult};
+use crate::hidden::{Visibility, VisibilityQuery};
+use crate::text_index;
+
+const DEFAULT_COUNT: u64 = 50;
+const MAX_COUNT: u64 = 500;
+const MAX_SEED_LENGTH: usize = 128;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ShuffleQuery {
+    /// Any string; the same seed gives the same order. Generated when left out
+    pub seed: Option<String>,
+    /// Tracks to return (default 50, max 500)
+    pub count: Option<u64>,
+    /// Position in the shuffle to start from, for paging (default 0)
+    pub offset: Option<u64>,
+    pub genre: Option<String>,
+    pub artist: Option<String>,
+    pub year: Option<i32>,
+    pub year_from: Option<i32>,
+    pub year_to: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ShuffleResponse {
+    /// Seed to pass back for the next page of the same shuffle
+    pub seed: String,
+    pub offset: u64,
+    pub count: u64,
+    /// Tracks matching the filters
+    pub total: u64,
+    pub tracks: Vec<TrackResponse>,
+}
+
+// GET /tracks/shuffle - A reproducible random order of tracks, with optional filters
+#[utoipa::path(
+    get,
+    path = "/tracks/shuffle",
+    tag = "tracks",
+    params(ShuffleQuery, MediaTypeQuery, VisibilityQuery),
+    responses(
+        (status = 200, description = "Page of shuffled tracks", body = ShuffleResponse),
+        (status = 400, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn shuffle_tracks(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<ShuffleQuery>,
+    media_types: MediaTypes,
+    visibility: Visibility,
+) -> ApiResult<Json<ShuffleResponse>> {
+    let count = params.count.unwrap_or(DEFAULT_COUNT);
+    if count == 0 {
+        return Err(ApiError::invalid_field("count", "must be 1 or greater"));
+    }
+    let count = count.min(MAX_COUNT);
+    let offset = params.offset.unwrap_or(0);
+    let seed = match params.seed {
+        Some(seed) if seed.is_empty() || seed.len() > MAX_SEED_LENGTH => {
+            return Err(ApiError::invalid_field("seed", "must be 1 to 128 characters"));
+        }
+        Some(seed) => seed,
+        None => uuid::Uuid::new_v4().simple().to_string(),
+    };
+    if let (Some(from), Some(to)) = (params.year_from, params.year_to) {
+        if from > to {
+            return Err(ApiError::invalid_field("year_from", "must not be after year_to"));
+        }
+    }
+
+    let mut condition = visibility.condition();
+    if let Some(genre) = params.genre {
+        condition = condition.add(text_index::contains_ignoring_case(state.text_normalization, track::Column::SearchGenre, track::Column::Genre, &genre));
+    }
+    if let Some(artist) = params.artist {
+        condition = condition.add(text_index::contains_ignoring_case(state.text_normalization, track::Column::SearchArtist, track::Column::Artist, &artist));
+    }
+    if let Some(year) = params.year {
+        condition = condition.add(track::Column::Year.eq(year));
+    }
+    if let Some(year_from) = params.year_from {
+        condition = condition.add(track::Column::Year.gte(year_from));
+    }
+    if let Some(year_to) = params.year_to {
+        condition = condition.add(track::Column::Year.lte(year_to));
+    }
+    let query = Track::find().filter(condition).filter(media_types.condition());
+    let total = query.clone().count(&state.db).await?;
+
+    // Each track's place is a hash of the seed and its ID, so pages of the
+    // same shuffle never overlap, and tracks added in between slot in
+    // without reshuffling the rest
+    let tracks = query
+        .order_by(Expr::cust_with_values("md5($1 || ':' || \"track\".\"id\"::text)", [seed.clone()]), Order::Asc)
+        .order_by_asc(track::Column::Id)
+        .offset(offset)
+        .limit(count)
+        .all(&state.db)
+        .await?
+        .into_iter()
+        .map(TrackResponse::from)
+        .collect();
+
+    Ok(Json(ShuffleResponse {
+        seed,
+        offset,
+        count,
+        total,
+        tracks,
+    }))
+}
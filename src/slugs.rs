@@ -0,0 +1,170 @@
+use std::collections::{HashMap, HashSet};
+
+use log::info;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, NotSet, QueryFilter, QuerySelect};
+
+use entity::prelude::{Slug, Track, TrackArtist};
+use entity::{slug, track, track_artist};
+
+use crate::albums;
+
+pub const ARTIST: &str = "artist";
+pub const ALBUM: &str = "album";
+
+// Keep slugs readable in URLs even for very long names
+const MAX_SLUG_LENGTH: usize = 80;
+
+/// Turn a name into a URL-safe slug: lowercase letters and digits (any
+/// script) separated by single hyphens, e.g. "AC/DC" -> "ac-dc"
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.chars().count() >= MAX_SLUG_LENGTH {
+            break;
+        }
+    }
+
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "unknown".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// Pick a slug not yet taken, appending -2, -3, ... on collisions
+fn unique_slug(base: String, taken: &mut HashSet<String>) -> String {
+    let mut slug = base.clone();
+    let mut suffix = 2;
+    while taken.contains(&slug) {
+        slug = format!("{}-{}", base, suffix);
+        suffix += 1;
+    }
+    taken.insert(slug.clone());
+    slug
+}
+
+/// Give every artist and album in the library a stable slug. Existing slugs
+/// are never changed, so IDs handed out to clients keep working; the ID scheme
+/// used before slugs is stored as legacy_id.
+pub async fn assign_slugs(db: &DatabaseConnection) -> Result<usize, DbErr> {
+    let existing = Slug::find().all(db).await?;
+    let mut keys: HashSet<(String, String)> = HashSet::new();
+    let mut taken: HashMap<String, HashSet<String>> = HashMap::new();
+    for row in existing {
+        taken.entry(row.kind.clone()).or_default().insert(row.slug);
+        keys.insert((row.kind, row.key));
+    }
+
+    let artists: Vec<String> = TrackArtist::find()
+        .select_only()
+        .column(track_artist::Column::Artist)
+        .distinct()
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let album_pairs: Vec<(String, String)> = Track::find()
+        .select_only()
+        .column(track::Column::AlbumArtist)
+        .column(track::Column::Album)
+        .distinct()
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let now = chrono::Utc::now();
+    let mut new_rows = Vec::new();
+
+    for artist in artists {
+        if keys.contains(&(ARTIST.to_string(), artist.clone())) {
+            continue;
+        }
+        let slug = unique_slug(slugify(&artist), taken.entry(ARTIST.to_string()).or_default());
+        new_rows.push(slug::ActiveModel {
+            id: NotSet,
+            kind: Set(ARTIST.to_string()),
+            legacy_id: Set(Some(urlencoding::encode(&artist).into_owned())),
+            key: Set(artist),
+            slug: Set(slug),
+            created: Set(now),
+        });
+    }
+
+    for (album_artist, album) in album_pairs {
+        let key = albums::album_key(&album_artist, &album);
+        if keys.contains(&(ALBUM.to_string(), key.clone())) {
+            continue;
+        }
+        let name = albums::album_name(&album);
+        let base = if album_artist.is_empty() {
+            slugify(name)
+        } else {
+            format!("{}-{}", slugify(&album_artist), slugify(name))
+        };
+        let slug = unique_slug(base, taken.entry(ALBUM.to_string()).or_default());
+        new_rows.push(slug::ActiveModel {
+            id: NotSet,
+            kind: Set(ALBUM.to_string()),
+            key: Set(key),
+            slug: Set(slug),
+            legacy_id: Set(Some(albums::album_id(&album_artist, &album))),
+            created: Set(now),
+        });
+    }
+
+    let assigned = new_rows.len();
+    for chunk in new_rows.chunks(1000) {
+        Slug::insert_many(chunk.to_vec()).exec(db).await?;
+    }
+
+    if assigned > 0 {
+        info!("Assigned {} new artist/album slugs", assigned);
+    }
+    Ok(assigned)
+}
+
+/// Map of key to slug for one kind, for decorating listings
+pub async fn slugs_by_key(db: &DatabaseConnection, kind: &str) -> Result<HashMap<String, String>, DbErr> {
+    Ok(Slug::find()
+        .filter(slug::Column::Kind.eq(kind))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|row| (row.key, row.slug))
+        .collect())
+}
+
+/// Slug for a single key, if one has been assigned
+pub async fn slug_for(db: &DatabaseConnection, kind: &str, key: &str) -> Result<Option<String>, DbErr> {
+    let row = Slug::find()
+        .filter(slug::Column::Kind.eq(kind))
+        .filter(slug::Column::Key.eq(key))
+        .one(db)
+        .await?;
+    Ok(row.map(|row| row.slug))
+}
+
+/// Resolve a slug, legacy ID or (for artists) plain name to its key (artist name or album key)
+pub async fn resolve(db: &DatabaseConnection, kind: &str, id: &str) -> Result<Option<String>, DbErr> {
+    let rows = Slug::find()
+        .filter(slug::Column::Kind.eq(kind))
+        .filter(Condition::any()
+            .add(slug::Column::Slug.eq(id))
+            .add(slug::Column::LegacyId.eq(id))
+            .add(slug::Column::Key.eq(id)))
+        .all(db)
+        .await?;
+    // A slug can equal another entry's legacy ID or name (an album ID is any
+    // base64 string, an artist can be called anything); slugs win, then legacy IDs
+    let row = rows.iter().find(|row| row.slug == id)
+        .or_else(|| rows.iter().find(|row| row.legacy_id.as_deref() == Some(id)))
+        .or_else(|| rows.first());
+    Ok(row.map(|row| row.key.clone()))
+}
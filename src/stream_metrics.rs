@@ -0,0 +1,211 @@
+// Counters for sizing streaming hardware: how many ffmpeg processes run at
+// once and the CPU they use, how often the stream and preview caches are hit,
+// and which formats are streamed. Kept since the server started.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use axum::response::Json;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+pub static METRICS: StreamMetrics = StreamMetrics::new();
+
+#[derive(Serialize, ToSchema)]
+pub struct TranscodeMetrics {
+    /// ffmpeg processes running now, for streams, previews, conversions and checks
+    pub active: u64,
+    /// Most ffmpeg processes that ran at once
+    pub peak_active: u64,
+    pub started: u64,
+    pub failed: u64,
+    /// Wall clock time of finished ffmpeg processes
+    pub wall_seconds: f64,
+    /// CPU time (user and system) of finished child processes, nearly all of
+    /// them ffmpeg. None where the platform doesn't report it.
+    pub cpu_seconds: Option<f64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CacheMetrics {
+    pub hits: u64,
+    pub misses: u64,
+    /// Hits out of all lookups, None before the first one
+    pub hit_rate: Option<f64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StreamingMetricsResponse {
+    pub since: DateTime<Utc>,
+    pub transcodes: TranscodeMetrics,
+    /// 1 MB chunks read from STREAM_CACHE_DIR rather than the music storage
+    pub stream_cache: CacheMetrics,
+    /// Preview clips served from PREVIEW_CACHE_DIR rather than generated
+    pub preview_cache: CacheMetrics,
+    /// /tracks/:id/play and /download requests by file extension, for files
+    /// sent as they are
+    pub original_requests: BTreeMap<String, u64>,
+    /// /tracks/:id/play requests by the format they were transcoded to
+    pub transcoded_requests: BTreeMap<String, u64>,
+}
+
+struct Cache {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Cache {
+    const fn new() -> Self {
+        Self { hits: AtomicU64::new(0), misses: AtomicU64::new(0) }
+    }
+
+    fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn metrics(&self) -> CacheMetrics {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        CacheMetrics {
+            hits,
+            misses,
+            hit_rate: (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64),
+        }
+    }
+}
+
+pub struct StreamMetrics {
+    started: Mutex<Option<DateTime<Utc>>>,
+    active: AtomicU64,
+    peak_active: AtomicU64,
+    transcodes: AtomicU64,
+    failed: AtomicU64,
+    wall_ms: AtomicU64,
+    stream_cache: Cache,
+    preview_cache: Cache,
+    original: Mutex<BTreeMap<String, u64>>,
+    transcoded: Mutex<BTreeMap<String, u64>>,
+}
+
+/// An ffmpeg process being counted as active until dropped
+pub struct TranscodeGuard {
+    started: Instant,
+}
+
+impl TranscodeGuard {
+    pub fn failed(self) {
+        METRICS.failed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for TranscodeGuard {
+    fn drop(&mut self) {
+        METRICS.active.fetch_sub(1, Ordering::Relaxed);
+        METRICS.wall_ms.fetch_add(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+impl StreamMetrics {
+    const fn new() -> Self {
+        Self {
+            started: Mutex::new(None),
+            active: AtomicU64::new(0),
+            peak_active: AtomicU64::new(0),
+            transcodes: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            wall_ms: AtomicU64::new(0),
+            stream_cache: Cache::new(),
+            preview_cache: Cache::new(),
+            original: Mutex::new(BTreeMap::new()),
+            transcoded: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Note when the server started, for `since`
+    pub fn start(&self) {
+        self.started.lock().unwrap().get_or_insert_with(Utc::now);
+    }
+
+    /// Count an ffmpeg process as running until the guard is dropped
+    pub fn transcode_started(&self) -> TranscodeGuard {
+        let active = self.active.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_active.fetch_max(active, Ordering::Relaxed);
+        self.transcodes.fetch_add(1, Ordering::Relaxed);
+        TranscodeGuard { started: Instant::now() }
+    }
+
+    pub fn stream_cache_lookup(&self, hit: bool) {
+        self.stream_cache.record(hit);
+    }
+
+    pub fn preview_cache_lookup(&self, hit: bool) {
+        self.preview_cache.record(hit);
+    }
+
+    /// Count a file sent as it is, by extension
+    pub fn original_request(&self, extension: &str) {
+        let extension = if extension.is_empty() { "unknown".to_string() } else { extension.to_lowercase() };
+        *self.original.lock().unwrap().entry(extension).or_default() += 1;
+    }
+
+    /// Count a stream transcoded to `format`, e.g. "opus"
+    pub fn transcoded_request(&self, format: &str) {
+        *self.transcoded.lock().unwrap().entry(format.to_string()).or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> StreamingMetricsResponse {
+        StreamingMetricsResponse {
+            since: self.started.lock().unwrap().unwrap_or_else(Utc::now),
+            transcodes: TranscodeMetrics {
+                active: self.active.load(Ordering::Relaxed),
+                peak_active: self.peak_active.load(Ordering::Relaxed),
+                started: self.transcodes.load(Ordering::Relaxed),
+                failed: self.failed.load(Ordering::Relaxed),
+                wall_seconds: self.wall_ms.load(Ordering::Relaxed) as f64 / 1000.0,
+                cpu_seconds: children_cpu_seconds(),
+            },
+            stream_cache: self.stream_cache.metrics(),
+            preview_cache: self.preview_cache.metrics(),
+            original_requests: self.original.lock().unwrap().clone(),
+            transcoded_requests: self.transcoded.lock().unwrap().clone(),
+        }
+    }
+}
+
+// CPU time of child processes that have finished and been waited for
+#[cfg(unix)]
+fn children_cpu_seconds() -> Option<f64> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage fills in the struct it is given and reports failure
+    // without touching it
+    let usage = unsafe {
+        if libc::getrusage(libc::RUSAGE_CHILDREN, usage.as_mut_ptr()) != 0 {
+            return None;
+        }
+        usage.assume_init()
+    };
+    let seconds = |time: libc::timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1_000_000.0;
+    Some(seconds(usage.ru_utime) + seconds(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn children_cpu_seconds() -> Option<f64> {
+    None
+}
+
+// GET /admin/streaming-metrics - Transcoding load, cache hit rates and streamed formats
+#[utoipa::path(
+    get,
+    path = "/admin/streaming-metrics",
+    tag = "library",
+    responses(
+        (status = 200, description = "Streaming metrics since the server started", body = StreamingMetricsResponse),
+    )
+)]
+pub async fn get_streaming_metrics() -> Json<StreamingMetricsResponse> {
+    Json(METRICS.snapshot())
+}
@@ -0,0 +1,252 @@
+use std::io;
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::api::AppState;
+use crate::error::{ApiError, ApiResult};
+use crate::sessions::{tracked_stream, NewSession};
+use crate::storage::Storage;
+
+// Local files are read in small blocks so the first bytes go out as soon as
+// they are read; object storage and the read cache get larger ranged reads to
+// keep round trips down
+const LOCAL_READ_SIZE: usize = 64 * 1024;
+const REMOTE_READ_SIZE: u64 = 1024 * 1024;
+
+/// What a request's Range header asks of a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// The whole file. Range headers in other units, with several ranges or
+    /// that don't parse are ignored, as RFC 9110 allows.
+    Full,
+    /// The inclusive byte range `start..=end`, clamped to the file
+    Partial(u64, u64),
+    /// A range starting past the end of the file, or an empty suffix
+    Unsatisfiable,
+}
+
+impl RangeRequest {
+    /// Inclusive byte range of a file of `file_size` bytes to send, None for an empty file
+    fn bounds(self, file_size: u64) -> Option<(u64, u64)> {
+        match self {
+            Self::Full => file_size.checked_sub(1).map(|end| (0, end)),
+            Self::Partial(start, end) => Some((start, end)),
+            Self::Unsatisfiable => None,
+        }
+    }
+}
+
+// A position in a range, which must be plain digits: no sign, no whitespace
+fn parse_position(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// Parse a Range header value for a file of `file_size` bytes
+pub fn parse_range(value: Option<&HeaderValue>, file_size: u64) -> RangeRequest {
+    let Some((unit, spec)) = value.and_then(|value| value.to_str().ok()).and_then(|value| value.split_once('=')) else {
+        return RangeRequest::Full;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") || spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+
+    match (parse_position(first.trim()), last.trim()) {
+        // Suffix range like "-500" (the last 500 bytes)
+        (None, last) if first.trim().is_empty() => match parse_position(last) {
+            Some(0) => RangeRequest::Unsatisfiable,
+            Some(length) => match file_size.checked_sub(1) {
+                Some(end) => RangeRequest::Partial(file_size.saturating_sub(length), end),
+                None => RangeRequest::Unsatisfiable,
+            },
+            None => RangeRequest::Full,
+        },
+        (None, _) => RangeRequest::Full,
+        (Some(start), last) => {
+            let end = match last {
+                // Range like "500-" (from 500 to the end)
+                "" => u64::MAX,
+                last => match parse_position(last) {
+                    Some(end) if end >= start => end,
+                    _ => return RangeRequest::Full,
+                },
+            };
+            if start >= file_size {
+                return RangeRequest::Unsatisfiable;
+            }
+            RangeRequest::Partial(start, end.min(file_size - 1))
+        }
+    }
+}
+
+/// Stream the inclusive byte range `start..=end` of a file as it is read.
+/// Files on disk are opened and seeked before returning, so a missing file
+/// fails here rather than mid-response. With a read cache every file goes
+/// through it, local ones included.
+pub async fn open(storage: Arc<dyn Storage>, path: &str, start: u64, end: u64) -> io::Result<BoxStream<'static, io::Result<Bytes>>> {
+    if let Some(local_path) = storage.local_path(path).filter(|_| !storage.caches_reads()) {
+        let mut file = tokio::fs::File::open(local_path).await?;
+        file.seek(io::SeekFrom::Start(start)).await?;
+        return Ok(ReaderStream::with_capacity(file.take(end - start + 1), LOCAL_READ_SIZE).boxed());
+    }
+
+    let path = path.to_string();
+    Ok(stream::try_unfold(start, move |offset| {
+        let storage = storage.clone();
+        let path = path.clone();
+        async move {
+            if offset > end {
+                return Ok(None);
+            }
+            let to = end.min(offset + REMOTE_READ_SIZE - 1);
+            let data = storage.read_range(&path, offset, to).await?;
+            Ok(Some((Bytes::from(data), to + 1)))
+        }
+    })
+    .boxed())
+}
+
+fn not_satisfiable(file_size: u64) -> Response {
+    let mut response = ApiError::new(
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        "range_not_satisfiable",
+        format!("Requested range is not satisfiable for a {} byte file", file_size),
+    )
+    .into_response();
+    response.headers_mut().insert(header::CONTENT_RANGE, HeaderValue::from_str(&format!("bytes */{}", file_size)).unwrap());
+    response
+}
+
+/// Stream a file, or the part of it `range` asks for, tracked as a stream
+/// session. Nothing is read before the headers go out. `accept_ranges` is
+/// false for clients that ranges are ignored for.
+pub async fn file_response(
+    state: &AppState,
+    path: &str,
+    file_size: u64,
+    content_type: &str,
+    range: RangeRequest,
+    accept_ranges: bool,
+    new_session: NewSession,
+) -> ApiResult<Response<Body>> {
+    if range == RangeRequest::Unsatisfiable {
+        return Ok(not_satisfiable(file_size));
+    }
+
+    let bounds = range.bounds(file_size);
+    let body = match bounds {
+        Some((start, end)) => open(state.storage.clone(), path, start, end).await?,
+        None => stream::empty().boxed(),
+    };
+    let content_length = bounds.map_or(0, |(start, end)| end - start + 1);
+
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, content_length.to_string())
+        .header(header::ACCEPT_RANGES, if accept_ranges { "bytes" } else { "none" })
+        .header(header::CACHE_CONTROL, "public, max-age=3600")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, OPTIONS")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Range")
+        .header(header::ACCESS_CONTROL_EXPOSE_HEADERS, "Accept-Ranges, Content-Range, Content-Length");
+    response = match (range, bounds) {
+        (RangeRequest::Partial(..), Some((start, end))) => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, file_size)),
+        _ => response.status(StatusCode::OK),
+    };
+
+    Ok(response.body(tracked_stream(body, state.sessions.start(new_session)))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::{parse_range, RangeRequest};
+
+    fn range(value: &str, file_size: u64) -> RangeRequest {
+        parse_range(Some(&HeaderValue::from_str(value).unwrap()), file_size)
+    }
+
+    #[test]
+    fn parses_closed_ranges() {
+        assert_eq!(range("bytes=0-99", 1000), RangeRequest::Partial(0, 99));
+        assert_eq!(range("bytes=500-500", 1000), RangeRequest::Partial(500, 500));
+        assert_eq!(range("Bytes = 10-19", 1000), RangeRequest::Partial(10, 19));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(range("bytes=-100", 1000), RangeRequest::Partial(900, 999));
+        // Longer than the file: the whole file
+        assert_eq!(range("bytes=-5000", 1000), RangeRequest::Partial(0, 999));
+        assert_eq!(range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn parses_open_ended_ranges() {
+        assert_eq!(range("bytes=100-", 1000), RangeRequest::Partial(100, 999));
+        assert_eq!(range("bytes=999-", 1000), RangeRequest::Partial(999, 999));
+    }
+
+    #[test]
+    fn clamps_ends_past_the_file() {
+        assert_eq!(range("bytes=100-5000", 1000), RangeRequest::Partial(100, 999));
+        assert_eq!(range("bytes=0-18446744073709551615", 1000), RangeRequest::Partial(0, 999));
+    }
+
+    #[test]
+    fn refuses_starts_past_the_file() {
+        assert_eq!(range("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(range("bytes=1000-1999", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(range("bytes=5000-6000", 1000), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_backwards_ranges() {
+        assert_eq!(range("bytes=500-100", 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn ignores_multiple_ranges_and_garbage() {
+        assert_eq!(parse_range(None, 1000), RangeRequest::Full);
+        assert_eq!(range("bytes=0-99,200-299", 1000), RangeRequest::Full);
+        assert_eq!(range("items=0-99", 1000), RangeRequest::Full);
+        assert_eq!(range("bytes=abc-def", 1000), RangeRequest::Full);
+        assert_eq!(range("bytes=+1-99", 1000), RangeRequest::Full);
+        assert_eq!(range("bytes=-", 1000), RangeRequest::Full);
+        assert_eq!(range("bytes=100", 1000), RangeRequest::Full);
+        assert_eq!(range("bytes 0-99", 1000), RangeRequest::Full);
+        assert_eq!(range("bytes=99999999999999999999-", 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn handles_empty_files() {
+        assert_eq!(parse_range(None, 0).bounds(0), None);
+        assert_eq!(range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+        assert_eq!(range("bytes=0-99", 0), RangeRequest::Unsatisfiable);
+        assert_eq!(range("bytes=-100", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn bounds_cover_the_whole_file_without_a_range() {
+        assert_eq!(RangeRequest::Full.bounds(1000), Some((0, 999)));
+        assert_eq!(RangeRequest::Partial(10, 19).bounds(1000), Some((10, 19)));
+        assert_eq!(RangeRequest::Unsatisfiable.bounds(1000), None);
+    }
+}
@@ -0,0 +1,120 @@
+use std::env;
+use std::io;
+
+use crate::storage::Storage;
+
+// ID3v1 tags are the last 128 bytes of an MP3
+const ID3V1_SIZE: u64 = 128;
+// Metadata blocks walked before giving up on a FLAC file
+const MAX_FLAC_BLOCKS: usize = 1024;
+// Top-level boxes walked before giving up on an MP4 file
+const MAX_MP4_BOXES: usize = 64;
+// Larger tag regions are cheaper to parse than to hash piecemeal
+const MAX_REGION_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Whether scans hash the tag region of files whose modification time
+/// changed and skip reading them when it hasn't, with SCAN_QUICK_CHECK=true
+pub fn quick_check_from_env() -> bool {
+    env::var("SCAN_QUICK_CHECK").map(|s| s == "true" || s == "1").unwrap_or(false)
+}
+
+async fn read_u32(storage: &dyn Storage, path: &str, offset: u64) -> io::Result<[u8; 4]> {
+    let bytes = storage.read_range(path, offset, offset + 3).await?;
+    bytes.try_into().map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))
+}
+
+// Byte ranges holding the tags: the ID3v2 header and frames at the start
+// (followed by FLAC metadata when a FLAC file has both), FLAC metadata blocks,
+// the MP4 moov box and the ID3v1 tag at the end. None for other formats.
+async fn tag_ranges(storage: &dyn Storage, path: &str, size: u64) -> io::Result<Option<Vec<(u64, u64)>>> {
+    if size < 12 {
+        return Ok(None);
+    }
+    let header = storage.read_range(path, 0, 11).await?;
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+
+    if &header[..3] == b"ID3" {
+        // Syncsafe size excluding the 10 byte header, plus a footer when flagged
+        let tag_size = header[6..10].iter().fold(0u64, |size, byte| (size << 7) | (*byte & 0x7f) as u64);
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        offset = 10 + tag_size + footer;
+        if offset > size || offset > MAX_REGION_SIZE {
+            return Ok(None);
+        }
+        ranges.push((0, offset - 1));
+        if size >= offset + ID3V1_SIZE {
+            ranges.push((size - ID3V1_SIZE, size - 1));
+        }
+        if size < offset + 4 || &read_u32(storage, path, offset).await? != b"fLaC" {
+            return Ok(Some(ranges));
+        }
+    }
+
+    if offset == 0 && &header[4..8] == b"ftyp" {
+        let mut boxes = 0;
+        while offset + 8 <= size && boxes < MAX_MP4_BOXES {
+            let box_header = storage.read_range(path, offset, offset + 7).await?;
+            let box_size = match u32::from_be_bytes([box_header[0], box_header[1], box_header[2], box_header[3]]) as u64 {
+                0 => size - offset,
+                1 if offset + 16 <= size => {
+                    let large = storage.read_range(path, offset + 8, offset + 15).await?;
+                    u64::from_be_bytes(large.try_into().map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?)
+                }
+                box_size => box_size,
+            };
+            if box_size < 8 || offset + box_size > size {
+                return Ok(None);
+            }
+            if &box_header[4..8] == b"moov" {
+                return Ok((box_size <= MAX_REGION_SIZE).then(|| vec![(offset, offset + box_size - 1)]));
+            }
+            offset += box_size;
+            boxes += 1;
+        }
+        return Ok(None);
+    }
+
+    if size >= offset + 4 && &read_u32(storage, path, offset).await? == b"fLaC" {
+        let start = offset;
+        offset += 4;
+        for _ in 0..MAX_FLAC_BLOCKS {
+            if offset + 4 > size {
+                return Ok(None);
+            }
+            let block_header = read_u32(storage, path, offset).await?;
+            let length = u32::from_be_bytes([0, block_header[1], block_header[2], block_header[3]]) as u64;
+            offset += 4 + length;
+            if block_header[0] & 0x80 != 0 {
+                if offset > size || offset - start > MAX_REGION_SIZE {
+                    return Ok(None);
+                }
+                ranges.push((start, offset - 1));
+                return Ok(Some(ranges));
+            }
+        }
+        return Ok(None);
+    }
+
+    // MP3s with only an ID3v1 tag start straight with an MPEG frame
+    if header[0] == 0xff && header[1] & 0xe0 == 0xe0 && size >= ID3V1_SIZE {
+        return Ok(Some(vec![(size - ID3V1_SIZE, size - 1)]));
+    }
+    Ok(None)
+}
+
+/// Hash of a file's size and the regions of it that hold tags (ID3, FLAC
+/// metadata blocks and MP4 moov boxes), reading only those regions. A file
+/// whose hash is unchanged has the same tags, so it needn't be read again.
+/// None for formats whose tags can't be located cheaply.
+pub async fn tag_hash(storage: &dyn Storage, path: &str, size: u64) -> io::Result<Option<String>> {
+    let Some(ranges) = tag_ranges(storage, path, size).await? else {
+        return Ok(None);
+    };
+    let mut context = md5::Context::new();
+    context.consume(size.to_le_bytes());
+    for (start, end) in ranges {
+        context.consume(storage.read_range(path, start, end).await?);
+    }
+    Ok(Some(format!("{:x}", context.compute())))
+}
@@ -0,0 +1,434 @@
+use std::env;
+use std::str::FromStr;
+
+use log::info;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, FromQueryResult, QuerySelect};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use entity::prelude::Track;
+use entity::track;
+
+/// Unicode normalization applied to search text and sort keys
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextNormalization {
+    /// Tags are indexed as written
+    #[default]
+    None,
+    /// Canonical composition, so precomposed and decomposed kana/accents match
+    Nfc,
+    /// Compatibility composition, which also folds full-width letters and digits
+    /// to half-width and half-width katakana to full-width
+    Nfkc,
+}
+
+impl FromStr for TextNormalization {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "" | "none" | "off" => Ok(TextNormalization::None),
+            "nfc" => Ok(TextNormalization::Nfc),
+            "nfkc" => Ok(TextNormalization::Nfkc),
+            other => Err(format!("unknown text normalization '{}', expected none, nfc or nfkc", other)),
+        }
+    }
+}
+
+impl TextNormalization {
+    /// Read TEXT_NORMALIZATION, falling back to no normalization
+    pub fn from_env() -> Self {
+        env::var("TEXT_NORMALIZATION")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            TextNormalization::None => text.to_string(),
+            TextNormalization::Nfc => text.nfc().collect(),
+            TextNormalization::Nfkc => text.nfkc().collect(),
+        }
+    }
+
+    /// Normalized, lowercased form used for matching search queries
+    pub fn search_key(self, text: &str) -> String {
+        self.apply(text).to_lowercase()
+    }
+}
+
+/// Leading words skipped when sorting and indexing artists, as in Subsonic's
+/// ignoredArticles, so "The Beatles" sorts under B
+const DEFAULT_IGNORED_ARTICLES: &str = "The El La Los Las Le Les";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IgnoredArticles {
+    articles: Vec<String>,
+}
+
+impl Default for IgnoredArticles {
+    fn default() -> Self {
+        Self::new(DEFAULT_IGNORED_ARTICLES)
+    }
+}
+
+impl IgnoredArticles {
+    /// Articles from a space separated list
+    pub fn new(articles: &str) -> Self {
+        Self { articles: articles.split_whitespace().map(str::to_string).collect() }
+    }
+
+    /// Read IGNORED_ARTICLES, falling back to the defaults. Set it empty to sort by the full name.
+    pub fn from_env() -> Self {
+        env::var("IGNORED_ARTICLES").map(|articles| Self::new(&articles)).unwrap_or_default()
+    }
+
+    /// The articles as a space separated list
+    pub fn list(&self) -> String {
+        self.articles.join(" ")
+    }
+
+    /// Drop a leading article followed by a space, leaving names that are
+    /// only an article ("The The" keeps its second word)
+    pub fn strip<'a>(&self, name: &'a str) -> &'a str {
+        let name = name.trim_start();
+        for article in &self.articles {
+            let Some(prefix) = name.get(..article.len()) else {
+                continue;
+            };
+            let rest = &name[article.len()..];
+            if prefix.eq_ignore_ascii_case(article) && rest.starts_with(' ') && !rest.trim().is_empty() {
+                return rest.trim_start();
+            }
+        }
+        name
+    }
+}
+
+/// Key that orders names the same way in every locale: accents and other
+/// combining marks are dropped and letters are case folded, so "Édith Piaf"
+/// sorts with "Edith" instead of after "Z"
+pub fn collation_key(normalization: TextNormalization, text: &str) -> String {
+    let mut key = String::with_capacity(text.len());
+    for c in normalization.apply(text).nfkd().filter(|c| !is_combining_mark(*c)) {
+        // Full case folding, where lowercasing alone leaves ß and ẞ as they are
+        match c {
+            'ß' | 'ẞ' => key.push_str("ss"),
+            c => key.extend(c.to_lowercase()),
+        }
+    }
+    key.nfc().collect()
+}
+
+/// Key for sorting and indexing an artist: the collation key without a leading article
+pub fn artist_sort_key(normalization: TextNormalization, articles: &IgnoredArticles, artist: &str) -> String {
+    collation_key(normalization, articles.strip(artist))
+}
+
+/// Index bucket for an artist sort key: its first letter A-Z, or "#" for
+/// digits, symbols and scripts without Latin letters
+pub fn index_letter(sort_key: &str) -> String {
+    match sort_key.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => c.to_ascii_uppercase().to_string(),
+        _ => "#".to_string(),
+    }
+}
+
+// Hepburn romanization of the hiragana block, starting at U+3041 (ぁ)
+const HIRAGANA_ROMAJI: &[&str] = &[
+    "a", "a", "i", "i", "u", "u", "e", "e", "o", "o",
+    "ka", "ga", "ki", "gi", "ku", "gu", "ke", "ge", "ko", "go",
+    "sa", "za", "shi", "ji", "su", "zu", "se", "ze", "so", "zo",
+    "ta", "da", "chi", "ji", "", "tsu", "zu", "te", "de", "to", "do",
+    "na", "ni", "nu", "ne", "no",
+    "ha", "ba", "pa", "hi", "bi", "pi", "fu", "bu", "pu", "he", "be", "pe", "ho", "bo", "po",
+    "ma", "mi", "mu", "me", "mo",
+    "ya", "ya", "yu", "yu", "yo", "yo",
+    "ra", "ri", "ru", "re", "ro",
+    "wa", "wa", "i", "e", "o", "n", "vu", "ka", "ke",
+];
+
+const HIRAGANA_START: u32 = 0x3041;
+const SMALL_TSU: char = 'っ';
+const LONG_VOWEL_MARK: char = 'ー';
+
+fn is_small_vowel(c: char) -> bool {
+    matches!(c, 'ぁ' | 'ぃ' | 'ぅ' | 'ぇ' | 'ぉ')
+}
+
+fn is_small_y(c: char) -> bool {
+    matches!(c, 'ゃ' | 'ゅ' | 'ょ')
+}
+
+fn to_hiragana(c: char) -> char {
+    match c {
+        'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+fn kana_romaji(c: char) -> Option<&'static str> {
+    let index = (c as u32).checked_sub(HIRAGANA_START)? as usize;
+    HIRAGANA_ROMAJI.get(index).copied()
+}
+
+/// Romanize the kana in a string (Hepburn), lowercasing everything else and
+/// leaving kanji as written. Returns None when there is no kana to romanize.
+pub fn romanize(text: &str) -> Option<String> {
+    let chars: Vec<char> = text.nfkc().map(to_hiragana).collect();
+    let mut output = String::new();
+    let mut found_kana = false;
+    let mut double_next = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+
+        if c == SMALL_TSU {
+            found_kana = true;
+            double_next = true;
+            continue;
+        }
+        if c == LONG_VOWEL_MARK {
+            if let Some(vowel) = output.chars().last().filter(|v| "aeiou".contains(*v)) {
+                output.push(vowel);
+            }
+            continue;
+        }
+
+        let Some(base) = kana_romaji(c).filter(|romaji| !romaji.is_empty()) else {
+            double_next = false;
+            output.extend(c.to_lowercase());
+            continue;
+        };
+        found_kana = true;
+
+        let mut syllable = base.to_string();
+        if let Some(&next) = chars.get(i) {
+            if is_small_y(next) && base.ends_with('i') && base.len() > 1 {
+                // きゃ -> kya, しゃ -> sha, じゃ -> ja
+                let stem = &base[..base.len() - 1];
+                let glide = &kana_romaji(next).unwrap_or("ya")[1..];
+                syllable = if matches!(stem, "sh" | "ch" | "j") {
+                    format!("{}{}", stem, glide)
+                } else {
+                    format!("{}y{}", stem, glide)
+                };
+                i += 1;
+            } else if is_small_vowel(next) && base.len() > 1 {
+                // ファ -> fa, ティ -> ti
+                let stem = &base[..base.len() - 1];
+                syllable = format!("{}{}", stem, kana_romaji(next).unwrap_or(""));
+                i += 1;
+            }
+        }
+
+        if double_next {
+            // っち -> tchi, っか -> kka
+            if syllable.starts_with("ch") {
+                output.push('t');
+            } else if let Some(consonant) = syllable.chars().next().filter(|c| !"aeiou".contains(*c)) {
+                output.push(consonant);
+            }
+            double_next = false;
+        }
+        output.push_str(&syllable);
+    }
+
+    found_kana.then_some(output)
+}
+
+/// Search and sort columns derived from a track's tags
+#[derive(PartialEq, Eq)]
+struct TextColumns {
+    search_text: Option<String>,
+    search_title: Option<String>,
+    search_artist: Option<String>,
+    search_album: Option<String>,
+    search_album_artist: Option<String>,
+    search_genre: Option<String>,
+    transliteration: Option<String>,
+    sort_artist: Option<String>,
+    sort_album: Option<String>,
+    sort_title: Option<String>,
+}
+
+/// The tags search and sort columns are derived from
+struct TextFields<'a> {
+    title: &'a str,
+    artist: &'a str,
+    album: &'a str,
+    album_artist: &'a str,
+    genre: &'a str,
+    comment: Option<&'a str>,
+}
+
+impl<'a> From<&'a track::Model> for TextFields<'a> {
+    fn from(track: &'a track::Model) -> Self {
+        Self {
+            title: &track.title,
+            artist: &track.artist,
+            album: &track.album,
+            album_artist: &track.album_artist,
+            genre: &track.genre,
+            comment: track.comment.as_deref(),
+        }
+    }
+}
+
+fn text_columns(normalization: TextNormalization, articles: &IgnoredArticles, fields: TextFields) -> TextColumns {
+    let TextFields { title, artist, album, album_artist, genre, comment } = fields;
+    // Comments are searched too, since they often hold DJ notes
+    let search_text = [title, artist, album, album_artist, genre]
+        .into_iter()
+        .chain(comment)
+        .map(|value| normalization.search_key(value))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let romanized: Vec<String> = [title, artist, album, album_artist]
+        .iter()
+        .filter_map(|value| romanize(value))
+        .collect();
+
+    TextColumns {
+        search_text: Some(search_text),
+        search_title: Some(normalization.search_key(title)),
+        search_artist: Some(normalization.search_key(artist)),
+        search_album: Some(normalization.search_key(album)),
+        search_album_artist: Some(normalization.search_key(album_artist)),
+        search_genre: Some(normalization.search_key(genre)),
+        transliteration: (!romanized.is_empty()).then(|| romanized.join("\n")),
+        sort_artist: Some(artist_sort_key(normalization, articles, artist)),
+        sort_album: Some(collation_key(normalization, album)),
+        sort_title: Some(collation_key(normalization, title)),
+    }
+}
+
+/// Set a track's search and sort columns from its tags, for writes outside a
+/// scan that change them
+pub fn set_text_columns(active: &mut track::ActiveModel, normalization: TextNormalization, articles: &IgnoredArticles, track: &track::Model) {
+    let columns = text_columns(normalization, articles, TextFields::from(track));
+    active.search_text = Set(columns.search_text);
+    active.search_title = Set(columns.search_title);
+    active.search_artist = Set(columns.search_artist);
+    active.search_album = Set(columns.search_album);
+    active.search_album_artist = Set(columns.search_album_artist);
+    active.search_genre = Set(columns.search_genre);
+    active.transliteration = Set(columns.transliteration);
+    active.sort_artist = Set(columns.sort_artist);
+    active.sort_album = Set(columns.sort_album);
+    active.sort_title = Set(columns.sort_title);
+}
+
+#[derive(FromQueryResult)]
+struct TrackText {
+    id: i32,
+    title: String,
+    artist: String,
+    album: String,
+    album_artist: String,
+    genre: String,
+    comment: Option<String>,
+    search_text: Option<String>,
+    search_title: Option<String>,
+    search_artist: Option<String>,
+    search_album: Option<String>,
+    search_album_artist: Option<String>,
+    search_genre: Option<String>,
+    transliteration: Option<String>,
+    sort_artist: Option<String>,
+    sort_album: Option<String>,
+    sort_title: Option<String>,
+}
+
+/// Bring every track's search text, transliteration and sort keys up to date
+/// with its tags, the configured normalization and ignored articles. Only rows
+/// whose derived columns changed are written, so this is cheap after the first run.
+pub async fn refresh_text_index(db: &DatabaseConnection, normalization: TextNormalization, articles: &IgnoredArticles) -> Result<usize, DbErr> {
+    let rows = Track::find()
+        .select_only()
+        .column(track::Column::Id)
+        .column(track::Column::Title)
+        .column(track::Column::Artist)
+        .column(track::Column::Album)
+        .column(track::Column::AlbumArtist)
+        .column(track::Column::Genre)
+        .column(track::Column::Comment)
+        .column(track::Column::SearchText)
+        .column(track::Column::SearchTitle)
+        .column(track::Column::SearchArtist)
+        .column(track::Column::SearchAlbum)
+        .column(track::Column::SearchAlbumArtist)
+        .column(track::Column::SearchGenre)
+        .column(track::Column::Transliteration)
+        .column(track::Column::SortArtist)
+        .column(track::Column::SortAlbum)
+        .column(track::Column::SortTitle)
+        .into_model::<TrackText>()
+        .all(db)
+        .await?;
+
+    let mut updated = 0;
+    for row in rows {
+        let current = TextColumns {
+            search_text: row.search_text,
+            search_title: row.search_title,
+            search_artist: row.search_artist,
+            search_album: row.search_album,
+            search_album_artist: row.search_album_artist,
+            search_genre: row.search_genre,
+            transliteration: row.transliteration,
+            sort_artist: row.sort_artist,
+            sort_album: row.sort_album,
+            sort_title: row.sort_title,
+        };
+        let expected = text_columns(normalization, articles, TextFields {
+            title: &row.title,
+            artist: &row.artist,
+            album: &row.album,
+            album_artist: &row.album_artist,
+            genre: &row.genre,
+            comment: row.comment.as_deref(),
+        });
+        if current == expected {
+            continue;
+        }
+
+        track::ActiveModel {
+            id: Set(row.id),
+            search_text: Set(expected.search_text),
+            search_title: Set(expected.search_title),
+            search_artist: Set(expected.search_artist),
+            search_album: Set(expected.search_album),
+            search_album_artist: Set(expected.search_album_artist),
+            search_genre: Set(expected.search_genre),
+            transliteration: Set(expected.transliteration),
+            sort_artist: Set(expected.sort_artist),
+            sort_album: Set(expected.sort_album),
+            sort_title: Set(expected.sort_title),
+            ..Default::default()
+        }
+        .update(db)
+        .await?;
+        updated += 1;
+    }
+
+    if updated > 0 {
+        info!("Updated search text and sort keys for {} tracks", updated);
+    }
+    Ok(updated)
+}
+
+/// Condition for a tag filter such as ?artist=: the tag contains the value,
+/// ignoring case. Compared against the lowercased search column so it works
+/// the same whatever the database's collation, and against the tag as written
+/// for tracks the text index hasn't reached yet.
+pub fn contains_ignoring_case(normalization: TextNormalization, search_column: track::Column, column: track::Column, value: &str) -> Condition {
+    Condition::any()
+        .add(search_column.contains(normalization.search_key(value)))
+        .add(Condition::all().add(search_column.is_null()).add(column.contains(value)))
+}
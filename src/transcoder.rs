@@ -0,0 +1,564 @@
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::str::FromStr;
+
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, StreamExt};
+use log::error;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio_util::io::ReaderStream;
+use utoipa::ToSchema;
+
+use entity::track;
+
+use crate::storage::Storage;
+use crate::stream_metrics::METRICS;
+
+/// Output settings for an ffmpeg transcode
+pub struct TranscodeOptions {
+    /// ffmpeg muxer name, e.g. "mp3" or "ogg"
+    pub format: &'static str,
+    /// ffmpeg audio encoder, e.g. "libmp3lame" or "libopus"
+    pub codec: &'static str,
+    pub content_type: &'static str,
+    pub bitrate_kbps: u32,
+    /// Downmix to this many channels
+    pub channels: Option<u32>,
+    /// Cut the output after this many seconds
+    pub max_seconds: Option<u32>,
+    /// Start this many seconds into the track
+    pub start_seconds: Option<u32>,
+    /// Normalize loudness with ffmpeg's loudnorm filter
+    pub normalize_loudness: bool,
+}
+
+impl TranscodeOptions {
+    /// MP3 output that every browser and client can play
+    pub fn mp3(bitrate_kbps: u32) -> Self {
+        Self {
+            format: "mp3",
+            codec: "libmp3lame",
+            content_type: "audio/mpeg",
+            bitrate_kbps,
+            channels: None,
+            max_seconds: None,
+            start_seconds: None,
+            normalize_loudness: false,
+        }
+    }
+
+    /// Lossless FLAC
+    pub fn flac() -> Self {
+        Self {
+            format: "flac",
+            codec: "flac",
+            content_type: "audio/flac",
+            bitrate_kbps: 0,
+            channels: None,
+            max_seconds: None,
+            start_seconds: None,
+            normalize_loudness: false,
+        }
+    }
+
+    /// Opus in an Ogg container
+    pub fn opus(bitrate_kbps: u32) -> Self {
+        Self {
+            format: "ogg",
+            codec: "libopus",
+            content_type: "audio/ogg; codecs=opus",
+            bitrate_kbps,
+            channels: None,
+            max_seconds: None,
+            start_seconds: None,
+            normalize_loudness: false,
+        }
+    }
+
+    /// AAC in an ADTS stream
+    pub fn aac(bitrate_kbps: u32) -> Self {
+        Self {
+            format: "adts",
+            codec: "aac",
+            content_type: "audio/aac",
+            bitrate_kbps,
+            channels: None,
+            max_seconds: None,
+            start_seconds: None,
+            normalize_loudness: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileFormat {
+    Mp3,
+    Opus,
+    Aac,
+    Flac,
+}
+
+impl FromStr for ProfileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "mp3" => Ok(ProfileFormat::Mp3),
+            "opus" => Ok(ProfileFormat::Opus),
+            "aac" => Ok(ProfileFormat::Aac),
+            "flac" => Ok(ProfileFormat::Flac),
+            other => Err(format!("unknown format '{}', expected mp3, opus, aac or flac", other)),
+        }
+    }
+}
+
+impl ProfileFormat {
+    // Extensions of files already in this format
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            ProfileFormat::Mp3 => &["mp3"],
+            ProfileFormat::Opus => &["opus"],
+            ProfileFormat::Aac => &["aac"],
+            ProfileFormat::Flac => &["flac"],
+        }
+    }
+}
+
+/// A named set of output settings a client streams with, e.g. "mobile" for
+/// 96 kbps stereo Opus
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct TranscodeProfile {
+    pub name: String,
+    pub format: ProfileFormat,
+    /// Target bitrate, 0 for lossless formats
+    pub bitrate_kbps: u32,
+    pub channels: Option<u32>,
+}
+
+impl FromStr for TranscodeProfile {
+    type Err = String;
+
+    /// Parse `name=format[/bitrate][/channels]`, e.g. `mobile=opus/96/2` or `archive=flac`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, settings) = s.split_once('=').ok_or("expected name=format/bitrate")?;
+        let name = name.trim().to_lowercase();
+        if name.is_empty() || name == "original" {
+            return Err(format!("invalid profile name '{}'", name));
+        }
+
+        let mut parts = settings.split('/');
+        let format: ProfileFormat = parts.next().unwrap_or_default().parse()?;
+        let bitrate_kbps = match parts.next() {
+            Some(bitrate) => bitrate.trim().parse().map_err(|_| format!("invalid bitrate '{}'", bitrate))?,
+            None if format == ProfileFormat::Flac => 0,
+            None => return Err(format!("{:?} needs a bitrate", format).to_lowercase()),
+        };
+        let channels = match parts.next() {
+            Some(channels) => Some(channels.trim().parse().map_err(|_| format!("invalid channel count '{}'", channels))?),
+            None => None,
+        };
+        if parts.next().is_some() {
+            return Err("too many settings".to_string());
+        }
+
+        Ok(Self { name, format, bitrate_kbps, channels })
+    }
+}
+
+impl TranscodeProfile {
+    pub fn options(&self) -> TranscodeOptions {
+        let mut options = match self.format {
+            ProfileFormat::Mp3 => TranscodeOptions::mp3(self.bitrate_kbps),
+            ProfileFormat::Opus => TranscodeOptions::opus(self.bitrate_kbps),
+            ProfileFormat::Aac => TranscodeOptions::aac(self.bitrate_kbps),
+            ProfileFormat::Flac => TranscodeOptions::flac(),
+        };
+        options.channels = self.channels;
+        options
+    }
+
+    /// Whether a track has to be transcoded to fit this profile. Files already
+    /// in the profile's format, at or below its bitrate and channel count, are
+    /// streamed as they are.
+    pub fn needs_transcode(&self, track: &track::Model) -> bool {
+        let extension = track.extension.to_lowercase();
+        let same_format = self.format.extensions().contains(&extension.as_str());
+        let within_bitrate = self.bitrate_kbps == 0
+            || (track.audio_bitrate > 0 && track.audio_bitrate as u32 <= self.bitrate_kbps);
+        let within_channels = self.channels
+            .is_none_or(|channels| track.channels > 0 && track.channels as u32 <= channels);
+        !(same_format && within_bitrate && within_channels)
+    }
+}
+
+/// Transcoding profiles and which users and clients stream with them
+#[derive(Default)]
+pub struct TranscodingConfig {
+    pub profiles: Vec<TranscodeProfile>,
+    // Lowercased client or user name -> profile name
+    clients: HashMap<String, String>,
+    users: HashMap<String, String>,
+}
+
+impl TranscodingConfig {
+    /// Read TRANSCODING_PROFILES, TRANSCODING_CLIENT_PROFILES and
+    /// TRANSCODING_USER_PROFILES (comma separated). Invalid entries are logged
+    /// and skipped.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        for entry in env_list("TRANSCODING_PROFILES") {
+            match entry.parse::<TranscodeProfile>() {
+                Ok(profile) => config.profiles.push(profile),
+                Err(e) => error!("Invalid transcoding profile '{}': {}", entry, e),
+            }
+        }
+        config.clients = config.assignments("TRANSCODING_CLIENT_PROFILES");
+        config.users = config.assignments("TRANSCODING_USER_PROFILES");
+        config
+    }
+
+    // `name=profile` pairs naming a configured profile
+    fn assignments(&self, var: &str) -> HashMap<String, String> {
+        env_list(var)
+            .into_iter()
+            .filter_map(|entry| {
+                let Some((name, profile)) = entry.split_once('=') else {
+                    error!("Invalid {} entry '{}', expected name=profile", var, entry);
+                    return None;
+                };
+                let profile = profile.trim().to_lowercase();
+                if self.profile(&profile).is_none() {
+                    error!("{} refers to unknown transcoding profile '{}'", var, profile);
+                    return None;
+                }
+                Some((name.trim().to_lowercase(), profile))
+            })
+            .collect()
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&TranscodeProfile> {
+        let name = name.trim().to_lowercase();
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    /// Profile a stream uses: the one requested, else the client's, else the
+    /// user's. `original` requests the file as it is. Errors name an unknown
+    /// requested profile.
+    pub fn select(&self, requested: Option<&str>, user: Option<&str>, client: Option<&str>) -> Result<Option<&TranscodeProfile>, String> {
+        if let Some(requested) = requested {
+            if requested.trim().eq_ignore_ascii_case("original") {
+                return Ok(None);
+            }
+            return self.profile(requested)
+                .map(Some)
+                .ok_or_else(|| format!("unknown profile '{}'", requested.trim()));
+        }
+
+        let assigned = client
+            .and_then(|client| self.clients.get(&client.trim().to_lowercase()))
+            .or_else(|| user.and_then(|user| self.users.get(&user.trim().to_lowercase())));
+        Ok(assigned.and_then(|name| self.profile(name)))
+    }
+}
+
+fn env_list(var: &str) -> Vec<String> {
+    env::var(var)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// ffmpeg from FFMPEG_PATH, or found on PATH
+pub fn ffmpeg_path() -> String {
+    env::var("FFMPEG_PATH").unwrap_or_else(|_| "ffmpeg".to_string())
+}
+
+// ffmpeg reading the track and encoding its first audio stream; the caller
+// adds the output. Local files are read by ffmpeg directly, anything else is
+// piped through stdin from the storage backend.
+fn ffmpeg_command(local_path: Option<&Path>, options: &TranscodeOptions) -> Command {
+    let mut command = Command::new(ffmpeg_path());
+    command.args(["-hide_banner", "-loglevel", "error", "-nostdin"]);
+    // Seeking before the input skips straight there instead of decoding up to it
+    if let Some(seconds) = options.start_seconds {
+        command.args(["-ss", &seconds.to_string()]);
+    }
+    match local_path {
+        Some(local_path) => command.arg("-i").arg(local_path),
+        None => command.args(["-i", "pipe:0"]),
+    };
+    if let Some(seconds) = options.max_seconds {
+        command.args(["-t", &seconds.to_string()]);
+    }
+    command
+        .args(["-map", "0:a:0", "-vn"])
+        .args(["-c:a", options.codec]);
+    // EBU R128 single pass normalization to -16 LUFS, as streaming services use
+    if options.normalize_loudness {
+        command.args(["-af", "loudnorm=I=-16:TP=-1.5:LRA=11"]);
+    }
+    // Lossless codecs have no bitrate to set
+    if options.bitrate_kbps > 0 {
+        command.args(["-b:a", &format!("{}k", options.bitrate_kbps)]);
+    }
+    if let Some(channels) = options.channels {
+        command.args(["-ac", &channels.to_string()]);
+    }
+    command
+        .stdin(if local_path.is_some() { Stdio::null() } else { Stdio::piped() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    command
+}
+
+async fn run_ffmpeg(storage: &dyn Storage, path: &str, command: Command) -> io::Result<Vec<u8>> {
+    let guard = METRICS.transcode_started();
+    let result = run_ffmpeg_process(storage, path, command).await;
+    if result.is_err() {
+        guard.failed();
+    }
+    result
+}
+
+async fn run_ffmpeg_process(storage: &dyn Storage, path: &str, mut command: Command) -> io::Result<Vec<u8>> {
+    let mut child = command.spawn()?;
+
+    // Feed remote input on its own task so ffmpeg can write output while reading
+    let writer = match child.stdin.take() {
+        Some(mut stdin) => {
+            let input = storage.read(path).await?;
+            Some(tokio::spawn(async move {
+                // ffmpeg closes stdin early once it has read enough for -t, which is fine
+                let _ = stdin.write_all(&input).await;
+            }))
+        }
+        None => None,
+    };
+
+    let output = child.wait_with_output().await?;
+    if let Some(writer) = writer {
+        let _ = writer.await;
+    }
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!("ffmpeg failed for {}: {}", path, stderr.trim());
+        return Err(io::Error::other(format!("ffmpeg exited with {}: {}", output.status, stderr.trim())));
+    }
+
+    Ok(output.stdout)
+}
+
+/// Transcode a track with ffmpeg into memory
+pub async fn transcode(storage: &dyn Storage, path: &str, options: &TranscodeOptions) -> io::Result<Vec<u8>> {
+    let local_path = storage.local_path(path);
+    let mut command = ffmpeg_command(local_path.as_deref(), options);
+    command.args(["-f", options.format, "pipe:1"]);
+    run_ffmpeg(storage, path, command).await
+}
+
+/// MD5 of a track's decoded audio as raw PCM in `pcm_codec`, e.g. "pcm_s16le"
+pub async fn decoded_md5(storage: &dyn Storage, path: &str, pcm_codec: &'static str) -> io::Result<String> {
+    let options = TranscodeOptions {
+        format: "md5",
+        codec: pcm_codec,
+        content_type: "text/plain",
+        bitrate_kbps: 0,
+        channels: None,
+        max_seconds: None,
+        start_seconds: None,
+        normalize_loudness: false,
+    };
+    let output = transcode(storage, path, &options).await?;
+    // The md5 muxer writes "MD5=<hex>"
+    String::from_utf8_lossy(&output)
+        .trim()
+        .strip_prefix("MD5=")
+        .map(str::to_string)
+        .ok_or_else(|| io::Error::other("ffmpeg didn't report an MD5"))
+}
+
+/// Convert a track with ffmpeg into a file, keeping its tags. The output is
+/// written next to `output` first and renamed into place once complete.
+pub async fn convert_to_file(storage: &dyn Storage, path: &str, options: &TranscodeOptions, output: &Path) -> io::Result<()> {
+    let local_path = storage.local_path(path);
+    let tmp_path = output.with_extension("partial");
+    let mut command = ffmpeg_command(local_path.as_deref(), options);
+    command
+        .args(["-map_metadata", "0"])
+        .args(["-f", options.format, "-y"])
+        .arg(&tmp_path);
+
+    if let Err(e) = run_ffmpeg(storage, path, command).await {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(e);
+    }
+    tokio::fs::rename(&tmp_path, output).await
+}
+
+/// A chapter of a concatenated stream, in milliseconds from its start
+pub struct ChapterMark {
+    pub title: String,
+    pub start_ms: i64,
+    pub end_ms: i64,
+}
+
+/// Tags and chapters written into a concatenated stream
+pub struct StreamMetadata {
+    pub title: String,
+    pub artist: String,
+    pub chapters: Vec<ChapterMark>,
+}
+
+// Escape the characters ffmpeg's metadata file format gives a meaning
+fn ffmetadata_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// ffmpeg metadata file with the stream's tags and a chapter per input
+fn ffmetadata(metadata: &StreamMetadata) -> String {
+    let mut file = format!(
+        ";FFMETADATA1\ntitle={0}\nalbum={0}\nartist={1}\n",
+        ffmetadata_escape(&metadata.title),
+        ffmetadata_escape(&metadata.artist),
+    );
+    for chapter in &metadata.chapters {
+        file.push_str(&format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            chapter.start_ms,
+            chapter.end_ms,
+            ffmetadata_escape(&chapter.title),
+        ));
+    }
+    file
+}
+
+/// Decode local files one after another into a single encoded stream, with
+/// nothing between them but what the files hold, and the chapters and tags of
+/// `metadata` in formats whose containers carry them (MP3, Ogg and FLAC).
+/// Inputs are resampled to `sample_rate` so files of different formats can
+/// follow each other. The output is returned as ffmpeg produces it; dropping
+/// the stream stops ffmpeg.
+pub fn concat_stream(inputs: &[PathBuf], sample_rate: u32, metadata: &StreamMetadata, options: &TranscodeOptions) -> io::Result<BoxStream<'static, io::Result<Bytes>>> {
+    let mut command = Command::new(ffmpeg_path());
+    command.args(["-hide_banner", "-loglevel", "error", "-nostdin"]);
+    // Input 0 is the metadata, piped in, and the files follow it
+    command.args(["-f", "ffmetadata", "-i", "pipe:0"]);
+    for input in inputs {
+        command.arg("-i").arg(input);
+    }
+
+    let layout = if options.channels == Some(1) { "mono" } else { "stereo" };
+    let mut filter = String::new();
+    for index in 0..inputs.len() {
+        filter.push_str(&format!("[{}:a:0]aformat=sample_rates={}:channel_layouts={}[a{}];", index + 1, sample_rate, layout, index));
+    }
+    for index in 0..inputs.len() {
+        filter.push_str(&format!("[a{}]", index));
+    }
+    filter.push_str(&format!("concat=n={}:v=0:a=1[out]", inputs.len()));
+
+    command
+        .args(["-filter_complex", &filter])
+        .args(["-map", "[out]", "-map_metadata", "0", "-map_chapters", "0"])
+        .args(["-c:a", options.codec]);
+    if options.bitrate_kbps > 0 {
+        command.args(["-b:a", &format!("{}k", options.bitrate_kbps)]);
+    }
+    command
+        .args(["-f", options.format, "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+
+    let mut child = command.spawn()?;
+    let guard = METRICS.transcode_started();
+    if let Some(mut stdin) = child.stdin.take() {
+        let metadata = ffmetadata(metadata);
+        tokio::spawn(async move {
+            let _ = stdin.write_all(metadata.as_bytes()).await;
+        });
+    }
+    let stdout = child.stdout.take().ok_or_else(|| io::Error::other("ffmpeg has no output"))?;
+
+    // The process is waited on once its output ends, to count failures
+    Ok(stream::unfold((ReaderStream::new(stdout), Some((child, guard))), |(mut output, mut process)| async move {
+        if let Some(chunk) = output.next().await {
+            return Some((chunk, (output, process)));
+        }
+        let (mut child, guard) = process.take()?;
+        let error = match child.wait().await {
+            Ok(status) if status.success() => return None,
+            Ok(status) => io::Error::other(format!("ffmpeg exited with {}", status)),
+            Err(e) => e,
+        };
+        error!("ffmpeg failed to stream concatenated files: {}", error);
+        guard.failed();
+        Some((Err(error), (output, None)))
+    })
+    .boxed())
+}
+
+/// Render a 2x2 mosaic of four images into a JPEG file, left to right and top
+/// to bottom, each cropped to a `tile_size` pixel square. Written next to
+/// `output` first and renamed into place once complete, like conversions.
+pub async fn image_mosaic(images: &[&Path; 4], tile_size: u32, output: &Path) -> io::Result<()> {
+    let tmp_path = output.with_extension("partial");
+    let mut command = Command::new(ffmpeg_path());
+    command.args(["-hide_banner", "-loglevel", "error", "-nostdin"]);
+    for image in images {
+        command.arg("-i").arg(image);
+    }
+    // Art comes in any size, aspect and pixel format; xstack needs them alike
+    let tile = format!(
+        "scale={0}:{0}:force_original_aspect_ratio=increase,crop={0}:{0},setsar=1,format=yuvj420p",
+        tile_size,
+    );
+    let filter = format!(
+        "[0:v]{0}[a];[1:v]{0}[b];[2:v]{0}[c];[3:v]{0}[d];[a][b][c][d]xstack=inputs=4:layout=0_0|w0_0|0_h0|w0_h0",
+        tile,
+    );
+    command
+        .args(["-filter_complex", &filter])
+        .args(["-frames:v", "1", "-q:v", "3", "-f", "mjpeg", "-y"])
+        .arg(&tmp_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let guard = METRICS.transcode_started();
+    let failure = match command.output().await {
+        Ok(finished) if finished.status.success() => None,
+        Ok(finished) => {
+            let stderr = String::from_utf8_lossy(&finished.stderr);
+            error!("ffmpeg failed to render {}: {}", output.display(), stderr.trim());
+            Some(io::Error::other(format!("ffmpeg exited with {}: {}", finished.status, stderr.trim())))
+        }
+        Err(e) => Some(e),
+    };
+    if let Some(e) = failure {
+        guard.failed();
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(e);
+    }
+    tokio::fs::rename(&tmp_path, output).await
+}
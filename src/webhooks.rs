@@ -0,0 +1,303 @@
+use std::env;
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use utoipa::ToSchema;
+
+use crate::api::AppState;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Failed deliveries are retried after 1, 2 and 4 seconds
+const MAX_ATTEMPTS: u32 = 4;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// A scan adding more albums than this only reports the count in scan_completed
+const MAX_ALBUM_EVENTS: usize = 20;
+
+/// Something that happened in the library, delivered to every webhook
+/// subscribed to its kind
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    NowPlaying {
+        user: String,
+        client: String,
+        track_id: i32,
+        title: String,
+        artist: String,
+        album: String,
+    },
+    ScanCompleted {
+        scan_id: String,
+        files_scanned: u64,
+        tracks_processed: usize,
+        errors_recorded: usize,
+        albums_added: usize,
+    },
+    AlbumAdded {
+        album_artist: String,
+        name: String,
+    },
+    Test,
+}
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::NowPlaying { .. } => "now_playing",
+            Event::ScanCompleted { .. } => "scan_completed",
+            Event::AlbumAdded { .. } => "album_added",
+            Event::Test => "test",
+        }
+    }
+
+    // One line for chat services
+    fn summary(&self) -> String {
+        match self {
+            Event::NowPlaying { user, title, artist, album, .. } if album.is_empty() => {
+                format!("🎵 **{}** is listening to **{}** by {}", user, title, artist)
+            }
+            Event::NowPlaying { user, title, artist, album, .. } => {
+                format!("🎵 **{}** is listening to **{}** by {} ({})", user, title, artist, album)
+            }
+            Event::ScanCompleted { files_scanned, tracks_processed, errors_recorded, albums_added, .. } => format!(
+                "📚 Library scan finished: {} files scanned, {} tracks updated, {} new albums, {} errors",
+                files_scanned, tracks_processed, albums_added, errors_recorded,
+            ),
+            Event::AlbumAdded { album_artist, name } if album_artist.is_empty() => format!("💿 New album: **{}**", name),
+            Event::AlbumAdded { album_artist, name } => format!("💿 New album: **{}** by {}", name, album_artist),
+            Event::Test => "✅ ongaku-server webhook test".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Template {
+    /// `{"event": ..., "timestamp": ..., ...event fields}`
+    Json,
+    /// Discord's `{"content": ...}` message body
+    Discord,
+}
+
+struct Target {
+    url: String,
+    template: Template,
+}
+
+/// Outgoing HTTP callbacks configured with WEBHOOK_URLS. Deliveries run in the
+/// background, are retried with backoff and, with WEBHOOK_SECRET, signed with
+/// an HMAC-SHA256 over `<timestamp>.<body>`.
+pub struct Webhooks {
+    client: Client,
+    targets: Vec<Target>,
+    // Event kinds to deliver, None for all
+    events: Option<Vec<String>>,
+    secret: Option<String>,
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        Self {
+            client: Client::new(),
+            targets: Vec::new(),
+            events: None,
+            secret: None,
+        }
+    }
+}
+
+impl Webhooks {
+    /// Read WEBHOOK_URLS (comma separated), WEBHOOK_EVENTS and WEBHOOK_SECRET.
+    /// Discord webhook URLs get Discord's message format, anything else JSON.
+    pub fn from_env() -> Self {
+        let targets = env::var("WEBHOOK_URLS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .filter_map(|url| {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    error!("Ignoring webhook URL '{}', it must start with http:// or https://", url);
+                    return None;
+                }
+                let template = if is_discord_url(url) { Template::Discord } else { Template::Json };
+                Some(Target { url: url.to_string(), template })
+            })
+            .collect();
+        let events = env::var("WEBHOOK_EVENTS")
+            .ok()
+            .map(|events| {
+                events.split(',')
+                    .map(|event| event.trim().to_lowercase())
+                    .filter(|event| !event.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .filter(|events| !events.is_empty());
+
+        Self {
+            client: Client::builder().timeout(REQUEST_TIMEOUT).build().unwrap_or_default(),
+            targets,
+            events,
+            secret: env::var("WEBHOOK_SECRET").ok().filter(|secret| !secret.is_empty()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.targets.is_empty()
+    }
+
+    fn wants(&self, event: &Event) -> bool {
+        matches!(event, Event::Test)
+            || self.events.as_ref().is_none_or(|events| events.iter().any(|kind| kind == event.kind()))
+    }
+
+    /// Deliver an event to every webhook in the background
+    pub fn send(&self, event: Event) {
+        if !self.is_enabled() || !self.wants(&event) {
+            return;
+        }
+        let timestamp = Utc::now();
+        for target in &self.targets {
+            let request = self.request(target, &event, timestamp);
+            let url = target.url.clone();
+            let kind = event.kind();
+            tokio::spawn(async move {
+                if let Err(e) = deliver(request, MAX_ATTEMPTS).await {
+                    error!("Failed to deliver {} webhook to {}: {}", kind, redact(&url), e);
+                }
+            });
+        }
+    }
+
+    /// Report a finished scan and the albums it added
+    pub fn scan_completed(&self, result: &crate::scanner::ScanResult) {
+        self.send(Event::ScanCompleted {
+            scan_id: result.scan_id.clone(),
+            files_scanned: result.files_scanned,
+            tracks_processed: result.tracks_processed,
+            errors_recorded: result.errors_recorded,
+            albums_added: result.new_albums.len(),
+        });
+        if result.new_albums.len() <= MAX_ALBUM_EVENTS {
+            for (album_artist, name) in &result.new_albums {
+                self.send(Event::AlbumAdded { album_artist: album_artist.clone(), name: name.clone() });
+            }
+        }
+    }
+
+    fn request(&self, target: &Target, event: &Event, timestamp: DateTime<Utc>) -> reqwest::RequestBuilder {
+        let body = match target.template {
+            Template::Discord => json!({ "content": event.summary() }),
+            Template::Json => {
+                let mut body = serde_json::to_value(event).unwrap_or_else(|_| json!({ "event": event.kind() }));
+                body["timestamp"] = json!(timestamp);
+                body
+            }
+        };
+        let body = body.to_string();
+
+        let mut request = self.client.post(&target.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Ongaku-Event", event.kind())
+            .header("X-Ongaku-Timestamp", timestamp.timestamp().to_string());
+        if let Some(secret) = &self.secret {
+            request = request.header("X-Ongaku-Signature", format!("sha256={}", sign(secret, timestamp.timestamp(), &body)));
+        }
+        request.body(body)
+    }
+}
+
+fn is_discord_url(url: &str) -> bool {
+    ["https://discord.com/api/webhooks/", "https://discordapp.com/api/webhooks/", "https://ptb.discord.com/api/webhooks/", "https://canary.discord.com/api/webhooks/"]
+        .iter()
+        .any(|prefix| url.starts_with(prefix))
+}
+
+/// Signature of a delivery, for receivers to check against X-Ongaku-Signature
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// Webhook URLs usually embed a token, so logs only show the host
+fn redact(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| format!("{}://{}/…", url.scheme(), host)))
+        .unwrap_or_else(|| "webhook".to_string())
+}
+
+// Retries connection errors, 429s and 5xx responses; other 4xx responses won't
+// succeed on a retry
+async fn deliver(request: reqwest::RequestBuilder, attempts: u32) -> Result<u16, String> {
+    let mut delay = Duration::from_secs(1);
+    let mut last_error = String::new();
+    for attempt in 1..=attempts {
+        let Some(request) = request.try_clone() else {
+            return Err("request body can't be retried".to_string());
+        };
+        match request.send().await {
+            Ok(response) if response.status().is_success() => return Ok(response.status().as_u16()),
+            Ok(response) => {
+                let status = response.status();
+                last_error = format!("HTTP {}", status);
+                if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+                    return Err(last_error);
+                }
+            }
+            Err(e) => last_error = e.to_string(),
+        }
+        if attempt < attempts {
+            warn!("Webhook delivery failed ({}), retrying in {}s", last_error, delay.as_secs());
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+    Err(last_error)
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhookTestResult {
+    /// Webhook URL with everything after the host left out
+    pub url: String,
+    pub success: bool,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+// POST /admin/webhooks/test - Send a test event to every webhook and report the results
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks/test",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Delivery result per webhook, without retries", body = [WebhookTestResult]),
+    )
+)]
+pub async fn test_webhooks(State(state): State<AppState>) -> Json<Vec<WebhookTestResult>> {
+    let webhooks = &state.webhooks;
+    let timestamp = Utc::now();
+    let mut results = Vec::new();
+    for target in &webhooks.targets {
+        let result = deliver(webhooks.request(target, &Event::Test, timestamp), 1).await;
+        results.push(WebhookTestResult {
+            url: redact(&target.url),
+            success: result.is_ok(),
+            status: result.as_ref().ok().copied(),
+            error: result.err(),
+        });
+    }
+    info!("Sent test event to {} webhooks", results.len());
+    Json(results)
+}
@@ -0,0 +1,267 @@
+// Write-behind queue for small, frequent writes: resume positions and the
+// plays counted from playback progress. Handlers hand these to a background
+// writer instead of waiting on the database. Writes that pile up while a batch
+// is committed go into the next one together, with repeated position updates
+// for the same user and track collapsed to the latest. A batch the database
+// rejects is written again one write at a time, so one bad write doesn't take
+// the rest with it.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait, NotSet, QueryFilter, TransactionTrait};
+use serde::Serialize;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use utoipa::ToSchema;
+
+use entity::prelude::{PlayHistory, PlaybackPosition};
+use entity::{play_history, playback_position};
+
+const DEFAULT_QUEUE_SIZE: usize = 10_000;
+// Most writes committed in one transaction
+const MAX_BATCH: usize = 500;
+
+/// A write the queue makes on a handler's behalf
+pub enum Write {
+    /// Where a user is in a track, replacing their earlier position
+    Position {
+        user: String,
+        client: Option<String>,
+        track_id: i32,
+        position_ms: i64,
+        updated: DateTime<Utc>,
+    },
+    /// Forget a user's position in a track they finished
+    ClearPosition {
+        user: String,
+        track_id: i32,
+    },
+    /// A play for the listening history
+    Play {
+        user: String,
+        client: Option<String>,
+        track_id: i32,
+        played_at: DateTime<Utc>,
+        seconds_played: i32,
+    },
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WriteQueueStats {
+    /// Writes waiting for the writer
+    pub pending: usize,
+    pub capacity: usize,
+    /// Writes committed since the server started, after collapsing repeated
+    /// position updates
+    pub written: u64,
+    /// Writes lost to database errors
+    pub failed: u64,
+    pub batches: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    written: AtomicU64,
+    failed: AtomicU64,
+    batches: AtomicU64,
+}
+
+pub struct WriteQueue {
+    sender: mpsc::Sender<Write>,
+    capacity: usize,
+    counters: Arc<Counters>,
+    shutdown: Arc<Notify>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl WriteQueue {
+    /// Start the background writer. The queue holds WRITE_QUEUE_SIZE writes
+    /// (default 10000); handlers only wait for it when it is full.
+    pub fn spawn(db: DatabaseConnection) -> Self {
+        let capacity = env::var("WRITE_QUEUE_SIZE")
+            .ok()
+            .and_then(|size| size.trim().parse().ok())
+            .filter(|&size: &usize| size > 0)
+            .unwrap_or(DEFAULT_QUEUE_SIZE);
+        let (sender, receiver) = mpsc::channel(capacity);
+        let counters = Arc::new(Counters::default());
+        let shutdown = Arc::new(Notify::new());
+        let writer = tokio::spawn(run_writer(db, receiver, counters.clone(), shutdown.clone()));
+        Self { sender, capacity, counters, shutdown, writer: Mutex::new(Some(writer)) }
+    }
+
+    /// Stop taking writes and wait for the ones already queued to be written,
+    /// for shutting down without losing them
+    pub async fn close(&self) {
+        self.shutdown.notify_one();
+        let writer = self.writer.lock().unwrap().take();
+        if let Some(writer) = writer {
+            if let Err(e) = writer.await {
+                error!("Write queue writer failed: {}", e);
+            }
+        }
+    }
+
+    /// Queue a write, waiting only while the queue is full
+    pub async fn push(&self, write: Write) {
+        if self.sender.capacity() == 0 {
+            warn!("Write queue is full, waiting for the database");
+        }
+        if self.sender.send(write).await.is_err() {
+            error!("Write queue writer has stopped, dropping a write");
+            self.counters.failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> WriteQueueStats {
+        WriteQueueStats {
+            pending: self.capacity - self.sender.capacity(),
+            capacity: self.capacity,
+            written: self.counters.written.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+            batches: self.counters.batches.load(Ordering::Relaxed),
+        }
+    }
+}
+
+async fn run_writer(db: DatabaseConnection, mut receiver: mpsc::Receiver<Write>, counters: Arc<Counters>, shutdown: Arc<Notify>) {
+    let mut batch = Vec::with_capacity(MAX_BATCH);
+    let mut closing = false;
+    loop {
+        // Once closed, the receiver still hands out what was queued before
+        // returning 0
+        let received = tokio::select! {
+            received = receiver.recv_many(&mut batch, MAX_BATCH) => received,
+            _ = shutdown.notified(), if !closing => {
+                closing = true;
+                receiver.close();
+                continue;
+            }
+        };
+        if received == 0 {
+            break;
+        }
+
+        let (plays, positions) = collapse(batch.drain(..));
+        let count = (plays.len() + positions.len()) as u64;
+        if let Err(e) = commit(&db, &plays, &positions).await {
+            warn!("Failed to write {} queued plays and positions together, writing them one at a time: {}", count, e);
+            let failed = commit_each(&db, plays, positions).await;
+            counters.written.fetch_add(count - failed, Ordering::Relaxed);
+            counters.failed.fetch_add(failed, Ordering::Relaxed);
+            continue;
+        }
+        counters.written.fetch_add(count, Ordering::Relaxed);
+        counters.batches.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+type PlayWrite = play_history::ActiveModel;
+// The position to store for a user and track, None to clear it
+type PositionWrites = HashMap<(String, i32), Option<playback_position::ActiveModel>>;
+
+fn collapse(writes: impl Iterator<Item = Write>) -> (Vec<PlayWrite>, PositionWrites) {
+    let mut plays = Vec::new();
+    let mut positions = HashMap::new();
+    for write in writes {
+        match write {
+            Write::Position { user, client, track_id, position_ms, updated } => {
+                let position = playback_position::ActiveModel {
+                    id: NotSet,
+                    user: Set(user.clone()),
+                    client: Set(client),
+                    track_id: Set(track_id),
+                    position_ms: Set(position_ms),
+                    updated: Set(updated),
+                };
+                positions.insert((user, track_id), Some(position));
+            }
+            Write::ClearPosition { user, track_id } => {
+                positions.insert((user, track_id), None);
+            }
+            Write::Play { user, client, track_id, played_at, seconds_played } => {
+                plays.push(play_history::ActiveModel {
+                    id: NotSet,
+                    user: Set(user),
+                    client: Set(client),
+                    track_id: Set(track_id),
+                    played_at: Set(played_at),
+                    seconds_played: Set(seconds_played),
+                });
+            }
+        }
+    }
+    (plays, positions)
+}
+
+async fn commit(db: &DatabaseConnection, plays: &[PlayWrite], positions: &PositionWrites) -> Result<(), DbErr> {
+    let txn = db.begin().await?;
+    if !plays.is_empty() {
+        PlayHistory::insert_many(plays.to_vec()).exec(&txn).await?;
+    }
+
+    let mut upserts = Vec::new();
+    for ((user, track_id), position) in positions {
+        match position {
+            Some(position) => upserts.push(position.clone()),
+            None => clear_position(&txn, user, *track_id).await?,
+        }
+    }
+    if !upserts.is_empty() {
+        upsert_positions(&txn, upserts).await?;
+    }
+    txn.commit().await
+}
+
+// Write a rejected batch's writes on their own, e.g. so a play of a track
+// deleted since only loses that play. Returns how many failed.
+async fn commit_each(db: &DatabaseConnection, plays: Vec<PlayWrite>, positions: PositionWrites) -> u64 {
+    let mut failed = 0;
+    for play in plays {
+        if let Err(e) = PlayHistory::insert(play).exec(db).await {
+            error!("Failed to write a queued play: {}", e);
+            failed += 1;
+        }
+    }
+    for ((user, track_id), position) in positions {
+        let result = match position {
+            Some(position) => upsert_positions(db, vec![position]).await,
+            None => clear_position(db, &user, track_id).await,
+        };
+        if let Err(e) = result {
+            error!("Failed to write the position of {} in track {}: {}", user, track_id, e);
+            failed += 1;
+        }
+    }
+    failed
+}
+
+async fn clear_position<C: ConnectionTrait>(db: &C, user: &str, track_id: i32) -> Result<(), DbErr> {
+    PlaybackPosition::delete_many()
+        .filter(playback_position::Column::User.eq(user))
+        .filter(playback_position::Column::TrackId.eq(track_id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+async fn upsert_positions<C: ConnectionTrait>(db: &C, positions: Vec<playback_position::ActiveModel>) -> Result<(), DbErr> {
+    let on_conflict = sea_query::OnConflict::columns([playback_position::Column::User, playback_position::Column::TrackId])
+        .update_columns([
+            playback_position::Column::Client,
+            playback_position::Column::PositionMs,
+            playback_position::Column::Updated,
+        ])
+        .to_owned();
+    PlaybackPosition::insert_many(positions)
+        .on_conflict(on_conflict)
+        .exec(db)
+        .await?;
+    Ok(())
+}